@@ -17,8 +17,9 @@ rustc --version
 banner build
 cargo build
 
-cargo fmt -- --check
-cargo clippy -- --deny warnings
+cargo fmt --all -- --check
+cargo clippy --workspace --all-features -- --deny warnings
 
 banner test
 cargo test
+cargo test --all-features
@@ -0,0 +1,38 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use ispf::{from_bytes_le, to_bytes_le};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Entry {
+    offset: u64,
+    typ: u8,
+    name: String,
+    #[serde(with = "ispf::str_lv16")]
+    uid: String,
+}
+
+fn null_terminated_strings(c: &mut Criterion) {
+    let entry = Entry {
+        offset: 47,
+        typ: 9,
+        name: "m".repeat(4096),
+        uid: "muffin".into(),
+    };
+    let buf = to_bytes_le(&entry).unwrap();
+
+    c.bench_function("decode 4k null-terminated string", |b| {
+        b.iter(|| {
+            let e: Entry = from_bytes_le(black_box(buf.as_slice())).unwrap();
+            e
+        })
+    });
+}
+
+criterion_group!(benches, null_terminated_strings);
+criterion_main!(benches);
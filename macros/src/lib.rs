@@ -0,0 +1,2829 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::ops::Range;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DataEnum, DeriveInput, Expr, Field, Fields,
+    GenericParam, Generics, Ident, LitInt, LitStr, Result, Token, Type,
+    Variant,
+};
+
+/// Derive `Serialize` and `Deserialize` for a struct or enum, honoring
+/// `#[ispf(..)]` attributes.
+///
+/// On structs with named fields:
+///
+/// - `#[ispf(count_from = "field")]` on a collection field: the number of
+///   elements is held by an earlier integer field rather than a prefix. That
+///   field is filled in from the collection's length on serialize.
+/// - `#[ispf(bytes_from = "field")]` on a `Vec<u8>` or `&[u8]` field: the
+///   number of bytes is held by an earlier integer field, filled in the same
+///   way.
+/// - `#[ispf(len_adjust = N)]` alongside either of the above: the length field
+///   holds the actual length plus `N`, for formats whose lengths count (or
+///   leave out) some fixed number of header bytes. `N` may be negative.
+/// - `#[ispf(tag_from = "field")]` on a field whose type is a derived enum:
+///   the enum's tag is held by an earlier integer field instead of being
+///   written inline. That field is filled in from the enum on serialize.
+/// - `#[ispf(size_of = "message")]` on an integer field: the field holds the
+///   encoded length of the whole struct, itself included, and is filled in on
+///   serialize. Adding `validate` checks it against the input on deserialize.
+/// - `#[ispf(checksum = "crc32")]` on the first or last field: the field holds
+///   a checksum, one of `crc32`, `crc16` or `sum8`, over the rest of the
+///   struct. It is computed on serialize and checked on deserialize. Adding
+///   `over = "a..c"` covers only the fields from `a` up to `c`, or to `c`
+///   inclusive with `"a..=c"`, and lets the checksum field sit anywhere
+///   outside them.
+/// - `#[ispf(magic = 0x39503031)]` on an integer field: the constant is
+///   written in place of the field's value on serialize and checked on
+///   deserialize, failing with the value found instead.
+/// - `#[ispf(since = N)]` on a trailing field: the field was added in version
+///   `N` of the protocol. It is left out when serializing for an earlier
+///   version, set with `Serializer::version`, and takes its default value
+///   when the input ends before it.
+/// - `#[ispf(align = 4)]`: the field starts at the next multiple of 4 bytes
+///   into the message, after zero padding that is skipped when decoding.
+/// - `#[ispf(bits = 3)]`: the field takes 3 bits of an integer shared with
+///   the adjacent fields marked `bits`, the first in the least significant
+///   bits. A run of them ends once it fills a `u8`, `u16`, `u32` or `u64`;
+///   `storage = "u16"` on its first field packs it into that type instead.
+///   The fields must be unsigned integers or `bool`.
+/// - `#[ispf(default)]`: the field takes its default value when the input
+///   ends before it, as sent by peers with shorter versions of the struct.
+/// - `#[ispf(lv = "u16")]` on a `String` or `Vec` field: the field is preceded
+///   by its length, in bytes for a string and in elements for a vector, as an
+///   integer of the given width. Adding `unit = "bytes"` counts a vector in
+///   bytes instead.
+/// - `#[ispf(payload)]` on a `Vec<u8>` field with `lv` or `bytes_from`: the
+///   type implements `Payload` with the field, so that `to_vectored` and
+///   `to_parts` can leave it out of the encoded head. Any type that is
+///   `AsRef<[u8]>` will do. At most one field is the payload.
+/// - `#[ispf(try_from = "u8")]`: the field is written as the given integer
+///   type, converted with `From`, and read back through `TryFrom`. A value
+///   the conversion rejects is reported along with the field's name.
+/// - `#[ispf(endian = "big")]` or `"little"` on the struct: everything in it
+///   is in that byte order, whichever the serializer or deserializer uses,
+///   except nested structs that declare an order of their own.
+///
+/// On enums:
+///
+/// - The enum is written as an integer tag holding the variant's discriminant,
+///   explicit or implicit, followed by the variant's fields, if any. Fields of
+///   a variant take the same attributes as fields of a struct.
+/// - `#[ispf(other)]` on a variant with two fields makes it the catch-all for
+///   unknown tags. The first field holds the tag and the second, a byte
+///   buffer, everything after it. It still takes its place when numbering
+///   implicit discriminants, and serializing it with another variant's tag
+///   fails.
+/// - `#[ispf(tag = "u16")]` on the enum sets the tag width to one of `u8`,
+///   `u16`, `u32` or `u64`. Without it, the width of an integer `#[repr]` is
+///   used, and failing that `u32`.
+///
+/// On either:
+///
+/// - `#[ispf(assert_size = N)]` fails the build unless the type always encodes
+///   to exactly `N` bytes, catching changes that would break a fixed layout.
+/// - `#[ispf(dual_endian)]`: the type gets `from_bytes_in` and `to_bytes_in`
+///   methods taking an `ispf::ByteOrder`, for data whose order is only known
+///   at run time.
+#[proc_macro_derive(Ispf, attributes(ispf))]
+pub fn derive_ispf(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derive `Serialize` and `Deserialize` for an enum of messages that share a
+/// header holding a type code, dispatching on that code.
+///
+/// - `#[ispf(type_offset = N)]` on the enum gives the offset of the code
+///   within each message, and `#[ispf(tag = "u8")]` its width, as for `Ispf`.
+/// - Each variant wraps a single message and is marked
+///   `#[ispf(type = code)]`.
+///
+/// On serialize, the code of the variant is written over the message at the
+/// offset. On deserialize, the code is read at the offset and the message of
+/// the matching variant is read from the start.
+#[proc_macro_derive(IspfMessage, attributes(ispf))]
+pub fn derive_ispf_message(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_message(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derive `FixedSize` for a type whose encoding always takes the same number
+/// of bytes, giving it a `WIRE_SIZE` constant.
+///
+/// Structs are sized as the sum of their fields, honoring `try_from` on a
+/// field. Enums with only unit variants are sized as their tag, as written by
+/// the `Ispf` derive.
+#[proc_macro_derive(FixedSize, attributes(ispf))]
+pub fn derive_fixed_size(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_size(&input, SizeKind::Fixed)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derive `MaxSize` for a type whose encoding has an upper bound, giving it a
+/// `MAX_WIRE_SIZE` constant.
+///
+/// Structs are bounded by the sum of their fields. A field with `lv`,
+/// `count_from` or `bytes_from` is bounded by the largest length its prefix or
+/// length field can hold. Enums are bounded by their tag and their largest
+/// variant, as written by the `Ispf` derive.
+#[proc_macro_derive(MaxSize, attributes(ispf))]
+pub fn derive_max_size(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_size(&input, SizeKind::Max)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Give `String` and `Vec` fields a length prefix of the given width by adding
+/// the matching `#[serde(with = ..)]` attribute to each.
+///
+/// `#[with_length(u16)]` on a struct or enum applies the width to every
+/// `String` and `Vec` field without a `with` attribute of its own, selecting
+/// `ispf::str_lv16` or `ispf::vec_lv16`. A field marked `#[with_length(u8)]`
+/// uses that width instead. The width is one of `u8`, `u16`, `u32` or `u64`,
+/// and may also be written as a string.
+///
+/// The attribute must come before `#[derive(Serialize, Deserialize)]` so that
+/// serde's derive sees the attributes it adds.
+#[proc_macro_attribute]
+pub fn with_length(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut input = parse_macro_input!(item as DeriveInput);
+    let width = if attr.is_empty() {
+        None
+    } else {
+        Some(parse_macro_input!(attr with parse_width))
+    };
+    match expand_with_length(width, &mut input) {
+        Ok(()) => quote!(#input).into(),
+        Err(e) => e.into_compile_error().into(),
+    }
+}
+
+/// Parse a width given as `u16` or `"u16"`.
+fn parse_width(input: syn::parse::ParseStream) -> Result<Ident> {
+    let ident = if input.peek(LitStr) {
+        input.parse::<LitStr>()?.parse::<Ident>()?
+    } else {
+        input.parse::<Ident>()?
+    };
+    if !TAG_TYPES.contains(&ident.to_string().as_str()) {
+        return Err(syn::Error::new_spanned(
+            &ident,
+            "width must be one of u8, u16, u32 or u64",
+        ));
+    }
+    Ok(ident)
+}
+
+fn expand_with_length(
+    width: Option<Ident>,
+    input: &mut DeriveInput,
+) -> Result<()> {
+    let fields: Vec<&mut Field> = match &mut input.data {
+        Data::Struct(data) => data.fields.iter_mut().collect(),
+        Data::Enum(data) => data
+            .variants
+            .iter_mut()
+            .flat_map(|v| v.fields.iter_mut())
+            .collect(),
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "with_length does not support unions",
+            ))
+        }
+    };
+    for field in fields {
+        let mut own = None;
+        let mut kept = Vec::new();
+        for attr in field.attrs.drain(..) {
+            if attr.path().is_ident("with_length") {
+                if own.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "with_length is already specified",
+                    ));
+                }
+                own = Some(attr.parse_args_with(parse_width)?);
+            } else {
+                kept.push(attr);
+            }
+        }
+        field.attrs = kept;
+
+        let has_with = field.attrs.iter().any(has_serde_with);
+        let (width, explicit) = match (own, &width) {
+            (Some(own), _) => {
+                if has_with {
+                    return Err(syn::Error::new_spanned(
+                        &field.ty,
+                        "with_length conflicts with serde's with attribute",
+                    ));
+                }
+                (own, true)
+            }
+            (None, Some(width)) if !has_with => (width.clone(), false),
+            _ => continue,
+        };
+        let kind = match length_kind(&field.ty) {
+            Some(kind) => kind,
+            None if explicit => {
+                return Err(syn::Error::new_spanned(
+                    &field.ty,
+                    "with_length applies to String and Vec fields",
+                ))
+            }
+            None => continue,
+        };
+        let path = format!("ispf::{}_lv{}", kind, &width.to_string()[1..]);
+        field.attrs.push(syn::parse_quote!(#[serde(with = #path)]));
+    }
+    Ok(())
+}
+
+/// The `Width` variant for `u8`, `u16`, `u32` or `u64`.
+fn width_variant(ty: &Ident) -> Ident {
+    Ident::new(&ty.to_string().to_uppercase(), ty.span())
+}
+
+/// Whether a field already has `#[serde(with = ..)]` or one of its halves.
+fn has_serde_with(attr: &syn::Attribute) -> bool {
+    if !attr.path().is_ident("serde") {
+        return false;
+    }
+    let mut found = false;
+    let _ = attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("with")
+            || meta.path.is_ident("serialize_with")
+            || meta.path.is_ident("deserialize_with")
+        {
+            found = true;
+        }
+        if meta.input.peek(Token![=]) {
+            meta.value()?.parse::<Expr>()?;
+        }
+        Ok(())
+    });
+    found
+}
+
+/// The formatter family for a field type: `str` for `String` and `vec` for
+/// `Vec`.
+fn length_kind(ty: &Type) -> Option<&'static str> {
+    let path = match ty {
+        Type::Path(ty) if ty.qself.is_none() => &ty.path,
+        _ => return None,
+    };
+    match path.segments.last()?.ident.to_string().as_str() {
+        "String" => Some("str"),
+        "Vec" => Some("vec"),
+        _ => None,
+    }
+}
+
+/// What a length held in another field counts.
+#[derive(Clone, Copy)]
+enum Unit {
+    Elements,
+    Bytes,
+}
+
+/// Attributes on the struct or enum itself.
+#[derive(Default)]
+struct ContainerAttrs {
+    tag: Option<Ident>,
+    type_offset: Option<usize>,
+    /// The fixed wire size the type is checked against at compile time.
+    assert_size: Option<LitInt>,
+    /// The byte order the struct is always written in, big endian if true.
+    big_endian: Option<bool>,
+    /// Generate methods taking the byte order at run time.
+    dual_endian: bool,
+}
+
+const TAG_TYPES: &[&str] = &["u8", "u16", "u32", "u64"];
+
+impl ContainerAttrs {
+    fn parse(input: &DeriveInput) -> Result<Self> {
+        let mut attrs = ContainerAttrs::default();
+        let mut repr = None;
+        for attr in &input.attrs {
+            if attr.path().is_ident("repr") {
+                // Other reprs (C, packed, ..) are of no interest here.
+                let _ = attr.parse_nested_meta(|meta| {
+                    if let Some(ident) = meta.path.get_ident() {
+                        if TAG_TYPES.contains(&ident.to_string().as_str()) {
+                            repr = Some(ident.clone());
+                        }
+                    }
+                    Ok(())
+                });
+                continue;
+            }
+            if !attr.path().is_ident("ispf") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    if attrs.tag.is_some() {
+                        return Err(meta.error("tag is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    if !TAG_TYPES.contains(&s.value().as_str()) {
+                        return Err(syn::Error::new_spanned(
+                            &s,
+                            "tag must be one of u8, u16, u32 or u64",
+                        ));
+                    }
+                    attrs.tag = Some(s.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("type_offset") {
+                    if attrs.type_offset.is_some() {
+                        return Err(
+                            meta.error("type_offset is already specified")
+                        );
+                    }
+                    let lit: LitInt = meta.value()?.parse()?;
+                    attrs.type_offset = Some(lit.base10_parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("assert_size") {
+                    if attrs.assert_size.is_some() {
+                        return Err(
+                            meta.error("assert_size is already specified")
+                        );
+                    }
+                    let lit: LitInt = meta.value()?.parse()?;
+                    lit.base10_parse::<usize>()?;
+                    attrs.assert_size = Some(lit);
+                    Ok(())
+                } else if meta.path.is_ident("dual_endian") {
+                    attrs.dual_endian = true;
+                    Ok(())
+                } else if meta.path.is_ident("endian") {
+                    if attrs.big_endian.is_some() {
+                        return Err(meta.error("endian is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    attrs.big_endian = match s.value().as_str() {
+                        "big" => Some(true),
+                        "little" => Some(false),
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                &s,
+                                "endian must be \"big\" or \"little\"",
+                            ))
+                        }
+                    };
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown ispf attribute"))
+                }
+            })?;
+        }
+        if attrs.tag.is_none() {
+            attrs.tag = repr;
+        }
+        Ok(attrs)
+    }
+
+    fn tag(&self) -> Ident {
+        self.tag
+            .clone()
+            .unwrap_or_else(|| Ident::new("u32", Span::call_site()))
+    }
+}
+
+/// Attributes on a single field.
+#[derive(Default)]
+struct FieldAttrs {
+    length_from: Option<(Ident, Unit)>,
+    len_adjust: Option<i64>,
+    /// The field holding the tag of this enum field.
+    tag_from: Option<Ident>,
+    /// The integer type this field is converted to and from on the wire.
+    try_from: Option<Type>,
+    /// The width of this field's length prefix.
+    lv: Option<Ident>,
+    /// What the length prefix counts, elements unless given.
+    unit: Option<Unit>,
+    /// This field holds the encoded length of the whole message.
+    size_of: bool,
+    /// The size is checked against the input on deserialize.
+    validate: bool,
+    /// The constant this field always holds on the wire.
+    magic: Option<Expr>,
+    /// The `Checksum` variant this field holds.
+    checksum: Option<Ident>,
+    /// The fields the checksum covers, as a range such as `"a..c"`, when not
+    /// the rest of the struct.
+    over: Option<LitStr>,
+    /// The protocol version that added this field.
+    since: Option<usize>,
+    /// The field takes its default value when the input ends before it.
+    default: bool,
+    /// Pad before the field up to a multiple of this many bytes.
+    align: Option<usize>,
+    /// The number of bits the field takes within an integer shared with the
+    /// fields around it.
+    bits: Option<u32>,
+    /// The integer a run of `bits` fields starting here is packed into.
+    storage: Option<Ident>,
+    /// The field is the message's payload, returned by `Payload::payload`.
+    payload: bool,
+}
+
+impl FieldAttrs {
+    fn parse(field: &Field) -> Result<Self> {
+        let mut attrs = FieldAttrs::default();
+        for attr in &field.attrs {
+            if !attr.path().is_ident("ispf") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                let unit = if meta.path.is_ident("count_from") {
+                    Some(Unit::Elements)
+                } else if meta.path.is_ident("bytes_from") {
+                    Some(Unit::Bytes)
+                } else {
+                    None
+                };
+                if meta.path.is_ident("len_adjust") {
+                    if attrs.len_adjust.is_some() {
+                        return Err(
+                            meta.error("len_adjust is already specified")
+                        );
+                    }
+                    let input = meta.value()?;
+                    let neg = input.parse::<Option<Token![-]>>()?.is_some();
+                    let lit: LitInt = input.parse()?;
+                    let n: i64 = lit.base10_parse()?;
+                    attrs.len_adjust = Some(if neg { -n } else { n });
+                    return Ok(());
+                }
+                if meta.path.is_ident("try_from") {
+                    if attrs.try_from.is_some() {
+                        return Err(meta.error("try_from is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    attrs.try_from = Some(s.parse()?);
+                    return Ok(());
+                }
+                if meta.path.is_ident("size_of") {
+                    if attrs.size_of {
+                        return Err(meta.error("size_of is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    if s.value() != "message" {
+                        return Err(syn::Error::new_spanned(
+                            &s,
+                            "size_of must be \"message\"",
+                        ));
+                    }
+                    attrs.size_of = true;
+                    return Ok(());
+                }
+                if meta.path.is_ident("bits") {
+                    if attrs.bits.is_some() {
+                        return Err(meta.error("bits is already specified"));
+                    }
+                    let lit: LitInt = meta.value()?.parse()?;
+                    let bits: u32 = lit.base10_parse()?;
+                    if !(1..=64).contains(&bits) {
+                        return Err(syn::Error::new_spanned(
+                            &lit,
+                            "bits must be between 1 and 64",
+                        ));
+                    }
+                    attrs.bits = Some(bits);
+                    return Ok(());
+                }
+                if meta.path.is_ident("storage") {
+                    if attrs.storage.is_some() {
+                        return Err(meta.error("storage is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    if !TAG_TYPES.contains(&s.value().as_str()) {
+                        return Err(syn::Error::new_spanned(
+                            &s,
+                            "storage must be one of u8, u16, u32 or u64",
+                        ));
+                    }
+                    attrs.storage = Some(s.parse()?);
+                    return Ok(());
+                }
+                if meta.path.is_ident("align") {
+                    if attrs.align.is_some() {
+                        return Err(meta.error("align is already specified"));
+                    }
+                    let lit: LitInt = meta.value()?.parse()?;
+                    let align: usize = lit.base10_parse()?;
+                    if align == 0 {
+                        return Err(syn::Error::new_spanned(
+                            &lit,
+                            "align must be at least 1",
+                        ));
+                    }
+                    attrs.align = Some(align);
+                    return Ok(());
+                }
+                if meta.path.is_ident("default") {
+                    attrs.default = true;
+                    return Ok(());
+                }
+                if meta.path.is_ident("since") {
+                    if attrs.since.is_some() {
+                        return Err(meta.error("since is already specified"));
+                    }
+                    let lit: LitInt = meta.value()?.parse()?;
+                    attrs.since = Some(lit.base10_parse()?);
+                    return Ok(());
+                }
+                if meta.path.is_ident("checksum") {
+                    if attrs.checksum.is_some() {
+                        return Err(meta.error("checksum is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    let variant =
+                        match s.value().as_str() {
+                            "crc32" => "Crc32",
+                            "crc16" => "Crc16",
+                            "sum8" => "Sum8",
+                            _ => return Err(syn::Error::new_spanned(
+                                &s,
+                                "checksum must be one of crc32, crc16 or sum8",
+                            )),
+                        };
+                    attrs.checksum = Some(Ident::new(variant, s.span()));
+                    return Ok(());
+                }
+                if meta.path.is_ident("over") {
+                    if attrs.over.is_some() {
+                        return Err(meta.error("over is already specified"));
+                    }
+                    attrs.over = Some(meta.value()?.parse()?);
+                    return Ok(());
+                }
+                if meta.path.is_ident("magic") {
+                    if attrs.magic.is_some() {
+                        return Err(meta.error("magic is already specified"));
+                    }
+                    attrs.magic = Some(meta.value()?.parse()?);
+                    return Ok(());
+                }
+                if meta.path.is_ident("validate") {
+                    attrs.validate = true;
+                    return Ok(());
+                }
+                if meta.path.is_ident("payload") {
+                    attrs.payload = true;
+                    return Ok(());
+                }
+                if meta.path.is_ident("lv") {
+                    if attrs.lv.is_some() {
+                        return Err(meta.error("lv is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    if !TAG_TYPES.contains(&s.value().as_str()) {
+                        return Err(syn::Error::new_spanned(
+                            &s,
+                            "lv must be one of u8, u16, u32 or u64",
+                        ));
+                    }
+                    attrs.lv = Some(s.parse()?);
+                    return Ok(());
+                }
+                if meta.path.is_ident("unit") {
+                    if attrs.unit.is_some() {
+                        return Err(meta.error("unit is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    attrs.unit = match s.value().as_str() {
+                        "elements" => Some(Unit::Elements),
+                        "bytes" => Some(Unit::Bytes),
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                &s,
+                                "unit must be \"elements\" or \"bytes\"",
+                            ))
+                        }
+                    };
+                    return Ok(());
+                }
+                if meta.path.is_ident("tag_from") {
+                    if attrs.tag_from.is_some() {
+                        return Err(meta.error("tag_from is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    attrs.tag_from = Some(s.parse()?);
+                    return Ok(());
+                }
+                if let Some(unit) = unit {
+                    if attrs.length_from.is_some() {
+                        return Err(meta.error("length is already specified"));
+                    }
+                    let s: LitStr = meta.value()?.parse()?;
+                    attrs.length_from = Some((s.parse()?, unit));
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown ispf attribute"))
+                }
+            })?;
+            if attrs.len_adjust.is_some() && attrs.length_from.is_none() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "len_adjust requires count_from or bytes_from",
+                ));
+            }
+            if attrs.tag_from.is_some() && attrs.length_from.is_some() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "tag_from cannot be combined with a length",
+                ));
+            }
+            if attrs.try_from.is_some()
+                && (attrs.tag_from.is_some() || attrs.length_from.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "try_from cannot be combined with a length or tag",
+                ));
+            }
+            if attrs.unit.is_some() && attrs.lv.is_none() {
+                return Err(syn::Error::new_spanned(attr, "unit requires lv"));
+            }
+            if attrs.lv.is_some()
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "lv cannot be combined with a length, tag or try_from",
+                ));
+            }
+            if attrs.magic.is_some()
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.lv.is_some()
+                    || attrs.size_of)
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "magic cannot be combined with other field attributes",
+                ));
+            }
+            if attrs.checksum.is_some()
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.lv.is_some()
+                    || attrs.size_of
+                    || attrs.magic.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "checksum cannot be combined with other field attributes",
+                ));
+            }
+            if (attrs.since.is_some() || attrs.default)
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.lv.is_some()
+                    || attrs.size_of
+                    || attrs.magic.is_some()
+                    || attrs.checksum.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "since and default cannot be combined with other field \
+                     attributes",
+                ));
+            }
+            if attrs.storage.is_some() && attrs.bits.is_none() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "storage requires bits",
+                ));
+            }
+            if attrs.bits.is_some()
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.lv.is_some()
+                    || attrs.size_of
+                    || attrs.magic.is_some()
+                    || attrs.checksum.is_some()
+                    || attrs.since.is_some()
+                    || attrs.default
+                    || attrs.align.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "bits cannot be combined with other field attributes",
+                ));
+            }
+            if attrs.align.is_some()
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.lv.is_some()
+                    || attrs.size_of
+                    || attrs.magic.is_some()
+                    || attrs.checksum.is_some()
+                    || attrs.since.is_some()
+                    || attrs.default)
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "align cannot be combined with other field attributes",
+                ));
+            }
+            if attrs.since.is_some() && attrs.default {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "since already implies default",
+                ));
+            }
+            if attrs.validate && !attrs.size_of {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "validate requires size_of",
+                ));
+            }
+            if attrs.over.is_some() && attrs.checksum.is_none() {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "over requires checksum",
+                ));
+            }
+            if attrs.size_of
+                && (attrs.length_from.is_some()
+                    || attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.lv.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "size_of cannot be combined with other field attributes",
+                ));
+            }
+            if attrs.payload
+                && (attrs.tag_from.is_some()
+                    || attrs.try_from.is_some()
+                    || attrs.size_of
+                    || attrs.magic.is_some()
+                    || attrs.checksum.is_some()
+                    || attrs.since.is_some()
+                    || attrs.default
+                    || attrs.bits.is_some()
+                    || attrs.align.is_some())
+            {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "payload can only be combined with lv or bytes_from",
+                ));
+            }
+        }
+        Ok(attrs)
+    }
+}
+
+/// A field and what the derive has to do with it.
+struct FieldInfo<'a> {
+    field: &'a Field,
+    /// The name the field's value is bound to in generated code.
+    ident: Ident,
+    /// The name passed to `serialize_field`.
+    key: String,
+    attrs: FieldAttrs,
+    /// The field whose length this field holds, if any, and the adjustment
+    /// applied to the stored value.
+    length_of: Option<(Ident, Unit, i64)>,
+    /// The enum field whose tag this field holds, if any.
+    tag_of: Option<Ident>,
+    /// Where a `bits` field sits in the integer it shares.
+    bit_slot: Option<BitSlot>,
+    /// The indices of the fields a checksum field covers, if given by `over`.
+    covers: Option<Range<usize>>,
+}
+
+/// The place of a `bits` field within the integer it shares with the fields
+/// either side of it.
+struct BitSlot {
+    /// The integer type holding the group.
+    storage: Ident,
+    /// The local the integer is read into.
+    var: Ident,
+    shift: u32,
+    width: u32,
+    /// The number of fields in the group, on its first field only.
+    group: Option<usize>,
+}
+
+/// Gather the fields of a struct or enum variant. Unnamed fields are bound as
+/// `__field0`, `__field1` and so on.
+fn field_infos(fields: &Fields) -> Result<Vec<FieldInfo<'_>>> {
+    let mut infos = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let ident = match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("__field{}", i),
+        };
+        infos.push(FieldInfo {
+            field,
+            key: ident.to_string(),
+            ident,
+            attrs: FieldAttrs::parse(field)?,
+            length_of: None,
+            tag_of: None,
+            bit_slot: None,
+            covers: None,
+        });
+    }
+    link_fields(&mut infos)?;
+    group_bits(&mut infos)?;
+    link_checksum(&mut infos)?;
+    check_trailing(&infos)?;
+    Ok(infos)
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let attrs = ContainerAttrs::parse(input)?;
+    if attrs.type_offset.is_some() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "type_offset is only meaningful with IspfMessage",
+        ));
+    }
+    if attrs.dual_endian && attrs.big_endian.is_some() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "dual_endian cannot be combined with endian",
+        ));
+    }
+    let mut check = match &attrs.assert_size {
+        Some(expected) => assert_size(input, expected)?,
+        None => TokenStream2::new(),
+    };
+    if attrs.dual_endian {
+        check.extend(dual_endian(input));
+    }
+    let fields =
+        match &input.data {
+            Data::Struct(s) => match &s.fields {
+                Fields::Named(_) => &s.fields,
+                _ => return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "Ispf can only be derived for structs with named fields",
+                )),
+            },
+            Data::Enum(e) => {
+                if attrs.big_endian.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        &input.ident,
+                        "endian is only meaningful on structs",
+                    ));
+                }
+                let expanded = expand_enum(input, &attrs, e)?;
+                return Ok(quote! {
+                    #expanded
+                    #check
+                });
+            }
+            Data::Union(_) => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "Ispf can only be derived for structs and enums",
+                ))
+            }
+        };
+    if attrs.tag.is_some() {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "tag is only meaningful on enums",
+        ));
+    }
+
+    let infos = field_infos(fields)?;
+    if let Some(f) = infos.iter().filter(|f| f.attrs.size_of).nth(1) {
+        return Err(syn::Error::new_spanned(
+            f.field,
+            "only one field can hold the size of the message",
+        ));
+    }
+    for (i, f) in infos.iter().enumerate() {
+        if f.attrs.checksum.is_some()
+            && f.covers.is_none()
+            && i != 0
+            && i != infos.len() - 1
+        {
+            return Err(syn::Error::new_spanned(
+                f.field,
+                "a checksum must be the first or last field unless given over",
+            ));
+        }
+    }
+    if let Some(f) = infos.iter().filter(|f| f.attrs.checksum.is_some()).nth(1)
+    {
+        return Err(syn::Error::new_spanned(
+            f.field,
+            "only one field can hold a checksum",
+        ));
+    }
+    if let Some(f) = infos.iter().filter(|f| f.attrs.payload).nth(1) {
+        return Err(syn::Error::new_spanned(
+            f.field,
+            "only one field can be the payload",
+        ));
+    }
+    if let Some(f) = infos.iter().find(|f| f.attrs.payload) {
+        check.extend(payload(input, &f.ident));
+    }
+    let layers = layers(&infos, attrs.big_endian);
+    let ser = expand_serialize(input, &infos, &layers);
+    let de = expand_deserialize(input, &infos, &layers);
+    Ok(quote! {
+        #ser
+        #de
+        #check
+    })
+}
+
+/// An implementation of `Payload` returning the given field.
+fn payload(input: &DeriveInput, field: &Ident) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::ispf::Payload for #name #ty_generics
+            #where_clause
+        {
+            fn payload(&self) -> ::std::option::Option<&[u8]> {
+                ::std::option::Option::Some(
+                    ::std::convert::AsRef::<[u8]>::as_ref(&self.#field),
+                )
+            }
+        }
+    }
+}
+
+/// Methods encoding and decoding the type in a byte order chosen at run
+/// time.
+fn dual_endian(input: &DeriveInput) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Decode a value from `b` in the given byte order.
+            pub fn from_bytes_in<'__a>(
+                b: &'__a [u8],
+                order: ::ispf::ByteOrder,
+            ) -> ::ispf::Result<Self>
+            where
+                Self: ::ispf::__private::serde::Deserialize<'__a>,
+            {
+                ::ispf::from_bytes_in(b, order)
+            }
+
+            /// Encode the value in the given byte order.
+            pub fn to_bytes_in(
+                &self,
+                order: ::ispf::ByteOrder,
+            ) -> ::ispf::Result<::std::vec::Vec<u8>> {
+                ::ispf::to_bytes_in(self, order)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SizeKind {
+    Fixed,
+    Max,
+}
+
+fn expand_size(input: &DeriveInput, kind: SizeKind) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let (trait_name, konst) = match kind {
+        SizeKind::Fixed => (quote!(FixedSize), quote!(WIRE_SIZE)),
+        SizeKind::Max => (quote!(MaxSize), quote!(MAX_WIRE_SIZE)),
+    };
+    let size = size_expr(input, kind)?;
+    let mut generics = input.generics.clone();
+    for param in &mut generics.params {
+        if let GenericParam::Type(t) = param {
+            t.bounds.push(syn::parse_quote!(::ispf::#trait_name));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::ispf::#trait_name
+            for #name #ty_generics #where_clause
+        {
+            const #konst: usize = #size;
+        }
+    })
+}
+
+/// The size, or the bound on the size, of a struct or enum.
+fn size_expr(input: &DeriveInput, kind: SizeKind) -> Result<TokenStream2> {
+    match &input.data {
+        Data::Struct(data) => fields_size(&data.fields, kind),
+        Data::Enum(data) => {
+            let tag = ContainerAttrs::parse(input)?.tag();
+            let mut variants = Vec::new();
+            for v in &data.variants {
+                if VariantAttrs::parse(v)?.other {
+                    return Err(syn::Error::new_spanned(
+                        v,
+                        "the other variant holds the rest of the input and \
+                         has no bound",
+                    ));
+                }
+                if kind == SizeKind::Fixed && !v.fields.is_empty() {
+                    return Err(syn::Error::new_spanned(
+                        v,
+                        "only enums with unit variants have a fixed size",
+                    ));
+                }
+                variants.push(fields_size(&v.fields, kind)?);
+            }
+            Ok(quote! {
+                ::std::mem::size_of::<#tag>()
+                    + ::ispf::__private::max_size(&[#(#variants),*])
+            })
+        }
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "sizes can only be derived for structs and enums",
+        )),
+    }
+}
+
+/// A compile-time check that the fixed size of the input is `expected`.
+fn assert_size(input: &DeriveInput, expected: &LitInt) -> Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            expected,
+            "assert_size is not supported on generic types",
+        ));
+    }
+    let size = size_expr(input, SizeKind::Fixed)?;
+    let msg = format!(
+        "the wire size of {} does not match assert_size = {}",
+        input.ident, expected
+    );
+    Ok(quote! {
+        const _: () = ::std::assert!((#size) == #expected, #msg);
+    })
+}
+
+/// The size, or the bound on the size, of a struct's or variant's fields.
+fn fields_size(fields: &Fields, kind: SizeKind) -> Result<TokenStream2> {
+    let infos = field_infos(fields)?;
+    let mut terms = Vec::new();
+    for f in &infos {
+        let ty = &f.field.ty;
+        let unbounded = |what: &str| {
+            let msg = match kind {
+                SizeKind::Fixed => {
+                    format!("a field with {} has no fixed size", what)
+                }
+                SizeKind::Max => format!("a field with {} has no bound", what),
+            };
+            Err(syn::Error::new_spanned(f.field, msg))
+        };
+        if f.attrs.tag_from.is_some() {
+            return unbounded("tag_from");
+        }
+        if let Some(slot) = &f.bit_slot {
+            if slot.group.is_some() {
+                let storage = &slot.storage;
+                terms.push(quote!(::std::mem::size_of::<#storage>()));
+            }
+            continue;
+        }
+        if let Some(repr) = &f.attrs.try_from {
+            terms.push(quote!(<#repr as ::ispf::FixedSize>::WIRE_SIZE));
+            continue;
+        }
+        if let Some(lv) = &f.attrs.lv {
+            if kind == SizeKind::Fixed {
+                return unbounded("lv");
+            }
+            if let Some(Unit::Bytes) = f.attrs.unit {
+                terms.push(quote! {
+                    ::std::mem::size_of::<#lv>()
+                        + <#lv as ::ispf::__private::CountField>::MAX_LEN
+                });
+                continue;
+            }
+            terms.push(quote! {
+                ::ispf::__private::lv_max_size::<#ty>(
+                    ::std::mem::size_of::<#lv>(),
+                    <#lv as ::ispf::__private::CountField>::MAX_LEN,
+                )
+            });
+            continue;
+        }
+        if let Some((from, unit)) = &f.attrs.length_from {
+            if kind == SizeKind::Fixed {
+                return unbounded("a length field");
+            }
+            let from_ty = &infos
+                .iter()
+                .find(|g| g.ident == *from)
+                .expect("length fields are linked")
+                .field
+                .ty;
+            let mut len = quote! {
+                <#from_ty as ::ispf::__private::CountField>::MAX_LEN
+            };
+            // A negative adjustment lets the actual length exceed the
+            // largest stored value.
+            if let Some(adjust) = f.attrs.len_adjust.filter(|a| *a < 0) {
+                let extra = adjust.unsigned_abs() as usize;
+                len = quote!(#len.saturating_add(#extra));
+            }
+            terms.push(match unit {
+                Unit::Elements => quote! {
+                    ::ispf::__private::lv_max_size::<#ty>(0, #len)
+                },
+                Unit::Bytes => len,
+            });
+            continue;
+        }
+        terms.push(match kind {
+            SizeKind::Fixed => quote!(<#ty as ::ispf::FixedSize>::WIRE_SIZE),
+            SizeKind::Max => quote!(<#ty as ::ispf::MaxSize>::MAX_WIRE_SIZE),
+        });
+    }
+    Ok(quote!(0usize #(.saturating_add(#terms))*))
+}
+
+/// Check that fields marked `since` come last, in order of version, so that
+/// leaving out the newer ones only ever shortens the message.
+fn check_trailing(infos: &[FieldInfo]) -> Result<()> {
+    let mut prev = None;
+    for f in infos {
+        match (prev, f.attrs.since) {
+            (Some(p), Some(v)) if v < p => {
+                return Err(syn::Error::new_spanned(
+                    f.field,
+                    "fields marked since must be in order of version",
+                ))
+            }
+            (Some(_), None) if !f.attrs.default => {
+                return Err(syn::Error::new_spanned(
+                    f.field,
+                    "fields after one marked since must be marked too",
+                ))
+            }
+            (_, Some(v)) => prev = Some(v),
+            (_, None) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Pack each run of adjacent `bits` fields into one integer, the first field
+/// in its least significant bits. A run ends once it fills a `u8`, `u16`,
+/// `u32` or `u64`, or the integer named by `storage` on its first field.
+fn group_bits(infos: &mut [FieldInfo]) -> Result<()> {
+    let mut i = 0;
+    while i < infos.len() {
+        if infos[i].attrs.bits.is_none() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let want = infos[i].attrs.storage.as_ref().map(|s| {
+            match s.to_string().as_str() {
+                "u8" => 8,
+                "u16" => 16,
+                "u32" => 32,
+                _ => 64,
+            }
+        });
+        let mut total = 0;
+        while let Some(bits) = infos.get(i).and_then(|f| f.attrs.bits) {
+            if i > start && infos[i].attrs.storage.is_some() {
+                break;
+            }
+            total += bits;
+            i += 1;
+            let full = match want {
+                Some(want) => total >= want,
+                None => [8, 16, 32, 64].contains(&total),
+            };
+            if full {
+                break;
+            }
+        }
+        let storage = match total {
+            8 => "u8",
+            16 => "u16",
+            32 => "u32",
+            64 => "u64",
+            _ => "",
+        };
+        if storage.is_empty() || want.is_some_and(|want| want != total) {
+            return Err(syn::Error::new_spanned(
+                infos[i - 1].field,
+                format!(
+                    "bit fields must add up to {}, not {}",
+                    match want {
+                        Some(want) => format!("{} bits", want),
+                        None => "8, 16, 32 or 64 bits".to_string(),
+                    },
+                    total,
+                ),
+            ));
+        }
+        let var = format_ident!("__bits_{}", infos[start].ident);
+        let mut shift = 0;
+        for (j, f) in infos[start..i].iter_mut().enumerate() {
+            let width = f.attrs.bits.unwrap_or_default();
+            if let Some(max) = bit_capacity(&f.field.ty) {
+                if width > max {
+                    return Err(syn::Error::new_spanned(
+                        f.field,
+                        "the field is too narrow for its bits",
+                    ));
+                }
+            }
+            f.bit_slot = Some(BitSlot {
+                storage: Ident::new(storage, Span::call_site()),
+                var: var.clone(),
+                shift,
+                width,
+                group: if j == 0 { Some(i - start) } else { None },
+            });
+            shift += width;
+        }
+    }
+    Ok(())
+}
+
+/// The number of bits a field of a known type can hold.
+fn bit_capacity(ty: &Type) -> Option<u32> {
+    let ident = match ty {
+        Type::Path(p) => p.path.get_ident()?,
+        _ => return None,
+    };
+    match ident.to_string().as_str() {
+        "bool" => Some(1),
+        "u8" => Some(8),
+        "u16" => Some(16),
+        "u32" => Some(32),
+        "u64" => Some(64),
+        _ => None,
+    }
+}
+
+/// Point each length or tag field at the field it describes, checking that
+/// it comes first and is only used once.
+fn link_fields(infos: &mut [FieldInfo]) -> Result<()> {
+    for i in 0..infos.len() {
+        let from = match (&infos[i].attrs.length_from, &infos[i].attrs.tag_from)
+        {
+            (Some((from, _)), _) | (None, Some(from)) => from.clone(),
+            (None, None) => continue,
+        };
+        let target = infos[..i]
+            .iter()
+            .position(|f| f.ident == from)
+            .ok_or_else(|| {
+                syn::Error::new_spanned(
+                    &from,
+                    "length or tag must come from an earlier field",
+                )
+            })?;
+        if infos[target].length_of.is_some()
+            || infos[target].tag_of.is_some()
+            || infos[target].attrs.size_of
+        {
+            return Err(syn::Error::new_spanned(
+                &from,
+                "field already describes another field",
+            ));
+        }
+        let ident = infos[i].ident.clone();
+        if let Some((_, unit)) = infos[i].attrs.length_from {
+            let adjust = infos[i].attrs.len_adjust.unwrap_or(0);
+            infos[target].length_of = Some((ident, unit, adjust));
+        } else {
+            infos[target].tag_of = Some(ident);
+        }
+    }
+    Ok(())
+}
+
+/// Resolve the range of fields named by a checksum field's `over`, either
+/// end of which may be left out, as in `"a.."`.
+fn link_checksum(infos: &mut [FieldInfo]) -> Result<()> {
+    for i in 0..infos.len() {
+        let over = match &infos[i].attrs.over {
+            Some(over) => over.clone(),
+            None => continue,
+        };
+        let text = over.value();
+        let (from, to, inclusive) = match text.split_once("..=") {
+            Some((from, to)) => (from, to, true),
+            None => match text.split_once("..") {
+                Some((from, to)) => (from, to, false),
+                None => {
+                    return Err(syn::Error::new_spanned(
+                        &over,
+                        "over must be a range of fields, as in \"a..c\"",
+                    ))
+                }
+            },
+        };
+        let index = |name: &str| {
+            infos.iter().position(|f| f.ident == name).ok_or_else(|| {
+                syn::Error::new_spanned(
+                    &over,
+                    format!("no field named `{}`", name),
+                )
+            })
+        };
+        let start = match from.trim() {
+            "" => 0,
+            name => index(name)?,
+        };
+        let end = match to.trim() {
+            "" if inclusive => {
+                return Err(syn::Error::new_spanned(
+                    &over,
+                    "an inclusive range must name its last field",
+                ))
+            }
+            "" => infos.len(),
+            name => index(name)? + inclusive as usize,
+        };
+        if start >= end {
+            return Err(syn::Error::new_spanned(
+                &over,
+                "over must cover at least one field",
+            ));
+        }
+        if (start..end).contains(&i) {
+            return Err(syn::Error::new_spanned(
+                &over,
+                "a checksum cannot cover its own field",
+            ));
+        }
+        // A run of bits fields is written as one integer, so cannot be cut.
+        let splits = |j: usize| {
+            infos
+                .get(j)
+                .and_then(|f| f.bit_slot.as_ref())
+                .is_some_and(|slot| slot.group.is_none())
+        };
+        if splits(start) || splits(end) {
+            return Err(syn::Error::new_spanned(
+                &over,
+                "over cannot split a run of bits fields",
+            ));
+        }
+        infos[i].covers = Some(start..end);
+    }
+    Ok(())
+}
+
+/// The input's generics with every type parameter bound by `Serialize`.
+fn serialize_generics(input: &DeriveInput) -> Generics {
+    let mut generics = input.generics.clone();
+    for param in &mut generics.params {
+        if let GenericParam::Type(t) = param {
+            t.bounds
+                .push(syn::parse_quote!(::ispf::__private::serde::Serialize));
+        }
+    }
+    generics
+}
+
+/// The input's generics preceded by a `'de` lifetime that outlives all the
+/// others, with every type parameter bound by `Deserialize<'de>`.
+fn deserialize_generics(input: &DeriveInput) -> (syn::Lifetime, Generics) {
+    let mut generics = input.generics.clone();
+    let de = syn::Lifetime::new("'de", Span::call_site());
+    let mut de_param = syn::LifetimeParam::new(de.clone());
+    for param in &mut generics.params {
+        match param {
+            GenericParam::Type(t) => t.bounds.push(syn::parse_quote!(
+                ::ispf::__private::serde::Deserialize<#de>
+            )),
+            GenericParam::Lifetime(l) => {
+                de_param.bounds.push(l.lifetime.clone())
+            }
+            GenericParam::Const(_) => {}
+        }
+    }
+    generics.params.insert(0, GenericParam::Lifetime(de_param));
+    (de, generics)
+}
+
+/// Statements writing each field to the `SerializeStruct` in `__st`. `access`
+/// gives the place expression holding a field's value.
+fn serialize_fields(
+    infos: &[FieldInfo],
+    access: impl Fn(&Ident) -> TokenStream2,
+) -> Vec<TokenStream2> {
+    infos
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let value = access(&f.ident);
+            let key = &f.key;
+            let ty = &f.field.ty;
+            if let Some(slot) = &f.bit_slot {
+                // The whole group is written with its first field.
+                let n = match slot.group {
+                    Some(n) => n,
+                    None => return TokenStream2::new(),
+                };
+                let storage = &slot.storage;
+                let parts = infos[i..i + n].iter().map(|f| {
+                    let value = access(&f.ident);
+                    let key = &f.key;
+                    let slot = f.bit_slot.as_ref().unwrap();
+                    let (shift, width) = (slot.shift, slot.width);
+                    quote! {
+                        | ::ispf::__private::pack_bits::<_, __S::Error>(
+                            #value,
+                            #width,
+                            #key,
+                        )? << #shift
+                    }
+                });
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &((0u64 #(#parts)*) as #storage),
+                    )?;
+                };
+            }
+            if let Some((target, unit, adjust)) = &f.length_of {
+                let target = access(target);
+                let mut len = match unit {
+                    Unit::Elements => quote! {
+                        ::ispf::__private::len(&#target)
+                    },
+                    Unit::Bytes => quote! {
+                        ::ispf::__private::byte_len(&#target)
+                    },
+                };
+                if *adjust != 0 {
+                    len = quote! {
+                        ::ispf::__private::adjust_len::<__S::Error>(
+                            #len,
+                            #adjust,
+                        )?
+                    };
+                }
+                // The stored value is replaced by the actual length, but is
+                // still touched so the field does not look unused.
+                return quote! {
+                    let _ = &#value;
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &<#ty as ::ispf::__private::CountField>::from_len::<
+                            __S::Error,
+                        >(#len)?,
+                    )?;
+                };
+            }
+            if let Some(target) = &f.tag_of {
+                let target = access(target);
+                return quote! {
+                    let _ = &#value;
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &<#ty as ::std::convert::From<_>>::from(
+                            ::ispf::__private::Tagged::tag(&#target),
+                        ),
+                    )?;
+                };
+            }
+            if let Some(align) = f.attrs.align {
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::Wrap("align", #align, &#value),
+                    )?;
+                };
+            }
+            if let Some(since) = f.attrs.since {
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::Wrap("since", #since, &#value),
+                    )?;
+                };
+            }
+            if f.attrs.checksum.is_some() {
+                return quote! {
+                    let _ = &#value;
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &{
+                            let __zero: #ty = 0;
+                            __zero
+                        },
+                    )?;
+                };
+            }
+            if let Some(magic) = &f.attrs.magic {
+                return quote! {
+                    let _ = &#value;
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &{
+                            let __magic: #ty = #magic;
+                            __magic
+                        },
+                    )?;
+                };
+            }
+            if f.attrs.size_of {
+                return quote! {
+                    let _ = &#value;
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::SizeField(
+                            ::std::mem::size_of::<#ty>(),
+                        ),
+                    )?;
+                };
+            }
+            if let Some(repr) = &f.attrs.try_from {
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &<#repr as ::std::convert::From<#ty>>::from(
+                            ::std::clone::Clone::clone(&#value),
+                        ),
+                    )?;
+                };
+            }
+            if f.attrs.tag_from.is_some() {
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::Body(&#value),
+                    )?;
+                };
+            }
+            if let (Some(lv), true) = (&f.attrs.lv, f.attrs.payload) {
+                // Written as one run of bytes, so that it can be left out of
+                // a vectored encoding.
+                let width = width_variant(lv);
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::LvRaw(
+                            ::std::convert::AsRef::<[u8]>::as_ref(&#value),
+                            ::ispf::Width::#width,
+                        ),
+                    )?;
+                };
+            }
+            if let Some(lv) = &f.attrs.lv {
+                let width = width_variant(lv);
+                let wrapper = match f.attrs.unit {
+                    Some(Unit::Bytes) => quote!(LvBytes),
+                    _ => quote!(Lv),
+                };
+                return quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::#wrapper(
+                            &#value,
+                            ::ispf::Width::#width,
+                        ),
+                    )?;
+                };
+            }
+            match &f.attrs.length_from {
+                Some((_, Unit::Bytes)) => quote! {
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #key,
+                        &::ispf::__private::RawBytes(
+                            ::std::convert::AsRef::<[u8]>::as_ref(&#value),
+                        ),
+                    )?;
+                },
+                _ => quote! {
+                    SerializeStruct::serialize_field(&mut __st, #key, &#value)?;
+                },
+            }
+        })
+        .collect()
+}
+
+/// Statements reading each field from the `SeqAccess` in `__seq` into a local
+/// named after it. `offset` is the number of elements read before the first
+/// field, for error messages.
+fn deserialize_fields(infos: &[FieldInfo], offset: usize) -> Vec<TokenStream2> {
+    infos
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let i = i + offset;
+            let ident = &f.ident;
+            let ty = &f.field.ty;
+            let missing = quote! {
+                .ok_or_else(|| {
+                    ::ispf::__private::serde::de::Error::invalid_length(
+                        #i, &self,
+                    )
+                })?
+            };
+            if let Some(slot) = &f.bit_slot {
+                let BitSlot {
+                    storage,
+                    var,
+                    shift,
+                    width,
+                    ..
+                } = slot;
+                let read = match slot.group {
+                    Some(_) => quote! {
+                        let #var: #storage = __seq.next_element()? #missing;
+                    },
+                    None => TokenStream2::new(),
+                };
+                return quote! {
+                    #read
+                    let #ident: #ty = ::ispf::__private::unpack_bits(
+                        #var as u64,
+                        #shift,
+                        #width,
+                    );
+                };
+            }
+            if let Some(repr) = &f.attrs.try_from {
+                let key = &f.key;
+                return quote! {
+                    let #ident: #ty = {
+                        let __raw: #repr = __seq.next_element()? #missing;
+                        <#ty as ::std::convert::TryFrom<#repr>>::try_from(
+                            __raw,
+                        )
+                        .map_err(|_| {
+                            ::ispf::__private::serde::de::Error::custom(
+                                ::std::format_args!(
+                                    "invalid value {} for field `{}`",
+                                    __raw,
+                                    #key,
+                                ),
+                            )
+                        })?
+                    };
+                };
+            }
+            if let Some(align) = f.attrs.align {
+                return quote! {
+                    let #ident: #ty = __seq
+                        .next_element_seed(
+                            ::ispf::__private::Aligned::<#ty>::new(#align),
+                        )?
+                        #missing;
+                };
+            }
+            if f.attrs.default {
+                return quote! {
+                    let #ident: #ty = __seq
+                        .next_element_seed(
+                            ::ispf::__private::Trailing::<#ty>::new(0),
+                        )?
+                        #missing;
+                };
+            }
+            if let Some(since) = f.attrs.since {
+                return quote! {
+                    let #ident: #ty = __seq
+                        .next_element_seed(
+                            ::ispf::__private::Trailing::<#ty>::new(#since),
+                        )?
+                        #missing;
+                };
+            }
+            if let Some(magic) = &f.attrs.magic {
+                return quote! {
+                    let #ident: #ty = __seq.next_element()? #missing;
+                    {
+                        let __magic: #ty = #magic;
+                        if #ident != __magic {
+                            let __into = ::std::convert::Into::<u64>::into;
+                            let __err = ::ispf::Error::BadMagic {
+                                expected: __into(__magic),
+                                actual: __into(#ident),
+                            };
+                            return Err(::ispf::__private::de_error(__err));
+                        }
+                    }
+                };
+            }
+            if let Some(from) = &f.attrs.tag_from {
+                return quote! {
+                    let #ident: #ty = __seq
+                        .next_element_seed(
+                            ::ispf::__private::WithTag::<#ty>::new(
+                                ::ispf::__private::tag_from::<
+                                    #ty,
+                                    _,
+                                    __A::Error,
+                                >(#from)?,
+                            ),
+                        )?
+                        #missing;
+                };
+            }
+            if let Some(lv) = &f.attrs.lv {
+                let width = width_variant(lv);
+                let seed = match f.attrs.unit {
+                    Some(Unit::Bytes) => quote!(LvBytesSeed),
+                    _ => quote!(LvSeed),
+                };
+                return quote! {
+                    let #ident: #ty = __seq
+                        .next_element_seed(
+                            ::ispf::__private::#seed::<#ty>::new(
+                                ::ispf::Width::#width,
+                            ),
+                        )?
+                        #missing;
+                };
+            }
+            match &f.attrs.length_from {
+                Some((from, unit)) => {
+                    let seed = match unit {
+                        Unit::Elements => quote! {
+                            ::ispf::__private::Counted::<#ty, _>
+                        },
+                        Unit::Bytes => quote! {
+                            ::ispf::__private::ByteCount::<#ty>
+                        },
+                    };
+                    let mut len = quote! {
+                        ::ispf::__private::CountField::to_len::<__A::Error>(
+                            #from,
+                        )?
+                    };
+                    if let Some(adjust) = f.attrs.len_adjust.filter(|a| *a != 0)
+                    {
+                        len = quote! {
+                            ::ispf::__private::unadjust_len::<__A::Error>(
+                                #len,
+                                #adjust,
+                            )?
+                        };
+                    }
+                    quote! {
+                        let #ident: #ty = __seq
+                            .next_element_seed(#seed::new(#len))?
+                            #missing;
+                    }
+                }
+                None => quote! {
+                    let #ident: #ty = __seq.next_element()? #missing;
+                },
+            }
+        })
+        .collect()
+}
+
+/// An expression or pattern for `path` built from locals named after its
+/// fields.
+fn construct(
+    path: TokenStream2,
+    fields: &Fields,
+    infos: &[FieldInfo],
+) -> TokenStream2 {
+    let idents = infos.iter().map(|f| &f.ident);
+    match fields {
+        Fields::Named(_) => quote! { #path { #(#idents),* } },
+        Fields::Unnamed(_) => quote! { #path ( #(#idents),* ) },
+        Fields::Unit => path,
+    }
+}
+
+fn expand_serialize(
+    input: &DeriveInput,
+    infos: &[FieldInfo],
+    layers: &[Layer],
+) -> TokenStream2 {
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let len = infos.len();
+
+    let generics = serialize_generics(input);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let access = |ident: &Ident| {
+        if layers.is_empty() {
+            quote! { self.#ident }
+        } else {
+            quote! { self.0.#ident }
+        }
+    };
+    let mut fields = serialize_fields(infos, access);
+    checksum_marks(infos, &mut fields, true, |mark| {
+        quote! {
+            SerializeStruct::serialize_field(
+                &mut __st,
+                #mark,
+                &::ispf::__private::Mark(#mark),
+            )?;
+        }
+    });
+    let body = quote! {
+        use ::ispf::__private::serde::ser::SerializeStruct;
+        let mut __st = __s.serialize_struct(#name_str, #len)?;
+        #(#fields)*
+        SerializeStruct::end(__st)
+    };
+
+    // A struct with fields filled in after the fact is written inside tuple
+    // structs that the serializer completes once the struct is written.
+    let body = if layers.is_empty() {
+        body
+    } else {
+        let mut value = quote! { &__Body(self) };
+        let mut checks = Vec::new();
+        for layer in layers {
+            let (name, len) = (layer.name, &layer.len);
+            value = quote! { &::ispf::__private::Wrap(#name, #len, #value) };
+            let field = match layer.field {
+                Some(f) => f,
+                None => continue,
+            };
+            if let Some(sum) = &field.attrs.checksum {
+                let ty = &field.field.ty;
+                checks.push(quote! {
+                    const _: () = ::std::assert!(
+                        ::std::mem::size_of::<#ty>()
+                            == ::ispf::Checksum::#sum.width(),
+                        "the checksum field is the wrong width",
+                    );
+                });
+            }
+        }
+        quote! {
+            #(#checks)*
+
+            struct __Body<'__a, __T: ?Sized>(&'__a __T);
+
+            impl #impl_generics ::ispf::__private::serde::Serialize
+                for __Body<'_, #name #ty_generics> #where_clause
+            {
+                fn serialize<__S>(
+                    &self,
+                    __s: __S,
+                ) -> ::std::result::Result<__S::Ok, __S::Error>
+                where
+                    __S: ::ispf::__private::serde::Serializer,
+                {
+                    #body
+                }
+            }
+
+            ::ispf::__private::serde::Serialize::serialize(#value, __s)
+        }
+    };
+
+    quote! {
+        impl #impl_generics ::ispf::__private::serde::Serialize
+            for #name #ty_generics #where_clause
+        {
+            fn serialize<__S>(
+                &self,
+                __s: __S,
+            ) -> ::std::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::ispf::__private::serde::Serializer,
+            {
+                #body
+            }
+        }
+    }
+}
+
+/// Add the marks of a checksum over a range of fields to the statements for
+/// each field, `mark` giving the statement for one, and return their names.
+/// With `at`, the checksum field is marked too, for the serializer to fill in.
+fn checksum_marks(
+    infos: &[FieldInfo],
+    stmts: &mut [TokenStream2],
+    at: bool,
+    mark: impl Fn(&str) -> TokenStream2,
+) -> Vec<&'static str> {
+    let (i, covers) = match infos
+        .iter()
+        .enumerate()
+        .find_map(|(i, f)| Some((i, f.covers.clone()?)))
+    {
+        Some(found) => found,
+        None => return Vec::new(),
+    };
+    let mut marks = vec!["sumfrom", "sumto"];
+    let (from, to) = (mark("sumfrom"), mark("sumto"));
+    let first = &stmts[covers.start];
+    stmts[covers.start] = quote! { #from #first };
+    let last = &stmts[covers.end - 1];
+    stmts[covers.end - 1] = quote! { #last #to };
+    if at {
+        let at = mark("sumat");
+        let field = &stmts[i];
+        stmts[i] = quote! { #at #field };
+        marks.push("sumat");
+    }
+    marks
+}
+
+/// A tuple struct a struct is wrapped in so that the serializer can fill in
+/// `field` once the rest is written, and the deserializer can report what the
+/// struct took of the input.
+struct Layer<'a> {
+    name: &'static str,
+    len: TokenStream2,
+    /// The field filled in or checked once the rest is done, if any.
+    field: Option<&'a FieldInfo<'a>>,
+    /// The field is the last of the struct rather than the first.
+    last: bool,
+}
+
+/// The layers a struct is wrapped in, innermost first. The checksum comes
+/// after the size so that it covers it, and the byte order last so that it
+/// applies to both.
+fn layers<'a>(
+    infos: &'a [FieldInfo<'a>],
+    big_endian: Option<bool>,
+) -> Vec<Layer<'a>> {
+    let mut layers = Vec::new();
+    if let Some(f) = infos.iter().find(|f| f.attrs.size_of) {
+        let ty = &f.field.ty;
+        layers.push(Layer {
+            name: "sizeof",
+            len: quote! { ::std::mem::size_of::<#ty>() },
+            field: Some(f),
+            last: false,
+        });
+    }
+    for (i, f) in infos.iter().enumerate() {
+        if let Some(sum) = &f.attrs.checksum {
+            let over = f.covers.is_some();
+            let last = !over && i == infos.len() - 1;
+            layers.push(Layer {
+                name: "checksum",
+                len: quote! {
+                    ::ispf::__private::checksum_spec(
+                        ::ispf::Checksum::#sum,
+                        #last,
+                        #over,
+                    )
+                },
+                field: Some(f),
+                last,
+            });
+        }
+    }
+    if let Some(big) = big_endian {
+        layers.push(Layer {
+            name: "endian",
+            len: quote! { #big as usize },
+            field: None,
+            last: false,
+        });
+    }
+    layers
+}
+
+/// How a local visitor is handed to the deserializer.
+enum Drive<'a> {
+    /// `deserialize_struct` with these field names.
+    Struct(&'a [String]),
+    /// `deserialize_tuple` with no bound on the number of elements, for a
+    /// tag followed by the fields of whichever variant it names.
+    Open,
+    /// A field read this many bytes in ahead of the value, as set up by the
+    /// "at" tuple struct.
+    At(usize),
+    /// A value followed by what it took of the input, as set up by the named
+    /// tuple struct with the given length.
+    Layer(&'static str, TokenStream2),
+}
+
+/// An expression that drives a local visitor on the deserializer `d`. The
+/// visitor carries `state`, a list of field names, types and initial values,
+/// and runs `body` as its `visit_seq`.
+fn visit_seq(
+    input: &DeriveInput,
+    expecting: &str,
+    drive: Drive,
+    state: &[(Ident, TokenStream2, TokenStream2)],
+    body: TokenStream2,
+) -> TokenStream2 {
+    let name = &input.ident;
+    let name_str = name.to_string();
+
+    let (de, de_generics) = deserialize_generics(input);
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let (impl_generics, visitor_ty_generics, where_clause) =
+        de_generics.split_for_impl();
+    let state_names: Vec<&Ident> = state.iter().map(|s| &s.0).collect();
+    let state_types = state.iter().map(|s| &s.1);
+    let state_values = state.iter().map(|s| &s.2);
+    let drive = match drive {
+        Drive::Struct(fields) => quote! {
+            const FIELDS: &[&str] = &[#(#fields),*];
+            d.deserialize_struct(#name_str, FIELDS, __visitor)
+        },
+        Drive::Open => quote! {
+            d.deserialize_tuple(::std::primitive::usize::MAX, __visitor)
+        },
+        Drive::At(offset) => quote! {
+            d.deserialize_tuple_struct("at", #offset, __visitor)
+        },
+        Drive::Layer(name, len) => quote! {
+            d.deserialize_tuple_struct(#name, #len, __visitor)
+        },
+    };
+
+    quote! {{
+        struct __Visitor #impl_generics #where_clause {
+            #(#state_names: #state_types,)*
+            marker: ::std::marker::PhantomData<#name #ty_generics>,
+            lifetime: ::std::marker::PhantomData<&#de ()>,
+        }
+
+        impl #impl_generics ::ispf::__private::serde::de::Visitor<#de>
+            for __Visitor #visitor_ty_generics #where_clause
+        {
+            type Value = #name #ty_generics;
+
+            fn expecting(
+                &self,
+                f: &mut ::std::fmt::Formatter,
+            ) -> ::std::fmt::Result {
+                f.write_str(#expecting)
+            }
+
+            fn visit_seq<__A>(
+                self,
+                mut __seq: __A,
+            ) -> ::std::result::Result<Self::Value, __A::Error>
+            where
+                __A: ::ispf::__private::serde::de::SeqAccess<#de>,
+            {
+                #body
+            }
+        }
+
+        let __visitor = __Visitor {
+            #(#state_names: #state_values,)*
+            marker: ::std::marker::PhantomData,
+            lifetime: ::std::marker::PhantomData,
+        };
+        #drive
+    }}
+}
+
+/// A `Deserialize` impl whose body is `visit`.
+fn deserialize_impl(input: &DeriveInput, visit: TokenStream2) -> TokenStream2 {
+    let name = &input.ident;
+    let (de, de_generics) = deserialize_generics(input);
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    let (impl_generics, _, where_clause) = de_generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::ispf::__private::serde::Deserialize<#de>
+            for #name #ty_generics #where_clause
+        {
+            fn deserialize<__D>(
+                d: __D,
+            ) -> ::std::result::Result<Self, __D::Error>
+            where
+                __D: ::ispf::__private::serde::Deserializer<#de>,
+            {
+                #visit
+            }
+        }
+    }
+}
+
+fn expand_deserialize(
+    input: &DeriveInput,
+    infos: &[FieldInfo],
+    layers: &[Layer],
+) -> TokenStream2 {
+    let name = &input.ident;
+    let mut reads = deserialize_fields(infos, 0);
+    let marks = checksum_marks(infos, &mut reads, false, |mark| {
+        quote! {
+            __seq.next_element_seed(::ispf::__private::Mark(#mark))?;
+        }
+    });
+    let value = match &input.data {
+        Data::Struct(s) => construct(quote! { #name }, &s.fields, infos),
+        _ => unreachable!(),
+    };
+    let mut fields: Vec<String> = infos.iter().map(|f| f.key.clone()).collect();
+    fields.extend(marks.iter().map(|m| m.to_string()));
+    let expecting = format!("struct {}", name);
+    let visit = visit_seq(
+        input,
+        &expecting,
+        Drive::Struct(&fields),
+        &[],
+        quote! {
+            #(#reads)*
+            Ok(#value)
+        },
+    );
+    if layers.is_empty() {
+        return deserialize_impl(input, visit);
+    }
+
+    // Each layer reads the one inside it, wrapped in a local newtype, then
+    // checks its field against what that took of the input.
+    let (de, de_generics) = deserialize_generics(input);
+    let (impl_generics, ty_generics, where_clause) =
+        input.generics.split_for_impl();
+    let (de_impl_generics, _, de_where_clause) = de_generics.split_for_impl();
+    let mut items = Vec::new();
+    let mut inner = visit;
+    for (i, layer) in layers.iter().enumerate() {
+        let wrapper = format_ident!("__Layer{}", i);
+        items.push(quote! {
+            struct #wrapper #impl_generics (#name #ty_generics) #where_clause;
+
+            impl #de_impl_generics ::ispf::__private::serde::Deserialize<#de>
+                for #wrapper #ty_generics #de_where_clause
+            {
+                fn deserialize<__D>(
+                    d: __D,
+                ) -> ::std::result::Result<Self, __D::Error>
+                where
+                    __D: ::ispf::__private::serde::Deserializer<#de>,
+                {
+                    let __value = #inner;
+                    __value.map(#wrapper)
+                }
+            }
+        });
+
+        let check = match layer.field {
+            Some(f) => layer_check(f, layer.last, &de),
+            None => TokenStream2::new(),
+        };
+        inner = visit_seq(
+            input,
+            &expecting,
+            Drive::Layer(layer.name, layer.len.clone()),
+            &[],
+            quote! {
+                let __inner: #wrapper #ty_generics = __seq
+                    .next_element()?
+                    .ok_or_else(|| {
+                        ::ispf::__private::serde::de::Error::invalid_length(
+                            0, &self,
+                        )
+                    })?;
+                let __value = __inner.0;
+                #check
+                Ok(__value)
+            },
+        );
+    }
+    deserialize_impl(
+        input,
+        quote! {
+            #(#items)*
+            #inner
+        },
+    )
+}
+
+/// What a layer checks its field against once the value inside it is read.
+fn layer_check(
+    field: &FieldInfo,
+    last: bool,
+    de: &syn::Lifetime,
+) -> TokenStream2 {
+    let ident = &field.ident;
+    if let Some(sum) = &field.attrs.checksum {
+        let over = field.covers.is_some();
+        quote! {
+            if let Some(__bytes) = __seq.next_element::<&#de [u8]>()? {
+                ::ispf::__private::verify_checksum::<__A::Error>(
+                    ::ispf::Checksum::#sum,
+                    #last,
+                    #over,
+                    __bytes,
+                    ::std::convert::Into::<u64>::into(__value.#ident),
+                )?;
+            }
+        }
+    } else if field.attrs.validate {
+        quote! {
+            if let Some(__actual) = __seq.next_element::<u64>()? {
+                let __size = ::ispf::__private::CountField::to_len::<
+                    __A::Error,
+                >(__value.#ident)?;
+                if __size as u64 != __actual {
+                    return Err(::ispf::__private::de_error(
+                        ::ispf::Error::SizeMismatch {
+                            size: __size,
+                            actual: __actual as usize,
+                        },
+                    ));
+                }
+            }
+        }
+    } else {
+        TokenStream2::new()
+    }
+}
+
+/// Attributes on an enum variant.
+#[derive(Default)]
+struct VariantAttrs {
+    /// Marked `#[ispf(other)]`, the catch-all for unknown tags.
+    other: bool,
+    /// The type code given by `#[ispf(type = ..)]`.
+    code: Option<Expr>,
+}
+
+impl VariantAttrs {
+    fn parse(v: &Variant) -> Result<Self> {
+        let mut attrs = VariantAttrs::default();
+        for attr in &v.attrs {
+            if !attr.path().is_ident("ispf") {
+                continue;
+            }
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("other") {
+                    attrs.other = true;
+                    Ok(())
+                } else if meta.path.is_ident("type") {
+                    if attrs.code.is_some() {
+                        return Err(meta.error("type is already specified"));
+                    }
+                    attrs.code = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown ispf attribute"))
+                }
+            })?;
+        }
+        Ok(attrs)
+    }
+}
+
+/// One `const` per variant holding its tag, named by `tag_const`. The
+/// catch-all variant, if any, has no tag of its own.
+fn tag_consts(
+    data: &DataEnum,
+    tag: &Ident,
+    other: Option<usize>,
+) -> Vec<TokenStream2> {
+    let mut prev: Option<Ident> = None;
+    data.variants
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let name = tag_const(i);
+            let value = match (&v.discriminant, &prev) {
+                (Some((_, expr)), _) => quote! { (#expr) as #tag },
+                (None, Some(prev)) => quote! { #prev + 1 },
+                (None, None) => quote! { 0 },
+            };
+            prev = Some(name.clone());
+            // The other variant's own tag only numbers the variants after
+            // it, as it is written with the tag it holds.
+            let allow =
+                (Some(i) == other).then(|| quote! { #[allow(dead_code)] });
+            quote! { #allow const #name: #tag = #value; }
+        })
+        .collect()
+}
+
+fn tag_const(i: usize) -> Ident {
+    format_ident!("__TAG_{}", i)
+}
+
+fn expand_enum(
+    input: &DeriveInput,
+    attrs: &ContainerAttrs,
+    data: &DataEnum,
+) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let expecting = format!("enum {}", name);
+    let tag = attrs.tag();
+
+    let mut other = None;
+    for (i, v) in data.variants.iter().enumerate() {
+        let variant_attrs = VariantAttrs::parse(v)?;
+        if variant_attrs.code.is_some() {
+            return Err(syn::Error::new_spanned(
+                v,
+                "type is only meaningful with IspfMessage",
+            ));
+        }
+        if !variant_attrs.other {
+            continue;
+        }
+        if other.is_some() {
+            return Err(syn::Error::new_spanned(
+                v,
+                "only one variant can be marked other",
+            ));
+        }
+        if v.fields.len() != 2 {
+            return Err(syn::Error::new_spanned(
+                v,
+                "the other variant must hold the tag and the remaining bytes",
+            ));
+        }
+        other = Some(i);
+    }
+    let consts = tag_consts(data, &tag, other);
+    // Only the other variant's arm of `serialize_body` looks at the tags.
+    let body_consts = match other {
+        Some(_) => quote! { #(#consts)* },
+        None => quote! {},
+    };
+    let known: Vec<Ident> = (0..data.variants.len())
+        .filter(|i| Some(*i) != other)
+        .map(tag_const)
+        .collect();
+
+    let mut tag_arms = Vec::new();
+    let mut body_arms = Vec::new();
+    let mut read_arms = Vec::new();
+    let mut unknown = quote! {
+        Err(::ispf::__private::serde::de::Error::invalid_value(
+            ::ispf::__private::serde::de::Unexpected::Unsigned(
+                __tag as u64,
+            ),
+            &self,
+        ))
+    };
+    for (i, v) in data.variants.iter().enumerate() {
+        let infos = field_infos(&v.fields)?;
+        if let Some(f) = infos.iter().find(|f| {
+            f.attrs.size_of || f.attrs.checksum.is_some() || f.attrs.payload
+        }) {
+            return Err(syn::Error::new_spanned(
+                f.field,
+                "size_of, checksum and payload are only supported on structs",
+            ));
+        }
+        let variant = &v.ident;
+        let path = quote! { #name::#variant };
+        let pattern = construct(path.clone(), &v.fields, &infos);
+
+        if Some(i) == other {
+            // The tag is held by the first field and the bytes that follow by
+            // the second.
+            let (t, b) = (&infos[0], &infos[1]);
+            let (t_ident, b_ident) = (&t.ident, &b.ident);
+            let (t_ty, b_ty) = (&t.field.ty, &b.field.ty);
+            let b_key = &b.key;
+            let variant_str = variant.to_string();
+            tag_arms.push(quote! { #pattern => {
+                let _ = #b_ident;
+                *#t_ident
+            } });
+            // Written with a known variant's tag, it would read back as that
+            // variant.
+            body_arms.push(quote! {
+                #pattern => {
+                    let __known: &[#tag] = &[#(#known),*];
+                    if __known.contains(#t_ident) {
+                        return Err(
+                            ::ispf::__private::serde::ser::Error::custom(
+                                ::std::format!(
+                                    "tag {} of {}::{} belongs to another \
+                                     variant",
+                                    #t_ident, #name_str, #variant_str,
+                                ),
+                            ),
+                        );
+                    }
+                    let mut __st = __s.serialize_struct(#name_str, 1)?;
+                    SerializeStruct::serialize_field(
+                        &mut __st,
+                        #b_key,
+                        &::ispf::__private::RawBytes(
+                            ::std::convert::AsRef::<[u8]>::as_ref(#b_ident),
+                        ),
+                    )?;
+                    SerializeStruct::end(__st)
+                }
+            });
+            unknown = quote! {{
+                let #t_ident: #t_ty = __tag;
+                let #b_ident: #b_ty = __seq
+                    .next_element_seed(::ispf::__private::Rest::new())?
+                    .ok_or_else(|| {
+                        ::ispf::__private::serde::de::Error::invalid_length(
+                            0, &self,
+                        )
+                    })?;
+                Ok(#pattern)
+            }};
+            continue;
+        }
+        let tag_const = tag_const(i);
+        let len = infos.len();
+
+        let wildcard = match &v.fields {
+            Fields::Named(_) => quote! { #path { .. } },
+            Fields::Unnamed(_) => quote! { #path(..) },
+            Fields::Unit => path,
+        };
+        tag_arms.push(quote! { #wildcard => #tag_const, });
+
+        let writes = serialize_fields(&infos, |ident| quote! { (*#ident) });
+        body_arms.push(quote! {
+            #pattern => {
+                let mut __st = __s.serialize_struct(#name_str, #len)?;
+                #(#writes)*
+                SerializeStruct::end(__st)
+            }
+        });
+
+        let reads = deserialize_fields(&infos, 0);
+        read_arms.push(quote! {
+            #tag_const => {
+                #(#reads)*
+                Ok(#pattern)
+            }
+        });
+    }
+    let read_body = quote! {
+        #(#consts)*
+        match __tag {
+            #(#read_arms)*
+            _ => #unknown,
+        }
+    };
+
+    let (plain_impl_generics, ty_generics, plain_where_clause) =
+        input.generics.split_for_impl();
+    let generics = serialize_generics(input);
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let tagged = quote! {
+        impl #plain_impl_generics ::ispf::__private::Tagged
+            for #name #ty_generics #plain_where_clause
+        {
+            type Tag = #tag;
+
+            fn tag(&self) -> #tag {
+                #(#consts)*
+                match self {
+                    #(#tag_arms)*
+                }
+            }
+        }
+
+        impl #impl_generics ::ispf::__private::TaggedSer
+            for #name #ty_generics #where_clause
+        {
+            fn serialize_body<__S>(
+                &self,
+                __s: __S,
+            ) -> ::std::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::ispf::__private::serde::Serializer,
+            {
+                use ::ispf::__private::serde::ser::SerializeStruct;
+                #body_consts
+                match self {
+                    #(#body_arms)*
+                }
+            }
+        }
+    };
+
+    let (de, de_generics) = deserialize_generics(input);
+    let (de_impl_generics, _, de_where_clause) = de_generics.split_for_impl();
+    let visit_body = visit_seq(
+        input,
+        &expecting,
+        Drive::Open,
+        &[(format_ident!("tag"), quote! { #tag }, quote! { tag })],
+        quote! {
+            let __tag = self.tag;
+            #read_body
+        },
+    );
+    let tagged_de = quote! {
+        impl #de_impl_generics ::ispf::__private::TaggedDe<#de>
+            for #name #ty_generics #de_where_clause
+        {
+            fn deserialize_body<__D>(
+                tag: #tag,
+                d: __D,
+            ) -> ::std::result::Result<Self, __D::Error>
+            where
+                __D: ::ispf::__private::serde::Deserializer<#de>,
+            {
+                #visit_body
+            }
+        }
+    };
+
+    if data.variants.iter().all(|v| v.fields.is_empty()) {
+        let unit = expand_unit_enum(input, attrs, data);
+        return Ok(quote! {
+            #unit
+            #tagged
+            #tagged_de
+        });
+    }
+
+    // Inline, the tag is simply written ahead of the body.
+    let ser = quote! {
+        impl #impl_generics ::ispf::__private::serde::Serialize
+            for #name #ty_generics #where_clause
+        {
+            fn serialize<__S>(
+                &self,
+                __s: __S,
+            ) -> ::std::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::ispf::__private::serde::Serializer,
+            {
+                use ::ispf::__private::serde::ser::SerializeTuple;
+                let mut __st = __s.serialize_tuple(2)?;
+                SerializeTuple::serialize_element(
+                    &mut __st,
+                    &::ispf::__private::Tagged::tag(self),
+                )?;
+                SerializeTuple::serialize_element(
+                    &mut __st,
+                    &::ispf::__private::Body(self),
+                )?;
+                SerializeTuple::end(__st)
+            }
+        }
+    };
+    let visit = visit_seq(
+        input,
+        &expecting,
+        Drive::Open,
+        &[],
+        quote! {
+            let __tag: #tag = __seq.next_element()?.ok_or_else(|| {
+                ::ispf::__private::serde::de::Error::invalid_length(0, &self)
+            })?;
+            __seq
+                .next_element_seed(::ispf::__private::WithTag::<
+                    #name #ty_generics,
+                >::new(__tag))?
+                .ok_or_else(|| {
+                    ::ispf::__private::serde::de::Error::invalid_length(
+                        1, &self,
+                    )
+                })
+        },
+    );
+    let de = deserialize_impl(input, visit);
+
+    Ok(quote! {
+        #tagged
+        #tagged_de
+        #ser
+        #de
+    })
+}
+
+/// An enum with only unit variants is written as a bare tag.
+fn expand_unit_enum(
+    input: &DeriveInput,
+    attrs: &ContainerAttrs,
+    data: &DataEnum,
+) -> TokenStream2 {
+    let name = &input.ident;
+    let tag = attrs.tag();
+    let consts = tag_consts(data, &tag, None);
+    let expecting = format!("a tag of enum {}", name);
+    let variants: Vec<&Ident> =
+        data.variants.iter().map(|v| &v.ident).collect();
+    let tags: Vec<Ident> = (0..variants.len()).map(tag_const).collect();
+
+    let ser_generics = serialize_generics(input);
+    let (ser_impl, ty_generics, ser_where) = ser_generics.split_for_impl();
+    let (de, de_generics) = deserialize_generics(input);
+    let (de_impl, _, de_where) = de_generics.split_for_impl();
+
+    quote! {
+        impl #ser_impl ::ispf::__private::serde::Serialize
+            for #name #ty_generics #ser_where
+        {
+            fn serialize<__S>(
+                &self,
+                __s: __S,
+            ) -> ::std::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::ispf::__private::serde::Serializer,
+            {
+                #(#consts)*
+                let tag: #tag = match self {
+                    #(#name::#variants => #tags,)*
+                };
+                ::ispf::__private::serde::Serialize::serialize(&tag, __s)
+            }
+        }
+
+        impl #de_impl ::ispf::__private::serde::Deserialize<#de>
+            for #name #ty_generics #de_where
+        {
+            fn deserialize<__D>(
+                d: __D,
+            ) -> ::std::result::Result<Self, __D::Error>
+            where
+                __D: ::ispf::__private::serde::Deserializer<#de>,
+            {
+                #(#consts)*
+                let tag = <#tag as ::ispf::__private::serde::Deserialize>
+                    ::deserialize(d)?;
+                match tag {
+                    #(#tags => Ok(#name::#variants),)*
+                    _ => Err(
+                        ::ispf::__private::serde::de::Error::invalid_value(
+                            ::ispf::__private::serde::de::Unexpected::Unsigned(
+                                tag as u64,
+                            ),
+                            &#expecting,
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+fn expand_message(input: &DeriveInput) -> Result<TokenStream2> {
+    let data = match &input.data {
+        Data::Enum(e) => e,
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "IspfMessage can only be derived for enums",
+            ))
+        }
+    };
+    let attrs = ContainerAttrs::parse(input)?;
+    let offset = attrs.type_offset.ok_or_else(|| {
+        syn::Error::new_spanned(
+            &input.ident,
+            "IspfMessage requires #[ispf(type_offset = N)]",
+        )
+    })?;
+    if let Some(expected) = &attrs.assert_size {
+        return Err(syn::Error::new_spanned(
+            expected,
+            "assert_size is only meaningful with Ispf",
+        ));
+    }
+    if attrs.big_endian.is_some() || attrs.dual_endian {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "endian and dual_endian are only meaningful with Ispf",
+        ));
+    }
+    let name = &input.ident;
+    let tag = attrs.tag();
+
+    let mut consts = Vec::new();
+    let mut writes = Vec::new();
+    let mut reads = Vec::new();
+    for (i, v) in data.variants.iter().enumerate() {
+        let variant_attrs = VariantAttrs::parse(v)?;
+        if variant_attrs.other {
+            return Err(syn::Error::new_spanned(
+                v,
+                "other is not supported by IspfMessage",
+            ));
+        }
+        let code = variant_attrs.code.ok_or_else(|| {
+            syn::Error::new_spanned(v, "message requires #[ispf(type = N)]")
+        })?;
+        if !matches!(&v.fields, Fields::Unnamed(f) if f.unnamed.len() == 1) {
+            return Err(syn::Error::new_spanned(
+                v,
+                "message variants must wrap a single message",
+            ));
+        }
+        let variant = &v.ident;
+        let tag_const = tag_const(i);
+        consts.push(quote! { const #tag_const: #tag = #code; });
+        writes.push(quote! {
+            #name::#variant(__m) => {
+                let mut __st = __s.serialize_tuple_struct("at", #offset)?;
+                SerializeTupleStruct::serialize_field(&mut __st, &#tag_const)?;
+                SerializeTupleStruct::serialize_field(&mut __st, __m)?;
+                SerializeTupleStruct::end(__st)
+            }
+        });
+        reads.push(quote! {
+            #tag_const => __seq.next_element()?.map(#name::#variant),
+        });
+    }
+
+    let generics = serialize_generics(input);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let ser = quote! {
+        impl #impl_generics ::ispf::__private::serde::Serialize
+            for #name #ty_generics #where_clause
+        {
+            fn serialize<__S>(
+                &self,
+                __s: __S,
+            ) -> ::std::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::ispf::__private::serde::Serializer,
+            {
+                use ::ispf::__private::serde::ser::SerializeTupleStruct;
+                #(#consts)*
+                match self {
+                    #(#writes)*
+                }
+            }
+        }
+    };
+
+    let visit = visit_seq(
+        input,
+        &format!("message {}", name),
+        Drive::At(offset),
+        &[],
+        quote! {
+            #(#consts)*
+            let __tag: #tag = __seq.next_element()?.ok_or_else(|| {
+                ::ispf::__private::serde::de::Error::invalid_length(0, &self)
+            })?;
+            let __value = match __tag {
+                #(#reads)*
+                _ => {
+                    return Err(
+                        ::ispf::__private::serde::de::Error::invalid_value(
+                            ::ispf::__private::serde::de::Unexpected::Unsigned(
+                                __tag as u64,
+                            ),
+                            &self,
+                        ),
+                    )
+                }
+            };
+            __value.ok_or_else(|| {
+                ::ispf::__private::serde::de::Error::invalid_length(1, &self)
+            })
+        },
+    );
+    let de = deserialize_impl(input, visit);
+
+    Ok(quote! {
+        #ser
+        #de
+    })
+}
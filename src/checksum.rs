@@ -0,0 +1,122 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+/// A checksum over some of the bytes of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    /// CRC-32 as used by zlib and Ethernet (CRC-32/ISO-HDLC).
+    Crc32,
+    /// CRC-16 with polynomial 0x1021 and initial value 0xffff
+    /// (CRC-16/IBM-3740, often called CCITT-FALSE).
+    Crc16,
+    /// The sum of the bytes, modulo 256.
+    Sum8,
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = (i as u16) << 8;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 0x8000 != 0 {
+                (c << 1) ^ 0x1021
+            } else {
+                c << 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+impl Checksum {
+    /// The number of bytes the checksum occupies on the wire.
+    pub const fn width(self) -> usize {
+        match self {
+            Checksum::Crc32 => 4,
+            Checksum::Crc16 => 2,
+            Checksum::Sum8 => 1,
+        }
+    }
+
+    pub fn compute(self, b: &[u8]) -> u64 {
+        match self {
+            Checksum::Crc32 => {
+                let crc = b.iter().fold(!0u32, |c, &x| {
+                    CRC32_TABLE[((c ^ u32::from(x)) & 0xff) as usize] ^ (c >> 8)
+                });
+                u64::from(!crc)
+            }
+            Checksum::Crc16 => {
+                let crc = b.iter().fold(0xffffu16, |c, &x| {
+                    CRC16_TABLE[((c >> 8) ^ u16::from(x)) as usize] ^ (c << 8)
+                });
+                u64::from(crc)
+            }
+            Checksum::Sum8 => {
+                u64::from(b.iter().fold(0u8, |s, &x| s.wrapping_add(x)))
+            }
+        }
+    }
+
+    /// Pack the checksum and where its field sits into the length of a
+    /// "checksum" tuple struct. With `over`, the field covers a range of
+    /// fields marked within the value instead of the rest of it.
+    pub(crate) const fn spec(self, last: bool, over: bool) -> usize {
+        let kind = match self {
+            Checksum::Crc32 => 0,
+            Checksum::Crc16 => 1,
+            Checksum::Sum8 => 2,
+        };
+        kind << 2 | (over as usize) << 1 | last as usize
+    }
+
+    /// The inverse of `spec`.
+    pub(crate) fn from_spec(spec: usize) -> Option<(Self, bool, bool)> {
+        let kind = match spec >> 2 {
+            0 => Checksum::Crc32,
+            1 => Checksum::Crc16,
+            2 => Checksum::Sum8,
+            _ => return None,
+        };
+        Some((kind, spec & 1 != 0, spec & 2 != 0))
+    }
+
+    /// The bytes of `message` covered by a checksum field at its start, or at
+    /// its end if `last`.
+    pub(crate) fn covered(self, message: &[u8], last: bool) -> Option<&[u8]> {
+        let n = message.len().checked_sub(self.width())?;
+        Some(if last {
+            &message[..n]
+        } else {
+            &message[self.width()..]
+        })
+    }
+}
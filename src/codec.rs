@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::marker::PhantomData;
+
+use bytes::BytesMut;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::de::NumDe;
+use crate::error::{Error, Result};
+use crate::frame::frame_size;
+use crate::ser::{NumSer, Serializer};
+use crate::Width;
+
+/// A `tokio_util` codec for messages of type `T`, each starting with a field
+/// holding its whole size, as read by `ispf::tokio::read_message`.
+///
+/// With `Framed`, a byte stream becomes a stream and sink of messages. A
+/// frame larger than the maximum size fails to decode as soon as its size is
+/// read, and a message larger than it fails to encode. So does a message
+/// whose leading field does not hold its size, as it could not be decoded.
+pub struct IspfCodec<T, Endian> {
+    width: Width,
+    max_size: usize,
+    phantom: PhantomData<fn() -> (T, Endian)>,
+}
+
+impl<T, Endian> IspfCodec<T, Endian> {
+    /// A codec for frames whose size is held in a leading field of the given
+    /// width and is at most `max_size`.
+    pub fn new(width: Width, max_size: usize) -> Self {
+        IspfCodec {
+            width,
+            max_size,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+}
+
+impl<T, Endian> Clone for IspfCodec<T, Endian> {
+    fn clone(&self) -> Self {
+        IspfCodec::new(self.width, self.max_size)
+    }
+}
+
+impl<T: DeserializeOwned, Endian: NumDe> Decoder for IspfCodec<T, Endian> {
+    type Item = T;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>> {
+        if src.len() < self.width.size() {
+            return Ok(None);
+        }
+        let size = frame_size::<Endian>(&src[..], self.width, self.max_size)?;
+        if src.len() < size {
+            src.reserve(size - src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(size);
+        crate::from_bytes::<Endian, T>(&frame).map(Some)
+    }
+}
+
+impl<T: Serialize, Endian: NumSer + NumDe> Encoder<&T>
+    for IspfCodec<T, Endian>
+{
+    type Error = Error;
+
+    fn encode(&mut self, item: &T, dst: &mut BytesMut) -> Result<()> {
+        // The message is serialized on its own, so that offsets within it
+        // count from its start, then joined back on.
+        let mut serializer =
+            Serializer::<Endian, _>::with_output(dst.split_off(dst.len()));
+        item.serialize(&mut serializer)?;
+        let frame = serializer.into_output();
+        if frame.len() > self.max_size {
+            return Err(Error::LengthOverflow {
+                max: self.max_size,
+                actual: frame.len(),
+            });
+        }
+        // The decoder frames on the leading size field, so a message must
+        // start with its own size to be read back.
+        if frame.len() < self.width.size() {
+            return Err(Error::LengthUnderflow {
+                min: self.width.size(),
+                actual: frame.len(),
+            });
+        }
+        let size = frame_size::<Endian>(&frame, self.width, self.max_size)?;
+        if size != frame.len() {
+            return Err(Error::SizeMismatch {
+                size,
+                actual: frame.len(),
+            });
+        }
+        dst.unsplit(frame);
+        Ok(())
+    }
+}
+
+impl<T: Serialize, Endian: NumSer + NumDe> Encoder<T> for IspfCodec<T, Endian> {
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<()> {
+        Encoder::<&T>::encode(self, &item, dst)
+    }
+}
@@ -4,26 +4,42 @@
 
 // Copyright 2022 Oxide Computer Company
 
-use std::convert::TryInto;
+use std::borrow::Cow;
+use std::convert::{TryFrom, TryInto};
+use std::ffi::CStr;
 use std::fmt;
+use std::io;
+use std::iter::FromIterator;
 use std::marker::PhantomData;
+use std::ops::Deref;
 use std::str::from_utf8;
 
-use crate::{BigEndian, LittleEndian};
-use serde::de::{self, DeserializeSeed, SeqAccess, Visitor};
+use crate::{BigEndian, ByteOrder, LittleEndian, Width};
+use serde::de::value::{BorrowedBytesDeserializer, SeqDeserializer};
+use serde::de::DeserializeOwned;
+use serde::de::{
+    self, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
 use serde::Deserialize;
 
 pub trait NumDe {
+    const BIG_ENDIAN: bool;
     fn deserialize_u16(v: [u8; 2]) -> u16;
+    fn deserialize_u24(v: [u8; 3]) -> u32;
     fn deserialize_u32(v: [u8; 4]) -> u32;
     fn deserialize_u64(v: [u8; 8]) -> u64;
     fn deserialize_u128(v: [u8; 16]) -> u128;
 }
 
 impl NumDe for LittleEndian {
+    const BIG_ENDIAN: bool = false;
     fn deserialize_u16(v: [u8; 2]) -> u16 {
         u16::from_le_bytes(v)
     }
+    fn deserialize_u24(v: [u8; 3]) -> u32 {
+        u32::from_le_bytes([v[0], v[1], v[2], 0])
+    }
     fn deserialize_u32(v: [u8; 4]) -> u32 {
         u32::from_le_bytes(v)
     }
@@ -36,9 +52,13 @@ impl NumDe for LittleEndian {
 }
 
 impl NumDe for BigEndian {
+    const BIG_ENDIAN: bool = true;
     fn deserialize_u16(v: [u8; 2]) -> u16 {
         u16::from_be_bytes(v)
     }
+    fn deserialize_u24(v: [u8; 3]) -> u32 {
+        u32::from_be_bytes([0, v[0], v[1], v[2]])
+    }
     fn deserialize_u32(v: [u8; 4]) -> u32 {
         u32::from_be_bytes(v)
     }
@@ -51,10 +71,16 @@ impl NumDe for BigEndian {
 }
 
 trait ReadSize {
+    const WIDTH: usize;
     fn read_size<Endian: NumDe>(bytes: &[u8]) -> Result<usize>;
 }
 
+/// Marker for 24-bit length prefixes, which have no native integer type.
+struct U24;
+
 impl ReadSize for u8 {
+    const WIDTH: usize = std::mem::size_of::<u8>();
+
     fn read_size<Endian: NumDe>(bytes: &[u8]) -> Result<usize> {
         match bytes.first() {
             Some(x) => Ok(*x as usize),
@@ -64,6 +90,8 @@ impl ReadSize for u8 {
 }
 
 impl ReadSize for u16 {
+    const WIDTH: usize = std::mem::size_of::<u16>();
+
     fn read_size<Endian: NumDe>(bytes: &[u8]) -> Result<usize> {
         Ok(Endian::deserialize_u16(
             bytes.try_into().map_err(|_| Error::ExpectedInteger)?,
@@ -72,6 +100,8 @@ impl ReadSize for u16 {
 }
 
 impl ReadSize for u32 {
+    const WIDTH: usize = std::mem::size_of::<u32>();
+
     fn read_size<Endian: NumDe>(bytes: &[u8]) -> Result<usize> {
         Ok(Endian::deserialize_u32(
             bytes.try_into().map_err(|_| Error::ExpectedInteger)?,
@@ -80,6 +110,8 @@ impl ReadSize for u32 {
 }
 
 impl ReadSize for u64 {
+    const WIDTH: usize = std::mem::size_of::<u64>();
+
     fn read_size<Endian: NumDe>(bytes: &[u8]) -> Result<usize> {
         Ok(Endian::deserialize_u64(
             bytes.try_into().map_err(|_| Error::ExpectedInteger)?,
@@ -87,10 +119,37 @@ impl ReadSize for u64 {
     }
 }
 
+impl ReadSize for U24 {
+    const WIDTH: usize = 3;
+
+    fn read_size<Endian: NumDe>(bytes: &[u8]) -> Result<usize> {
+        Ok(Endian::deserialize_u24(
+            bytes.try_into().map_err(|_| Error::ExpectedInteger)?,
+        ) as usize)
+    }
+}
+
 use crate::error::{Error, Result};
 
 pub struct Deserializer<'de, Endian: NumDe> {
     input: &'de [u8],
+    /// The whole input, for measuring offsets from its start.
+    origin: &'de [u8],
+    /// Whether integers are read in the opposite order to `Endian`, as
+    /// within a value that declares its own byte order.
+    swap: bool,
+    max_str_len: usize,
+    presence: (u8, u8),
+    variant_width: Width,
+    /// Whether more input may follow, in which case running out of it fails
+    /// with `Error::Eof` rather than anything more specific.
+    partial: bool,
+    /// How long the whole input must be, at least, to get past where a
+    /// partial read ran out.
+    need: usize,
+    /// The positions marked as the start and end of what the checksum of the
+    /// innermost "checksum" value covers, when it covers a range of fields.
+    sum_range: (Option<usize>, Option<usize>),
     endian: PhantomData<Endian>,
 }
 
@@ -98,20 +157,186 @@ impl<'de, Endian: NumDe> Deserializer<'de, Endian> {
     pub fn from_bytes(input: &'de [u8]) -> Self {
         Deserializer {
             input,
+            origin: input,
+            swap: false,
+            max_str_len: usize::MAX,
+            presence: (0, 1),
+            variant_width: Width::U32,
+            partial: false,
+            need: 0,
+            sum_range: (None, None),
             endian: PhantomData::<Endian> {},
         }
     }
 
+    /// Report running out of input as `Error::Eof`, so that the caller can
+    /// tell it apart from malformed input and retry with more.
+    pub(crate) fn partial(mut self, partial: bool) -> Self {
+        self.partial = partial;
+        self
+    }
+
+    /// `e` if there is no more input to come, otherwise `Error::Eof`,
+    /// noting that at least `more` bytes past the current position are
+    /// needed.
+    fn short(&mut self, more: usize, e: Error) -> Error {
+        if self.partial {
+            self.need = self.need.max(self.position().saturating_add(more));
+            Error::Eof
+        } else {
+            e
+        }
+    }
+
+    /// After a partial read fails with `Error::Eof`, how long the whole
+    /// input must be, at least, before it is worth trying again.
+    pub(crate) fn needed(&self) -> usize {
+        self.need
+    }
+
+    /// Limit how far ahead null-terminated strings are scanned for their
+    /// terminator. Strings longer than `max` bytes fail with
+    /// `Error::MissingNullTerminator`.
+    pub fn max_str_len(mut self, max: usize) -> Self {
+        self.max_str_len = max;
+        self
+    }
+
+    /// Set the flag bytes read ahead of an `Option` to mark it absent or
+    /// present. The defaults are 0 and 1; any other value fails with
+    /// `Error::InvalidPresenceFlag`.
+    pub fn presence_flags(mut self, absent: u8, present: u8) -> Self {
+        self.presence = (absent, present);
+        self
+    }
+
+    /// Set the width of the variant index read for an enum. The default is
+    /// `Width::U32`; `Width::U8` suits enums declared `#[repr(u8)]`.
+    pub fn variant_width(mut self, width: Width) -> Self {
+        self.variant_width = width;
+        self
+    }
+
+    /// The input that has not been consumed yet.
+    pub(crate) fn remaining(&self) -> &'de [u8] {
+        self.input
+    }
+
+    /// How far into the whole input reading has got.
+    fn position(&self) -> usize {
+        self.input.as_ptr() as usize - self.origin.as_ptr() as usize
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'de [u8]> {
+        if self.input.len() < n {
+            return Err(self.short(n, Error::Eof));
+        }
+        let (b, rest) = self.input.split_at(n);
+        self.input = rest;
+        Ok(b)
+    }
+
+    /// Read up to and including the next null byte.
+    fn read_until_nul(&mut self) -> Result<&'de [u8]> {
+        let limit = self.input.len().min(self.max_str_len.saturating_add(1));
+        let end = match memchr::memchr(b'\0', &self.input[..limit]) {
+            Some(end) => end,
+            None if limit == self.input.len() => {
+                return Err(self.short(limit + 1, Error::MissingNullTerminator))
+            }
+            None => return Err(Error::MissingNullTerminator),
+        };
+        self.take(end + 1)
+    }
+
+    /// Read a length prefix of type `T`.
+    fn read_len<T: ReadSize>(&mut self) -> Result<usize> {
+        let b = self.take(T::WIDTH)?;
+        if self.swap {
+            let mut buf = [0u8; 8];
+            let buf = &mut buf[..b.len()];
+            buf.copy_from_slice(b);
+            buf.reverse();
+            return T::read_size::<Endian>(buf);
+        }
+        T::read_size::<Endian>(b)
+    }
+
+    /// Take `len` bytes whose length was declared by the input, failing if
+    /// the input is not long enough to back the declaration.
+    fn take_declared(&mut self, len: usize) -> Result<&'de [u8]> {
+        if len > self.input.len() {
+            let remaining = self.input.len();
+            return Err(
+                self.short(len, Error::LengthTooLarge { len, remaining })
+            );
+        }
+        self.take(len)
+    }
+
     fn read_tlv_string<T: ReadSize>(&mut self) -> Result<&'de str> {
-        use std::mem::size_of;
+        let b = self.read_tlv_bytes::<T>()?;
+        from_utf8(b).map_err(|_| Error::ExpectedString)
+    }
+
+    fn read_tlv_bytes<T: ReadSize>(&mut self) -> Result<&'de [u8]> {
+        let len = self.read_len::<T>()?;
+        self.take_declared(len)
+    }
+
+    /// Read a length-value pair, handing `visitor` the offset of the value
+    /// in the whole input and its length rather than its bytes.
+    fn read_range<T, V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        T: ReadSize,
+        V: Visitor<'de>,
+    {
+        let b = self.read_tlv_bytes::<T>()?;
+        let offset = b.as_ptr() as usize - self.origin.as_ptr() as usize;
+        visitor.visit_seq(SeqDeserializer::new(IntoIterator::into_iter([
+            offset,
+            b.len(),
+        ])))
+    }
+
+    /// Read a length prefix that counts its own width, returning the length
+    /// of what follows it.
+    fn read_len_inclusive<T: ReadSize>(&mut self) -> Result<usize> {
+        let len = self.read_len::<T>()?;
+        len.checked_sub(T::WIDTH).ok_or(Error::LengthUnderflow {
+            min: T::WIDTH,
+            actual: len,
+        })
+    }
 
-        let n = size_of::<T>();
+    fn read_tlv_bytes_inclusive<T: ReadSize>(&mut self) -> Result<&'de [u8]> {
+        let len = self.read_len_inclusive::<T>()?;
+        self.take_declared(len)
+    }
 
-        let len = T::read_size::<Endian>(&self.input[..n])?;
-        let s = from_utf8(&self.input[n..n + len]).map_err(|_| Error::Eof)?;
+    /// Hand the next `len` bytes to `visitor` as a deserializer of their
+    /// own, which must consume all of them. As they are all there, running
+    /// out of them is never put down to input yet to come.
+    fn read_sized<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let b = self.take_declared(len)?;
+        let mut sub = Deserializer {
+            input: b,
+            partial: false,
+            ..*self
+        };
+        let value = visitor.visit_newtype_struct(&mut sub)?;
+        if !sub.input.is_empty() {
+            return Err(Error::TrailingBytes);
+        }
+        Ok(value)
+    }
 
-        self.input = &self.input[n + len..];
-        Ok(s)
+    fn read_tlv_string_inclusive<T: ReadSize>(&mut self) -> Result<&'de str> {
+        let b = self.read_tlv_bytes_inclusive::<T>()?;
+        from_utf8(b).map_err(|_| Error::ExpectedString)
     }
 }
 
@@ -129,6 +354,17 @@ where
     from_bytes::<'a, BigEndian, T>(b)
 }
 
+/// Deserialize from `b` in a byte order chosen at run time.
+pub fn from_bytes_in<'a, T>(b: &'a [u8], order: ByteOrder) -> Result<T>
+where
+    T: Deserialize<'a>,
+{
+    match order {
+        ByteOrder::Little => from_bytes_le(b),
+        ByteOrder::Big => from_bytes_be(b),
+    }
+}
+
 pub fn from_bytes<'a, Endian, T>(b: &'a [u8]) -> Result<T>
 where
     T: Deserialize<'a>,
@@ -139,1071 +375,5616 @@ where
     Ok(t)
 }
 
-pub struct TlvStringVisitor;
-impl<'de> Visitor<'de> for TlvStringVisitor {
-    type Value = String;
+/// Deserialize a value from `r`, reading only as far as decoding needs, as
+/// `ReadDeserializer` does, rather than to the end of `r`. A socket is not
+/// waited on once the value has arrived, and a corrupt length only buffers
+/// what `r` actually sends. Reads come in chunks, so bytes after the value
+/// may be read and dropped. A value ending in a field that takes the rest
+/// of the input gets whatever the last read brought; read such input whole
+/// and use `from_bytes` instead.
+pub fn from_reader<Endian, T, R>(r: R) -> Result<T>
+where
+    T: DeserializeOwned,
+    Endian: NumDe,
+    R: io::Read,
+{
+    crate::ReadDeserializer::<R, Endian>::new(r)
+        .next_value()?
+        .ok_or(Error::Eof)
+}
 
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a string prifixed by a length")
+pub fn from_reader_le<T, R>(r: R) -> Result<T>
+where
+    T: DeserializeOwned,
+    R: io::Read,
+{
+    from_reader::<LittleEndian, T, R>(r)
+}
+
+pub fn from_reader_be<T, R>(r: R) -> Result<T>
+where
+    T: DeserializeOwned,
+    R: io::Read,
+{
+    from_reader::<BigEndian, T, R>(r)
+}
+
+/// Deserialize a value from the front of a `bytes::Buf`, which may hold its
+/// bytes in several pieces, as from a chain of network buffers, and advance
+/// the buffer past the bytes the value took. Pass `&mut buf` to go on using
+/// what follows it.
+///
+/// A value within the first piece is decoded in place. Otherwise the pieces
+/// are copied into one `Vec`, taking from the buffer only as many bytes as
+/// decoding is found to need. A value ending in a field that takes the rest
+/// of the input therefore gets only the bytes copied by then.
+#[cfg(feature = "bytes")]
+pub fn from_buf<Endian, T, B>(mut buf: B) -> Result<T>
+where
+    T: DeserializeOwned,
+    Endian: NumDe,
+    B: bytes::Buf,
+{
+    let chunk = buf.chunk();
+    let more = chunk.len() < buf.remaining();
+    let mut de = Deserializer::<Endian>::from_bytes(chunk).partial(more);
+    let mut need = match T::deserialize(&mut de) {
+        Ok(value) => {
+            let n = chunk.len() - de.remaining().len();
+            buf.advance(n);
+            return Ok(value);
+        }
+        Err(Error::Eof) if more && de.needed() > chunk.len() => de.needed(),
+        Err(e) => return Err(e),
+    };
+    let mut b = Vec::new();
+    loop {
+        while b.len() < need && buf.has_remaining() {
+            let chunk = buf.chunk();
+            let n = chunk.len().min(need - b.len());
+            b.extend_from_slice(&chunk[..n]);
+            buf.advance(n);
+        }
+        let more = buf.has_remaining();
+        let mut de = Deserializer::<Endian>::from_bytes(&b).partial(more);
+        match T::deserialize(&mut de) {
+            Err(Error::Eof) if more && de.needed() > b.len() => {
+                need = de.needed()
+            }
+            result => return result,
+        }
     }
+}
 
-    fn visit_borrowed_str<E>(
-        self,
-        value: &'de str,
-    ) -> core::result::Result<Self::Value, E> {
-        Ok(value.to_string())
+#[cfg(feature = "bytes")]
+pub fn from_buf_le<T, B>(buf: B) -> Result<T>
+where
+    T: DeserializeOwned,
+    B: bytes::Buf,
+{
+    from_buf::<LittleEndian, T, B>(buf)
+}
+
+#[cfg(feature = "bytes")]
+pub fn from_buf_be<T, B>(buf: B) -> Result<T>
+where
+    T: DeserializeOwned,
+    B: bytes::Buf,
+{
+    from_buf::<BigEndian, T, B>(buf)
+}
+
+/// Deserialize a value spread across `chunks`, read as one run of bytes, such
+/// as the buffers of a virtio descriptor chain or a list of `IoSlice`s.
+/// Chunks holding the bytes in one piece are decoded in place. Otherwise all
+/// of them are first copied into one `Vec`, once, so offsets in errors count
+/// from the start of the first chunk either way.
+pub fn from_slices<Endian, T, S>(chunks: &[S]) -> Result<T>
+where
+    T: DeserializeOwned,
+    Endian: NumDe,
+    S: Deref<Target = [u8]>,
+{
+    let mut pieces = chunks.iter().map(|c| &**c).filter(|c| !c.is_empty());
+    match (pieces.next(), pieces.next()) {
+        (None, _) => from_bytes::<Endian, T>(&[]),
+        (Some(only), None) => from_bytes::<Endian, T>(only),
+        _ => {
+            let len = chunks.iter().map(|c| c.len()).sum();
+            let mut joined = Vec::with_capacity(len);
+            for chunk in chunks {
+                joined.extend_from_slice(chunk);
+            }
+            from_bytes::<Endian, T>(&joined)
+        }
     }
 }
 
-pub struct TlvVecVisitor<'de, T: serde::Deserialize<'de>> {
+pub fn from_slices_le<T, S>(chunks: &[S]) -> Result<T>
+where
+    T: DeserializeOwned,
+    S: Deref<Target = [u8]>,
+{
+    from_slices::<LittleEndian, T, S>(chunks)
+}
+
+pub fn from_slices_be<T, S>(chunks: &[S]) -> Result<T>
+where
+    T: DeserializeOwned,
+    S: Deref<Target = [u8]>,
+{
+    from_slices::<BigEndian, T, S>(chunks)
+}
+
+pub struct TlvStringVisitor<'de, T: From<&'de str>> {
+    max: usize,
     phantom: PhantomData<T>,
     of_the_opera: PhantomData<&'de ()>,
 }
 
-impl<'de, T: serde::Deserialize<'de>> TlvVecVisitor<'de, T> {
+impl<'de, T: From<&'de str>> TlvStringVisitor<'de, T> {
     pub fn new() -> Self {
-        TlvVecVisitor {
+        Self::bounded(usize::MAX)
+    }
+
+    /// A visitor that rejects strings longer than `max` bytes.
+    pub fn bounded(max: usize) -> Self {
+        TlvStringVisitor {
+            max,
             phantom: PhantomData::<T> {},
             of_the_opera: PhantomData::<&'de ()> {},
         }
     }
 }
 
-impl<'de, T: serde::Deserialize<'de>> Visitor<'de> for TlvVecVisitor<'de, T> {
-    type Value = Vec<T>;
+impl<'de, T: From<&'de str>> Default for TlvStringVisitor<'de, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'de, T: From<&'de str>> Visitor<'de> for TlvStringVisitor<'de, T> {
+    type Value = T;
 
     fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("an array prifixed by a length")
+        formatter.write_str("a string prifixed by a length")
     }
 
-    fn visit_seq<A>(
+    fn visit_borrowed_str<E>(
         self,
-        mut seq: A,
-    ) -> core::result::Result<Self::Value, A::Error>
+        value: &'de str,
+    ) -> core::result::Result<Self::Value, E>
     where
-        A: SeqAccess<'de>,
+        E: de::Error,
     {
-        let mut value = Vec::new();
-        while let Some(x) = seq.next_element()? {
-            value.push(x)
+        if value.len() > self.max {
+            return Err(Error::LengthOverflow {
+                max: self.max,
+                actual: value.len(),
+            }
+            .into_de());
         }
-        Ok(value)
+        Ok(T::from(value))
     }
 }
 
-struct PackedArray<'a, 'de: 'a, Endian: NumDe> {
-    de: &'a mut Deserializer<'de, Endian>,
-    count: usize,
+pub struct TlvOptStringVisitor<'de, T: From<&'de str>> {
+    phantom: PhantomData<T>,
+    of_the_opera: PhantomData<&'de ()>,
 }
 
-impl<'de, 'a, Endian: NumDe> PackedArray<'a, 'de, Endian> {
-    fn new(de: &'a mut Deserializer<'de, Endian>, count: usize) -> Self {
-        PackedArray { de, count }
+impl<'de, T: From<&'de str>> TlvOptStringVisitor<'de, T> {
+    pub fn new() -> Self {
+        TlvOptStringVisitor {
+            phantom: PhantomData::<T> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
     }
 }
 
-impl<'de, 'a, Endian: NumDe> SeqAccess<'de> for PackedArray<'a, 'de, Endian> {
-    type Error = Error;
+impl<'de, T: From<&'de str>> Visitor<'de> for TlvOptStringVisitor<'de, T> {
+    type Value = Option<T>;
 
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
-    where
-        T: DeserializeSeed<'de>,
-    {
-        self.count -= 1;
-        if self.count == 0 {
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an optional string prefixed by a length")
+    }
+
+    fn visit_borrowed_str<E>(
+        self,
+        value: &'de str,
+    ) -> core::result::Result<Self::Value, E> {
+        if value.is_empty() {
             return Ok(None);
         }
-        seed.deserialize(&mut *self.de).map(Some)
+        Ok(Some(T::from(value)))
     }
 }
 
-struct PackedArrayByteSized<'a, 'de: 'a, Endian: NumDe> {
-    de: &'a mut Deserializer<'de, Endian>,
-    bytes: usize,
+pub struct TlvLossyStringVisitor<'de, T: From<Cow<'de, str>>> {
+    phantom: PhantomData<T>,
+    of_the_opera: PhantomData<&'de ()>,
 }
 
-impl<'de, 'a, Endian: NumDe> PackedArrayByteSized<'a, 'de, Endian> {
-    fn new(de: &'a mut Deserializer<'de, Endian>, bytes: usize) -> Self {
-        PackedArrayByteSized { de, bytes }
+impl<'de, T: From<Cow<'de, str>>> TlvLossyStringVisitor<'de, T> {
+    pub fn new() -> Self {
+        TlvLossyStringVisitor {
+            phantom: PhantomData::<T> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
     }
 }
 
-impl<'de, 'a, Endian: NumDe> SeqAccess<'de>
-    for PackedArrayByteSized<'a, 'de, Endian>
+impl<'de, T: From<Cow<'de, str>>> Visitor<'de>
+    for TlvLossyStringVisitor<'de, T>
 {
-    type Error = Error;
+    type Value = T;
 
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
-    where
-        T: DeserializeSeed<'de>,
-    {
-        if self.bytes == 0 {
-            return Ok(None);
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string prefixed by a length")
+    }
+
+    fn visit_borrowed_bytes<E>(
+        self,
+        value: &'de [u8],
+    ) -> core::result::Result<Self::Value, E> {
+        Ok(T::from(String::from_utf8_lossy(value)))
+    }
+}
+
+pub struct TlvLatin1Visitor<T: From<String>> {
+    phantom: PhantomData<T>,
+}
+
+impl<T: From<String>> TlvLatin1Visitor<T> {
+    pub fn new() -> Self {
+        TlvLatin1Visitor {
+            phantom: PhantomData::<T> {},
         }
-        let before = self.de.input.len();
-        let res = seed.deserialize(&mut *self.de).map(Some);
-        let after = self.de.input.len();
-        self.bytes -= before - after;
-        res
     }
 }
 
-impl<'de, 'a, Endian: NumDe> de::Deserializer<'de>
-    for &'a mut Deserializer<'de, Endian>
-{
-    type Error = Error;
+impl<'de, T: From<String>> Visitor<'de> for TlvLatin1Visitor<T> {
+    type Value = T;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!();
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a latin-1 string prefixed by a length")
     }
 
-    fn deserialize_bool<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!();
+    fn visit_bytes<E>(self, value: &[u8]) -> core::result::Result<T, E> {
+        // Latin-1 code points map one-to-one onto the first 256 Unicode
+        // scalar values.
+        Ok(T::from(value.iter().map(|c| char::from(*c)).collect()))
     }
+}
 
-    fn deserialize_i8<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!();
+pub struct TlvUtf16Visitor<T: From<String>> {
+    phantom: PhantomData<T>,
+}
+
+impl<T: From<String>> TlvUtf16Visitor<T> {
+    pub fn new() -> Self {
+        TlvUtf16Visitor {
+            phantom: PhantomData::<T> {},
+        }
     }
+}
 
-    fn deserialize_i16<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!();
+impl<'de, T: From<String>> Visitor<'de> for TlvUtf16Visitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a utf-16 string prefixed by a code unit count")
     }
 
-    fn deserialize_i32<V>(self, _visitor: V) -> Result<V::Value>
+    fn visit_seq<A>(self, mut seq: A) -> core::result::Result<T, A::Error>
     where
-        V: Visitor<'de>,
+        A: SeqAccess<'de>,
     {
-        unimplemented!();
+        let mut units = Vec::new();
+        while let Some(u) = seq.next_element::<u16>()? {
+            units.push(u)
+        }
+        String::from_utf16(&units)
+            .map(T::from)
+            .map_err(|_| de::Error::custom(Error::ExpectedString))
     }
+}
 
-    fn deserialize_i64<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!();
+pub struct CStrVisitor<'de, T: From<&'de CStr>> {
+    phantom: PhantomData<T>,
+    of_the_opera: PhantomData<&'de ()>,
+}
+
+impl<'de, T: From<&'de CStr>> CStrVisitor<'de, T> {
+    pub fn new() -> Self {
+        CStrVisitor {
+            phantom: PhantomData::<T> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
+    }
+}
+
+impl<'de, T: From<&'de CStr>> Visitor<'de> for CStrVisitor<'de, T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a null-terminated string")
     }
 
-    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+    fn visit_borrowed_bytes<E>(
+        self,
+        value: &'de [u8],
+    ) -> core::result::Result<Self::Value, E>
     where
-        V: Visitor<'de>,
+        E: de::Error,
     {
-        let byte = self.input[0];
-        self.input = &self.input[1..];
-        visitor.visit_u8(byte)
+        let s = CStr::from_bytes_with_nul(value).map_err(E::custom)?;
+        Ok(T::from(s))
     }
+}
 
-    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        let bytes = self.input[..2].try_into().map_err(|_| Error::Eof)?;
-        self.input = &self.input[2..];
-        visitor.visit_u16(Endian::deserialize_u16(bytes))
-    }
+pub struct TlvBytesVisitor<'de, T: From<&'de [u8]>> {
+    phantom: PhantomData<T>,
+    of_the_opera: PhantomData<&'de ()>,
+}
 
-    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        let bytes = self.input[..4].try_into().map_err(|_| Error::Eof)?;
-        self.input = &self.input[4..];
-        visitor.visit_u32(Endian::deserialize_u32(bytes))
+impl<'de, T: From<&'de [u8]>> TlvBytesVisitor<'de, T> {
+    pub fn new() -> Self {
+        TlvBytesVisitor {
+            phantom: PhantomData::<T> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
     }
+}
 
-    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        let bytes = self.input[..8].try_into().map_err(|_| Error::Eof)?;
-        self.input = &self.input[8..];
-        visitor.visit_u64(Endian::deserialize_u64(bytes))
+impl<'de, T: From<&'de [u8]>> Default for TlvBytesVisitor<'de, T> {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
-    }
+impl<'de, T: From<&'de [u8]>> Visitor<'de> for TlvBytesVisitor<'de, T> {
+    type Value = T;
 
-    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array prefixed by a length")
     }
 
-    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+    fn visit_borrowed_bytes<E>(
+        self,
+        value: &'de [u8],
+    ) -> core::result::Result<Self::Value, E> {
+        Ok(T::from(value))
     }
+}
 
-    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        let mut i = 0;
-        loop {
-            if self.input[i] == b'\0' {
-                break;
-            }
-            i += 1
+#[cfg(feature = "serde_bytes")]
+pub struct TlvByteBufVisitor<'de, T: serde_bytes::Deserialize<'de>> {
+    phantom: PhantomData<T>,
+    of_the_opera: PhantomData<&'de ()>,
+}
+
+#[cfg(feature = "serde_bytes")]
+impl<'de, T: serde_bytes::Deserialize<'de>> TlvByteBufVisitor<'de, T> {
+    pub fn new() -> Self {
+        TlvByteBufVisitor {
+            phantom: PhantomData::<T> {},
+            of_the_opera: PhantomData::<&'de ()> {},
         }
-        let s =
-            from_utf8(&self.input[..i]).map_err(|_| Error::ExpectedString)?;
-        self.input = &self.input[i + 1..];
-        visitor.visit_borrowed_str(s)
     }
+}
 
-    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        self.deserialize_str(visitor)
-    }
+#[cfg(feature = "serde_bytes")]
+impl<'de, T: serde_bytes::Deserialize<'de>> Visitor<'de>
+    for TlvByteBufVisitor<'de, T>
+{
+    type Value = T;
 
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        let res = visitor.visit_bytes(self.input)?;
-        Ok(res)
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array prefixed by a length")
     }
 
-    fn deserialize_byte_buf<V>(self, _visitor: V) -> Result<V::Value>
+    fn visit_borrowed_bytes<E>(
+        self,
+        value: &'de [u8],
+    ) -> core::result::Result<Self::Value, E>
     where
-        V: Visitor<'de>,
+        E: de::Error,
     {
-        unimplemented!()
+        T::deserialize(de::value::BorrowedBytesDeserializer::new(value))
     }
+}
 
-    fn deserialize_option<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
-    }
+pub struct TlvVecVisitor<'de, C, T> {
+    max: usize,
+    phantom: PhantomData<(C, T)>,
+    of_the_opera: PhantomData<&'de ()>,
+}
 
-    fn deserialize_unit<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+impl<'de, C, T> TlvVecVisitor<'de, C, T> {
+    pub fn new() -> Self {
+        Self::bounded(usize::MAX)
     }
 
-    fn deserialize_unit_struct<V>(
-        self,
-        _name: &'static str,
-        _visitor: V,
-    ) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+    /// A visitor that rejects arrays of more than `max` elements, checking
+    /// the count before any element is decoded.
+    pub fn bounded(max: usize) -> Self {
+        TlvVecVisitor {
+            max,
+            phantom: PhantomData::<(C, T)> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
     }
+}
 
-    fn deserialize_newtype_struct<V>(
-        self,
-        _name: &'static str,
-        _visitor: V,
-    ) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+impl<'de, C, T> Default for TlvVecVisitor<'de, C, T> {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
-    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        let value = visitor.visit_seq(TlvStruct::new(self))?;
-        Ok(value)
-    }
+impl<'de, C, T> Visitor<'de> for TlvVecVisitor<'de, C, T>
+where
+    C: FromIterator<T>,
+    T: serde::Deserialize<'de>,
+{
+    type Value = C;
 
-    fn deserialize_tuple<V>(self, _len: usize, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array prifixed by a length")
     }
 
-    fn deserialize_tuple_struct<V>(
+    fn visit_seq<A>(
         self,
-        name: &'static str,
-        _len: usize,
-        visitor: V,
-    ) -> Result<V::Value>
+        mut seq: A,
+    ) -> core::result::Result<Self::Value, A::Error>
     where
-        V: Visitor<'de>,
+        A: SeqAccess<'de>,
     {
-        use std::mem::size_of;
-
-        match name {
-            "string8" => {
-                let s = self.read_tlv_string::<u8>()?;
-                visitor.visit_borrowed_str(s)
-            }
-            "string16" => {
-                let s = self.read_tlv_string::<u16>()?;
-                visitor.visit_borrowed_str(s)
-            }
-            "string32" => {
-                let s = self.read_tlv_string::<u32>()?;
-                visitor.visit_borrowed_str(s)
-            }
-            "string64" => {
-                let s = self.read_tlv_string::<u64>()?;
-                visitor.visit_borrowed_str(s)
-            }
-            "vec8" => {
-                let n = size_of::<u8>();
-                let len = u8::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArray::new(self, len + 1))
-            }
-            "vec16" => {
-                let n = size_of::<u16>();
-                let len = u16::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArray::new(self, len + 1))
-            }
-            "vec32" => {
-                let n = size_of::<u32>();
-                let len = u32::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArray::new(self, len + 1))
-            }
-            "vec64" => {
-                let n = size_of::<u64>();
-                let len = u64::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArray::new(self, len + 1))
-            }
-            "vec8b" => {
-                let n = size_of::<u8>();
-                let len = u8::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArrayByteSized::new(self, len))
+        let overflow = |actual| {
+            Error::LengthOverflow {
+                max: self.max,
+                actual,
             }
-            "vec16b" => {
-                let n = size_of::<u16>();
-                let len = u16::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArrayByteSized::new(self, len))
+            .into_de::<A::Error>()
+        };
+        if let Some(len) = seq.size_hint() {
+            if len > self.max {
+                return Err(overflow(len));
             }
-            "vec32b" => {
-                let n = size_of::<u32>();
-                let len = u32::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArrayByteSized::new(self, len))
+        }
+        // Collect straight from the input so that collections with inline
+        // storage never touch the heap.
+        let mut err = None;
+        let mut n = 0;
+        let value = std::iter::from_fn(|| match seq.next_element() {
+            Ok(Some(_)) if n == self.max => {
+                err = Some(overflow(n + 1));
+                None
             }
-            "vec64b" => {
-                let n = size_of::<u64>();
-                let len = u64::read_size::<Endian>(&self.input[..n])?;
-                self.input = &self.input[n..];
-                visitor.visit_seq(PackedArrayByteSized::new(self, len))
+            Ok(x) => {
+                n += 1;
+                x
             }
-            s => {
-                unimplemented!("{}", s)
+            Err(e) => {
+                err = Some(e);
+                None
             }
+        })
+        .collect();
+        match err {
+            Some(e) => Err(e),
+            None => Ok(value),
         }
     }
+}
 
-    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+#[cfg(feature = "arrayvec")]
+pub struct TlvArrayVecVisitor<'de, T, const CAP: usize> {
+    phantom: PhantomData<T>,
+    of_the_opera: PhantomData<&'de ()>,
+}
+
+#[cfg(feature = "arrayvec")]
+impl<'de, T, const CAP: usize> TlvArrayVecVisitor<'de, T, CAP> {
+    pub fn new() -> Self {
+        TlvArrayVecVisitor {
+            phantom: PhantomData::<T> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
     }
+}
 
-    fn deserialize_struct<V>(
-        self,
-        _name: &'static str,
-        _fields: &'static [&'static str],
-        visitor: V,
-    ) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        self.deserialize_seq(visitor)
+#[cfg(feature = "arrayvec")]
+impl<'de, T, const CAP: usize> Visitor<'de> for TlvArrayVecVisitor<'de, T, CAP>
+where
+    T: serde::Deserialize<'de>,
+{
+    type Value = arrayvec::ArrayVec<T, CAP>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "at most {} elements prefixed by a length", CAP)
     }
 
-    //TODO: however, enums actually work fine if the derive macro from
-    //serde_repr is used, which crates the exact desired behavior, so perhaps
-    //not a TODO
-    fn deserialize_enum<V>(
+    fn visit_seq<A>(
         self,
-        _name: &'static str,
-        _variants: &'static [&'static str],
-        _visitor: V,
-    ) -> Result<V::Value>
+        mut seq: A,
+    ) -> core::result::Result<Self::Value, A::Error>
     where
-        V: Visitor<'de>,
+        A: SeqAccess<'de>,
     {
-        unimplemented!()
+        let overflow =
+            |actual| Error::LengthOverflow { max: CAP, actual }.into_de();
+        if let Some(n) = seq.size_hint().filter(|n| *n > CAP) {
+            return Err(overflow(n));
+        }
+        let mut value = arrayvec::ArrayVec::new();
+        while let Some(x) = seq.next_element()? {
+            value.try_push(x).map_err(|_| overflow(CAP + 1))?;
+        }
+        Ok(value)
     }
+}
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
-    where
-        V: Visitor<'de>,
-    {
-        unimplemented!()
+pub struct TlvSetVisitor<'de, S, T> {
+    strict: bool,
+    phantom: PhantomData<(S, T)>,
+    of_the_opera: PhantomData<&'de ()>,
+}
+
+impl<'de, S, T> TlvSetVisitor<'de, S, T> {
+    pub fn new() -> Self {
+        TlvSetVisitor {
+            strict: false,
+            phantom: PhantomData::<(S, T)> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
+    }
+
+    /// Fail with `Error::DuplicateElement` rather than folding repeated
+    /// elements together.
+    pub fn strict() -> Self {
+        TlvSetVisitor {
+            strict: true,
+            ..Self::new()
+        }
+    }
+}
+
+impl<'de, S, T> Visitor<'de> for TlvSetVisitor<'de, S, T>
+where
+    S: FromIterator<T>,
+    for<'a> &'a S: IntoIterator,
+    T: serde::Deserialize<'de>,
+{
+    type Value = S;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a set prefixed by a length")
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn visit_seq<A>(self, mut seq: A) -> core::result::Result<S, A::Error>
     where
-        V: Visitor<'de>,
+        A: SeqAccess<'de>,
     {
-        unimplemented!()
+        let mut count = 0usize;
+        let mut elements = Vec::new();
+        while let Some(x) = seq.next_element()? {
+            elements.push(x);
+            count += 1;
+        }
+        let set: S = elements.into_iter().collect();
+        if self.strict && (&set).into_iter().count() != count {
+            return Err(Error::DuplicateElement.into_de());
+        }
+        Ok(set)
     }
 }
 
-struct TlvStruct<'a, 'de: 'a, Endian: NumDe> {
-    de: &'a mut Deserializer<'de, Endian>,
+pub struct TlvMapVisitor<'de, M: serde::Deserialize<'de>> {
+    phantom: PhantomData<M>,
+    of_the_opera: PhantomData<&'de ()>,
 }
 
-impl<'de, 'a, Endian: NumDe> TlvStruct<'a, 'de, Endian> {
-    fn new(de: &'a mut Deserializer<'de, Endian>) -> Self {
-        TlvStruct { de }
+impl<'de, M: serde::Deserialize<'de>> TlvMapVisitor<'de, M> {
+    pub fn new() -> Self {
+        TlvMapVisitor {
+            phantom: PhantomData::<M> {},
+            of_the_opera: PhantomData::<&'de ()> {},
+        }
     }
 }
 
-impl<'de, 'a, Endian: NumDe> SeqAccess<'de> for TlvStruct<'a, 'de, Endian> {
-    type Error = Error;
+impl<'de, M: serde::Deserialize<'de>> Visitor<'de> for TlvMapVisitor<'de, M> {
+    type Value = M;
 
-    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map prefixed by a length")
+    }
+
+    fn visit_map<A>(self, map: A) -> core::result::Result<M, A::Error>
     where
-        T: DeserializeSeed<'de>,
+        A: MapAccess<'de>,
     {
-        seed.deserialize(&mut *self.de).map(Some)
+        M::deserialize(de::value::MapAccessDeserializer::new(map))
     }
 }
 
-///////////////////////////////////////////////////////////////////////////////
+pub struct SizedVisitor<T> {
+    phantom: PhantomData<T>,
+}
 
-#[test]
-fn test_struct_lv() {
-    #[derive(Deserialize, PartialEq, Debug)]
+impl<T> SizedVisitor<T> {
+    pub fn new() -> Self {
+        SizedVisitor {
+            phantom: PhantomData::<T> {},
+        }
+    }
+}
+
+impl<'de, T: serde::Deserialize<'de>> Visitor<'de> for SizedVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a value prefixed by its length in bytes")
+    }
+
+    fn visit_newtype_struct<D>(
+        self,
+        d: D,
+    ) -> core::result::Result<Self::Value, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        T::deserialize(d)
+    }
+}
+
+struct PackedArray<'a, 'de: 'a, Endian: NumDe> {
+    de: &'a mut Deserializer<'de, Endian>,
+    count: usize,
+}
+
+impl<'de, 'a, Endian: NumDe> PackedArray<'a, 'de, Endian> {
+    fn new(de: &'a mut Deserializer<'de, Endian>, count: usize) -> Self {
+        PackedArray { de, count }
+    }
+}
+
+impl<'de, 'a, Endian: NumDe> SeqAccess<'de> for PackedArray<'a, 'de, Endian> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.count == 0 {
+            return Ok(None);
+        }
+        self.count -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.count)
+    }
+}
+
+struct PackedArrayByteSized<'a, 'de: 'a, Endian: NumDe> {
+    de: &'a mut Deserializer<'de, Endian>,
+    bytes: usize,
+}
+
+impl<'de, 'a, Endian: NumDe> PackedArrayByteSized<'a, 'de, Endian> {
+    fn new(
+        de: &'a mut Deserializer<'de, Endian>,
+        bytes: usize,
+    ) -> Result<Self> {
+        if bytes > de.input.len() {
+            let remaining = de.input.len();
+            return Err(de.short(
+                bytes,
+                Error::LengthTooLarge {
+                    len: bytes,
+                    remaining,
+                },
+            ));
+        }
+        Ok(PackedArrayByteSized { de, bytes })
+    }
+}
+
+impl<'de, 'a, Endian: NumDe> SeqAccess<'de>
+    for PackedArrayByteSized<'a, 'de, Endian>
+{
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.bytes == 0 {
+            return Ok(None);
+        }
+        let before = self.de.input.len();
+        let res = seed.deserialize(&mut *self.de).map(Some);
+        let after = self.de.input.len();
+        let used = before - after;
+        self.bytes =
+            self.bytes.checked_sub(used).ok_or(Error::LengthOverflow {
+                max: self.bytes,
+                actual: used,
+            })?;
+        res
+    }
+}
+
+impl<'de, Endian: NumDe> de::Deserializer<'de>
+    for &mut Deserializer<'de, Endian>
+{
+    type Error = Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!();
+    }
+
+    fn deserialize_bool<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!();
+    }
+
+    fn deserialize_i8<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!();
+    }
+
+    fn deserialize_i16<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!();
+    }
+
+    fn deserialize_i32<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!();
+    }
+
+    fn deserialize_i64<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!();
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let byte = self.take(1)?[0];
+        visitor.visit_u8(byte)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut bytes: [u8; 2] =
+            self.take(2)?.try_into().map_err(|_| Error::Eof)?;
+        if self.swap {
+            bytes.reverse();
+        }
+        visitor.visit_u16(Endian::deserialize_u16(bytes))
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut bytes: [u8; 4] =
+            self.take(4)?.try_into().map_err(|_| Error::Eof)?;
+        if self.swap {
+            bytes.reverse();
+        }
+        visitor.visit_u32(Endian::deserialize_u32(bytes))
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut bytes: [u8; 8] =
+            self.take(8)?.try_into().map_err(|_| Error::Eof)?;
+        if self.swap {
+            bytes.reverse();
+        }
+        visitor.visit_u64(Endian::deserialize_u64(bytes))
+    }
+
+    fn deserialize_f32<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!()
+    }
+
+    fn deserialize_f64<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!()
+    }
+
+    fn deserialize_char<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!()
+    }
+
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let b = self.read_until_nul()?;
+        let s =
+            from_utf8(&b[..b.len() - 1]).map_err(|_| Error::ExpectedString)?;
+        visitor.visit_borrowed_str(s)
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        // Unprefixed bytes have no framing of their own, so they run to the
+        // end of the input.
+        let b = self.input;
+        self.input = &[];
+        visitor.visit_borrowed_bytes(b)
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let flag = self.take(1)?[0];
+        if flag == self.presence.0 {
+            visitor.visit_none()
+        } else if flag == self.presence.1 {
+            visitor.visit_some(self)
+        } else {
+            Err(Error::InvalidPresenceFlag(flag))
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let value = visitor.visit_seq(TlvStruct::new(self))?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(PackedArray::new(self, len))
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match name {
+            "string8" => {
+                let s = self.read_tlv_string::<u8>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string16" => {
+                let s = self.read_tlv_string::<u16>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string24" => {
+                let s = self.read_tlv_string::<U24>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string32" => {
+                let s = self.read_tlv_string::<u32>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string64" => {
+                let s = self.read_tlv_string::<u64>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "bytes8" => {
+                let b = self.read_tlv_bytes::<u8>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes16" => {
+                let b = self.read_tlv_bytes::<u16>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes24" => {
+                let b = self.read_tlv_bytes::<U24>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes32" => {
+                let b = self.read_tlv_bytes::<u32>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes64" => {
+                let b = self.read_tlv_bytes::<u64>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "string8i" => {
+                let s = self.read_tlv_string_inclusive::<u8>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string16i" => {
+                let s = self.read_tlv_string_inclusive::<u16>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string24i" => {
+                let s = self.read_tlv_string_inclusive::<U24>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string32i" => {
+                let s = self.read_tlv_string_inclusive::<u32>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "string64i" => {
+                let s = self.read_tlv_string_inclusive::<u64>()?;
+                visitor.visit_borrowed_str(s)
+            }
+            "bytes8i" => {
+                let b = self.read_tlv_bytes_inclusive::<u8>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes16i" => {
+                let b = self.read_tlv_bytes_inclusive::<u16>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes24i" => {
+                let b = self.read_tlv_bytes_inclusive::<U24>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes32i" => {
+                let b = self.read_tlv_bytes_inclusive::<u32>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "bytes64i" => {
+                let b = self.read_tlv_bytes_inclusive::<u64>()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "range8" => self.read_range::<u8, V>(visitor),
+            "range16" => self.read_range::<u16, V>(visitor),
+            "range32" => self.read_range::<u32, V>(visitor),
+            "range64" => self.read_range::<u64, V>(visitor),
+            "cstr" => {
+                let b = self.read_until_nul()?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "fixed" => {
+                let b = self.take(len)?;
+                let end = memchr::memchr(b'\0', b)
+                    .ok_or(Error::MissingNullTerminator)?;
+                let s =
+                    from_utf8(&b[..end]).map_err(|_| Error::ExpectedString)?;
+                visitor.visit_borrowed_str(s)
+            }
+            "vec8" => {
+                let len = self.read_len::<u8>()?;
+                visitor.visit_seq(PackedArray::new(self, len))
+            }
+            "vec16" => {
+                let len = self.read_len::<u16>()?;
+                visitor.visit_seq(PackedArray::new(self, len))
+            }
+            "vec24" => {
+                let len = self.read_len::<U24>()?;
+                visitor.visit_seq(PackedArray::new(self, len))
+            }
+            "vec32" => {
+                let len = self.read_len::<u32>()?;
+                visitor.visit_seq(PackedArray::new(self, len))
+            }
+            "vec64" => {
+                let len = self.read_len::<u64>()?;
+                visitor.visit_seq(PackedArray::new(self, len))
+            }
+            "vec8b" => {
+                let len = self.read_len::<u8>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec16b" => {
+                let len = self.read_len::<u16>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec24b" => {
+                let len = self.read_len::<U24>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec32b" => {
+                let len = self.read_len::<u32>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec64b" => {
+                let len = self.read_len::<u64>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec8bi" => {
+                let len = self.read_len_inclusive::<u8>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec16bi" => {
+                let len = self.read_len_inclusive::<u16>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec24bi" => {
+                let len = self.read_len_inclusive::<U24>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec32bi" => {
+                let len = self.read_len_inclusive::<u32>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "vec64bi" => {
+                let len = self.read_len_inclusive::<u64>()?;
+                visitor.visit_seq(PackedArrayByteSized::new(self, len)?)
+            }
+            "lazy8b" => {
+                let len = self.read_len::<u8>()?;
+                let b = self.take_declared(len)?;
+                visitor.visit_seq(LazyParts::new(self, b))
+            }
+            "lazy16b" => {
+                let len = self.read_len::<u16>()?;
+                let b = self.take_declared(len)?;
+                visitor.visit_seq(LazyParts::new(self, b))
+            }
+            "lazy32b" => {
+                let len = self.read_len::<u32>()?;
+                let b = self.take_declared(len)?;
+                visitor.visit_seq(LazyParts::new(self, b))
+            }
+            "lazy64b" => {
+                let len = self.read_len::<u64>()?;
+                let b = self.take_declared(len)?;
+                visitor.visit_seq(LazyParts::new(self, b))
+            }
+            "sized8" => {
+                let len = self.read_len::<u8>()?;
+                self.read_sized(len, visitor)
+            }
+            "sized16" => {
+                let len = self.read_len::<u16>()?;
+                self.read_sized(len, visitor)
+            }
+            "sized32" => {
+                let len = self.read_len::<u32>()?;
+                self.read_sized(len, visitor)
+            }
+            "sized64" => {
+                let len = self.read_len::<u64>()?;
+                self.read_sized(len, visitor)
+            }
+            "raw" => {
+                let b = self.take_declared(len)?;
+                visitor.visit_borrowed_bytes(b)
+            }
+            "at" => visitor.visit_seq(PeekAt::new(self, len)),
+            "sizeof" => visitor.visit_seq(Measured::new(self, false)),
+            "checksum" => visitor.visit_seq(Measured::new(self, true)),
+            "sumfrom" => {
+                self.sum_range.0 = Some(self.position());
+                visitor.visit_unit()
+            }
+            "sumto" => {
+                self.sum_range.1 = Some(self.position());
+                visitor.visit_unit()
+            }
+            "endian" => {
+                let swap = self.swap;
+                self.swap = (len != 0) != Endian::BIG_ENDIAN;
+                let value = visitor.visit_seq(PackedArray::new(self, 1));
+                self.swap = swap;
+                value
+            }
+            "align" => {
+                let misalign = self.position() % len.max(1);
+                if misalign != 0 {
+                    self.take(len - misalign)?;
+                }
+                visitor.visit_seq(PackedArray::new(self, 1))
+            }
+            "since" => {
+                // A field from a later version than the peer's is simply
+                // missing from the end of the input.
+                let count = if self.input.is_empty() { 0 } else { 1 };
+                visitor.visit_seq(PackedArray::new(self, count))
+            }
+            "map8" => {
+                let len = self.read_len::<u8>()?;
+                visitor.visit_map(PackedMap::new(self, Some(len)))
+            }
+            "map16" => {
+                let len = self.read_len::<u16>()?;
+                visitor.visit_map(PackedMap::new(self, Some(len)))
+            }
+            "map32" => {
+                let len = self.read_len::<u32>()?;
+                visitor.visit_map(PackedMap::new(self, Some(len)))
+            }
+            "map64" => {
+                let len = self.read_len::<u64>()?;
+                visitor.visit_map(PackedMap::new(self, Some(len)))
+            }
+            _ => visitor.visit_seq(PackedArray::new(self, len)),
+        }
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        // Like unprefixed bytes, an unprefixed map runs to the end of the
+        // input.
+        visitor.visit_map(PackedMap::new(self, None))
+    }
+
+    /// Struct fields are read in order, as many as the struct has once
+    /// skipped fields are left out.
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(PackedArray::new(self, fields.len()))
+    }
+
+    /// Enums are read as a variant index, 32 bits wide unless set with
+    /// `variant_width`, followed by the variant's fields, if any. Use the
+    /// `Ispf` derive for explicit discriminants.
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        unimplemented!()
+    }
+
+    /// The input does not describe itself, so there is no telling how much
+    /// of it an ignored value would take. Ignored values are taken to be
+    /// absent from the wire, as fields marked `#[serde(skip)]` are, and
+    /// consume nothing.
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+struct PackedMap<'a, 'de: 'a, Endian: NumDe> {
+    de: &'a mut Deserializer<'de, Endian>,
+    count: Option<usize>,
+}
+
+impl<'de, 'a, Endian: NumDe> PackedMap<'a, 'de, Endian> {
+    fn new(
+        de: &'a mut Deserializer<'de, Endian>,
+        count: Option<usize>,
+    ) -> Self {
+        PackedMap { de, count }
+    }
+}
+
+impl<'de, 'a, Endian: NumDe> MapAccess<'de> for PackedMap<'a, 'de, Endian> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        match &mut self.count {
+            Some(0) => return Ok(None),
+            Some(n) => *n -= 1,
+            None if self.de.input.is_empty() => return Ok(None),
+            None => {}
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+/// The pieces of a `LazyVec`: the byte order of the input, the raw element
+/// bytes, then the decoder's string limit, presence flags and variant index
+/// width, so that the elements are decoded as the rest of the input was.
+struct LazyParts<'de> {
+    big_endian: bool,
+    bytes: &'de [u8],
+    max_str_len: usize,
+    presence: (u8, u8),
+    variant_width: Width,
+    next: usize,
+}
+
+impl<'de> LazyParts<'de> {
+    fn new<Endian: NumDe>(
+        de: &Deserializer<'de, Endian>,
+        bytes: &'de [u8],
+    ) -> Self {
+        LazyParts {
+            big_endian: Endian::BIG_ENDIAN != de.swap,
+            bytes,
+            max_str_len: de.max_str_len,
+            presence: de.presence,
+            variant_width: de.variant_width,
+            next: 0,
+        }
+    }
+}
+
+impl<'de> SeqAccess<'de> for LazyParts<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        use de::value::{
+            BoolDeserializer, BorrowedBytesDeserializer, U64Deserializer,
+            U8Deserializer,
+        };
+
+        self.next += 1;
+        match self.next {
+            1 => seed.deserialize(BoolDeserializer::new(self.big_endian)),
+            2 => seed.deserialize(BorrowedBytesDeserializer::new(self.bytes)),
+            3 => seed.deserialize(U64Deserializer::new(
+                u64::try_from(self.max_str_len).unwrap_or(u64::MAX),
+            )),
+            4 => seed.deserialize(U8Deserializer::new(self.presence.0)),
+            5 => seed.deserialize(U8Deserializer::new(self.presence.1)),
+            6 => seed.deserialize(U8Deserializer::new(
+                self.variant_width.size() as u8,
+            )),
+            _ => return Ok(None),
+        }
+        .map(Some)
+    }
+}
+
+struct TlvStruct<'a, 'de: 'a, Endian: NumDe> {
+    de: &'a mut Deserializer<'de, Endian>,
+}
+
+impl<'de, 'a, Endian: NumDe> TlvStruct<'a, 'de, Endian> {
+    fn new(de: &'a mut Deserializer<'de, Endian>) -> Self {
+        TlvStruct { de }
+    }
+}
+
+impl<'de, 'a, Endian: NumDe> SeqAccess<'de> for TlvStruct<'a, 'de, Endian> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+}
+
+/// A field read `offset` bytes into the input without consuming anything,
+/// followed by a value read as usual.
+struct PeekAt<'a, 'de: 'a, Endian: NumDe> {
+    de: &'a mut Deserializer<'de, Endian>,
+    offset: usize,
+    index: usize,
+}
+
+impl<'de, 'a, Endian: NumDe> PeekAt<'a, 'de, Endian> {
+    fn new(de: &'a mut Deserializer<'de, Endian>, offset: usize) -> Self {
+        PeekAt {
+            de,
+            offset,
+            index: 0,
+        }
+    }
+}
+
+impl<'de, 'a, Endian: NumDe> SeqAccess<'de> for PeekAt<'a, 'de, Endian> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.index += 1;
+        match self.index {
+            1 => {
+                let len = self.de.input.len();
+                let input = match self.de.input.get(self.offset..) {
+                    Some(input) => input,
+                    None => {
+                        return Err(self.de.short(
+                            self.offset,
+                            Error::OffsetOutOfRange {
+                                offset: self.offset,
+                                len,
+                            },
+                        ))
+                    }
+                };
+                let mut sub = Deserializer { input, ..*self.de };
+                let value = seed.deserialize(&mut sub);
+                self.de.need = sub.need;
+                value.map(Some)
+            }
+            2 => seed.deserialize(&mut *self.de).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(2usize.saturating_sub(self.index))
+    }
+}
+
+/// Reads a value, then what it took of the input: the number of bytes, for
+/// checking against a size field within the value, or the bytes themselves,
+/// for checking a checksum. Those are narrowed to the range the value marks,
+/// if it marks one.
+struct Measured<'a, 'de: 'a, Endian: NumDe> {
+    de: &'a mut Deserializer<'de, Endian>,
+    bytes: bool,
+    start: &'de [u8],
+    consumed: Option<&'de [u8]>,
+    /// The range marked for the checksum around this one, put back once
+    /// this value is read.
+    outer: (Option<usize>, Option<usize>),
+    index: usize,
+}
+
+impl<'de, 'a, Endian: NumDe> Measured<'a, 'de, Endian> {
+    fn new(de: &'a mut Deserializer<'de, Endian>, bytes: bool) -> Self {
+        let outer = if bytes {
+            std::mem::take(&mut de.sum_range)
+        } else {
+            de.sum_range
+        };
+        Measured {
+            start: de.input,
+            de,
+            bytes,
+            consumed: None,
+            outer,
+            index: 0,
+        }
+    }
+}
+
+impl<'de, 'a, Endian: NumDe> SeqAccess<'de> for Measured<'a, 'de, Endian> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.index += 1;
+        match (self.index, self.consumed) {
+            (1, _) => {
+                let origin = self.de.position();
+                let value = seed.deserialize(&mut *self.de);
+                let range = if self.bytes {
+                    std::mem::replace(&mut self.de.sum_range, self.outer)
+                } else {
+                    (None, None)
+                };
+                let value = value?;
+                let len = self.start.len() - self.de.input.len();
+                let mut consumed = &self.start[..len];
+                if let (Some(from), Some(to)) = range {
+                    consumed = &consumed[from - origin..to - origin];
+                }
+                self.consumed = Some(consumed);
+                Ok(Some(value))
+            }
+            (2, Some(consumed)) if self.bytes => seed
+                .deserialize(BorrowedBytesDeserializer::<Error>::new(consumed))
+                .map(Some),
+            (2, Some(consumed)) => seed
+                .deserialize(IntoDeserializer::<Error>::into_deserializer(
+                    consumed.len() as u64,
+                ))
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(2usize.saturating_sub(self.index))
+    }
+}
+
+impl<'de, Endian: NumDe> EnumAccess<'de> for &mut Deserializer<'de, Endian> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let index = match self.variant_width {
+            Width::U8 => u32::from(u8::deserialize(&mut *self)?),
+            Width::U16 => u32::from(u16::deserialize(&mut *self)?),
+            Width::U32 => u32::deserialize(&mut *self)?,
+            Width::U64 => {
+                let index = u64::deserialize(&mut *self)?;
+                u32::try_from(index).map_err(|_| {
+                    <Error as de::Error>::invalid_value(
+                        de::Unexpected::Unsigned(index),
+                        &"a variant index",
+                    )
+                })?
+            }
+        };
+        let value = seed
+            .deserialize(IntoDeserializer::<Error>::into_deserializer(index))?;
+        Ok((value, self))
+    }
+}
+
+impl<'de, Endian: NumDe> VariantAccess<'de> for &mut Deserializer<'de, Endian> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn test_struct_lv() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        version: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, b'm', b'u', b'f', b'f', b'i', b'n',
+        b'\0',
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_str_lv8() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(with = "crate::str_lv8")]
+        version: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, b'm', b'u', b'f', b'f', b'i',
+        b'n',
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_str_lv16() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(with = "crate::str_lv16")]
+        version: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, b'm', b'u', b'f', b'f', b'i',
+        b'n',
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_str_lv32() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(with = "crate::str_lv32")]
+        version: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, b'm', b'u', b'f', b'f',
+        b'i', b'n',
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_str_lv64() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(with = "crate::str_lv64")]
+        version: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
+        b'f', b'f', b'i', b'n',
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_nested() {
+    #[derive(Deserialize, PartialEq, Debug)]
     struct Version {
         size: u32,
         typ: u8,
         tag: u16,
         msize: u32,
+        #[serde(with = "crate::str_lv64")]
+        version: String,
+        info: Info,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Info {
+        typ: u8,
+        version: u32,
+        path: u64,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
+        b'f', b'f', b'i', b'n', 3, 57, 48, 0, 0, 254, 91, 10, 0, 0, 0, 0, 0,
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+        info: Info {
+            typ: 3,
+            version: 12345,
+            path: 678910,
+        },
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv8() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv8")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv16() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv16")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv32() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv32")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv64() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv64")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, 0, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv8b() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv8b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv16b() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv16b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv32b() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv32b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lv64b() {
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv64b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, 0, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    let expected = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_str_lv16_cow() {
+    use std::borrow::Cow;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version<'a> {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(borrow, with = "crate::str_lv16")]
+        version: Cow<'a, str>,
+    }
+
+    let b = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, b'm', b'u', b'f', b'f', b'i',
+        b'n',
+    ];
+
+    let expected = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    let v: Version = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(expected, v);
+    assert!(matches!(v.version, Cow::Borrowed(_)));
+}
+
+#[test]
+fn test_struct_bytes_lv32() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rread<'a> {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        #[serde(borrow, with = "crate::bytes_lv32")]
+        data: &'a [u8],
+    }
+
+    let b = vec![15, 0, 0, 0, 117, 4, 0, 4, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef];
+
+    let r: Rread = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(
+        r,
+        Rread {
+            size: 15,
+            typ: 117,
+            tag: 4,
+            data: &[0xde, 0xad, 0xbe, 0xef],
+        }
+    );
+    assert_eq!(r.data.as_ptr(), b[11..].as_ptr());
+}
+
+#[test]
+fn test_struct_trailing_bytes() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rread<'a> {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        data: &'a [u8],
+    }
+
+    let b = vec![11, 0, 0, 0, 117, 4, 0, 0xde, 0xad, 0xbe, 0xef];
+
+    let expected = Rread {
+        size: 11,
+        typ: 117,
+        tag: 4,
+        data: &[0xde, 0xad, 0xbe, 0xef],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[cfg(feature = "serde_bytes")]
+#[test]
+fn test_struct_bytebuf_lv16() {
+    use serde_bytes::{ByteBuf, Bytes};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rread<'a> {
+        size: u32,
+        #[serde(with = "crate::bytebuf_lv16")]
+        owned: ByteBuf,
+        #[serde(borrow, with = "crate::bytebuf_lv16")]
+        borrowed: &'a Bytes,
+    }
+
+    let b = vec![12, 0, 0, 0, 2, 0, 0xab, 0xcd, 2, 0, 0xde, 0xad];
+
+    let expected = Rread {
+        size: 12,
+        owned: ByteBuf::from(vec![0xab, 0xcd]),
+        borrowed: Bytes::new(&[0xde, 0xad]),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_bytes_lv16_vec() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Twrite {
+        fid: u32,
+        offset: u64,
+        #[serde(with = "crate::bytes_lv16")]
+        data: Vec<u8>,
+    }
+
+    let b = vec![7, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 3, 0, 1, 2, 3];
+
+    let expected = Twrite {
+        fid: 7,
+        offset: 512,
+        data: vec![1, 2, 3],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_rest() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Frame<'a> {
+        typ: u8,
+        tag: u16,
+        #[serde(borrow, with = "crate::rest")]
+        body: &'a [u8],
+    }
+
+    let b = vec![100, 1, 0, 9, 8, 7, 6];
+
+    let expected = Frame {
+        typ: 100,
+        tag: 1,
+        body: &[9, 8, 7, 6],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_fixed_array() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rattach {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        qid: [u8; 13],
+    }
+
+    let b = vec![
+        20, 0, 0, 0, 105, 1, 0, 0x80, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8,
+    ];
+
+    let expected = Rattach {
+        size: 20,
+        typ: 105,
+        tag: 1,
+        qid: [0x80, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    assert_eq!(from_bytes_le::<Rattach>(&b[..b.len() - 1]), Err(Error::Eof));
+}
+
+#[test]
+fn test_struct_str_fixed() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Entry {
+        id: u16,
+        #[serde(deserialize_with = "crate::str_fixed::deserialize::<_, _, 8>")]
+        name: String,
+    }
+
+    let b = vec![3, 0, b'e', b't', b'h', b'0', 0, 0xff, 0xff, 0xff];
+
+    let expected = Entry {
+        id: 3,
+        name: "eth0".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![3, 0, b'e', b't', b'h', b'e', b'r', b'n', b'e', b't'];
+    assert_eq!(
+        from_bytes_le::<Entry>(b.as_slice()),
+        Err(Error::MissingNullTerminator)
+    );
+}
+
+#[test]
+fn test_struct_str_lv16_lossy() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Dirent {
+        typ: u8,
+        #[serde(with = "crate::str_lv16_lossy")]
+        name: String,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct StrictDirent {
+        typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        name: String,
+    }
+
+    let b = vec![2, 4, 0, b'a', 0xff, b'b', b'c'];
+
+    let expected = Dirent {
+        typ: 2,
+        name: "a\u{fffd}bc".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    assert_eq!(
+        from_bytes_le::<StrictDirent>(b.as_slice()),
+        Err(Error::ExpectedString)
+    );
+}
+
+#[test]
+fn test_struct_str_lv8_latin1() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct User {
+        id: u8,
+        #[serde(with = "crate::str_lv8_latin1")]
+        name: String,
+    }
+
+    let b = vec![1, 3, b'Z', b'o', 0xeb];
+
+    let expected = User {
+        id: 1,
+        name: "Zoë".into(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_cstr() {
+    use std::ffi::CString;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Open<'a> {
+        mode: u8,
+        #[serde(with = "crate::cstr")]
+        path: CString,
+        #[serde(borrow, with = "crate::cstr")]
+        name: &'a CStr,
+    }
+
+    let b = b"\x02/etc\0passwd\0".to_vec();
+
+    let expected = Open {
+        mode: 2,
+        path: CString::new("/etc").unwrap(),
+        name: CStr::from_bytes_with_nul(b"passwd\0").unwrap(),
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    assert_eq!(
+        from_bytes_le::<Open>(&b[..b.len() - 1]),
+        Err(Error::MissingNullTerminator)
+    );
+}
+
+#[test]
+fn test_struct_str_lv16_bounded() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Twalk {
+        fid: u32,
+        #[serde(
+            deserialize_with = "crate::str_lv16::deserialize_bounded::<_, _, 4>"
+        )]
+        name: String,
+    }
+
+    let b = vec![1, 0, 0, 0, 3, 0, b'u', b's', b'r'];
+    let expected = Twalk {
+        fid: 1,
+        name: "usr".into(),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![1, 0, 0, 0, 6, 0, b'm', b'u', b'f', b'f', b'i', b'n'];
+    assert_eq!(
+        from_bytes_le::<Twalk>(b.as_slice()),
+        Err(Error::LengthOverflow { max: 4, actual: 6 })
+    );
+}
+
+#[test]
+fn test_struct_missing_null_terminator() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Version {
+        msize: u32,
+        version: String,
+    }
+
+    let b = vec![99, 0, 0, 0, b'm', b'u', b'f', b'f', b'i', b'n'];
+    assert_eq!(
+        from_bytes_le::<Version>(b.as_slice()),
+        Err(Error::MissingNullTerminator)
+    );
+
+    let b = vec![99, 0, 0, 0, b'm', b'u', b'f', b'f', b'i', b'n', 0];
+    let mut de = Deserializer::<LittleEndian>::from_bytes(&b).max_str_len(6);
+    assert_eq!(
+        Version::deserialize(&mut de),
+        Ok(Version {
+            msize: 99,
+            version: "muffin".into(),
+        })
+    );
+
+    let mut de = Deserializer::<LittleEndian>::from_bytes(&b).max_str_len(5);
+    assert_eq!(
+        Version::deserialize(&mut de),
+        Err(Error::MissingNullTerminator)
+    );
+}
+
+#[test]
+fn test_struct_opt_str_lv16() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Tauth {
+        afid: u32,
+        #[serde(with = "crate::opt_str_lv16")]
+        uname: Option<String>,
+        #[serde(with = "crate::opt_str_lv16")]
+        aname: Option<String>,
+    }
+
+    let b = vec![1, 0, 0, 0, 4, 0, b'r', b'o', b'o', b't', 0, 0];
+
+    let expected = Tauth {
+        afid: 1,
+        uname: Some("root".into()),
+        aname: None,
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_lv24() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Record {
+        #[serde(with = "crate::str_lv24")]
+        name: String,
+        #[serde(with = "crate::vec_lv24")]
+        ids: Vec<u16>,
+    }
+
+    let expected = Record {
+        name: "abc".into(),
+        ids: vec![1, 2],
+    };
+
+    let b = vec![3, 0, 0, b'a', b'b', b'c', 2, 0, 0, 1, 0, 2, 0];
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![0, 0, 3, b'a', b'b', b'c', 0, 0, 2, 0, 1, 0, 2];
+    assert_eq!(expected, from_bytes_be(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_lv_inclusive() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Attr {
+        typ: u8,
+        #[serde(with = "crate::str_lv16_inclusive")]
+        name: String,
+        #[serde(with = "crate::bytes_lv8_inclusive")]
+        value: Vec<u8>,
+    }
+
+    let b = vec![1, 4, 0, b'a', b'b', 4, 7, 8, 9];
+
+    let expected = Attr {
+        typ: 1,
+        name: "ab".into(),
+        value: vec![7, 8, 9],
+    };
+
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![1, 1, 0, b'a', b'b', 4, 7, 8, 9];
+    assert_eq!(
+        from_bytes_le::<Attr>(b.as_slice()),
+        Err(Error::LengthUnderflow { min: 2, actual: 1 })
+    );
+}
+
+#[test]
+fn test_declared_length_too_large() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Name {
+        #[serde(with = "crate::str_lv16")]
+        name: String,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Ids {
+        #[serde(with = "crate::vec_lv16")]
+        ids: Vec<u16>,
+    }
+
+    let b = vec![100, 0, b'a', b'b', b'c'];
+    assert_eq!(
+        from_bytes_le::<Name>(b.as_slice()),
+        Err(Error::LengthTooLarge {
+            len: 100,
+            remaining: 3
+        })
+    );
+
+    let b = vec![3, 0, 1, 0, 2, 0];
+    assert_eq!(from_bytes_le::<Ids>(b.as_slice()), Err(Error::Eof));
+
+    let b = vec![0xff, 0xff];
+    assert_eq!(from_bytes_le::<Ids>(b.as_slice()), Err(Error::Eof));
+}
+
+#[test]
+fn test_struct_lv_utf16() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Name {
+        #[serde(with = "crate::str_lv16_utf16")]
+        name: String,
+    }
+
+    let b = vec![4, 0, b'h', 0, 0xe9, 0, 0x3d, 0xd8, 0x00, 0xde];
+    let expected = Name {
+        name: "h\u{e9}\u{1f600}".into(),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    // A lone high surrogate is not a valid string.
+    let b = vec![1, 0, 0x3d, 0xd8];
+    assert!(from_bytes_le::<Name>(b.as_slice()).is_err());
+}
+
+#[test]
+fn test_tuple() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Point(u16, u32);
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Entry {
+        pair: (u16, u32),
+        point: Point,
+    }
+
+    let b = vec![1, 0, 2, 0, 0, 0, 3, 0, 4, 0, 0, 0];
+    let expected = Entry {
+        pair: (1, 2),
+        point: Point(3, 4),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![1, 0, 2, 0];
+    assert_eq!(from_bytes_le::<Entry>(b.as_slice()), Err(Error::Eof));
+}
+
+#[test]
+fn test_newtype() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Fid(u32);
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Tclunk {
+        tag: u16,
+        fid: Fid,
+    }
+
+    let b = vec![1, 0, 7, 0, 0, 0];
+    let expected = Tclunk {
+        tag: 1,
+        fid: Fid(7),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_unit() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Marker;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rflush {
+        tag: u16,
+        marker: Marker,
+        nothing: (),
+        phantom: PhantomData<u64>,
+    }
+
+    let b = vec![3, 0];
+    let expected = Rflush {
+        tag: 3,
+        marker: Marker,
+        nothing: (),
+        phantom: PhantomData,
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_option() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Qid {
+        typ: u8,
+        path: u64,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rwalk {
+        tag: u16,
+        qid: Option<Qid>,
+        version: Option<u32>,
+    }
+
+    let expected = Rwalk {
+        tag: 1,
+        qid: Some(Qid { typ: 2, path: 3 }),
+        version: None,
+    };
+
+    let b = vec![1, 0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![1, 0, 0x80, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0xff];
+    let mut de =
+        Deserializer::<LittleEndian>::from_bytes(&b).presence_flags(0xff, 0x80);
+    assert_eq!(Rwalk::deserialize(&mut de), Ok(expected));
+
+    let b = vec![1, 0, 2];
+    assert_eq!(
+        from_bytes_le::<Rwalk>(b.as_slice()),
+        Err(Error::InvalidPresenceFlag(2))
+    );
+}
+
+#[test]
+fn test_opt_sentinel() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Twalk {
+        #[serde(with = "crate::opt_u32")]
+        newfid: Option<u32>,
+        #[serde(
+            deserialize_with = "crate::opt_u16::deserialize_sentinel::<_, 0>"
+        )]
+        port: Option<u16>,
+    }
+
+    let b = vec![0xff, 0xff, 0xff, 0xff, 80, 0];
+    let expected = Twalk {
+        newfid: None,
+        port: Some(80),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![7, 0, 0, 0, 0, 0];
+    let expected = Twalk {
+        newfid: Some(7),
+        port: None,
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_map_lv() {
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Attrs {
+        #[serde(with = "crate::map_lv16")]
+        attrs: BTreeMap<u8, u16>,
+        tail: u8,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Hashed {
+        #[serde(with = "crate::map_lv8")]
+        attrs: HashMap<u8, u16>,
+    }
+
+    let b = vec![2, 0, 1, 10, 0, 2, 20, 0, 9];
+    let mut attrs = BTreeMap::new();
+    attrs.insert(1, 10);
+    attrs.insert(2, 20);
+    let expected = Attrs { attrs, tail: 9 };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![1, 3, 30, 0];
+    let h: Hashed = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(h.attrs.get(&3), Some(&30));
+
+    // Without a prefix a map runs to the end of the input.
+    let b = vec![1, 10, 0, 2, 20, 0];
+    let m: BTreeMap<u8, u16> = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(m, expected.attrs);
+}
+
+#[test]
+fn test_struct_set_lv() {
+    use std::collections::{BTreeSet, HashSet};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Groups {
+        #[serde(with = "crate::set_lv8")]
+        gids: BTreeSet<u16>,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct StrictGroups {
+        #[serde(with = "crate::set_lv8_strict")]
+        gids: HashSet<u16>,
+    }
+
+    let b = vec![2, 3, 0, 5, 0];
+    let expected = Groups {
+        gids: vec![3, 5].into_iter().collect(),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let expected = StrictGroups {
+        gids: vec![3, 5].into_iter().collect(),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![3, 3, 0, 5, 0, 3, 0];
+    let g: Groups = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(g.gids.len(), 2);
+    assert_eq!(
+        from_bytes_le::<StrictGroups>(b.as_slice()),
+        Err(Error::DuplicateElement)
+    );
+}
+
+#[test]
+fn test_struct_vec_lv_collections() {
+    use std::collections::{BinaryHeap, LinkedList, VecDeque};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Queues {
+        #[serde(with = "crate::vec_lv8")]
+        deque: VecDeque<u16>,
+        #[serde(with = "crate::vec_lv16")]
+        list: LinkedList<u8>,
+    }
+
+    #[derive(Deserialize)]
+    struct Heap {
+        #[serde(with = "crate::vec_lv8")]
+        heap: BinaryHeap<u8>,
+    }
+
+    let b = vec![2, 1, 0, 2, 0, 3, 0, 3, 4, 5];
+    let expected = Queues {
+        deque: vec![1, 2].into_iter().collect(),
+        list: vec![3, 4, 5].into_iter().collect(),
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![3, 4, 9, 1];
+    let h: Heap = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(h.heap.into_sorted_vec(), vec![1, 4, 9]);
+}
+
+#[test]
+fn test_struct_vec_lvb_primitive() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Ids {
+        #[serde(with = "crate::vec_lv8b")]
+        ids: Vec<u16>,
+        #[serde(with = "crate::vec_lv8b")]
+        pairs: Vec<[u8; 2]>,
+    }
+
+    let b = vec![6, 1, 0, 2, 0, 3, 0, 2, 4, 5];
+    let expected = Ids {
+        ids: vec![1, 2, 3],
+        pairs: vec![[4, 5]],
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+}
+
+#[test]
+fn test_struct_vec_lvb_inclusive() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Tlv {
+        typ: u8,
+        #[serde(with = "crate::vec_lv8b_inclusive")]
+        values: Vec<u16>,
+    }
+
+    let b = vec![3, 5, 1, 0, 2, 0];
+    let expected = Tlv {
+        typ: 3,
+        values: vec![1, 2],
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![3, 0, 1, 0, 2, 0];
+    assert_eq!(
+        from_bytes_le::<Tlv>(b.as_slice()),
+        Err(Error::LengthUnderflow { min: 1, actual: 0 })
+    );
+}
+
+#[test]
+#[cfg(feature = "smallvec")]
+fn test_struct_smallvec() {
+    use smallvec::SmallVec;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rwalk {
+        #[serde(with = "crate::vec_lv16")]
+        qids: SmallVec<[u32; 4]>,
+    }
+
+    let b = vec![2, 0, 1, 0, 0, 0, 2, 0, 0, 0];
+    let r: Rwalk = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(r.qids.as_slice(), &[1, 2]);
+    assert!(!r.qids.spilled());
+}
+
+#[test]
+#[cfg(feature = "arrayvec")]
+fn test_struct_arrayvec() {
+    use arrayvec::ArrayVec;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rwalk {
+        #[serde(with = "crate::arrayvec_lv16")]
+        qids: ArrayVec<u32, 2>,
+    }
+
+    let b = vec![2, 0, 1, 0, 0, 0, 2, 0, 0, 0];
+    let r: Rwalk = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(r.qids.as_slice(), &[1, 2]);
+
+    let b = vec![3, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Rwalk>(b.as_slice()),
+        Err(Error::LengthOverflow { max: 2, actual: 3 })
+    );
+}
+
+#[test]
+fn test_struct_lazy_lv() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Dirent {
+        offset: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Rreaddir<'a> {
+        tag: u16,
+        #[serde(borrow, with = "crate::lazy_lv16b")]
+        entries: crate::LazyVec<'a, Dirent>,
+    }
+
+    let b = vec![1, 0, 9, 0, 1, 0, 1, b'a', 2, 0, 2, b'b', b'c'];
+    let r: Rreaddir = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(r.tag, 1);
+    assert_eq!(r.entries.as_bytes(), &b[4..]);
+
+    let mut it = r.entries.iter();
+    assert_eq!(
+        it.next(),
+        Some(Ok(Dirent {
+            offset: 1,
+            name: "a".into()
+        }))
+    );
+    assert_eq!(
+        it.next(),
+        Some(Ok(Dirent {
+            offset: 2,
+            name: "bc".into()
+        }))
+    );
+    assert_eq!(it.next(), None);
+
+    let b = vec![0, 1, 0, 4, 0, 1, 1, b'a'];
+    let r: Rreaddir = from_bytes_be(b.as_slice()).unwrap();
+    let names: Vec<_> = r.entries.iter().map(|d| d.unwrap().name).collect();
+    assert_eq!(names, vec!["a"]);
+
+    // A truncated element ends the iteration with its error.
+    let b = vec![1, 0, 5, 0, 1, 0, 4, b'a', b'b'];
+    let r: Rreaddir = from_bytes_le(b.as_slice()).unwrap();
+    let mut it = r.entries.iter();
+    assert!(matches!(it.next(), Some(Err(_))));
+    assert_eq!(it.next(), None);
+
+    // Elements are decoded with the settings of the decoder that read them.
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[repr(u8)]
+    enum Mode {
+        Read,
+        Write(Option<u16>),
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Modes<'a> {
+        #[serde(borrow, with = "crate::lazy_lv8b")]
+        modes: crate::LazyVec<'a, Mode>,
+    }
+
+    let b = [5, 0, 1, b'y', 0, 7];
+    let mut de = Deserializer::<BigEndian>::from_bytes(&b)
+        .variant_width(Width::U8)
+        .presence_flags(b'n', b'y');
+    let r = Modes::deserialize(&mut de).unwrap();
+    let modes: Vec<_> = r.modes.iter().collect();
+    assert_eq!(modes, vec![Ok(Mode::Read), Ok(Mode::Write(Some(7)))]);
+}
+
+#[test]
+fn test_struct_sized_lv() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Qid {
+        typ: u8,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rstat {
+        tag: u16,
+        #[serde(with = "crate::sized_lv16")]
+        qid: Qid,
+        tail: u8,
+    }
+
+    let b = vec![1, 0, 4, 0, 2, 2, b'a', b'b', 9];
+    let expected = Rstat {
+        tag: 1,
+        qid: Qid {
+            typ: 2,
+            name: "ab".into(),
+        },
+        tail: 9,
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    // The inner value may not read past its declared size...
+    let b = vec![1, 0, 3, 0, 2, 2, b'a', b'b', 9];
+    assert_eq!(
+        from_bytes_le::<Rstat>(b.as_slice()),
+        Err(Error::LengthTooLarge {
+            len: 2,
+            remaining: 1
+        })
+    );
+
+    // ...nor leave any of it unread.
+    let b = vec![1, 0, 5, 0, 2, 2, b'a', b'b', 9, 9];
+    assert_eq!(
+        from_bytes_le::<Rstat>(b.as_slice()),
+        Err(Error::TrailingBytes)
+    );
+}
+
+#[test]
+fn test_struct_sized_lv16_double() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Stat {
+        typ: u16,
+        #[serde(with = "crate::str_lv16")]
+        name: String,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Twstat {
+        tag: u16,
+        fid: u32,
+        #[serde(with = "crate::sized_lv16_double")]
+        stat: Stat,
+    }
+
+    let b = vec![1, 0, 2, 0, 0, 0, 7, 0, 5, 0, 3, 0, 1, 0, b'a'];
+    let expected = Twstat {
+        tag: 1,
+        fid: 2,
+        stat: Stat {
+            typ: 3,
+            name: "a".into(),
+        },
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    // Inner and outer sizes that disagree are rejected.
+    let b = vec![1, 0, 2, 0, 0, 0, 7, 0, 4, 0, 3, 0, 1, 0, b'a'];
+    assert_eq!(
+        from_bytes_le::<Twstat>(b.as_slice()),
+        Err(Error::LengthTooLarge {
+            len: 1,
+            remaining: 0
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_with_length() {
+    #[crate::with_length("u16")]
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Req {
+        Attach {
+            fid: u32,
+            uname: String,
+            #[with_length(u8)]
+            aname: String,
+        },
+        Walk(Vec<u32>),
+    }
+
+    let b = [0, 0, 0, 0, 1, 0, 0, 0, 2, 0, b'a', b'b', 1, b'c'];
+    assert_eq!(
+        from_bytes_le::<Req>(&b).unwrap(),
+        Req::Attach {
+            fid: 1,
+            uname: "ab".into(),
+            aname: "c".into(),
+        }
+    );
+    let b = [1, 0, 0, 0, 1, 0, 7, 0, 0, 0];
+    assert_eq!(from_bytes_le::<Req>(&b).unwrap(), Req::Walk(vec![7]));
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_lv() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Tversion {
+        msize: u32,
+        #[ispf(lv = "u16")]
         version: String,
+        #[ispf(lv = "u8")]
+        extra: Vec<u16>,
+    }
+
+    let b = [0, 32, 0, 0, 2, 0, b'9', b'P', 2, 1, 0, 2, 0];
+    assert_eq!(
+        from_bytes_le::<Tversion>(&b).unwrap(),
+        Tversion {
+            msize: 8192,
+            version: "9P".into(),
+            extra: vec![1, 2],
+        }
+    );
+    assert!(from_bytes_le::<Tversion>(&b[..7]).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_lv_unit() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Records {
+        #[ispf(lv = "u8", unit = "bytes")]
+        bytes: Vec<u16>,
+        #[ispf(lv = "u8", unit = "elements")]
+        elements: Vec<u16>,
+    }
+
+    assert_eq!(
+        from_bytes_le::<Records>(&[4, 1, 0, 2, 0, 1, 3, 0]).unwrap(),
+        Records {
+            bytes: vec![1, 2],
+            elements: vec![3],
+        }
+    );
+    // A byte count that splits an element.
+    assert!(from_bytes_le::<Records>(&[3, 1, 0, 2, 0, 1, 3, 0]).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_size_of() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Tclunk {
+        #[ispf(size_of = "message", validate)]
+        size: u32,
+        typ: u8,
+        tag: u16,
+        fid: u32,
+    }
+
+    let b = [11, 0, 0, 0, 120, 1, 0, 5, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Tclunk>(&b).unwrap(),
+        Tclunk {
+            size: 11,
+            typ: 120,
+            tag: 1,
+            fid: 5,
+        }
+    );
+    let b = [12, 0, 0, 0, 120, 1, 0, 5, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Tclunk>(&b),
+        Err(Error::SizeMismatch {
+            size: 12,
+            actual: 11
+        })
+    );
+
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Loose {
+        #[ispf(size_of = "message")]
+        size: u8,
+        fid: u32,
+    }
+
+    let b = [0, 5, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Loose>(&b).unwrap(),
+        Loose { size: 0, fid: 5 }
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_magic() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Header {
+        #[ispf(magic = 0x3950_3031)]
+        magic: u32,
+        len: u16,
+    }
+
+    let b = [b'9', b'P', b'0', b'1', 0, 3];
+    assert_eq!(
+        from_bytes_be::<Header>(&b).unwrap(),
+        Header {
+            magic: 0x3950_3031,
+            len: 3,
+        }
+    );
+    let b = [b'9', b'P', b'0', b'2', 0, 3];
+    let bad = Error::BadMagic {
+        expected: 0x3950_3031,
+        actual: 0x3950_3032,
+    };
+    assert_eq!(from_bytes_be::<Header>(&b), Err(bad.clone()));
+
+    // Other deserializers get the error's message.
+    use serde::de::value::{Error as ValueError, SeqDeserializer};
+    let fields = SeqDeserializer::<_, ValueError>::new(
+        vec![0x3950_3032_u32, 3].into_iter(),
+    );
+    assert_eq!(
+        Header::deserialize(fields).map_err(|e| e.to_string()),
+        Err(bad.to_string())
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_checksum() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Trailer {
+        data: [u8; 9],
+        #[ispf(checksum = "sum8")]
+        sum: u8,
+    }
+
+    let b = *b"123456789\xdd";
+    assert_eq!(
+        from_bytes_le::<Trailer>(&b).unwrap(),
+        Trailer {
+            data: *b"123456789",
+            sum: 0xdd,
+        }
+    );
+    let b = *b"123456780\xdd";
+    assert_eq!(
+        from_bytes_le::<Trailer>(&b),
+        Err(Error::ChecksumMismatch {
+            expected: 0xd4,
+            actual: 0xdd,
+        })
+    );
+
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Header {
+        #[ispf(checksum = "crc16")]
+        crc: u16,
+        data: [u8; 9],
+    }
+
+    let b = *b"\x29\xb1123456789";
+    assert_eq!(
+        from_bytes_be::<Header>(&b).unwrap(),
+        Header {
+            crc: 0x29b1,
+            data: *b"123456789",
+        }
+    );
+    assert!(from_bytes_le::<Header>(&b).is_err());
+
+    // A checksum over some fields only leaves the others free to change.
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Partial {
+        hops: u8,
+        a: u8,
+        b: [u8; 2],
+        #[ispf(checksum = "sum8", over = "a..=b")]
+        sum: u8,
+        flags: u8,
+    }
+
+    let p = |hops, flags| Partial {
+        hops,
+        a: b'1',
+        b: *b"23",
+        sum: 0x96,
+        flags,
+    };
+    let b = *b"\x05123\x96\x01";
+    assert_eq!(from_bytes_le::<Partial>(&b).unwrap(), p(5, 1));
+    let b = *b"\x06123\x96\x02";
+    assert_eq!(from_bytes_le::<Partial>(&b).unwrap(), p(6, 2));
+    let b = *b"\x05124\x96\x01";
+    assert_eq!(
+        from_bytes_le::<Partial>(&b),
+        Err(Error::ChecksumMismatch {
+            expected: 0x97,
+            actual: 0x96,
+        })
+    );
+    assert_eq!(crate::to_bytes_le(&p(6, 2)).unwrap(), b"\x06123\x96\x02");
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_since() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Hello {
+        fid: u16,
+        #[ispf(since = 2)]
+        flags: u8,
+        #[ispf(since = 3)]
+        mode: u16,
+    }
+
+    let h = |flags, mode| Hello {
+        fid: 1,
+        flags,
+        mode,
+    };
+    assert_eq!(from_bytes_le::<Hello>(&[1, 0, 2, 3, 0]).unwrap(), h(2, 3));
+    assert_eq!(from_bytes_le::<Hello>(&[1, 0, 2]).unwrap(), h(2, 0));
+    assert_eq!(from_bytes_le::<Hello>(&[1, 0]).unwrap(), h(0, 0));
+    // A field cut off partway is still an error.
+    assert!(from_bytes_le::<Hello>(&[1, 0, 2, 3]).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_default() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Rlerror {
+        ecode: u32,
+        #[ispf(default)]
+        detail: String,
+        #[ispf(since = 2)]
+        flags: u8,
+    }
+
+    let r = |detail: &str, flags| Rlerror {
+        ecode: 2,
+        detail: detail.into(),
+        flags,
+    };
+    assert_eq!(
+        from_bytes_le::<Rlerror>(&[2, 0, 0, 0, b'x', 0, 1]).unwrap(),
+        r("x", 1)
+    );
+    assert_eq!(
+        from_bytes_le::<Rlerror>(&[2, 0, 0, 0, b'x', 0]).unwrap(),
+        r("x", 0)
+    );
+    assert_eq!(from_bytes_le::<Rlerror>(&[2, 0, 0, 0]).unwrap(), r("", 0));
+    assert!(from_bytes_le::<Rlerror>(&[2, 0, 0]).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_align() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Entry {
+        kind: u8,
+        #[ispf(align = 4)]
+        ino: u32,
+        #[ispf(align = 8)]
+        len: u16,
+    }
+
+    assert_eq!(
+        from_bytes_le::<Entry>(&[1, 9, 9, 9, 2, 0, 0, 0, 3, 0]).unwrap(),
+        Entry {
+            kind: 1,
+            ino: 2,
+            len: 3
+        }
+    );
+    assert_eq!(from_bytes_le::<Entry>(&[1, 0, 0]), Err(Error::Eof));
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_endian() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    #[ispf(endian = "big")]
+    struct Header {
+        #[ispf(size_of = "message", validate)]
+        size: u16,
+        id: u32,
+    }
+
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    #[ispf(endian = "little")]
+    struct Frame {
+        header: Header,
+        crc: u16,
+    }
+
+    let b = [0, 6, 0, 0, 0, 1, 2, 0];
+    let expected = Frame {
+        header: Header { size: 6, id: 1 },
+        crc: 2,
+    };
+    assert_eq!(from_bytes_le::<Frame>(&b).unwrap(), expected);
+    assert_eq!(from_bytes_be::<Frame>(&b).unwrap(), expected);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_dual_endian() {
+    use crate::ByteOrder;
+
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    #[ispf(dual_endian)]
+    struct Record<'a> {
+        kind: u16,
+        name: &'a str,
+    }
+
+    let order = |b: &[u8]| match b {
+        [0, ..] => ByteOrder::Big,
+        _ => ByteOrder::Little,
+    };
+    for b in [&[1, 0, b'a', 0], &[0, 1, b'a', 0]] {
+        assert_eq!(
+            Record::from_bytes_in(b, order(b)).unwrap(),
+            Record { kind: 1, name: "a" }
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_bits() {
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Descriptor {
+        #[ispf(bits = 4)]
+        version: u8,
+        #[ispf(bits = 3)]
+        kind: u8,
+        #[ispf(bits = 1)]
+        dir: bool,
+        #[ispf(bits = 12)]
+        len: u16,
+        #[ispf(bits = 4)]
+        flags: u8,
+    }
+
+    let expected = Descriptor {
+        version: 5,
+        kind: 2,
+        dir: true,
+        len: 0x123,
+        flags: 0xa,
+    };
+    assert_eq!(
+        from_bytes_be::<Descriptor>(&[0xa5, 0xa1, 0x23]).unwrap(),
+        expected
+    );
+    assert_eq!(
+        from_bytes_le::<Descriptor>(&[0xa5, 0x23, 0xa1]).unwrap(),
+        expected
+    );
+    assert_eq!(from_bytes_be::<Descriptor>(&[0xa5, 0xa1]), Err(Error::Eof));
+
+    #[derive(crate::Ispf, Debug, PartialEq)]
+    struct Wide {
+        #[ispf(bits = 4, storage = "u16")]
+        a: u8,
+        #[ispf(bits = 4)]
+        b: u8,
+        #[ispf(bits = 8)]
+        c: u8,
+    }
+
+    assert_eq!(
+        from_bytes_be::<Wide>(&[0x03, 0x21]).unwrap(),
+        Wide { a: 1, b: 2, c: 3 }
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_byte_range() {
+    use crate::ByteRange;
+
+    #[derive(Debug, PartialEq, crate::Ispf)]
+    struct Twrite {
+        #[ispf(size_of = "message")]
+        size: u32,
+        offset: u64,
+        #[ispf(lv = "u32")]
+        data: ByteRange,
+    }
+
+    fn decode<T: serde::de::DeserializeOwned>(b: &[u8]) -> T {
+        from_bytes_le(b).unwrap()
+    }
+
+    let mut b = vec![19, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0, 0];
+    b.extend_from_slice(b"abc");
+    let w: Twrite = decode(&b);
+    assert_eq!(w.offset, 2);
+    assert_eq!(w.data, ByteRange { offset: 16, len: 3 });
+    assert_eq!(w.data.get(&b), Some(&b"abc"[..]));
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_count_from() {
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Twalk<'a> {
+        fid: u32,
+        nwqid: u16,
+        #[ispf(count_from = "nwqid")]
+        qids: Vec<u32>,
+        name: &'a str,
+    }
+
+    let b = vec![1, 0, 0, 0, 2, 0, 7, 0, 0, 0, 8, 0, 0, 0, b'a', 0];
+    let expected = Twalk {
+        fid: 1,
+        nwqid: 2,
+        qids: vec![7, 8],
+        name: "a",
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    let b = vec![1, 0, 0, 0, 3, 0, 7, 0, 0, 0, 8, 0, 0, 0];
+    assert_eq!(from_bytes_le::<Twalk>(b.as_slice()), Err(Error::Eof));
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_bytes_from() {
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Rread {
+        tag: u16,
+        count: u32,
+        #[ispf(bytes_from = "count")]
+        data: Vec<u8>,
+        tail: u8,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, b'm', b'u', b'f', b'f', b'i', b'n',
-        b'\0',
-    ];
+    let b = vec![1, 0, 3, 0, 0, 0, b'a', b'b', b'c', 9];
+    let expected = Rread {
+        tag: 1,
+        count: 3,
+        data: b"abc".to_vec(),
+        tail: 9,
+    };
+    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
 
-    let expected = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    let b = vec![1, 0, 9, 0, 0, 0, b'a', b'b', b'c'];
+    assert_eq!(
+        from_bytes_le::<Rread>(b.as_slice()),
+        Err(Error::LengthTooLarge {
+            len: 9,
+            remaining: 3
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_len_adjust() {
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Record {
+        size: u16,
+        #[ispf(bytes_from = "size", len_adjust = 2)]
+        data: Vec<u8>,
+    }
+
+    let b = vec![0, 5, b'a', b'b', b'c'];
+    let expected = Record {
+        size: 5,
+        data: b"abc".to_vec(),
     };
+    assert_eq!(expected, from_bytes_be(b.as_slice()).unwrap());
+
+    let b = vec![0, 1];
+    assert!(from_bytes_be::<Record>(b.as_slice()).is_err());
+
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Short {
+        n: u8,
+        #[ispf(count_from = "n", len_adjust = -1)]
+        v: Vec<u8>,
+    }
 
+    let b = vec![1, 7, 8];
+    let expected = Short {
+        n: 1,
+        v: vec![7, 8],
+    };
     assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
 }
 
 #[test]
-fn test_struct_str_lv8() {
-    #[derive(Deserialize, PartialEq, Debug)]
-    struct Version {
-        size: u32,
-        typ: u8,
-        tag: u16,
-        msize: u32,
-        #[serde(with = "crate::str_lv8")]
-        version: String,
+#[cfg(feature = "derive")]
+fn test_derive_try_from() {
+    use std::convert::TryFrom;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum QidType {
+        File,
+        Dir,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, b'm', b'u', b'f', b'f', b'i',
-        b'n',
-    ];
+    impl From<QidType> for u8 {
+        fn from(t: QidType) -> u8 {
+            match t {
+                QidType::File => 0x00,
+                QidType::Dir => 0x80,
+            }
+        }
+    }
 
-    let expected = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    impl TryFrom<u8> for QidType {
+        type Error = ();
+
+        fn try_from(v: u8) -> std::result::Result<Self, ()> {
+            match v {
+                0x00 => Ok(QidType::File),
+                0x80 => Ok(QidType::Dir),
+                _ => Err(()),
+            }
+        }
+    }
+
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Qid {
+        #[ispf(try_from = "u8")]
+        typ: QidType,
+        version: u32,
+    }
+
+    let expected = Qid {
+        typ: QidType::Dir,
+        version: 1,
     };
+    assert_eq!(from_bytes_le::<Qid>(&[0x80, 1, 0, 0, 0]).unwrap(), expected);
+    assert_eq!(
+        from_bytes_le::<Qid>(&[5, 1, 0, 0, 0]),
+        Err(Error::Message("invalid value 5 for field `typ`".into()))
+    );
+}
+
+#[test]
+fn test_lv_types() {
+    use crate::{Lv16, Lv8};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Tattach {
+        uname: Lv16<String>,
+        qids: Lv8<Vec<u16>>,
+    }
+
+    let b = vec![2, 0, b'a', b'b', 2, 1, 0, 2, 0];
+    let t: Tattach = from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(t.uname.as_str(), "ab");
+    assert_eq!(t.qids.into_inner(), vec![1, 2]);
+}
+
+#[test]
+fn test_struct_str_list_lv() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Twalk {
+        fid: u32,
+        #[serde(with = "crate::str_list_lv16")]
+        wname: Vec<String>,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Bounded {
+        fid: u32,
+        #[serde(
+            deserialize_with = "crate::str_list_lv16::deserialize_bounded::<_, _, 1>"
+        )]
+        wname: Vec<String>,
+    }
 
+    let b = vec![1, 0, 0, 0, 2, 0, 3, 0, b'u', b's', b'r', 1, 0, b'b'];
+    let expected = Twalk {
+        fid: 1,
+        wname: vec!["usr".into(), "b".into()],
+    };
     assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+
+    assert_eq!(
+        from_bytes_le::<Bounded>(b.as_slice()),
+        Err(Error::LengthOverflow { max: 1, actual: 2 })
+    );
+
+    // The count promises more strings than there are.
+    let b = vec![1, 0, 0, 0, 3, 0, 3, 0, b'u', b's', b'r', 1, 0, b'b'];
+    assert_eq!(from_bytes_le::<Twalk>(b.as_slice()), Err(Error::Eof));
+
+    // A bounded list fails on its count, before reading any string.
+    let b = vec![1, 0, 0, 0, 0xff, 0xff];
+    assert_eq!(
+        from_bytes_le::<Bounded>(b.as_slice()),
+        Err(Error::LengthOverflow {
+            max: 1,
+            actual: 0xffff
+        })
+    );
 }
 
 #[test]
-fn test_struct_str_lv16() {
+fn test_unit_variant() {
     #[derive(Deserialize, PartialEq, Debug)]
-    struct Version {
-        size: u32,
+    enum Mode {
+        Read,
+        Write,
+    }
+
+    assert_eq!(from_bytes_le::<Mode>(&[1, 0, 0, 0]).unwrap(), Mode::Write);
+    assert_eq!(from_bytes_be::<Mode>(&[0, 0, 0, 0]).unwrap(), Mode::Read);
+    assert!(from_bytes_le::<Mode>(&[2, 0, 0, 0]).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_unit_enum() {
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    #[ispf(tag = "u16")]
+    enum Kind {
+        Plain,
+        Dir = 0x80,
+        Link,
+    }
+
+    assert_eq!(from_bytes_be::<Kind>(&[0, 0]).unwrap(), Kind::Plain);
+    assert_eq!(from_bytes_be::<Kind>(&[0, 0x80]).unwrap(), Kind::Dir);
+    assert_eq!(from_bytes_le::<Kind>(&[0x81, 0]).unwrap(), Kind::Link);
+    assert!(from_bytes_le::<Kind>(&[1, 0]).is_err());
+}
+
+#[test]
+fn test_data_variants() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    enum Msg {
+        Ping,
+        Data(u16),
+        Pair(u8, u8),
+        Walk { fid: u32, newfid: u32 },
+    }
+
+    let b = [1, 0, 0, 0, 7, 0];
+    assert_eq!(from_bytes_le::<Msg>(&b).unwrap(), Msg::Data(7));
+    let b = [0, 0, 0, 1, 0, 7];
+    assert_eq!(from_bytes_be::<Msg>(&b).unwrap(), Msg::Data(7));
+    let b = [0, 0, 0, 2, 1, 2];
+    assert_eq!(from_bytes_be::<Msg>(&b).unwrap(), Msg::Pair(1, 2));
+    let b = [3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Msg>(&b).unwrap(),
+        Msg::Walk { fid: 1, newfid: 2 }
+    );
+    assert_eq!(from_bytes_le::<Msg>(&[0, 0, 0, 0]).unwrap(), Msg::Ping);
+}
+
+#[test]
+fn test_variant_lv_fields() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    enum Req {
+        Attach(#[serde(with = "ispf::str_lv16")] String, u32),
+        Walk {
+            fid: u32,
+            #[serde(with = "ispf::vec_lv16")]
+            names: Vec<u8>,
+        },
+    }
+
+    let b = [0, 0, 0, 0, 2, 0, b'a', b'b', 9, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Req>(&b).unwrap(),
+        Req::Attach("ab".into(), 9)
+    );
+    let b = [1, 0, 0, 0, 1, 0, 0, 0, 2, 0, 5, 6];
+    assert_eq!(
+        from_bytes_le::<Req>(&b).unwrap(),
+        Req::Walk {
+            fid: 1,
+            names: vec![5, 6]
+        }
+    );
+}
+
+#[test]
+fn test_lv_module() {
+    crate::lv_module!(#[allow(dead_code)] mod str_lv24i: str, u24, inclusive);
+    crate::lv_module!(#[allow(dead_code)] mod bytes_lv24: bytes, u24);
+    crate::lv_module!(
+        #[allow(dead_code)]
+        mod vec_lv24bi: vec_bytes, u24, inclusive
+    );
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Entry<'a> {
+        #[serde(with = "str_lv24i")]
+        name: String,
+        #[serde(with = "bytes_lv24")]
+        data: &'a [u8],
+        #[serde(with = "vec_lv24bi")]
+        ids: Vec<u16>,
+    }
+
+    let b = [0, 0, 5, b'a', b'b', 0, 0, 1, 7, 0, 0, 5, 0, 1];
+    assert_eq!(
+        from_bytes_be::<Entry>(&b).unwrap(),
+        Entry {
+            name: "ab".into(),
+            data: &[7],
+            ids: vec![1],
+        }
+    );
+    assert_eq!(
+        from_bytes_be::<Entry>(&[0, 0, 2]),
+        Err(Error::LengthUnderflow { min: 3, actual: 2 })
+    );
+}
+
+#[test]
+fn test_skip() {
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Entry {
+        id: u16,
+        #[serde(skip)]
+        cached: u32,
+        #[serde(skip_deserializing)]
+        seen: bool,
+        len: u8,
+    }
+
+    assert_eq!(
+        from_bytes_le::<Entry>(&[1, 0, 3]).unwrap(),
+        Entry {
+            id: 1,
+            cached: 0,
+            seen: false,
+            len: 3
+        }
+    );
+
+    // Ignored values take up nothing on the wire.
+    #[derive(Deserialize)]
+    struct Ignoring(u8, serde::de::IgnoredAny, u8);
+
+    let Ignoring(a, _, b) = from_bytes_le(&[1, 2]).unwrap();
+    assert_eq!((a, b), (1, 2));
+}
+
+#[test]
+fn test_variant_width() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[repr(u8)]
+    enum Mode {
+        Read,
+        Write(u16),
+    }
+
+    let b = [1, 0, 7];
+    let mut de =
+        Deserializer::<BigEndian>::from_bytes(&b).variant_width(Width::U8);
+    assert_eq!(Mode::deserialize(&mut de), Ok(Mode::Write(7)));
+
+    let b = [0, 0, 0, 0, 0, 0, 0, 0];
+    let mut de =
+        Deserializer::<BigEndian>::from_bytes(&b).variant_width(Width::U64);
+    assert_eq!(Mode::deserialize(&mut de), Ok(Mode::Read));
+
+    let b = [2];
+    let mut de =
+        Deserializer::<BigEndian>::from_bytes(&b).variant_width(Width::U8);
+    assert!(Mode::deserialize(&mut de).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_data_enum() {
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    #[repr(u8)]
+    enum Body {
+        Clunk {
+            fid: u32,
+        },
+        Walk {
+            fid: u32,
+            n: u8,
+            #[ispf(count_from = "n")]
+            names: Vec<u16>,
+        } = 110,
+        Flush(u16),
+        Ack,
+    }
+
+    let b = [110, 1, 0, 0, 0, 2, 3, 0, 4, 0];
+    let expected = Body::Walk {
+        fid: 1,
+        n: 2,
+        names: vec![3, 4],
+    };
+    assert_eq!(from_bytes_le::<Body>(&b).unwrap(), expected);
+    assert_eq!(
+        from_bytes_le::<Body>(&[0, 5, 0, 0, 0]).unwrap(),
+        Body::Clunk { fid: 5 }
+    );
+    assert_eq!(from_bytes_be::<Body>(&[111, 0, 9]).unwrap(), Body::Flush(9));
+    assert_eq!(from_bytes_be::<Body>(&[112]).unwrap(), Body::Ack);
+    assert!(from_bytes_be::<Body>(&[1, 0]).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_tag_from() {
+    use crate::Lv16;
+
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    #[repr(u8)]
+    enum Body {
+        Tversion { msize: u32, version: Lv16<String> } = 100,
+        Rclunk = 121,
+    }
+
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Message {
         typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(with = "crate::str_lv16")]
-        version: String,
+        #[ispf(tag_from = "typ")]
+        body: Body,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, b'm', b'u', b'f', b'f', b'i',
-        b'n',
-    ];
+    let b = [100, 1, 0, 0, 0x20, 0, 0, 2, 0, b'9', b'P'];
+    let expected = Message {
+        typ: 100,
+        tag: 1,
+        body: Body::Tversion {
+            msize: 8192,
+            version: Lv16("9P".into()),
+        },
+    };
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap(), expected);
 
-    let expected = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    let expected = Message {
+        typ: 121,
+        tag: 2,
+        body: Body::Rclunk,
     };
+    assert_eq!(from_bytes_le::<Message>(&[121, 2, 0]).unwrap(), expected);
+    assert!(from_bytes_le::<Message>(&[7, 2, 0]).is_err());
+}
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_other_variant() {
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    #[repr(u8)]
+    enum Body<'a> {
+        Ping = 1,
+        Pong,
+        #[ispf(other)]
+        Unknown(u8, &'a [u8]),
+    }
+
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    struct Message<'a> {
+        typ: u8,
+        tag: u16,
+        #[ispf(tag_from = "typ")]
+        body: Body<'a>,
+    }
+
+    assert_eq!(from_bytes_le::<Body>(&[1]).unwrap(), Body::Ping);
+    assert_eq!(from_bytes_le::<Body>(&[2]).unwrap(), Body::Pong);
+    assert_eq!(
+        from_bytes_le::<Body>(&[3, 4, 5]).unwrap(),
+        Body::Unknown(3, &[4, 5])
+    );
+
+    let b = [77, 1, 0, 0xde, 0xad];
+    let expected = Message {
+        typ: 77,
+        tag: 1,
+        body: Body::Unknown(77, &[0xde, 0xad]),
+    };
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap(), expected);
+
+    // The other variant still counts when numbering those after it.
+    #[derive(crate::Ispf, PartialEq, Debug)]
+    #[repr(u8)]
+    enum Mid<'a> {
+        A = 1,
+        #[ispf(other)]
+        Unknown(u8, &'a [u8]),
+        B,
+    }
+
+    assert_eq!(from_bytes_le::<Mid>(&[1]).unwrap(), Mid::A);
+    assert_eq!(from_bytes_le::<Mid>(&[3]).unwrap(), Mid::B);
+    assert_eq!(
+        from_bytes_le::<Mid>(&[2, 7]).unwrap(),
+        Mid::Unknown(2, &[7])
+    );
 }
 
 #[test]
-fn test_struct_str_lv32() {
-    #[derive(Deserialize, PartialEq, Debug)]
-    struct Version {
+#[cfg(feature = "derive")]
+fn test_derive_message() {
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Tflush {
         size: u32,
         typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(with = "crate::str_lv32")]
-        version: String,
+        oldtag: u16,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, b'm', b'u', b'f', b'f',
-        b'i', b'n',
-    ];
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Rflush {
+        size: u32,
+        typ: u8,
+        tag: u16,
+    }
 
-    let expected = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
-    };
+    #[derive(crate::IspfMessage, PartialEq, Debug)]
+    #[ispf(type_offset = 4, tag = "u8")]
+    enum Message {
+        #[ispf(type = 108)]
+        Tflush(Tflush),
+        #[ispf(type = 109)]
+        Rflush(Rflush),
+    }
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let b = [9, 0, 0, 0, 108, 1, 0, 2, 0];
+    let expected = Message::Tflush(Tflush {
+        size: 9,
+        typ: 108,
+        tag: 1,
+        oldtag: 2,
+    });
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap(), expected);
+
+    let b = [7, 0, 0, 0, 109, 1, 0];
+    let expected = Message::Rflush(Rflush {
+        size: 7,
+        typ: 109,
+        tag: 1,
+    });
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap(), expected);
+
+    assert!(from_bytes_le::<Message>(&[7, 0, 0, 0, 1, 1, 0]).is_err());
+    assert_eq!(
+        from_bytes_le::<Message>(&[7, 0]),
+        Err(Error::OffsetOutOfRange { offset: 4, len: 2 })
+    );
 }
 
 #[test]
-fn test_struct_str_lv64() {
-    #[derive(Deserialize, PartialEq, Debug)]
-    struct Version {
+fn test_dispatcher() {
+    use crate::{Dispatcher, LittleEndian, Message};
+
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Tflush {
         size: u32,
         typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(with = "crate::str_lv64")]
-        version: String,
+        oldtag: u16,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
-        b'f', b'f', b'i', b'n',
-    ];
+    impl Message for Tflush {
+        const TYPE: u8 = 108;
+    }
 
-    let expected = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Rflush {
+        size: u32,
+        typ: u8,
+        tag: u16,
+    }
+
+    impl Message for Rflush {
+        const TYPE: u8 = 109;
+    }
+
+    let tflush = [9, 0, 0, 0, 108, 1, 0, 2, 0];
+    let rflush = [7, 0, 0, 0, 109, 1, 0];
+
+    let mut d: Dispatcher<LittleEndian> = Dispatcher::new(4);
+    d.register::<Tflush>().register::<Rflush>();
+    let m = d.decode(&tflush).unwrap();
+    assert_eq!(
+        m.downcast_ref::<Tflush>(),
+        Some(&Tflush {
+            size: 9,
+            typ: 108,
+            tag: 1,
+            oldtag: 2,
+        })
+    );
+    assert!(d.decode(&rflush).unwrap().is::<Rflush>());
+
+    let mut d: Dispatcher<LittleEndian, u16> = Dispatcher::new(4);
+    d.route(|m: Tflush| m.oldtag).route(|m: Rflush| m.tag);
+    assert_eq!(d.dispatch(&tflush), Ok(2));
+    assert_eq!(d.dispatch(&rflush), Ok(1));
+    assert_eq!(
+        d.dispatch(&[7, 0, 0, 0, 100, 1, 0]),
+        Err(Error::UnknownMessageType(100))
+    );
+    assert_eq!(
+        d.dispatch(&[7, 0]),
+        Err(Error::OffsetOutOfRange { offset: 4, len: 2 })
+    );
+
+    fn is_send_sync<T: Send + Sync>(_: &T) {}
+    is_send_sync(&d);
+    is_send_sync(&Dispatcher::<LittleEndian>::new(4));
+}
+
+#[test]
+fn test_raw_message() {
+    use crate::RawMessage;
+    use std::borrow::Cow;
+
+    let b = [9, 0, 0, 0, 108, 1, 0, 2, 0];
+    let m: RawMessage = from_bytes_le(&b).unwrap();
+    assert_eq!(m.typ, 108);
+    assert_eq!(m.tag, 1);
+    assert!(matches!(m.body, Cow::Borrowed(&[2, 0])));
+
+    let b = [7, 0, 0, 0, 109, 1, 0, 8, 0, 0, 0, 120, 2, 0, 9];
+    let (a, m): (RawMessage, RawMessage) = from_bytes_le(&b).unwrap();
+    assert_eq!(a.typ, 109);
+    assert!(a.body.is_empty());
+    assert_eq!(m.typ, 120);
+    assert_eq!(m.body.as_ref(), &[9]);
+
+    assert!(from_bytes_le::<RawMessage>(&[6, 0, 0, 0, 109, 1, 0]).is_err());
+    assert!(from_bytes_le::<RawMessage>(&[9, 0, 0, 0, 109, 1, 0]).is_err());
+}
+
+#[test]
+fn test_peek() {
+    use crate::{
+        peek_be, peek_header_be, peek_header_le, peek_le, peek_type, Header,
     };
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
-}
+    // The body is never looked at, however short or bogus.
+    let b = [200, 0, 0, 0, 116, 3, 0, 0xff];
+    assert_eq!(
+        peek_header_le(&b),
+        Ok(Header {
+            size: 200,
+            typ: 116,
+            tag: 3
+        })
+    );
+    assert_eq!(peek_header_be(&b).unwrap().tag, 0x300);
+    assert_eq!(peek_type(&b), Ok(116));
+    assert_eq!(peek_le::<(u32, u8)>(&b), Ok((200, 116)));
+    assert_eq!(peek_be::<u16>(&b), Ok(0xc800));
+
+    assert_eq!(peek_header_le(&b[..6]), Err(Error::Eof));
+    assert_eq!(peek_type(&b[..4]), Err(Error::Eof));
+    assert_eq!(peek_le::<[u32; 2]>(&b[..7]), Err(Error::Eof));
+}
+
+#[test]
+fn test_retag() {
+    use crate::{patch_field_be, patch_field_le, retag_be, retag_le};
+
+    let mut b = [9, 0, 0, 0, 108, 1, 0, 2, 0];
+    assert_eq!(retag_le(&mut b, 0x1234), Ok(1));
+    assert_eq!(b, [9, 0, 0, 0, 108, 0x34, 0x12, 2, 0]);
+    assert_eq!(retag_be(&mut b, 7), Ok(0x3412));
+    assert_eq!(b, [9, 0, 0, 0, 108, 0, 7, 2, 0]);
+    assert_eq!(retag_le(&mut b[..6], 1), Err(Error::Eof));
+
+    patch_field_le(&mut b, 7, &0x0a0bu16).unwrap();
+    assert_eq!(b[7..], [0x0b, 0x0a]);
+    patch_field_be(&mut b, 0, &(20u32, 3u8)).unwrap();
+    assert_eq!(b[..5], [0, 0, 0, 20, 3]);
+    assert_eq!(
+        patch_field_le(&mut b, 8, &1u16),
+        Err(Error::OffsetOutOfRange { offset: 8, len: 9 })
+    );
+    assert_eq!(
+        patch_field_le(&mut b, usize::MAX, &1u8),
+        Err(Error::OffsetOutOfRange {
+            offset: usize::MAX,
+            len: 9
+        })
+    );
+    assert_eq!(b, [0, 0, 0, 20, 3, 0, 7, 0x0b, 0x0a]);
+}
+
+#[test]
+#[cfg(feature = "bitflags")]
+fn test_bits() {
+    bitflags::bitflags! {
+        #[derive(Clone, Copy, PartialEq, Debug)]
+        struct Mode: u16 {
+            const READ = 0x1;
+            const WRITE = 0x2;
+        }
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Open {
+        #[serde(with = "crate::bits")]
+        mode: Mode,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Strict {
+        #[serde(with = "crate::bits_strict")]
+        mode: Mode,
+    }
+
+    let o: Open = from_bytes_be(&[1, 1]).unwrap();
+    assert_eq!(o.mode, Mode::READ | Mode::from_bits_retain(0x100));
+    assert_eq!(o.mode.bits(), 0x101);
+
+    let s: Strict = from_bytes_be(&[0, 3]).unwrap();
+    assert_eq!(s.mode, Mode::READ | Mode::WRITE);
+    assert_eq!(
+        from_bytes_be::<Strict>(&[1, 1]),
+        Err(Error::Message("unknown flag bits 0x100".into()))
+    );
+}
+
+#[test]
+fn test_pad() {
+    use crate::Pad;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Header {
+        kind: u8,
+        reserved: Pad<3>,
+        len: u32,
+    }
+
+    assert_eq!(
+        from_bytes_be::<Header>(&[1, 9, 9, 9, 0, 0, 0, 2]).unwrap(),
+        Header {
+            kind: 1,
+            reserved: Pad,
+            len: 2,
+        }
+    );
+    assert_eq!(from_bytes_be::<Header>(&[1, 0, 0]), Err(Error::Eof));
+}
+
+#[test]
+fn test_from_reader() {
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Msg {
+        typ: u8,
+        name: String,
+    }
+
+    let input: &[u8] = &[1, b'h', b'i', 0];
+    assert_eq!(
+        from_reader_le::<Msg, _>(input).unwrap(),
+        Msg {
+            typ: 1,
+            name: "hi".into(),
+        }
+    );
+    assert_eq!(
+        from_reader_be::<Msg, _>(&[1u8, b'h'][..]),
+        Err(Error::MissingNullTerminator)
+    );
+    assert_eq!(from_reader_le::<Msg, _>(&[][..]), Err(Error::Eof));
+
+    /// Hands out its bytes, then fails as a socket left open would block.
+    struct Open<'a>(&'a [u8]);
+
+    impl io::Read for Open<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = self.0.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    assert_eq!(
+        from_reader_le::<Msg, _>(Open(&[2, b'o', b'k', 0])).unwrap(),
+        Msg {
+            typ: 2,
+            name: "ok".into(),
+        }
+    );
+}
+
+#[test]
+fn test_read_deserializer() {
+    use crate::ReadDeserializer;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Msg {
+        typ: u8,
+        name: String,
+        #[serde(with = "crate::vec_lv16")]
+        data: Vec<u16>,
+    }
+
+    /// Hands out one byte per read.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let b = [1, b'h', b'i', 0, 1, 0, 7, 0, 2, 0, 0, 0];
+    let mut r = ReadDeserializer::<_, LittleEndian>::new(Trickle(&b));
+    assert_eq!(
+        r.next_value::<Msg>().unwrap(),
+        Some(Msg {
+            typ: 1,
+            name: "hi".into(),
+            data: vec![7],
+        })
+    );
+    assert_eq!(
+        r.next_value::<Msg>().unwrap(),
+        Some(Msg {
+            typ: 2,
+            name: "".into(),
+            data: vec![],
+        })
+    );
+    assert_eq!(r.next_value::<Msg>(), Ok(None));
+
+    let mut r = ReadDeserializer::<_, LittleEndian>::new(&b[..10]);
+    assert!(r.next_value::<Msg>().unwrap().is_some());
+    assert_eq!(r.next_value::<Msg>(), Err(Error::Eof));
+
+    let mut r = ReadDeserializer::<_, LittleEndian>::new(&b[..9]);
+    assert!(r.next_value::<Msg>().unwrap().is_some());
+    assert_eq!(r.next_value::<Msg>(), Err(Error::MissingNullTerminator));
+
+    // A large field is read whole before decoding starts over.
+    static DECODES: AtomicUsize = AtomicUsize::new(0);
+
+    fn counted<'de, D>(d: D) -> std::result::Result<u8, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        DECODES.fetch_add(1, Ordering::Relaxed);
+        u8::deserialize(d)
+    }
 
-#[test]
-fn test_nested() {
     #[derive(Deserialize, PartialEq, Debug)]
-    struct Version {
-        size: u32,
+    struct Rread {
+        #[serde(deserialize_with = "counted")]
         typ: u8,
-        tag: u16,
-        msize: u32,
-        #[serde(with = "crate::str_lv64")]
-        version: String,
-        info: Info,
+        #[serde(with = "crate::bytes_lv32")]
+        data: Vec<u8>,
     }
 
-    #[derive(Deserialize, PartialEq, Debug)]
-    struct Info {
-        typ: u8,
-        version: u32,
-        path: u64,
+    /// Hands out up to 1000 bytes per read, and fails if read past its end
+    /// as a socket with nothing more to come would block.
+    struct Chunked<'a>(&'a [u8]);
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            assert!(!self.0.is_empty(), "read past the end of the message");
+            let n = self.0.len().min(buf.len()).min(1000);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
-        b'f', b'f', b'i', b'n', 3, 57, 48, 0, 0, 254, 91, 10, 0, 0, 0, 0, 0,
-    ];
+    let mut b = vec![117, 0x20, 0x4e, 0, 0];
+    b.resize(5 + 20000, 0xaa);
+    let mut r = ReadDeserializer::<_, LittleEndian>::new(Chunked(&b));
+    let m = r.next_value::<Rread>().unwrap().unwrap();
+    assert_eq!(m.data.len(), 20000);
+    assert_eq!(DECODES.load(Ordering::Relaxed), 2);
 
-    let expected = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
-        info: Info {
-            typ: 3,
-            version: 12345,
-            path: 678910,
-        },
-    };
+    // Running out within a sized field is malformed, not a reason to wait.
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Framed {
+        #[serde(with = "crate::sized_lv8")]
+        inner: u32,
+    }
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let mut r = ReadDeserializer::<_, LittleEndian>::new(Chunked(&[2, 1, 2]));
+    assert_eq!(r.next_value::<Framed>(), Err(Error::Eof));
 }
 
 #[test]
-fn test_struct_vec_lv8() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv8")]
-        pub data: Vec<Dirent>,
-    }
+#[cfg(feature = "bytes")]
+fn test_from_buf() {
+    use bytes::{Buf, Bytes};
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Msg {
+        typ: u8,
         #[serde(with = "crate::str_lv16")]
-        pub name: String,
+        name: String,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
-
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let m = Msg {
+        typ: 1,
+        name: "hello".into(),
     };
-
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let head = Bytes::from_static(&[1, 5, 0, b'h', b'e']);
+    let tail = Bytes::from_static(b"llo");
+    assert_eq!(from_buf_le::<Msg, _>(head.clone().chain(tail)).unwrap(), m);
+    assert_eq!(
+        from_buf_le::<Msg, _>(Bytes::from_static(b"\x01\x05\x00hello"))
+            .unwrap(),
+        m
+    );
+    assert_eq!(
+        from_buf_le::<Msg, _>(head.clone()),
+        Err(Error::LengthTooLarge {
+            len: 5,
+            remaining: 2
+        })
+    );
+
+    // The buffer is left just past the message, in either path.
+    let mut buf = Bytes::from_static(b"\x01\x05\x00hello\x07");
+    assert_eq!(from_buf_le::<Msg, _>(&mut buf).unwrap(), m);
+    assert_eq!(buf.chunk(), &[7]);
+    let mut buf = head.chain(Bytes::from_static(b"llo\x07\x08"));
+    assert_eq!(from_buf_le::<Msg, _>(&mut buf).unwrap(), m);
+    assert_eq!(buf.remaining(), 2);
+    assert_eq!(buf.chunk(), &[7, 8]);
 }
 
 #[test]
-fn test_struct_vec_lv16() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv16")]
-        pub data: Vec<Dirent>,
-    }
+fn test_from_slices() {
+    use std::io::IoSlice;
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Msg {
+        typ: u8,
+        name: String,
         #[serde(with = "crate::str_lv16")]
-        pub name: String,
+        data: String,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
-
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let m = Msg {
+        typ: 1,
+        name: "hi".into(),
+        data: "abc".into(),
     };
-
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let whole: &[u8] = &[1, b'h', b'i', 0, 3, 0, b'a', b'b', b'c'];
+    assert_eq!(from_slices_le::<Msg, _>(&[whole]).unwrap(), m);
+
+    let chunks: [&[u8]; 4] =
+        [&[1, b'h'], &[], &[b'i', 0, 3], &[0, b'a', b'b', b'c', 9]];
+    assert_eq!(from_slices_le::<Msg, _>(&chunks).unwrap(), m);
+
+    let slices: Vec<IoSlice> = chunks.iter().map(|c| IoSlice::new(c)).collect();
+    assert_eq!(from_slices_le::<Msg, _>(&slices).unwrap(), m);
+
+    assert_eq!(from_slices_le::<Msg, _>(&chunks[..3]), Err(Error::Eof));
+    assert_eq!(from_slices_le::<Msg, &[u8]>(&[]), Err(Error::Eof));
+
+    // Errors are those of decoding the chunks joined together.
+    let short: [&[u8]; 2] = [&[1, b'h'], &[b'i', 0, 9, 0, b'a']];
+    assert_eq!(
+        from_slices_le::<Msg, _>(&short),
+        from_bytes_le::<Msg>(&[1, b'h', b'i', 0, 9, 0, b'a'])
+    );
+    assert_eq!(
+        from_slices_le::<Msg, _>(&short),
+        Err(Error::LengthTooLarge {
+            len: 9,
+            remaining: 1
+        })
+    );
 }
 
 #[test]
-fn test_struct_vec_lv32() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv32")]
-        pub data: Vec<Dirent>,
+fn test_byte_range() {
+    use crate::{ByteRange, Lv16};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Rread {
+        tag: u16,
+        data: Lv16<ByteRange>,
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
+    let b = [1u8, 0, 4, 0, 9, 8, 7, 6, 5];
+    let r: Rread = from_bytes_le(&b).unwrap();
+    assert_eq!(r.data.0, ByteRange { offset: 4, len: 4 });
+    assert_eq!(r.data.range(), 4..8);
+    assert_eq!(r.data.get(&b), Some(&[9, 8, 7, 6][..]));
+
+    assert_eq!(
+        from_bytes_le::<Rread>(&b[..7]),
+        Err(Error::LengthTooLarge {
+            len: 4,
+            remaining: 3
+        })
+    );
+    assert!(crate::to_bytes_le(&r.data).is_err());
+}
+
+#[test]
+#[cfg(feature = "tokio")]
+fn test_tokio_message() {
+    use crate::tokio::{read_message_le, write_message_le};
+
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Msg {
+        size: u32,
+        tag: u16,
         #[serde(with = "crate::str_lv16")]
-        pub name: String,
+        name: String,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+    let msgs: Vec<Msg> = (0..20)
+        .map(|i| Msg {
+            size: 8 + i,
+            tag: i as u16,
+            name: "x".repeat(i as usize),
+        })
+        .collect();
 
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
-    };
+    async fn read(mut b: &[u8], max: usize) -> Result<Option<Msg>> {
+        read_message_le(&mut b, Width::U32, max).await
+    }
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let rt = ::tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        // A small pipe makes for short reads and partial writes.
+        let (mut a, mut b) = ::tokio::io::duplex(3);
+        let expected = msgs.iter().map(|m| crate::to_bytes_le(m).unwrap());
+        let expected: Vec<Vec<u8>> = expected.collect();
+        let writer = ::tokio::spawn(async move {
+            for m in &msgs {
+                write_message_le(&mut a, m).await.unwrap();
+            }
+        });
+        for e in &expected {
+            let m: Msg = read_message_le(&mut b, Width::U32, 1024)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&crate::to_bytes_le(&m).unwrap(), e);
+        }
+        writer.await.unwrap();
+        let end = read_message_le::<Msg, _>(&mut b, Width::U32, 1024).await;
+        assert_eq!(end, Ok(None));
+
+        let b: &[u8] = &[9, 0, 0, 0, 1, 0, 1, 0];
+        assert_eq!(read(b, 1024).await, Err(Error::Eof));
+        assert_eq!(read(&b[..2], 1024).await, Err(Error::Eof));
+        assert_eq!(
+            read(b, 8).await,
+            Err(Error::LengthOverflow { max: 8, actual: 9 })
+        );
+        assert_eq!(
+            read(&[3, 0, 0, 0], 8).await,
+            Err(Error::LengthUnderflow { min: 4, actual: 3 })
+        );
+    });
 }
 
 #[test]
-fn test_struct_vec_lv64() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv64")]
-        pub data: Vec<Dirent>,
+#[cfg(feature = "futures")]
+fn test_futures_message() {
+    use crate::futures::{read_message_be, write_message_be};
+    use ::futures::io::{AsyncRead, AsyncWrite};
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Moves at most three bytes at a time.
+    struct Trickle(Vec<u8>, usize);
+
+    impl AsyncRead for Trickle {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let n = buf.len().min(3).min(self.0.len() - self.1);
+            buf[..n].copy_from_slice(&self.0[self.1..self.1 + n]);
+            self.1 += n;
+            Poll::Ready(Ok(n))
+        }
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    impl AsyncWrite for Trickle {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let n = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, 0, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Msg {
+        size: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    let msgs = [
+        Msg {
+            size: 8,
+            name: "hello".into(),
+        },
+        Msg {
+            size: 3,
+            name: String::new(),
+        },
     ];
+    ::futures::executor::block_on(async {
+        let mut pipe = Trickle(Vec::new(), 0);
+        for m in &msgs {
+            write_message_be(&mut pipe, m).await.unwrap();
+        }
+        assert_eq!(pipe.0, b"\x00\x08\x05hello\x00\x03\x00");
+        for m in &msgs {
+            let r = read_message_be::<Msg, _>(&mut pipe, Width::U16, 64);
+            assert_eq!(r.await.unwrap().as_ref(), Some(m));
+        }
+        let r = read_message_be::<Msg, _>(&mut pipe, Width::U16, 64);
+        assert_eq!(r.await, Ok(None));
 
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+        let mut short = Trickle(vec![0, 8, 5, b'h'], 0);
+        let r = read_message_be::<Msg, _>(&mut short, Width::U16, 64);
+        assert_eq!(r.await, Err(Error::Eof));
+    });
+}
+
+#[test]
+#[cfg(feature = "codec")]
+fn test_codec() {
+    use crate::{BigEndian, IspfCodec};
+    use ::futures::{SinkExt, StreamExt};
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder, Framed};
+
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Msg {
+        size: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    let hello = Msg {
+        size: 8,
+        name: "hello".into(),
+    };
+    let mut codec = IspfCodec::<Msg, BigEndian>::new(Width::U16, 16);
+    let mut buf = BytesMut::from(&b"\x00"[..]);
+    assert_eq!(codec.decode(&mut buf), Ok(None));
+    buf.extend_from_slice(b"\x08\x05hel");
+    assert_eq!(codec.decode(&mut buf), Ok(None));
+    buf.extend_from_slice(b"lo\x00\x03");
+    assert_eq!(codec.decode(&mut buf), Ok(Some(hello.clone())));
+    assert_eq!(&buf[..], b"\x00\x03");
+
+    codec.encode(&hello, &mut buf).unwrap();
+    assert_eq!(&buf[..], b"\x00\x03\x00\x08\x05hello");
+    let long = Msg {
+        size: 18,
+        name: "x".repeat(15),
     };
+    assert_eq!(
+        codec.encode(long, &mut buf),
+        Err(Error::LengthOverflow {
+            max: 16,
+            actual: 18
+        })
+    );
+    assert_eq!(buf.len(), 10);
+    let wrong = Msg {
+        size: 7,
+        name: "hello".into(),
+    };
+    assert_eq!(
+        codec.encode(wrong, &mut buf),
+        Err(Error::SizeMismatch { size: 7, actual: 8 })
+    );
+    assert_eq!(buf.len(), 10);
+    let mut buf = BytesMut::from(&b"\x00\x11"[..]);
+    assert_eq!(
+        codec.decode(&mut buf),
+        Err(Error::LengthOverflow {
+            max: 16,
+            actual: 17
+        })
+    );
+
+    let rt = ::tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let (a, b) = ::tokio::io::duplex(64);
+        let mut a = Framed::new(a, codec.clone());
+        let mut b = Framed::new(b, codec);
+        a.send(hello.clone()).await.unwrap();
+        a.send(&hello).await.unwrap();
+        drop(a);
+        assert_eq!(b.next().await, Some(Ok(hello.clone())));
+        assert_eq!(b.next().await, Some(Ok(hello)));
+        assert_eq!(b.next().await, None);
+    });
+}
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+#[test]
+fn test_frames() {
+    use crate::{frames_be, frames_le, RawMessage};
+
+    let b = [7, 0, 0, 0, 100, 1, 0, 9, 0, 0, 0, 101, 2, 0, 0xab, 0xcd];
+    let all: Vec<&[u8]> =
+        frames_le(&b, Width::U32).map(Result::unwrap).collect();
+    assert_eq!(all, [&b[..7], &b[7..]]);
+    let m: RawMessage = from_bytes_le(all[1]).unwrap();
+    assert_eq!(&m.body[..], &[0xab, 0xcd]);
+
+    let mut it = frames_le(&b[..12], Width::U32);
+    assert_eq!(it.next(), Some(Ok(&b[..7])));
+    assert_eq!(
+        it.next(),
+        Some(Err(Error::LengthTooLarge {
+            len: 9,
+            remaining: 5
+        }))
+    );
+    assert_eq!(it.next(), None);
+    assert_eq!(it.remaining(), &b[7..12]);
+
+    let mut it = frames_le(&b[..9], Width::U32);
+    it.next();
+    assert_eq!(it.next(), Some(Err(Error::Eof)));
+
+    let b = [0, 2, 0, 1, 0];
+    let mut it = frames_be(&b, Width::U16);
+    assert_eq!(it.next(), Some(Ok(&b[..2])));
+    assert_eq!(
+        it.next(),
+        Some(Err(Error::LengthUnderflow { min: 2, actual: 1 }))
+    );
+    assert_eq!(it.next(), None);
+    assert_eq!(frames_be(&[], Width::U16).count(), 0);
 }
 
 #[test]
-fn test_struct_vec_lv8b() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv8b")]
-        pub data: Vec<Dirent>,
-    }
+fn test_batch() {
+    use crate::{
+        frames_le, from_bytes_batch_be, from_bytes_batch_le, to_bytes_batch_be,
+        to_bytes_batch_le,
+    };
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Msg<'a> {
+        tag: u16,
+        #[serde(borrow, with = "crate::str_lv8")]
+        name: &'a str,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    let msgs = [
+        Msg { tag: 1, name: "a" },
+        Msg { tag: 2, name: "" },
+        Msg { tag: 3, name: "cd" },
     ];
+    let b = to_bytes_batch_le(&msgs, Width::U16).unwrap();
+    assert_eq!(
+        b,
+        [
+            6, 0, 1, 0, 1, b'a', //
+            5, 0, 2, 0, 0, //
+            7, 0, 3, 0, 2, b'c', b'd',
+        ]
+    );
+    assert_eq!(frames_le(&b, Width::U16).count(), 3);
+    // Frames keep the size field the batch added.
+    assert_eq!(frames_le(&b, Width::U16).next(), Some(Ok(&b[..6])));
+    let back: Vec<Msg> = from_bytes_batch_le(&b, Width::U16)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(back, msgs);
+
+    let b = to_bytes_batch_be([1u32, 2], Width::U8).unwrap();
+    assert_eq!(b, [5, 0, 0, 0, 1, 5, 0, 0, 0, 2]);
+    let back: Vec<u32> = from_bytes_batch_be(&b, Width::U8)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(back, [1, 2]);
+    assert!(to_bytes_batch_le(Vec::<u8>::new(), Width::U32)
+        .unwrap()
+        .is_empty());
+
+    // A bad message is skipped; a bad size ends the batch.
+    let b = [3, 1, 0, 2, 7, 9, 0, 3];
+    let mut it = from_bytes_batch_le::<u16>(&b, Width::U8);
+    assert_eq!(it.next(), Some(Ok(1)));
+    assert_eq!(it.next(), Some(Err(Error::Eof)));
+    assert_eq!(
+        it.next(),
+        Some(Err(Error::LengthTooLarge {
+            len: 9,
+            remaining: 3
+        }))
+    );
+    assert_eq!(it.next(), None);
+    assert_eq!(it.remaining(), &b[5..]);
+
+    assert_eq!(
+        to_bytes_batch_le([vec![0u8; 255]], Width::U8),
+        Err(Error::LengthOverflow {
+            max: 255,
+            actual: 256
+        })
+    );
+}
 
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+#[test]
+fn test_ring() {
+    use crate::{ring_le, RingConsumer, RingProducer, RING_HEADER_LEN};
+
+    #[repr(C, align(64))]
+    struct Mem([u8; RING_HEADER_LEN + 32]);
+
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Msg {
+        tag: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    let msg = |tag, name: &str| Msg {
+        tag,
+        name: name.to_string(),
     };
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let mut mem = Mem([0xff; RING_HEADER_LEN + 32]);
+    let (mut tx, mut rx) = ring_le(&mut mem.0).unwrap();
+    assert!(rx.is_empty());
+    assert_eq!(rx.try_recv::<Msg>(), Ok(None));
+    assert_eq!(tx.free(), 32);
+
+    // Each takes 4 bytes of length and 8 of message, padded from 7.
+    assert_eq!(tx.try_send(&msg(1, "walk")), Ok(true));
+    assert_eq!(tx.try_send(&msg(2, "read")), Ok(true));
+    assert_eq!(tx.free(), 8);
+    assert_eq!(tx.try_send(&msg(3, "stat")), Ok(false));
+    assert_eq!(rx.try_recv(), Ok(Some(msg(1, "walk"))));
+
+    // The next message wraps round the end of the data area.
+    assert_eq!(tx.try_send(&msg(3, "stat")), Ok(true));
+    assert_eq!(rx.try_recv(), Ok(Some(msg(2, "read"))));
+    assert_eq!(rx.try_recv(), Ok(Some(msg(3, "stat"))));
+    assert_eq!(rx.try_recv::<Msg>(), Ok(None));
+    assert_eq!(
+        tx.try_send(&msg(4, "abcdefghijklmnopqrstuvwxyz")),
+        Err(Error::LengthOverflow {
+            max: 28,
+            actual: 29
+        })
+    );
+
+    // A message that fails to decode is still taken.
+    assert_eq!(tx.try_send(&[9u8]), Ok(true));
+    assert_eq!(tx.try_send(&msg(5, "")), Ok(true));
+    assert!(rx.try_recv::<Msg>().is_err());
+    assert_eq!(rx.try_recv(), Ok(Some(msg(5, ""))));
+
+    assert_eq!(
+        ring_le(&mut mem.0[..RING_HEADER_LEN + 24]).err(),
+        Some(Error::InvalidRing {
+            len: RING_HEADER_LEN + 24
+        })
+    );
+    assert_eq!(
+        ring_le(&mut mem.0[1..RING_HEADER_LEN + 17]).err(),
+        Some(Error::InvalidRing {
+            len: RING_HEADER_LEN + 16
+        })
+    );
+
+    // Indices or a length no producer could have written are refused.
+    let mut mem = Mem([0; RING_HEADER_LEN + 32]);
+    let p = mem.0.as_mut_ptr();
+    let len = mem.0.len();
+    let mut tx =
+        unsafe { RingProducer::<LittleEndian>::from_raw(p, len) }.unwrap();
+    let mut rx =
+        unsafe { RingConsumer::<LittleEndian>::from_raw(p, len) }.unwrap();
+    let corrupt = Error::InvalidRing {
+        len: RING_HEADER_LEN + 32,
+    };
+    unsafe { p.cast::<u32>().write(40) };
+    assert_eq!(rx.try_recv::<Msg>(), Err(corrupt.clone()));
+    assert_eq!(tx.try_send(&msg(1, "walk")), Err(corrupt));
+    unsafe {
+        p.cast::<u32>().write(8);
+        p.add(RING_HEADER_LEN).cast::<u32>().write(0xffff_fff0);
+    }
+    assert_eq!(
+        rx.try_recv::<Msg>(),
+        Err(Error::LengthTooLarge {
+            len: 0xffff_fff0,
+            remaining: 28
+        })
+    );
+    unsafe { p.add(RING_HEADER_LEN).cast::<u32>().write(8) };
+    assert_eq!(
+        rx.try_recv::<Msg>(),
+        Err(Error::LengthTooLarge {
+            len: 8,
+            remaining: 4
+        })
+    );
+
+    // Across threads, with the ends made from the raw region.
+    let mut mem = Mem([0; RING_HEADER_LEN + 32]);
+    let p = mem.0.as_mut_ptr();
+    let len = mem.0.len();
+    let mut tx =
+        unsafe { RingProducer::<LittleEndian>::from_raw(p, len) }.unwrap();
+    let mut rx =
+        unsafe { RingConsumer::<LittleEndian>::from_raw(p, len) }.unwrap();
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..1000u16 {
+                while !tx.try_send(&msg(i, "x")).unwrap() {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        for i in 0..1000u16 {
+            loop {
+                if let Some(m) = rx.try_recv::<Msg>().unwrap() {
+                    assert_eq!(m, msg(i, "x"));
+                    break;
+                }
+                std::thread::yield_now();
+            }
+        }
+    });
 }
 
 #[test]
-fn test_struct_vec_lv16b() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv16b")]
-        pub data: Vec<Dirent>,
+fn test_frame_accumulator() {
+    use crate::{Accumulated, FrameAccumulator};
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Msg {
+        size: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    let mut acc = FrameAccumulator::<LittleEndian>::new(Width::U16, 32);
+    assert_eq!(acc.next_frame(), Ok(Accumulated::Incomplete(2)));
+    acc.feed(&[6]);
+    assert_eq!(acc.next_frame(), Ok(Accumulated::Incomplete(1)));
+    acc.feed(&[0, 3, b'a']);
+    assert_eq!(acc.next_message::<Msg>(), Ok(Accumulated::Incomplete(2)));
+    acc.feed(&[b'b', b'c', 3, 0, 0, 4]);
+    assert_eq!(
+        acc.next_message(),
+        Ok(Accumulated::Complete(Msg {
+            size: 6,
+            name: "abc".into()
+        }))
+    );
+    assert_eq!(
+        acc.next_message(),
+        Ok(Accumulated::Complete(Msg {
+            size: 3,
+            name: String::new()
+        }))
+    );
+    assert_eq!(acc.buffered(), 1);
+    assert_eq!(acc.next_frame(), Ok(Accumulated::Incomplete(1)));
+    acc.feed(&[1]);
+    assert_eq!(
+        acc.next_frame(),
+        Err(Error::LengthOverflow {
+            max: 32,
+            actual: 260
+        })
+    );
+    assert_eq!(acc.buffered(), 2);
+}
+
+#[test]
+fn test_message_reader() {
+    use crate::{MessageReader, MessageWriter};
+    use std::io::Read;
+
+    #[derive(serde::Serialize, Deserialize, PartialEq, Debug)]
+    struct Msg {
+        size: u32,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+    /// A reader handing out two bytes at a time.
+    struct Trickle<'a>(&'a [u8]);
 
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
-    };
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(2);
+            self.0.read(&mut buf[..n])
+        }
+    }
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    let msgs = ["one", "", "three"].iter().map(|s| Msg {
+        size: 5 + s.len() as u32,
+        name: s.to_string(),
+    });
+    let mut w = MessageWriter::<_, Msg, BigEndian>::new(Vec::new());
+    for m in msgs {
+        w.write(&m).unwrap();
+    }
+    w.flush().unwrap();
+    let mut b = w.into_inner();
+    assert_eq!(&b[..8], &[0, 0, 0, 8, 3, b'o', b'n', b'e']);
+
+    let r =
+        MessageReader::<_, Msg, BigEndian>::new(Trickle(&b), Width::U32, 64);
+    let names: Vec<String> = r.map(|m| m.unwrap().name).collect();
+    assert_eq!(names, ["one", "", "three"]);
+
+    // A bad string length fails that message only.
+    b[4] = 9;
+    let mut r =
+        MessageReader::<_, Msg, BigEndian>::new(Trickle(&b), Width::U32, 64);
+    assert!(r.next().unwrap().is_err());
+    assert_eq!(r.next().unwrap().unwrap().name, "");
+
+    let mut r = MessageReader::<_, Msg, BigEndian>::new(
+        Trickle(&b[..16]),
+        Width::U32,
+        64,
+    );
+    r.next();
+    r.next();
+    assert_eq!(r.next(), Some(Err(Error::Eof)));
+    assert_eq!(r.next(), None);
 }
 
 #[test]
-fn test_struct_vec_lv32b() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv32b")]
-        pub data: Vec<Dirent>,
+#[cfg(feature = "futures")]
+fn test_message_stream() {
+    use crate::futures::MessageStream;
+    use ::futures::io::AsyncRead;
+    use ::futures::StreamExt;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Hands out at most five bytes at a time.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl AsyncRead for Trickle<'_> {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let n = buf.len().min(5).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Poll::Ready(Ok(n))
+        }
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Msg {
+        size: u8,
+        tag: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    let b = [
+        4, 1, 0, 0, //
+        10, 2, 0, 6, b'w', b'a', b'l', b'k', b'e', b'd', //
+        6, 3, 0, 9, b'x', b'x', //
+        4, 4, 0, 0, //
+        9, 5, 0,
     ];
-
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
-    };
-
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+    ::futures::executor::block_on(async {
+        let mut s = MessageStream::<_, Msg, LittleEndian>::new(
+            Trickle(&b),
+            Width::U8,
+            64,
+        );
+        assert_eq!(s.next().await.unwrap().unwrap().tag, 1);
+        assert_eq!(s.next().await.unwrap().unwrap().name, "walked");
+        assert!(s.next().await.unwrap().is_err());
+        assert_eq!(s.next().await.unwrap().unwrap().tag, 4);
+        assert_eq!(s.next().await, Some(Err(Error::Eof)));
+        assert_eq!(s.next().await, None);
+
+        let s = MessageStream::<_, Msg, LittleEndian>::new(
+            Trickle(&b[..20]),
+            Width::U8,
+            64,
+        );
+        let tags: Vec<u16> = s.map(|m| m.map_or(0, |m| m.tag)).collect().await;
+        assert_eq!(tags, [1, 2, 0]);
+    });
 }
 
 #[test]
-fn test_struct_vec_lv64b() {
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv64b")]
-        pub data: Vec<Dirent>,
+#[cfg(feature = "futures")]
+fn test_tag_matcher() {
+    use crate::futures::{MessageTag, TagMatcher};
+    use ::futures::{stream, SinkExt};
+
+    #[derive(Debug, PartialEq)]
+    struct Msg {
+        tag: u16,
+        body: &'static str,
     }
 
-    #[derive(Debug, Deserialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    impl MessageTag for Msg {
+        fn tag(&self) -> u16 {
+            self.tag
+        }
+
+        fn set_tag(&mut self, tag: u16) {
+            self.tag = tag;
+        }
     }
 
-    let b = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, 0, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    fn msg(tag: u16, body: &'static str) -> Msg {
+        Msg { tag, body }
+    }
+
+    ::futures::executor::block_on(async {
+        let m = TagMatcher::<Msg, Msg>::new();
+        let mut sent = Vec::new();
+        let mut sink = (&mut sent).sink_map_err(|e| match e {});
+        let walk = m.send(&mut sink, msg(0, "walk")).await.unwrap();
+        let read = m.send(&mut sink, msg(0, "read")).await.unwrap();
+        let stat = m.send(&mut sink, msg(0, "stat")).await.unwrap();
+        assert_eq!(sent.iter().map(|m| m.tag).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(m.pending(), 3);
+
+        // Replies come back out of order, and one is no longer awaited.
+        drop(stat);
+        m.dispatch(msg(1, "data")).unwrap();
+        m.dispatch(msg(2, "gone")).unwrap();
+        assert_eq!(m.dispatch(msg(9, "what")), Err(Error::UnknownTag(9)));
+        m.dispatch(msg(0, "qids")).unwrap();
+        assert_eq!(read.await, Ok(msg(1, "data")));
+        assert_eq!(walk.await, Ok(msg(0, "qids")));
+        assert_eq!(m.pending(), 0);
+
+        // Tags carry on from the last one handed out, skipping those in use.
+        let (t, _a) = m.start().unwrap();
+        assert_eq!(t, 3);
+        m.cancel(3);
+
+        // Requests still waiting when the replies end get Eof.
+        let mut sent = Vec::new();
+        let mut sink = (&mut sent).sink_map_err(|e| match e {});
+        let open = m.send(&mut sink, msg(0, "open")).await.unwrap();
+        let clunk = m.send(&mut sink, msg(0, "clunk")).await.unwrap();
+        let replies = stream::iter(vec![Ok(msg(5, "done"))]);
+        assert_eq!(m.run(replies).await, Ok(()));
+        assert_eq!(clunk.await, Ok(msg(5, "done")));
+        assert_eq!(open.await, Err(Error::Eof));
+        assert_eq!(m.pending(), 0);
+
+        // Unknown tags and undecodable replies are skipped; only a failing
+        // connection stops the run.
+        let mut sent = Vec::new();
+        let mut sink = (&mut sent).sink_map_err(|e| match e {});
+        let read = m.send(&mut sink, msg(0, "read")).await.unwrap();
+        let write = m.send(&mut sink, msg(0, "write")).await.unwrap();
+        let (r, w) = (sent[0].tag, sent[1].tag);
+        let reset = Error::io(io::ErrorKind::ConnectionReset.into());
+        let replies = stream::iter(vec![
+            Ok(msg(99, "stray")),
+            Err(Error::UnknownMessageType(200)),
+            Ok(msg(r, "data")),
+            Err(reset.clone()),
+            Ok(msg(w, "count")),
+        ]);
+        assert_eq!(m.run(replies).await, Err(reset));
+        assert_eq!(read.await, Ok(msg(r, "data")));
+        assert_eq!(write.await, Err(Error::Eof));
+    });
+
+    // A bad size or a cut short message ends a MessageStream, and the run
+    // with it.
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Rep {
+        size: u32,
+        tag: u16,
+    }
+
+    impl MessageTag for Rep {
+        fn tag(&self) -> u16 {
+            self.tag
+        }
+
+        fn set_tag(&mut self, tag: u16) {
+            self.tag = tag;
+        }
+    }
+
+    ::futures::executor::block_on(async {
+        use crate::futures::MessageStream;
+
+        let m = TagMatcher::<Rep, Rep>::new();
+        let (_, rx) = m.start().unwrap();
+        let b: &[u8] = &[3, 0, 0, 0, 0, 0, 0];
+        let s = MessageStream::<_, Rep, LittleEndian>::new(b, Width::U32, 64);
+        assert_eq!(
+            m.run(s).await,
+            Err(Error::LengthUnderflow { min: 4, actual: 3 })
+        );
+        drop(rx);
+
+        let b: &[u8] = &[6, 0, 0, 0, 0, 0, 9, 0, 0, 0, 0];
+        let s = MessageStream::<_, Rep, LittleEndian>::new(b, Width::U32, 64);
+        assert_eq!(m.run(s).await, Err(Error::Eof));
+
+        // A message too short for its fields is only skipped.
+        let (t, rx) = m.start().unwrap();
+        let b = [5, 0, 0, 0, 0, 6, 0, 0, 0, t as u8, (t >> 8) as u8];
+        let s =
+            MessageStream::<_, Rep, LittleEndian>::new(&b[..], Width::U32, 64);
+        assert_eq!(m.run(s).await, Ok(()));
+        assert_eq!(rx.await, Ok(Rep { size: 6, tag: t }));
+    });
+
+    let m = TagMatcher::<Msg, Msg>::new();
+    let held: Vec<_> = (0..0xffff).map(|_| m.start().unwrap()).collect();
+    assert!(held.iter().all(|(t, _)| *t != 0xffff));
+    assert_eq!(m.start().err(), Some(Error::NoFreeTag));
+    m.cancel(7);
+    assert_eq!(m.start().unwrap().0, 7);
+}
+
+#[test]
+#[cfg(feature = "p9")]
+fn test_p9() {
+    use crate::p9::*;
+    use crate::{to_bytes_le, FixedSize};
+
+    let b = [
+        19, 0, 0, 0, 100, 0xff, 0xff, 0, 0x20, 0, 0, 6, 0, b'9', b'P', b'2',
+        b'0', b'0', b'0',
     ];
+    let m = Message::new(
+        NOTAG,
+        Tversion {
+            msize: 8192,
+            version: VERSION.into(),
+        },
+    );
+    assert_eq!(to_bytes_le(&m).unwrap(), b);
+    let back: Message = from_bytes_le(&b).unwrap();
+    assert_eq!(back.size, 19);
+    assert_eq!(back.body, m.body);
+
+    let m = Message::new(
+        1,
+        Twalk {
+            fid: 0,
+            newfid: 1,
+            wnames: vec!["usr".into(), "glenda".into()],
+        },
+    );
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(
+        b,
+        [
+            30, 0, 0, 0, 110, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 3, 0, b'u',
+            b's', b'r', 6, 0, b'g', b'l', b'e', b'n', b'd', b'a',
+        ]
+    );
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+
+    let m = Message::new(
+        2,
+        Rwalk {
+            qids: vec![Qid {
+                typ: QTDIR,
+                version: 0,
+                path: 7,
+            }],
+        },
+    );
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(b.len(), HEADER_LEN + 2 + Qid::WIRE_SIZE);
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+
+    let b = [13, 0, 0, 0, 117, 3, 0, 2, 0, 0, 0, b'h', b'i'];
+    let m: Message = from_bytes_le(&b).unwrap();
+    assert_eq!(
+        m.body,
+        Body::Rread(Rread {
+            data: b"hi".to_vec()
+        })
+    );
+    assert_eq!(m.tag, 3);
+
+    // The data of a read or write is sent from where it is.
+    let v = crate::to_vectored_le(&m).unwrap();
+    assert_eq!(v.head(), &b[..11]);
+    assert_eq!(v.payload(), Some(&b"hi"[..]));
+    let w = Message::new(
+        4,
+        Twrite {
+            fid: 1,
+            offset: 0,
+            data: vec![7; 3],
+        },
+    );
+    let (head, payload) = crate::to_parts_le(&w).unwrap();
+    assert_eq!(head.len(), HEADER_LEN + 4 + 8 + 4);
+    assert_eq!(payload, Some(&[7, 7, 7][..]));
+    assert_eq!(crate::Payload::payload(&Message::new(5, Rclunk)), None);
+
+    let b = [7, 0, 0, 0, 121, 4, 0];
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, Rclunk.into());
+    assert_eq!(to_bytes_le(&Message::new(4, Rclunk)).unwrap(), b);
+
+    let stat = Stat {
+        qid: Qid {
+            typ: QTFILE,
+            version: 1,
+            path: 2,
+        },
+        mode: 0o644,
+        length: 5,
+        name: "hello".into(),
+        uid: "glenda".into(),
+        gid: "glenda".into(),
+        muid: "".into(),
+        ..Stat::default()
+    };
+    let m = Message::new(5, Rstat { stat: stat.clone() });
+    let b = to_bytes_le(&m).unwrap();
+    // The stat's own size excludes itself, and the outer one includes it.
+    let n = 2 + 4 + 13 + 4 * 3 + 8 + 2 * 4 + 5 + 6 + 6;
+    assert_eq!(b.len(), HEADER_LEN + 4 + n);
+    assert_eq!(&b[7..11], [n as u8 + 2, 0, n as u8, 0]);
+    assert_eq!(
+        from_bytes_le::<Message>(&b).unwrap().body,
+        Body::Rstat(Rstat { stat })
+    );
+
+    // A type with no message, and a size that disagrees with the input.
+    assert!(from_bytes_le::<Message>(&[7, 0, 0, 0, 106, 0, 0]).is_err());
+    assert!(from_bytes_le::<Message>(&[9, 0, 0, 0, 121, 0, 0]).is_err());
+}
 
-    let expected = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
+#[test]
+#[cfg(feature = "p9")]
+fn test_p9l() {
+    use crate::p9l::*;
+    use crate::to_bytes_le;
+
+    let b = [15, 0, 0, 0, 12, 1, 0, 3, 0, 0, 0, 0x02, 0x80, 0, 0];
+    let m = Message::new(
+        1,
+        Tlopen {
+            fid: 3,
+            flags: 0x8002,
+        },
+    );
+    assert_eq!(to_bytes_le(&m).unwrap(), b);
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+
+    let m = Message::new(
+        2,
+        Tattach {
+            fid: 0,
+            afid: NOFID,
+            uname: "root".into(),
+            aname: "".into(),
+            n_uname: 0,
+        },
+    );
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(b.len(), 7 + 4 + 4 + 6 + 2 + 4);
+    assert_eq!(b[4], 104);
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+
+    // Entries are counted in bytes, and each ends with its name.
+    let entries = vec![
+        Dirent {
+            qid: Qid {
+                typ: 0x80,
+                version: 0,
+                path: 1,
             },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
+            offset: 1,
+            typ: 4,
+            name: ".".into(),
+        },
+        Dirent {
+            qid: Qid {
+                typ: 0,
+                version: 0,
+                path: 2,
             },
-        ],
+            offset: 2,
+            typ: 8,
+            name: "file".into(),
+        },
+    ];
+    let m = Message::new(3, Rreaddir { entries });
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(b[4], 41);
+    assert_eq!(&b[7..11], [25 + 28, 0, 0, 0]);
+    assert_eq!(&b[11 + 22..11 + 25], [1, 0, b'.']);
+    assert_eq!(b.len(), 11 + 25 + 28);
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+
+    let b = [11, 0, 0, 0, 7, 4, 0, 2, 0, 0, 0];
+    assert_eq!(
+        from_bytes_le::<Message>(&b).unwrap().body,
+        Body::Rlerror(Rlerror { ecode: 2 })
+    );
+
+    let m = Message::new(
+        NOTAG,
+        Tversion {
+            msize: 8192,
+            version: VERSION.into(),
+        },
+    );
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(
+        from_bytes_le::<Message>(&b).unwrap(),
+        Message { size: 21, ..m }
+    );
+
+    let b = to_bytes_le(&Message::new(5, Rgetattr::default())).unwrap();
+    assert_eq!(b.len(), 7 + 8 + 13 + 4 * 3 + 8 * 15);
+
+    // 9P2000's Topen has no place in 9P2000.L.
+    assert!(
+        from_bytes_le::<Message>(&[12, 0, 0, 0, 112, 0, 0, 1, 0, 0, 0, 0])
+            .is_err()
+    );
+}
+
+#[test]
+#[cfg(feature = "p9")]
+fn test_p9u() {
+    use crate::p9u::*;
+    use crate::{p9, to_bytes_le};
+
+    let m = Message::new(
+        1,
+        Tcreate {
+            fid: 2,
+            name: "null".into(),
+            perm: DMDEVICE | 0o666,
+            mode: 0,
+            extension: "c 1 3".into(),
+        },
+    );
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(b.len(), 7 + 4 + 6 + 4 + 1 + 7);
+    assert_eq!(&b[b.len() - 7..], [5, 0, b'c', b' ', b'1', b' ', b'3']);
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+
+    // Plain 9P2000 reads the same bytes without the extension, leaving it
+    // over, which the size check catches.
+    assert!(from_bytes_le::<p9::Message>(&b).is_err());
+
+    let b = [
+        16, 0, 0, 0, 107, 3, 0, 3, 0, b'b', b'a', b'd', 0x16, 0, 0, 0,
+    ];
+    assert_eq!(
+        from_bytes_le::<Message>(&b).unwrap().body,
+        Body::Rerror(Rerror {
+            ename: "bad".into(),
+            errno: 22
+        })
+    );
+
+    let stat = Stat {
+        qid: Qid {
+            typ: QTSYMLINK,
+            version: 0,
+            path: 9,
+        },
+        mode: DMSYMLINK | 0o777,
+        name: "lib".into(),
+        uid: "root".into(),
+        gid: "root".into(),
+        extension: "usr/lib".into(),
+        n_uid: 0,
+        n_gid: 0,
+        n_muid: NONUNAME,
+        ..Stat::default()
     };
+    let m = Message::new(4, Rstat { stat: stat.clone() });
+    let b = to_bytes_le(&m).unwrap();
+    let n = 2 + 4 + 13 + 4 * 3 + 8 + 2 * 5 + 3 + 4 + 4 + 7 + 4 * 3;
+    assert_eq!(&b[7..11], [n as u8 + 2, 0, n as u8, 0]);
+    assert_eq!(b.len(), 11 + n);
+    assert_eq!(
+        from_bytes_le::<Message>(&b).unwrap().body,
+        Body::Rstat(Rstat { stat })
+    );
+
+    let m = Message::new(
+        5,
+        Tattach {
+            fid: 0,
+            afid: NOFID,
+            uname: "".into(),
+            aname: "".into(),
+            n_uname: 1000,
+        },
+    );
+    let b = to_bytes_le(&m).unwrap();
+    assert_eq!(&b[b.len() - 4..], [0xe8, 3, 0, 0]);
+    assert_eq!(from_bytes_le::<Message>(&b).unwrap().body, m.body);
+}
 
-    assert_eq!(expected, from_bytes_le(b.as_slice()).unwrap());
+#[test]
+fn test_tagged() {
+    use crate::Tagged;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Record {
+        a: Tagged<1, u16>,
+        b: Tagged<2, (u8, u8)>,
+    }
+
+    let r: Record = from_bytes_be(&[1, 1, 2, 2, 3, 4]).unwrap();
+    assert_eq!(*r.a, 0x0102);
+    assert_eq!(r.b.into_inner(), (3, 4));
+    assert_eq!(
+        from_bytes_be::<Record>(&[1, 1, 2, 7, 3, 4]),
+        Err(Error::UnexpectedType {
+            expected: 2,
+            actual: 7
+        })
+    );
 }
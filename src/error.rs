@@ -4,7 +4,9 @@
 
 // Copyright 2022 Oxide Computer Company
 
+use std::cell::Cell;
 use std::fmt::{self, Display};
+use std::io;
 
 use serde::{de, ser};
 
@@ -23,6 +25,62 @@ pub enum Error {
     ExpectedArray,
     ExpectedEnum,
     TrailingBytes,
+    LengthOverflow {
+        max: usize,
+        actual: usize,
+    },
+    LengthUnderflow {
+        min: usize,
+        actual: usize,
+    },
+    MissingNullTerminator,
+    InteriorNul,
+    LengthTooLarge {
+        len: usize,
+        remaining: usize,
+    },
+    InvalidPresenceFlag(u8),
+    DuplicateElement,
+    OffsetOutOfRange {
+        offset: usize,
+        len: usize,
+    },
+    UnknownMessageType(u8),
+    UnexpectedType {
+        expected: u8,
+        actual: u8,
+    },
+    SizeMismatch {
+        size: usize,
+        actual: usize,
+    },
+    BadMagic {
+        expected: u64,
+        actual: u64,
+    },
+    ChecksumMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The message does not fit in the buffer given to write it into.
+    BufferTooSmall {
+        capacity: usize,
+    },
+    /// Every tag is held by a request still waiting for its reply.
+    NoFreeTag,
+    /// A reply carries a tag no request is waiting on.
+    UnknownTag(u16),
+    /// A shared-memory ring's region has the wrong length or alignment, or
+    /// its indices are further apart than its data area holds.
+    InvalidRing {
+        len: usize,
+    },
+    /// Reading or writing failed. The I/O error is kept as its kind and
+    /// message so that `Error` stays comparable.
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+    },
 }
 
 impl ser::Error for Error {
@@ -31,9 +89,30 @@ impl ser::Error for Error {
     }
 }
 
+thread_local! {
+    /// An error being handed to `de::Error::custom` by `Error::into_de`.
+    static HANDOFF: Cell<Option<Error>> = const { Cell::new(None) };
+}
+
+impl Error {
+    /// This error as the error type of whichever deserializer is in use.
+    /// Visitors only see that type, and can only build it from a message;
+    /// an ispf deserializer gets the error back whole, so that callers can
+    /// match on it, and any other gets its message.
+    pub(crate) fn into_de<E: de::Error>(self) -> E {
+        let msg = self.to_string();
+        HANDOFF.with(|h| h.set(Some(self)));
+        let e = E::custom(msg);
+        HANDOFF.with(|h| h.take());
+        e
+    }
+}
+
 impl de::Error for Error {
     fn custom<T: Display>(msg: T) -> Self {
-        Error::Message(msg.to_string())
+        HANDOFF
+            .with(|h| h.take())
+            .unwrap_or_else(|| Error::Message(msg.to_string()))
     }
 }
 
@@ -54,8 +133,95 @@ impl Display for Error {
             Error::TrailingBytes => {
                 formatter.write_str("unexpected trailing bytes")
             }
+            Error::LengthOverflow { max, actual } => write!(
+                formatter,
+                "length {} exceeds the maximum of {}",
+                actual, max
+            ),
+            Error::LengthUnderflow { min, actual } => write!(
+                formatter,
+                "length {} is less than the minimum of {}",
+                actual, min
+            ),
+            Error::MissingNullTerminator => {
+                formatter.write_str("missing null terminator")
+            }
+            Error::InteriorNul => {
+                formatter.write_str("null byte inside null-terminated string")
+            }
+            Error::LengthTooLarge { len, remaining } => write!(
+                formatter,
+                "declared length {} exceeds the {} bytes remaining",
+                len, remaining
+            ),
+            Error::InvalidPresenceFlag(flag) => {
+                write!(formatter, "invalid option presence flag {:#04x}", flag)
+            }
+            Error::DuplicateElement => {
+                formatter.write_str("duplicate element in set")
+            }
+            Error::OffsetOutOfRange { offset, len } => write!(
+                formatter,
+                "offset {} is past the end of a {} byte value",
+                offset, len
+            ),
+            Error::UnknownMessageType(typ) => {
+                write!(formatter, "unknown message type {}", typ)
+            }
+            Error::UnexpectedType { expected, actual } => write!(
+                formatter,
+                "expected type {} but found {}",
+                expected, actual
+            ),
+            Error::SizeMismatch { size, actual } => write!(
+                formatter,
+                "size field holds {} but the message is {} bytes",
+                size, actual
+            ),
+            Error::BadMagic { expected, actual } => write!(
+                formatter,
+                "expected magic {:#x} but found {:#x}",
+                expected, actual
+            ),
+            Error::ChecksumMismatch { expected, actual } => write!(
+                formatter,
+                "expected checksum {:#x} but found {:#x}",
+                expected, actual
+            ),
+            Error::BufferTooSmall { capacity } => write!(
+                formatter,
+                "message does not fit in a buffer of {} bytes",
+                capacity
+            ),
+            Error::NoFreeTag => {
+                formatter.write_str("every tag is held by a pending request")
+            }
+            Error::UnknownTag(tag) => {
+                write!(formatter, "no request is waiting on tag {}", tag)
+            }
+            Error::InvalidRing { len } => write!(
+                formatter,
+                "a region of {} bytes does not hold a valid ring",
+                len
+            ),
+            Error::Io { message, .. } => formatter.write_str(message),
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+impl Error {
+    pub(crate) fn io(e: io::Error) -> Self {
+        Error::Io {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::io(e)
+    }
+}
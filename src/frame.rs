@@ -0,0 +1,585 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::convert::TryFrom;
+use std::io;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::de::NumDe;
+use crate::error::{Error, Result};
+use crate::ser::NumSer;
+use crate::{BigEndian, LittleEndian, Width};
+
+/// The size of a message held in the `width`-byte field at the start of
+/// `head`, counting the whole message, field included. A size too small to
+/// hold the field itself, or larger than `max`, is rejected.
+pub(crate) fn frame_size<Endian: NumDe>(
+    head: &[u8],
+    width: Width,
+    max: usize,
+) -> Result<usize> {
+    let size = match width {
+        Width::U8 => u64::from(head[0]),
+        Width::U16 => u64::from(Endian::deserialize_u16([head[0], head[1]])),
+        Width::U32 => u64::from(Endian::deserialize_u32([
+            head[0], head[1], head[2], head[3],
+        ])),
+        Width::U64 => {
+            let mut b = [0; 8];
+            b.copy_from_slice(&head[..8]);
+            Endian::deserialize_u64(b)
+        }
+    };
+    let size = usize::try_from(size).unwrap_or(usize::MAX);
+    if size < width.size() {
+        return Err(Error::LengthUnderflow {
+            min: width.size(),
+            actual: size,
+        });
+    }
+    if size > max {
+        return Err(Error::LengthOverflow { max, actual: size });
+    }
+    Ok(size)
+}
+
+/// The `read_message` and `write_message` functions of `ispf::tokio` and
+/// `ispf::futures`, written once for both. The caller brings its runtime's
+/// `AsyncRead`, `AsyncWrite` and their extension traits into scope.
+#[cfg(any(feature = "tokio", feature = "futures"))]
+macro_rules! message_io {
+    () => {
+        /// Read one message whose size is held in a leading field of the
+        /// given width, or return `None` if `r` ends before it starts. A
+        /// message larger than `max_size` is rejected before any more of it
+        /// is read. Reads that return less than asked for are continued, and
+        /// a reader ending part way through a message fails with
+        /// `Error::Eof`.
+        pub async fn read_message<Endian, T, R>(
+            r: &mut R,
+            width: $crate::Width,
+            max_size: usize,
+        ) -> $crate::Result<Option<T>>
+        where
+            Endian: $crate::de::NumDe,
+            T: serde::de::DeserializeOwned,
+            R: AsyncRead + Unpin,
+        {
+            use $crate::Error;
+
+            let mut head = [0; 8];
+            let head = &mut head[..width.size()];
+            let mut n = 0;
+            while n < head.len() {
+                match r.read(&mut head[n..]).await.map_err(Error::io)? {
+                    0 if n == 0 => return Ok(None),
+                    0 => return Err(Error::Eof),
+                    k => n += k,
+                }
+            }
+            let size =
+                $crate::frame::frame_size::<Endian>(head, width, max_size)?;
+            let mut b = vec![0; size];
+            b[..n].copy_from_slice(head);
+            r.read_exact(&mut b[n..])
+                .await
+                .map_err(|e| match e.kind() {
+                    std::io::ErrorKind::UnexpectedEof => Error::Eof,
+                    _ => Error::io(e),
+                })?;
+            $crate::from_bytes::<Endian, T>(&b).map(Some)
+        }
+
+        pub async fn read_message_le<T, R>(
+            r: &mut R,
+            width: $crate::Width,
+            max_size: usize,
+        ) -> $crate::Result<Option<T>>
+        where
+            T: serde::de::DeserializeOwned,
+            R: AsyncRead + Unpin,
+        {
+            read_message::<$crate::LittleEndian, T, R>(r, width, max_size).await
+        }
+
+        pub async fn read_message_be<T, R>(
+            r: &mut R,
+            width: $crate::Width,
+            max_size: usize,
+        ) -> $crate::Result<Option<T>>
+        where
+            T: serde::de::DeserializeOwned,
+            R: AsyncRead + Unpin,
+        {
+            read_message::<$crate::BigEndian, T, R>(r, width, max_size).await
+        }
+
+        /// Serialize `value` and write all of it to `w`, continuing after
+        /// partial writes, then flush `w`.
+        pub async fn write_message<Endian, T, W>(
+            w: &mut W,
+            value: &T,
+        ) -> $crate::Result<()>
+        where
+            Endian: $crate::ser::NumSer,
+            T: serde::Serialize,
+            W: AsyncWrite + Unpin,
+        {
+            let b = $crate::to_bytes::<Endian, T>(value)?;
+            w.write_all(&b).await.map_err($crate::Error::io)?;
+            w.flush().await.map_err($crate::Error::io)
+        }
+
+        pub async fn write_message_le<T, W>(
+            w: &mut W,
+            value: &T,
+        ) -> $crate::Result<()>
+        where
+            T: serde::Serialize,
+            W: AsyncWrite + Unpin,
+        {
+            write_message::<$crate::LittleEndian, T, W>(w, value).await
+        }
+
+        pub async fn write_message_be<T, W>(
+            w: &mut W,
+            value: &T,
+        ) -> $crate::Result<()>
+        where
+            T: serde::Serialize,
+            W: AsyncWrite + Unpin,
+        {
+            write_message::<$crate::BigEndian, T, W>(w, value).await
+        }
+    };
+}
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub(crate) use message_io;
+
+/// An iterator over the back-to-back messages in a buffer, each starting with
+/// a field holding its whole size, made by `frames`.
+///
+/// Each item is one message, size field included. A size too small to hold
+/// the field itself, or running past the end of the buffer, is an error, after
+/// which the iterator ends.
+#[derive(Clone)]
+pub struct Frames<'a, Endian> {
+    buf: &'a [u8],
+    width: Width,
+    failed: bool,
+    endian: PhantomData<Endian>,
+}
+
+impl<'a, Endian> Frames<'a, Endian> {
+    /// The bytes not yet split off, including any that failed to.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+impl<'a, Endian: NumDe> Frames<'a, Endian> {
+    fn split(&mut self) -> Result<&'a [u8]> {
+        if self.buf.len() < self.width.size() {
+            return Err(Error::Eof);
+        }
+        let size = frame_size::<Endian>(self.buf, self.width, usize::MAX)?;
+        if size > self.buf.len() {
+            return Err(Error::LengthTooLarge {
+                len: size,
+                remaining: self.buf.len(),
+            });
+        }
+        let (frame, rest) = self.buf.split_at(size);
+        self.buf = rest;
+        Ok(frame)
+    }
+}
+
+impl<'a, Endian: NumDe> Iterator for Frames<'a, Endian> {
+    type Item = Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Result<&'a [u8]>> {
+        if self.failed || self.buf.is_empty() {
+            return None;
+        }
+        let frame = self.split();
+        self.failed = frame.is_err();
+        Some(frame)
+    }
+}
+
+/// Split `buf` into the messages it holds back to back, each starting with
+/// a field of the given width holding its whole size.
+pub fn frames<Endian: NumDe>(buf: &[u8], width: Width) -> Frames<'_, Endian> {
+    Frames {
+        buf,
+        width,
+        failed: false,
+        endian: PhantomData,
+    }
+}
+
+pub fn frames_le(buf: &[u8], width: Width) -> Frames<'_, LittleEndian> {
+    frames::<LittleEndian>(buf, width)
+}
+
+pub fn frames_be(buf: &[u8], width: Width) -> Frames<'_, BigEndian> {
+    frames::<BigEndian>(buf, width)
+}
+
+/// Serialize each message from `iter` into one buffer, preceded by a field
+/// of the given width holding its size, field included, so the whole batch
+/// can go out in a single write. The batch reads back with
+/// `from_bytes_batch`. `frames` splits it too, but yields each message with
+/// the added size field still in front.
+///
+/// The size field is added whatever the message holds. Messages that start
+/// with their own size, such as `p9::Message` with its `size[4]`, come out
+/// framed twice, which their peer will not expect. Serialize those one after
+/// another with `to_bytes_into` instead, and split them with `frames`.
+pub fn to_bytes_batch<Endian, I>(iter: I, width: Width) -> Result<Vec<u8>>
+where
+    Endian: NumSer,
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    let mut out = Vec::new();
+    for value in iter {
+        let start = out.len();
+        out.resize(start + width.size(), 0);
+        let n = crate::to_bytes_into::<Endian, _>(&value, &mut out)?;
+        put_frame_size::<Endian>(&mut out[start..], width, width.size() + n)?;
+    }
+    Ok(out)
+}
+
+pub fn to_bytes_batch_le<I>(iter: I, width: Width) -> Result<Vec<u8>>
+where
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    to_bytes_batch::<LittleEndian, I>(iter, width)
+}
+
+pub fn to_bytes_batch_be<I>(iter: I, width: Width) -> Result<Vec<u8>>
+where
+    I: IntoIterator,
+    I::Item: Serialize,
+{
+    to_bytes_batch::<BigEndian, I>(iter, width)
+}
+
+/// Write `size` into the `width`-byte field at the start of `head`.
+fn put_frame_size<Endian: NumSer>(
+    head: &mut [u8],
+    width: Width,
+    size: usize,
+) -> Result<()> {
+    let max = match width {
+        Width::U8 => u8::MAX as usize,
+        Width::U16 => u16::MAX as usize,
+        Width::U32 => u32::MAX as usize,
+        Width::U64 => usize::MAX,
+    };
+    if size > max {
+        return Err(Error::LengthOverflow { max, actual: size });
+    }
+    match width {
+        Width::U8 => head[0] = size as u8,
+        Width::U16 => {
+            head[..2].copy_from_slice(&Endian::serialize_u16(size as u16))
+        }
+        Width::U32 => {
+            head[..4].copy_from_slice(&Endian::serialize_u32(size as u32))
+        }
+        Width::U64 => {
+            head[..8].copy_from_slice(&Endian::serialize_u64(size as u64))
+        }
+    }
+    Ok(())
+}
+
+/// An iterator over the messages in a batch written by `to_bytes_batch`,
+/// made by `from_bytes_batch`.
+///
+/// A message that fails to decode is an error, after which the iterator
+/// goes on to the next. A size that cannot be followed ends it, as for
+/// `Frames`.
+pub struct Batch<'a, Endian, T> {
+    frames: Frames<'a, Endian>,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<'a, Endian, T> Batch<'a, Endian, T> {
+    /// The bytes of the messages not yet decoded.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.frames.remaining()
+    }
+}
+
+impl<'a, Endian: NumDe, T: Deserialize<'a>> Iterator for Batch<'a, Endian, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        let width = self.frames.width.size();
+        let frame = self.frames.next()?;
+        Some(frame.and_then(|b| crate::from_bytes::<Endian, T>(&b[width..])))
+    }
+}
+
+/// Decode the messages of a batch written by `to_bytes_batch` in turn.
+pub fn from_bytes_batch<'a, Endian, T>(
+    buf: &'a [u8],
+    width: Width,
+) -> Batch<'a, Endian, T>
+where
+    Endian: NumDe,
+    T: Deserialize<'a>,
+{
+    Batch {
+        frames: frames::<Endian>(buf, width),
+        phantom: PhantomData,
+    }
+}
+
+pub fn from_bytes_batch_le<'a, T>(
+    buf: &'a [u8],
+    width: Width,
+) -> Batch<'a, LittleEndian, T>
+where
+    T: Deserialize<'a>,
+{
+    from_bytes_batch::<LittleEndian, T>(buf, width)
+}
+
+pub fn from_bytes_batch_be<'a, T>(
+    buf: &'a [u8],
+    width: Width,
+) -> Batch<'a, BigEndian, T>
+where
+    T: Deserialize<'a>,
+{
+    from_bytes_batch::<BigEndian, T>(buf, width)
+}
+
+/// What a `FrameAccumulator` has to give.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Accumulated<T> {
+    /// A whole message.
+    Complete(T),
+    /// No whole message yet: at least this many more bytes are needed
+    /// before there can be.
+    Incomplete(usize),
+}
+
+/// Collects chunks of a stream of messages, each starting with a field
+/// holding its whole size, as they arrive and gives back each message once
+/// all of it has.
+///
+/// Feed it whatever a non-blocking read returns, then take messages until it
+/// reports how many more bytes it needs.
+pub struct FrameAccumulator<Endian> {
+    buf: Vec<u8>,
+    /// Where the bytes not yet taken start in `buf`.
+    pos: usize,
+    width: Width,
+    max_size: usize,
+    endian: PhantomData<fn() -> Endian>,
+}
+
+impl<Endian: NumDe> FrameAccumulator<Endian> {
+    /// An accumulator for messages whose size is held in a leading field of
+    /// the given width and is at most `max_size`.
+    pub fn new(width: Width, max_size: usize) -> Self {
+        FrameAccumulator {
+            buf: Vec::new(),
+            pos: 0,
+            width,
+            max_size,
+            endian: PhantomData,
+        }
+    }
+
+    /// Add the next bytes of the stream.
+    pub fn feed(&mut self, b: &[u8]) {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(b);
+    }
+
+    /// Take the next whole message, size field included, without decoding
+    /// it. A size too small to hold the field itself or larger than the
+    /// maximum fails, and goes on failing, as the stream cannot be followed
+    /// past it.
+    pub fn next_frame(&mut self) -> Result<Accumulated<&[u8]>> {
+        let have = &self.buf[self.pos..];
+        if have.len() < self.width.size() {
+            return Ok(Accumulated::Incomplete(self.width.size() - have.len()));
+        }
+        let size = frame_size::<Endian>(have, self.width, self.max_size)?;
+        if have.len() < size {
+            return Ok(Accumulated::Incomplete(size - have.len()));
+        }
+        let start = self.pos;
+        self.pos += size;
+        Ok(Accumulated::Complete(&self.buf[start..self.pos]))
+    }
+
+    /// Take and decode the next whole message. A message that fails to
+    /// decode is still taken.
+    pub fn next_message<T: DeserializeOwned>(
+        &mut self,
+    ) -> Result<Accumulated<T>> {
+        match self.next_frame()? {
+            Accumulated::Complete(b) => {
+                crate::from_bytes::<Endian, T>(b).map(Accumulated::Complete)
+            }
+            Accumulated::Incomplete(n) => Ok(Accumulated::Incomplete(n)),
+        }
+    }
+
+    /// The number of bytes fed but not yet taken.
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+}
+
+/// Reads messages one after another from a `std::io::Read`, each starting
+/// with a field holding its whole size, until the reader ends.
+///
+/// A message that fails to decode is reported and reading carries on with
+/// the next one. A bad size or a failed read ends the iteration after it is
+/// reported, as does a reader ending part way through a message, reported
+/// as `Error::Eof`.
+pub struct MessageReader<R, T, Endian> {
+    r: R,
+    buf: Vec<u8>,
+    width: Width,
+    max_size: usize,
+    done: bool,
+    phantom: PhantomData<fn() -> (T, Endian)>,
+}
+
+impl<R, T, Endian> MessageReader<R, T, Endian>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+    Endian: NumDe,
+{
+    /// A reader of messages whose size is held in a leading field of the
+    /// given width and is at most `max_size`.
+    pub fn new(r: R, width: Width, max_size: usize) -> Self {
+        MessageReader {
+            r,
+            buf: Vec::new(),
+            width,
+            max_size,
+            done: false,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Give back the reader, positioned after the last message read.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    /// Read the next message into `buf`, or return false if the reader ends
+    /// before it starts.
+    fn read_frame(&mut self) -> Result<bool> {
+        let width = self.width.size();
+        self.buf.resize(width, 0);
+        let mut n = 0;
+        while n < width {
+            match self.r.read(&mut self.buf[n..]) {
+                Ok(0) if n == 0 => return Ok(false),
+                Ok(0) => return Err(Error::Eof),
+                Ok(k) => n += k,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::io(e)),
+            }
+        }
+        let size = frame_size::<Endian>(&self.buf, self.width, self.max_size)?;
+        self.buf.resize(size, 0);
+        self.r.read_exact(&mut self.buf[width..]).map_err(|e| {
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => Error::Eof,
+                _ => Error::io(e),
+            }
+        })?;
+        Ok(true)
+    }
+}
+
+impl<R, T, Endian> Iterator for MessageReader<R, T, Endian>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+    Endian: NumDe,
+{
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.done {
+            return None;
+        }
+        match self.read_frame() {
+            Ok(true) => Some(crate::from_bytes::<Endian, T>(&self.buf)),
+            Ok(false) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Writes messages one after another to a `std::io::Write`, the counterpart
+/// of `MessageReader`. Each message is serialized whole, then written.
+/// Nothing is flushed until `flush` is called.
+pub struct MessageWriter<W, T, Endian> {
+    w: W,
+    buf: Vec<u8>,
+    phantom: PhantomData<fn(T) -> Endian>,
+}
+
+impl<W, T, Endian> MessageWriter<W, T, Endian>
+where
+    W: io::Write,
+    T: Serialize,
+    Endian: NumSer,
+{
+    pub fn new(w: W) -> Self {
+        MessageWriter {
+            w,
+            buf: Vec::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Serialize `value` and write all of it. Nothing is written if it fails
+    /// to serialize.
+    pub fn write(&mut self, value: &T) -> Result<()> {
+        self.buf.clear();
+        crate::to_bytes_into::<Endian, T>(value, &mut self.buf)?;
+        self.w.write_all(&self.buf).map_err(Error::io)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.w.flush().map_err(Error::io)
+    }
+
+    /// Give back the writer, without flushing it.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
@@ -0,0 +1,385 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Reading and writing whole messages over the `AsyncRead` and `AsyncWrite`
+//! of the `futures` crate, for executors other than tokio, such as smol.
+//! Messages are framed as for `ispf::tokio`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use ::futures::channel::oneshot;
+use ::futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use ::futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::de::NumDe;
+use crate::error::{Error, Result};
+use crate::frame::{Accumulated, FrameAccumulator};
+use crate::ser::NumSer;
+use crate::Width;
+
+crate::frame::message_io!();
+
+/// A stream of the messages read from an `AsyncRead`, framed as for
+/// `read_message`, ending when the reader does.
+///
+/// As with `MessageReader`, a message that fails to decode is reported and
+/// the stream carries on, while a bad size, a failed read or a reader ending
+/// part way through a message ends the stream after it is reported.
+pub struct MessageStream<R, T, Endian> {
+    r: R,
+    acc: FrameAccumulator<Endian>,
+    chunk: Vec<u8>,
+    done: bool,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<R, T, Endian: NumDe> MessageStream<R, T, Endian> {
+    /// The least read from the reader at once.
+    const CHUNK: usize = 4 * 1024;
+
+    /// A stream of messages whose size is held in a leading field of the
+    /// given width and is at most `max_size`.
+    pub fn new(r: R, width: Width, max_size: usize) -> Self {
+        MessageStream {
+            r,
+            acc: FrameAccumulator::new(width, max_size),
+            chunk: Vec::new(),
+            done: false,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Give back the reader. Anything read but not yet taken is lost.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+}
+
+impl<R, T, Endian> Stream for MessageStream<R, T, Endian>
+where
+    R: AsyncRead + Unpin,
+    T: DeserializeOwned,
+    Endian: NumDe,
+{
+    type Item = Result<T>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<T>>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        loop {
+            let needed = match this.acc.next_frame() {
+                Ok(Accumulated::Complete(b)) => {
+                    return Poll::Ready(Some(crate::from_bytes::<Endian, T>(
+                        b,
+                    )));
+                }
+                Ok(Accumulated::Incomplete(n)) => n,
+                Err(e) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            };
+            this.chunk.resize(needed.max(Self::CHUNK), 0);
+            let n = match Pin::new(&mut this.r).poll_read(cx, &mut this.chunk) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(e))
+                    if e.kind() == io::ErrorKind::Interrupted =>
+                {
+                    continue
+                }
+                Poll::Ready(Err(e)) => {
+                    this.done = true;
+                    return Poll::Ready(Some(Err(Error::io(e))));
+                }
+            };
+            if n == 0 {
+                this.done = true;
+                return Poll::Ready(match this.acc.buffered() {
+                    0 => None,
+                    _ => Some(Err(Error::Eof)),
+                });
+            }
+            this.acc.feed(&this.chunk[..n]);
+        }
+    }
+}
+
+/// A sink serializing messages to an `AsyncWrite`, the counterpart of
+/// `MessageStream`.
+///
+/// Messages are serialized into a buffer of the sink's own. It is written
+/// out once it holds at least the buffer limit when the next message is
+/// sent, and whenever the sink is flushed or closed.
+pub struct MessageSink<W, T, Endian> {
+    w: W,
+    buf: Vec<u8>,
+    /// How much of `buf` has been written.
+    written: usize,
+    limit: usize,
+    phantom: PhantomData<fn(T) -> Endian>,
+}
+
+impl<W, T, Endian> MessageSink<W, T, Endian> {
+    pub fn new(w: W) -> Self {
+        MessageSink {
+            w,
+            buf: Vec::new(),
+            written: 0,
+            limit: 8 * 1024,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Write out the buffer once it holds at least `limit` bytes, rather
+    /// than the default of 8 KiB. With a limit of zero, each message is
+    /// written out before the next is taken.
+    pub fn buffer_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// The bytes serialized but not yet written.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+
+    /// Give back the writer. Anything buffered is lost.
+    pub fn into_inner(self) -> W {
+        self.w
+    }
+}
+
+impl<W: AsyncWrite + Unpin, T, Endian> MessageSink<W, T, Endian> {
+    /// Write out the whole buffer.
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.written < self.buf.len() {
+            let b = &self.buf[self.written..];
+            match Pin::new(&mut self.w).poll_write(cx, b) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(0)) => {
+                    let e = io::Error::from(io::ErrorKind::WriteZero);
+                    return Poll::Ready(Err(Error::io(e)));
+                }
+                Poll::Ready(Ok(n)) => self.written += n,
+                Poll::Ready(Err(e))
+                    if e.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(Error::io(e))),
+            }
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W, T, Endian> Sink<T> for MessageSink<W, T, Endian>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+    Endian: NumSer,
+{
+    type Error = Error;
+
+    fn poll_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.buf.len() - this.written < this.limit {
+            return Poll::Ready(Ok(()));
+        }
+        this.poll_write_buf(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<()> {
+        let this = self.get_mut();
+        crate::to_bytes_into::<Endian, T>(&item, &mut this.buf)?;
+        Ok(())
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        match this.poll_write_buf(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        Pin::new(&mut this.w).poll_flush(cx).map_err(Error::io)
+    }
+
+    fn poll_close(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
+        match self.as_mut().poll_flush(cx) {
+            Poll::Ready(Ok(())) => {}
+            other => return other,
+        }
+        Pin::new(&mut self.get_mut().w)
+            .poll_close(cx)
+            .map_err(Error::io)
+    }
+}
+
+/// A message carrying a tag that pairs a reply with its request, as in 9P.
+pub trait MessageTag {
+    fn tag(&self) -> u16;
+    fn set_tag(&mut self, tag: u16);
+}
+
+/// The client side of a protocol whose requests carry a tag echoed by their
+/// replies, which may come back in any order.
+///
+/// `send` gives each request a free tag, sends it, and returns a `Reply` to
+/// await. Whatever reads the connection passes each reply to `dispatch`, or
+/// hands the whole stream to `run`, and the reply goes to the request with
+/// its tag. A tag is free again once its reply has been dispatched, whether
+/// or not anyone still awaits it. Tag `0xffff`, 9P's `NOTAG`, is never
+/// handed out.
+pub struct TagMatcher<Req, Rep> {
+    inner: Mutex<Tags<Rep>>,
+    phantom: PhantomData<fn(Req)>,
+}
+
+struct Tags<Rep> {
+    next: u16,
+    pending: HashMap<u16, oneshot::Sender<Rep>>,
+}
+
+impl<Req, Rep> Default for TagMatcher<Req, Rep> {
+    fn default() -> Self {
+        TagMatcher {
+            inner: Mutex::new(Tags {
+                next: 0,
+                pending: HashMap::new(),
+            }),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<Req: MessageTag, Rep: MessageTag> TagMatcher<Req, Rep> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a free tag for a request, along with the receiver of its reply.
+    pub fn start(&self) -> Result<(u16, oneshot::Receiver<Rep>)> {
+        let mut tags = self.inner.lock().unwrap();
+        if tags.pending.len() >= usize::from(u16::MAX) {
+            return Err(Error::NoFreeTag);
+        }
+        let mut tag = tags.next;
+        while tag == u16::MAX || tags.pending.contains_key(&tag) {
+            tag = tag.wrapping_add(1);
+        }
+        tags.next = tag.wrapping_add(1);
+        let (tx, rx) = oneshot::channel();
+        tags.pending.insert(tag, tx);
+        Ok((tag, rx))
+    }
+
+    /// Give up on the request holding `tag`, freeing the tag at once. Only
+    /// do so once the peer can no longer reply to it.
+    pub fn cancel(&self, tag: u16) {
+        self.inner.lock().unwrap().pending.remove(&tag);
+    }
+
+    /// The number of requests waiting for their replies.
+    pub fn pending(&self) -> usize {
+        self.inner.lock().unwrap().pending.len()
+    }
+
+    /// Tag `req`, send it through `sink`, and return its reply to await.
+    /// The tag is freed again if sending fails.
+    pub async fn send<S>(
+        &self,
+        sink: &mut S,
+        mut req: Req,
+    ) -> Result<Reply<Rep>>
+    where
+        S: Sink<Req, Error = Error> + Unpin,
+    {
+        let (tag, rx) = self.start()?;
+        req.set_tag(tag);
+        if let Err(e) = sink.send(req).await {
+            self.cancel(tag);
+            return Err(e);
+        }
+        Ok(Reply(rx))
+    }
+
+    /// Pass `reply` to the request waiting on its tag.
+    pub fn dispatch(&self, reply: Rep) -> Result<()> {
+        let tag = reply.tag();
+        let tx = self.inner.lock().unwrap().pending.remove(&tag);
+        match tx {
+            // The request may have stopped waiting, which is no error.
+            Some(tx) => {
+                let _ = tx.send(reply);
+                Ok(())
+            }
+            None => Err(Error::UnknownTag(tag)),
+        }
+    }
+
+    /// Dispatch every reply from `replies` until it ends or fails with
+    /// `Error::Io`. A reply whose tag no request holds, and a message that
+    /// failed to decode, are skipped, as the connection is still usable. An
+    /// error the stream ends right after is returned instead: that is how
+    /// `MessageStream` reports a bad size or a reader ending part way through
+    /// a message. Any request still waiting then fails with `Error::Eof`.
+    pub async fn run<S>(&self, mut replies: S) -> Result<()>
+    where
+        S: Stream<Item = Result<Rep>> + Unpin,
+    {
+        let mut last = None;
+        let result = loop {
+            match replies.next().await {
+                Some(Ok(reply)) => {
+                    last = None;
+                    let _ = self.dispatch(reply);
+                }
+                Some(Err(e @ Error::Io { .. })) => break Err(e),
+                Some(Err(e)) => last = Some(e),
+                None => break last.map_or(Ok(()), Err),
+            }
+        };
+        self.inner.lock().unwrap().pending.clear();
+        result
+    }
+}
+
+/// The reply to a request sent through a `TagMatcher`. It fails with
+/// `Error::Eof` if the connection ends first.
+pub struct Reply<Rep>(oneshot::Receiver<Rep>);
+
+impl<Rep> Future for Reply<Rep> {
+    type Output = Result<Rep>;
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map_err(|_| Error::Eof)
+    }
+}
@@ -0,0 +1,177 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::de::Deserializer;
+use crate::error::{Error, Result};
+use crate::{BigEndian, LittleEndian, Width};
+
+/// A byte-counted vector whose elements are decoded only as they are iterated
+/// over. Deserializing a `LazyVec` borrows the element bytes from the input
+/// and remembers its byte order, so a consumer that stops after the first few
+/// elements never pays for decoding the rest. The elements are decoded with
+/// the string limit, presence flags and variant index width of the decoder
+/// that read the `LazyVec`.
+///
+/// Used through the `lazy_lv8b`, `lazy_lv16b`, `lazy_lv32b` and `lazy_lv64b`
+/// formatters. Serializing a `LazyVec` writes its bytes back unchanged, so it
+/// must be written in the byte order it was read in.
+pub struct LazyVec<'de, T> {
+    bytes: &'de [u8],
+    big_endian: bool,
+    max_str_len: usize,
+    presence: (u8, u8),
+    variant_width: Width,
+    phantom: PhantomData<T>,
+}
+
+impl<'de, T> LazyVec<'de, T> {
+    /// The undecoded element bytes.
+    pub fn as_bytes(&self) -> &'de [u8] {
+        self.bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> LazyVec<'de, T> {
+    /// Iterate over the elements, decoding each one as it is reached. The
+    /// iterator ends after the first element that fails to decode.
+    pub fn iter(&self) -> LazyIter<'de, T> {
+        let (absent, present) = self.presence;
+        let inner = if self.big_endian {
+            Inner::Be(
+                Deserializer::from_bytes(self.bytes)
+                    .max_str_len(self.max_str_len)
+                    .presence_flags(absent, present)
+                    .variant_width(self.variant_width),
+            )
+        } else {
+            Inner::Le(
+                Deserializer::from_bytes(self.bytes)
+                    .max_str_len(self.max_str_len)
+                    .presence_flags(absent, present)
+                    .variant_width(self.variant_width),
+            )
+        };
+        LazyIter {
+            inner: Some(inner),
+            phantom: PhantomData::<T> {},
+        }
+    }
+}
+
+impl<T> Clone for LazyVec<'_, T> {
+    fn clone(&self) -> Self {
+        LazyVec {
+            bytes: self.bytes,
+            big_endian: self.big_endian,
+            max_str_len: self.max_str_len,
+            presence: self.presence,
+            variant_width: self.variant_width,
+            phantom: PhantomData::<T> {},
+        }
+    }
+}
+
+impl<T> fmt::Debug for LazyVec<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LazyVec")
+            .field("bytes", &self.bytes)
+            .field("big_endian", &self.big_endian)
+            .finish()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> IntoIterator for &LazyVec<'de, T> {
+    type Item = Result<T>;
+    type IntoIter = LazyIter<'de, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+enum Inner<'de> {
+    Le(Deserializer<'de, LittleEndian>),
+    Be(Deserializer<'de, BigEndian>),
+}
+
+pub struct LazyIter<'de, T> {
+    inner: Option<Inner<'de>>,
+    phantom: PhantomData<T>,
+}
+
+impl<'de, T: Deserialize<'de>> Iterator for LazyIter<'de, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        let res = match self.inner.as_mut()? {
+            Inner::Le(d) if d.remaining().is_empty() => None,
+            Inner::Be(d) if d.remaining().is_empty() => None,
+            Inner::Le(d) => Some(T::deserialize(d)),
+            Inner::Be(d) => Some(T::deserialize(d)),
+        };
+        if !matches!(res, Some(Ok(_))) {
+            self.inner = None;
+        }
+        res
+    }
+}
+
+pub struct LazyVisitor<'de, T> {
+    phantom: PhantomData<LazyVec<'de, T>>,
+}
+
+impl<'de, T> LazyVisitor<'de, T> {
+    pub fn new() -> Self {
+        LazyVisitor {
+            phantom: PhantomData::<LazyVec<'de, T>> {},
+        }
+    }
+}
+
+impl<'de, T> Visitor<'de> for LazyVisitor<'de, T> {
+    type Value = LazyVec<'de, T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array prefixed by a byte length")
+    }
+
+    fn visit_seq<A>(
+        self,
+        mut seq: A,
+    ) -> core::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let missing = || de::Error::custom(Error::ExpectedArray);
+        let big_endian = seq.next_element()?.ok_or_else(missing)?;
+        let bytes: &'de [u8] = seq.next_element()?.ok_or_else(missing)?;
+        let max_str_len: u64 = seq.next_element()?.ok_or_else(missing)?;
+        let absent = seq.next_element()?.ok_or_else(missing)?;
+        let present = seq.next_element()?.ok_or_else(missing)?;
+        let width: u8 = seq.next_element()?.ok_or_else(missing)?;
+        let variant_width =
+            Width::from_size(width.into()).ok_or_else(missing)?;
+        Ok(LazyVec {
+            bytes,
+            big_endian,
+            max_str_len: usize::try_from(max_str_len).unwrap_or(usize::MAX),
+            presence: (absent, present),
+            variant_width,
+            phantom: PhantomData::<T> {},
+        })
+    }
+}
@@ -4,301 +4,2912 @@
 
 // Copyright 2022 Oxide Computer Company
 
+extern crate self as ispf;
+
+mod checksum;
+#[cfg(feature = "codec")]
+mod codec;
 mod de;
 mod error;
+mod frame;
+#[cfg(feature = "futures")]
+pub mod futures;
+mod lazy;
+mod lv;
+mod message;
+#[cfg(feature = "p9")]
+pub mod p9;
+#[cfg(feature = "p9")]
+pub mod p9l;
+#[cfg(feature = "p9")]
+pub mod p9u;
+mod pad;
+mod range;
+mod read;
+mod ring;
 mod ser;
+mod size;
+mod stream;
+mod tagged;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+mod vectored;
+
+#[doc(hidden)]
+#[path = "private.rs"]
+pub mod __private;
 
-pub use de::{from_bytes, from_bytes_be, from_bytes_le, Deserializer};
+#[cfg(feature = "derive")]
+pub use ispf_macros::{with_length, FixedSize, Ispf, IspfMessage, MaxSize};
+
+pub use checksum::Checksum;
+#[cfg(feature = "codec")]
+pub use codec::IspfCodec;
+#[cfg(feature = "bytes")]
+pub use de::{from_buf, from_buf_be, from_buf_le};
+pub use de::{
+    from_bytes, from_bytes_be, from_bytes_in, from_bytes_le, from_reader,
+    from_reader_be, from_reader_le, from_slices, from_slices_be,
+    from_slices_le, Deserializer,
+};
 pub use error::{Error, Result};
-pub use ser::{to_bytes, to_bytes_be, to_bytes_le, Serializer};
+pub use frame::{
+    frames, frames_be, frames_le, from_bytes_batch, from_bytes_batch_be,
+    from_bytes_batch_le, to_bytes_batch, to_bytes_batch_be, to_bytes_batch_le,
+    Accumulated, Batch, FrameAccumulator, Frames, MessageReader, MessageWriter,
+};
+pub use lazy::{LazyIter, LazyVec};
+pub use lv::{DeserializeLv, Lv16, Lv32, Lv64, Lv8, SerializeLv, Width};
+pub use message::{
+    patch_field, patch_field_be, patch_field_le, peek, peek_be, peek_header,
+    peek_header_be, peek_header_le, peek_le, peek_type, retag, retag_be,
+    retag_le, Dispatcher, Header, Message, RawMessage,
+};
+pub use pad::Pad;
+pub use range::ByteRange;
+pub use read::ReadDeserializer;
+pub use ring::{
+    ring, ring_be, ring_le, RingConsumer, RingProducer, RING_HEADER_LEN,
+};
+pub use ser::{
+    serialized_size, to_bytes, to_bytes_be, to_bytes_in, to_bytes_into,
+    to_bytes_into_be, to_bytes_into_le, to_bytes_le, to_slice, to_slice_be,
+    to_slice_le, to_writer, to_writer_be, to_writer_le, Output, Serializer,
+    SliceOutput, WriteOutput,
+};
+pub use size::{FixedSize, MaxSize};
+pub use stream::ReadBytes;
+pub use tagged::Tagged;
+pub use vectored::{
+    to_parts, to_parts_be, to_parts_le, to_vectored, to_vectored_be,
+    to_vectored_le, Payload, Vectored,
+};
 
 pub struct LittleEndian {}
 pub struct BigEndian {}
 
+/// A byte order chosen at run time, for data whose order is only known once
+/// it is read, such as dumps taken on different machines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
 pub mod str_lv8 {
-    use serde::ser::SerializeTuple;
+    use serde::ser::SerializeTupleStruct;
 
     pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u8>() + v.len())?;
-        t.serialize_element(&(v.len() as u8))?;
-        t.serialize_element(v.as_bytes())?;
+        let mut t = s.serialize_tuple_struct("string8", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D>(d: D) -> Result<String, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string8",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+
+    pub fn serialize_bounded<S, const MAX: usize>(
+        v: &str,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, T, const MAX: usize>(
+        d: D,
+    ) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
+        T: From<&'de str>,
     {
-        d.deserialize_tuple_struct("string8", 2, crate::de::TlvStringVisitor)
+        d.deserialize_tuple_struct(
+            "string8",
+            2,
+            crate::de::TlvStringVisitor::bounded(MAX),
+        )
     }
 }
 
 pub mod str_lv16 {
-    use serde::ser::SerializeTuple;
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("string16", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string16",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+
+    pub fn serialize_bounded<S, const MAX: usize>(
+        v: &str,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, T, const MAX: usize>(
+        d: D,
+    ) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string16",
+            2,
+            crate::de::TlvStringVisitor::bounded(MAX),
+        )
+    }
+}
+
+pub mod str_lv24 {
+    use serde::ser::SerializeTupleStruct;
 
     pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u16>() + v.len())?;
-        t.serialize_element(&(v.len() as u16))?;
-        t.serialize_element(v.as_bytes())?;
+        let mut t = s.serialize_tuple_struct("string24", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D>(d: D) -> Result<String, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string24",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+
+    pub fn serialize_bounded<S, const MAX: usize>(
+        v: &str,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, T, const MAX: usize>(
+        d: D,
+    ) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
+        T: From<&'de str>,
     {
-        d.deserialize_tuple_struct("string16", 2, crate::de::TlvStringVisitor)
+        d.deserialize_tuple_struct(
+            "string24",
+            2,
+            crate::de::TlvStringVisitor::bounded(MAX),
+        )
     }
 }
 
 pub mod str_lv32 {
-    use serde::ser::SerializeTuple;
+    use serde::ser::SerializeTupleStruct;
 
     pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u32>() + v.len())?;
-        t.serialize_element(&(v.len() as u32))?;
-        t.serialize_element(v.as_bytes())?;
+        let mut t = s.serialize_tuple_struct("string32", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D>(d: D) -> Result<String, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string32",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+
+    pub fn serialize_bounded<S, const MAX: usize>(
+        v: &str,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, T, const MAX: usize>(
+        d: D,
+    ) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
+        T: From<&'de str>,
     {
-        d.deserialize_tuple_struct("string32", 2, crate::de::TlvStringVisitor)
+        d.deserialize_tuple_struct(
+            "string32",
+            2,
+            crate::de::TlvStringVisitor::bounded(MAX),
+        )
     }
 }
 
 pub mod str_lv64 {
-    use serde::ser::SerializeTuple;
+    use serde::ser::SerializeTupleStruct;
 
     pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u64>() + v.len())?;
-        t.serialize_element(&(v.len() as u64))?;
-        t.serialize_element(v.as_bytes())?;
+        let mut t = s.serialize_tuple_struct("string64", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D>(d: D) -> Result<String, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string64",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+
+    pub fn serialize_bounded<S, const MAX: usize>(
+        v: &str,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, T, const MAX: usize>(
+        d: D,
+    ) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
+        T: From<&'de str>,
     {
-        d.deserialize_tuple_struct("string64", 2, crate::de::TlvStringVisitor)
+        d.deserialize_tuple_struct(
+            "string64",
+            2,
+            crate::de::TlvStringVisitor::bounded(MAX),
+        )
     }
 }
 
-pub mod vec_lv8 {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv8 {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u8>() + v.len())?;
-        t.serialize_element(&(v.len() as u8))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes8", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec8", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes8",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub mod vec_lv16 {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv16 {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u16>() + v.len())?;
-        t.serialize_element(&(v.len() as u16))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes16", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec16", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes16",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub mod vec_lv32 {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv32 {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u32>() + v.len())?;
-        t.serialize_element(&(v.len() as u32))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes32", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec32", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes32",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub mod vec_lv64 {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv64 {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize,
     {
-        let mut t = s.serialize_tuple(std::mem::size_of::<u64>() + v.len())?;
-        t.serialize_element(&(v.len() as u64))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes64", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec64", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes64",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub trait WireSize {
-    fn wire_size(&self) -> usize;
+pub mod bytes_lv8_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("bytes8i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de [u8]>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes8i",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
+    }
 }
 
-pub mod vec_lv8b {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv16_inclusive {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize + crate::WireSize,
     {
-        let mut sz = 0usize;
-        for e in v {
-            sz += e.wire_size();
-        }
-        let mut t = s.serialize_tuple(std::mem::size_of::<u8>() + v.len())?;
-        t.serialize_element(&(sz as u8))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes16i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec8b", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes16i",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub mod vec_lv16b {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv32_inclusive {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize + crate::WireSize,
     {
-        let mut sz = 0usize;
-        for e in v {
-            sz += e.wire_size();
-        }
-        let mut t = s.serialize_tuple(std::mem::size_of::<u16>() + v.len())?;
-        t.serialize_element(&(sz as u16))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes32i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec16b", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes32i",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub mod vec_lv32b {
-    use serde::ser::SerializeTuple;
+pub mod bytes_lv64_inclusive {
+    use serde::ser::SerializeTupleStruct;
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize + crate::WireSize,
     {
-        let mut sz = 0usize;
-        for e in v {
-            sz += e.wire_size();
-        }
-        let mut t = s.serialize_tuple(std::mem::size_of::<u32>() + v.len())?;
-        t.serialize_element(&(sz as u32))?;
-        t.serialize_element(&v)?;
+        let mut t = s.serialize_tuple_struct("bytes64i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: From<&'de [u8]>,
     {
-        d.deserialize_tuple_struct("vec32b", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes64i",
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )
     }
 }
 
-pub mod vec_lv64b {
-    use serde::ser::SerializeTuple;
+pub mod rest {
+    pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.serialize_bytes(v)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de [u8]>,
+    {
+        d.deserialize_bytes(crate::de::TlvBytesVisitor::new())
+    }
+}
 
-    pub fn serialize<S, T>(v: &Vec<T>, s: S) -> Result<S::Ok, S::Error>
+#[cfg(feature = "serde_bytes")]
+pub mod bytebuf_lv8 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
-        T: serde::Serialize + crate::WireSize,
+        T: ?Sized + AsRef<[u8]>,
     {
-        let mut sz = 0usize;
-        for e in v {
-            sz += e.wire_size();
-        }
-        let mut t = s.serialize_tuple(std::mem::size_of::<u64>() + v.len())?;
-        t.serialize_element(&(sz as u64))?;
-        t.serialize_element(&v)?;
+        let v = v.as_ref();
+        let mut t = s.serialize_tuple_struct("bytes8", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
         t.end()
     }
 
-    pub fn deserialize<'de, D, T>(d: D) -> Result<Vec<T>, D::Error>
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
     where
         D: serde::Deserializer<'de>,
-        T: serde::Deserialize<'de>,
+        T: serde_bytes::Deserialize<'de>,
     {
-        d.deserialize_tuple_struct("vec64b", 2, crate::de::TlvVecVisitor::new())
+        d.deserialize_tuple_struct(
+            "bytes8",
+            2,
+            crate::de::TlvByteBufVisitor::new(),
+        )
+    }
+}
+
+#[cfg(feature = "serde_bytes")]
+pub mod bytebuf_lv16 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: ?Sized + AsRef<[u8]>,
+    {
+        let v = v.as_ref();
+        let mut t = s.serialize_tuple_struct("bytes16", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde_bytes::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes16",
+            2,
+            crate::de::TlvByteBufVisitor::new(),
+        )
+    }
+}
+
+#[cfg(feature = "serde_bytes")]
+pub mod bytebuf_lv32 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: ?Sized + AsRef<[u8]>,
+    {
+        let v = v.as_ref();
+        let mut t = s.serialize_tuple_struct("bytes32", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde_bytes::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes32",
+            2,
+            crate::de::TlvByteBufVisitor::new(),
+        )
+    }
+}
+
+#[cfg(feature = "serde_bytes")]
+pub mod bytebuf_lv64 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: ?Sized + AsRef<[u8]>,
+    {
+        let v = v.as_ref();
+        let mut t = s.serialize_tuple_struct("bytes64", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde_bytes::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes64",
+            2,
+            crate::de::TlvByteBufVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv8_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("string8i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string8i",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv16_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("string16i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string16i",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv32_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("string32i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string32i",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv64_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("string64i", v.len())?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string64i",
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+}
+
+pub mod opt_str_lv8 {
+    pub fn serialize<S, T>(v: &Option<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::str_lv8::serialize(v.as_ref().map_or("", |x| x.as_ref()), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string8",
+            2,
+            crate::de::TlvOptStringVisitor::new(),
+        )
+    }
+}
+
+pub mod opt_str_lv16 {
+    pub fn serialize<S, T>(v: &Option<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::str_lv16::serialize(v.as_ref().map_or("", |x| x.as_ref()), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string16",
+            2,
+            crate::de::TlvOptStringVisitor::new(),
+        )
+    }
+}
+
+pub mod opt_str_lv32 {
+    pub fn serialize<S, T>(v: &Option<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::str_lv32::serialize(v.as_ref().map_or("", |x| x.as_ref()), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string32",
+            2,
+            crate::de::TlvOptStringVisitor::new(),
+        )
+    }
+}
+
+pub mod opt_str_lv64 {
+    pub fn serialize<S, T>(v: &Option<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::str_lv64::serialize(v.as_ref().map_or("", |x| x.as_ref()), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "string64",
+            2,
+            crate::de::TlvOptStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv8_lossy {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::str_lv8::serialize(v, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<std::borrow::Cow<'de, str>>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes8",
+            2,
+            crate::de::TlvLossyStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv16_lossy {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::str_lv16::serialize(v, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<std::borrow::Cow<'de, str>>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes16",
+            2,
+            crate::de::TlvLossyStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv32_lossy {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::str_lv32::serialize(v, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<std::borrow::Cow<'de, str>>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes32",
+            2,
+            crate::de::TlvLossyStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv64_lossy {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::str_lv64::serialize(v, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<std::borrow::Cow<'de, str>>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes64",
+            2,
+            crate::de::TlvLossyStringVisitor::new(),
+        )
+    }
+}
+
+pub mod str_lv8_latin1 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let b = crate::ser::encode_latin1::<S::Error>(v)?;
+        crate::bytes_lv8::serialize(&b, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes8",
+            2,
+            crate::de::TlvLatin1Visitor::new(),
+        )
+    }
+}
+
+pub mod str_lv16_latin1 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let b = crate::ser::encode_latin1::<S::Error>(v)?;
+        crate::bytes_lv16::serialize(&b, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes16",
+            2,
+            crate::de::TlvLatin1Visitor::new(),
+        )
+    }
+}
+
+pub mod str_lv32_latin1 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let b = crate::ser::encode_latin1::<S::Error>(v)?;
+        crate::bytes_lv32::serialize(&b, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes32",
+            2,
+            crate::de::TlvLatin1Visitor::new(),
+        )
+    }
+}
+
+pub mod str_lv64_latin1 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let b = crate::ser::encode_latin1::<S::Error>(v)?;
+        crate::bytes_lv64::serialize(&b, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "bytes64",
+            2,
+            crate::de::TlvLatin1Visitor::new(),
+        )
+    }
+}
+
+pub mod str_lv8_utf16 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let units: Vec<u16> = v.encode_utf16().collect();
+        crate::vec_lv8::serialize(&units, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct("vec8", 2, crate::de::TlvUtf16Visitor::new())
+    }
+}
+
+pub mod str_lv16_utf16 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let units: Vec<u16> = v.encode_utf16().collect();
+        crate::vec_lv16::serialize(&units, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "vec16",
+            2,
+            crate::de::TlvUtf16Visitor::new(),
+        )
+    }
+}
+
+pub mod str_lv32_utf16 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let units: Vec<u16> = v.encode_utf16().collect();
+        crate::vec_lv32::serialize(&units, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "vec32",
+            2,
+            crate::de::TlvUtf16Visitor::new(),
+        )
+    }
+}
+
+pub mod str_lv64_utf16 {
+    pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let units: Vec<u16> = v.encode_utf16().collect();
+        crate::vec_lv64::serialize(&units, s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<String>,
+    {
+        d.deserialize_tuple_struct(
+            "vec64",
+            2,
+            crate::de::TlvUtf16Visitor::new(),
+        )
+    }
+}
+
+/// Null-terminated C strings for `CString` and `&CStr` fields. A `CStr` cannot
+/// hold an interior null byte, so what is written is always read back intact.
+pub mod cstr {
+    use std::ffi::CStr;
+
+    pub fn serialize<S>(v: &CStr, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.serialize_bytes(v.to_bytes_with_nul())
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de CStr>,
+    {
+        d.deserialize_tuple_struct("cstr", 1, crate::de::CStrVisitor::new())
+    }
+}
+
+/// Null-terminated strings stored in a fixed-size field of `N` bytes, with the
+/// space after the terminator padded with zeros. Since the width is a const
+/// parameter these are used with `serialize_with` and `deserialize_with`.
+///
+/// ```
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct Header {
+///     #[serde(
+///         serialize_with = "ispf::str_fixed::serialize::<_, 16>",
+///         deserialize_with = "ispf::str_fixed::deserialize::<_, _, 16>"
+///     )]
+///     name: String,
+/// }
+/// ```
+pub mod str_fixed {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, const N: usize>(
+        v: &str,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut t = s.serialize_tuple_struct("fixed", N)?;
+        t.serialize_field(&crate::ser::RawBytes(v.as_bytes()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: From<&'de str>,
+    {
+        d.deserialize_tuple_struct(
+            "fixed",
+            N,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+}
+
+pub mod vec_lv8 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized,
+        &'a C: IntoIterator,
+        <&'a C as IntoIterator>::Item: serde::Serialize,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec8", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(v.into_iter(), len))?;
+        t.end()
+    }
+
+    /// Serialize anything that can be viewed as a slice, such as a `&[T]`
+    /// field, which `serialize` cannot iterate through a second reference.
+    pub fn serialize_slice<S, C, T>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized + AsRef<[T]>,
+        T: serde::Serialize,
+    {
+        serialize(v.as_ref(), s)
+    }
+
+    /// Serialize the elements of an iterator without collecting them first.
+    pub fn serialize_iter<S, I>(iter: I, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        I: ExactSizeIterator,
+        I::Item: serde::Serialize,
+    {
+        let len = iter.len();
+        let mut t = s.serialize_tuple_struct("vec8", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(iter, len))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec8", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv16 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized,
+        &'a C: IntoIterator,
+        <&'a C as IntoIterator>::Item: serde::Serialize,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec16", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(v.into_iter(), len))?;
+        t.end()
+    }
+
+    /// Serialize anything that can be viewed as a slice, such as a `&[T]`
+    /// field, which `serialize` cannot iterate through a second reference.
+    pub fn serialize_slice<S, C, T>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized + AsRef<[T]>,
+        T: serde::Serialize,
+    {
+        serialize(v.as_ref(), s)
+    }
+
+    /// Serialize the elements of an iterator without collecting them first.
+    pub fn serialize_iter<S, I>(iter: I, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        I: ExactSizeIterator,
+        I::Item: serde::Serialize,
+    {
+        let len = iter.len();
+        let mut t = s.serialize_tuple_struct("vec16", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(iter, len))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec16", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv24 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized,
+        &'a C: IntoIterator,
+        <&'a C as IntoIterator>::Item: serde::Serialize,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec24", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(v.into_iter(), len))?;
+        t.end()
+    }
+
+    /// Serialize anything that can be viewed as a slice, such as a `&[T]`
+    /// field, which `serialize` cannot iterate through a second reference.
+    pub fn serialize_slice<S, C, T>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized + AsRef<[T]>,
+        T: serde::Serialize,
+    {
+        serialize(v.as_ref(), s)
+    }
+
+    /// Serialize the elements of an iterator without collecting them first.
+    pub fn serialize_iter<S, I>(iter: I, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        I: ExactSizeIterator,
+        I::Item: serde::Serialize,
+    {
+        let len = iter.len();
+        let mut t = s.serialize_tuple_struct("vec24", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(iter, len))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec24", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv32 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized,
+        &'a C: IntoIterator,
+        <&'a C as IntoIterator>::Item: serde::Serialize,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec32", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(v.into_iter(), len))?;
+        t.end()
+    }
+
+    /// Serialize anything that can be viewed as a slice, such as a `&[T]`
+    /// field, which `serialize` cannot iterate through a second reference.
+    pub fn serialize_slice<S, C, T>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized + AsRef<[T]>,
+        T: serde::Serialize,
+    {
+        serialize(v.as_ref(), s)
+    }
+
+    /// Serialize the elements of an iterator without collecting them first.
+    pub fn serialize_iter<S, I>(iter: I, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        I: ExactSizeIterator,
+        I::Item: serde::Serialize,
+    {
+        let len = iter.len();
+        let mut t = s.serialize_tuple_struct("vec32", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(iter, len))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec32", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv64 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized,
+        &'a C: IntoIterator,
+        <&'a C as IntoIterator>::Item: serde::Serialize,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec64", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(v.into_iter(), len))?;
+        t.end()
+    }
+
+    /// Serialize anything that can be viewed as a slice, such as a `&[T]`
+    /// field, which `serialize` cannot iterate through a second reference.
+    pub fn serialize_slice<S, C, T>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: ?Sized + AsRef<[T]>,
+        T: serde::Serialize,
+    {
+        serialize(v.as_ref(), s)
+    }
+
+    /// Serialize the elements of an iterator without collecting them first.
+    pub fn serialize_iter<S, I>(iter: I, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        I: ExactSizeIterator,
+        I::Item: serde::Serialize,
+    {
+        let len = iter.len();
+        let mut t = s.serialize_tuple_struct("vec64", len)?;
+        t.serialize_field(&crate::ser::IterSeq::new(iter, len))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec64", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+pub mod arrayvec_lv8 {
+    use arrayvec::ArrayVec;
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T, const CAP: usize>(
+        v: &ArrayVec<T, CAP>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("vec8", v.len())?;
+        t.serialize_field(v.as_slice())?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T, const CAP: usize>(
+        d: D,
+    ) -> Result<ArrayVec<T, CAP>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec8",
+            2,
+            crate::de::TlvArrayVecVisitor::new(),
+        )
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+pub mod arrayvec_lv16 {
+    use arrayvec::ArrayVec;
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T, const CAP: usize>(
+        v: &ArrayVec<T, CAP>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("vec16", v.len())?;
+        t.serialize_field(v.as_slice())?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T, const CAP: usize>(
+        d: D,
+    ) -> Result<ArrayVec<T, CAP>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec16",
+            2,
+            crate::de::TlvArrayVecVisitor::new(),
+        )
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+pub mod arrayvec_lv32 {
+    use arrayvec::ArrayVec;
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T, const CAP: usize>(
+        v: &ArrayVec<T, CAP>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("vec32", v.len())?;
+        t.serialize_field(v.as_slice())?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T, const CAP: usize>(
+        d: D,
+    ) -> Result<ArrayVec<T, CAP>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec32",
+            2,
+            crate::de::TlvArrayVecVisitor::new(),
+        )
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+pub mod arrayvec_lv64 {
+    use arrayvec::ArrayVec;
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T, const CAP: usize>(
+        v: &ArrayVec<T, CAP>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("vec64", v.len())?;
+        t.serialize_field(v.as_slice())?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T, const CAP: usize>(
+        d: D,
+    ) -> Result<ArrayVec<T, CAP>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec64",
+            2,
+            crate::de::TlvArrayVecVisitor::new(),
+        )
+    }
+}
+
+/// The number of bytes a value occupies on the wire, used by the `vec_lv*b`
+/// formatters to compute their byte-length prefix.
+pub trait WireSize {
+    fn wire_size(&self) -> usize;
+}
+
+impl WireSize for u8 {
+    fn wire_size(&self) -> usize {
+        std::mem::size_of::<u8>()
+    }
+}
+
+impl WireSize for u16 {
+    fn wire_size(&self) -> usize {
+        std::mem::size_of::<u16>()
+    }
+}
+
+impl WireSize for u32 {
+    fn wire_size(&self) -> usize {
+        std::mem::size_of::<u32>()
+    }
+}
+
+impl WireSize for u64 {
+    fn wire_size(&self) -> usize {
+        std::mem::size_of::<u64>()
+    }
+}
+
+impl WireSize for u128 {
+    fn wire_size(&self) -> usize {
+        std::mem::size_of::<u128>()
+    }
+}
+
+impl<T: WireSize, const N: usize> WireSize for [T; N] {
+    fn wire_size(&self) -> usize {
+        self.iter().map(WireSize::wire_size).sum()
+    }
+}
+
+pub mod vec_lv8b {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec8b", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec8b", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv16b {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec16b", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec16b", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv32b {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec32b", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec32b", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv64b {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec64b", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec64b", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv8b_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec8bi", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec8bi", 2, crate::de::TlvVecVisitor::new())
+    }
+}
+
+pub mod vec_lv16b_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec16bi", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec16bi",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )
+    }
+}
+
+pub mod vec_lv32b_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec32bi", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec32bi",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )
+    }
+}
+
+pub mod vec_lv64b_inclusive {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<'a, S, C, T>(v: &'a C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        &'a C: IntoIterator<Item = &'a T>,
+        T: crate::WireSize + 'a,
+    {
+        let mut sz = 0usize;
+        for e in v {
+            sz += e.wire_size();
+        }
+        let mut t = s.serialize_tuple_struct("vec64bi", sz)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec64bi",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )
+    }
+}
+
+pub mod lazy_lv8b {
+    use crate::LazyVec;
+
+    pub fn serialize<S, T>(v: &LazyVec<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::bytes_lv8::serialize(v.as_bytes(), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<LazyVec<'de, T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct("lazy8b", 2, crate::lazy::LazyVisitor::new())
+    }
+}
+
+pub mod lazy_lv16b {
+    use crate::LazyVec;
+
+    pub fn serialize<S, T>(v: &LazyVec<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::bytes_lv16::serialize(v.as_bytes(), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<LazyVec<'de, T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "lazy16b",
+            2,
+            crate::lazy::LazyVisitor::new(),
+        )
+    }
+}
+
+pub mod lazy_lv32b {
+    use crate::LazyVec;
+
+    pub fn serialize<S, T>(v: &LazyVec<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::bytes_lv32::serialize(v.as_bytes(), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<LazyVec<'de, T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "lazy32b",
+            2,
+            crate::lazy::LazyVisitor::new(),
+        )
+    }
+}
+
+pub mod lazy_lv64b {
+    use crate::LazyVec;
+
+    pub fn serialize<S, T>(v: &LazyVec<T>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        crate::bytes_lv64::serialize(v.as_bytes(), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<LazyVec<'de, T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "lazy64b",
+            2,
+            crate::lazy::LazyVisitor::new(),
+        )
+    }
+}
+
+pub mod sized_lv8 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("sized8", 1)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("sized8", 1, crate::de::SizedVisitor::new())
+    }
+}
+
+pub mod sized_lv16 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("sized16", 1)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("sized16", 1, crate::de::SizedVisitor::new())
+    }
+}
+
+pub mod sized_lv32 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("sized32", 1)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("sized32", 1, crate::de::SizedVisitor::new())
+    }
+}
+
+pub mod sized_lv64 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        let mut t = s.serialize_tuple_struct("sized64", 1)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("sized64", 1, crate::de::SizedVisitor::new())
+    }
+}
+
+/// A value wrapped in two nested 16-bit byte-length prefixes, as 9P does for
+/// stat data in `Rstat` and `Twstat`. The outer length counts the inner
+/// prefix as well as the value, so it is always two more than the inner one.
+pub mod sized_lv16_double {
+    struct Inner<T>(T);
+
+    impl<T: serde::Serialize> serde::Serialize for Inner<&T> {
+        fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            crate::sized_lv16::serialize(self.0, s)
+        }
+    }
+
+    impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Inner<T> {
+        fn deserialize<D>(d: D) -> Result<Self, D::Error>
+        where
+            D: serde::Deserializer<'de>,
+        {
+            crate::sized_lv16::deserialize(d).map(Inner)
+        }
+    }
+
+    pub fn serialize<S, T>(v: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: serde::Serialize,
+    {
+        crate::sized_lv16::serialize(&Inner(v), s)
+    }
+
+    pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        T: serde::Deserialize<'de>,
+    {
+        crate::sized_lv16::deserialize::<D, Inner<T>>(d).map(|i| i.0)
+    }
+}
+
+/// A list of strings: a 8-bit count followed by that many `str_lv8`
+/// strings.
+pub mod str_list_lv8 {
+    use serde::ser::SerializeTupleStruct;
+
+    struct Item<'a>(&'a str);
+
+    impl serde::Serialize for Item<'_> {
+        fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            crate::str_lv8::serialize(self.0, s)
+        }
+    }
+
+    pub fn serialize<S, T>(v: &[T], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        let items = v.iter().map(|x| Item(x.as_ref()));
+        let mut t = s.serialize_tuple_struct("vec8", v.len())?;
+        t.serialize_field(&crate::ser::IterSeq::new(items, v.len()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv8<String>> = d.deserialize_tuple_struct(
+            "vec8",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )?;
+        Ok(v.into_iter().map(crate::Lv8::into_inner).collect())
+    }
+
+    pub fn serialize_bounded<S, T, const MAX: usize>(
+        v: &[T],
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, C, const MAX: usize>(
+        d: D,
+    ) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv8<String>> = d.deserialize_tuple_struct(
+            "vec8",
+            2,
+            crate::de::TlvVecVisitor::bounded(MAX),
+        )?;
+        Ok(v.into_iter().map(crate::Lv8::into_inner).collect())
+    }
+}
+
+/// A list of strings: a 16-bit count followed by that many `str_lv16`
+/// strings, as in 9P's `Twalk`.
+pub mod str_list_lv16 {
+    use serde::ser::SerializeTupleStruct;
+
+    struct Item<'a>(&'a str);
+
+    impl serde::Serialize for Item<'_> {
+        fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            crate::str_lv16::serialize(self.0, s)
+        }
+    }
+
+    pub fn serialize<S, T>(v: &[T], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        let items = v.iter().map(|x| Item(x.as_ref()));
+        let mut t = s.serialize_tuple_struct("vec16", v.len())?;
+        t.serialize_field(&crate::ser::IterSeq::new(items, v.len()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv16<String>> = d.deserialize_tuple_struct(
+            "vec16",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )?;
+        Ok(v.into_iter().map(crate::Lv16::into_inner).collect())
+    }
+
+    pub fn serialize_bounded<S, T, const MAX: usize>(
+        v: &[T],
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, C, const MAX: usize>(
+        d: D,
+    ) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv16<String>> = d.deserialize_tuple_struct(
+            "vec16",
+            2,
+            crate::de::TlvVecVisitor::bounded(MAX),
+        )?;
+        Ok(v.into_iter().map(crate::Lv16::into_inner).collect())
+    }
+}
+
+/// A list of strings: a 32-bit count followed by that many `str_lv32`
+/// strings.
+pub mod str_list_lv32 {
+    use serde::ser::SerializeTupleStruct;
+
+    struct Item<'a>(&'a str);
+
+    impl serde::Serialize for Item<'_> {
+        fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            crate::str_lv32::serialize(self.0, s)
+        }
+    }
+
+    pub fn serialize<S, T>(v: &[T], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        let items = v.iter().map(|x| Item(x.as_ref()));
+        let mut t = s.serialize_tuple_struct("vec32", v.len())?;
+        t.serialize_field(&crate::ser::IterSeq::new(items, v.len()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv32<String>> = d.deserialize_tuple_struct(
+            "vec32",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )?;
+        Ok(v.into_iter().map(crate::Lv32::into_inner).collect())
+    }
+
+    pub fn serialize_bounded<S, T, const MAX: usize>(
+        v: &[T],
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, C, const MAX: usize>(
+        d: D,
+    ) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv32<String>> = d.deserialize_tuple_struct(
+            "vec32",
+            2,
+            crate::de::TlvVecVisitor::bounded(MAX),
+        )?;
+        Ok(v.into_iter().map(crate::Lv32::into_inner).collect())
+    }
+}
+
+/// A list of strings: a 64-bit count followed by that many `str_lv64`
+/// strings.
+pub mod str_list_lv64 {
+    use serde::ser::SerializeTupleStruct;
+
+    struct Item<'a>(&'a str);
+
+    impl serde::Serialize for Item<'_> {
+        fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer,
+        {
+            crate::str_lv64::serialize(self.0, s)
+        }
+    }
+
+    pub fn serialize<S, T>(v: &[T], s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        let items = v.iter().map(|x| Item(x.as_ref()));
+        let mut t = s.serialize_tuple_struct("vec64", v.len())?;
+        t.serialize_field(&crate::ser::IterSeq::new(items, v.len()))?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv64<String>> = d.deserialize_tuple_struct(
+            "vec64",
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )?;
+        Ok(v.into_iter().map(crate::Lv64::into_inner).collect())
+    }
+
+    pub fn serialize_bounded<S, T, const MAX: usize>(
+        v: &[T],
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        T: AsRef<str>,
+    {
+        crate::ser::check_max_len::<S::Error>(v.len(), MAX)?;
+        serialize(v, s)
+    }
+
+    pub fn deserialize_bounded<'de, D, C, const MAX: usize>(
+        d: D,
+    ) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<String>,
+    {
+        let v: Vec<crate::Lv64<String>> = d.deserialize_tuple_struct(
+            "vec64",
+            2,
+            crate::de::TlvVecVisitor::bounded(MAX),
+        )?;
+        Ok(v.into_iter().map(crate::Lv64::into_inner).collect())
+    }
+}
+
+pub mod set_lv8 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec8", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec8", 2, crate::de::TlvSetVisitor::new())
+    }
+}
+
+/// Like `set_lv8`, but input that repeats an element is rejected with
+/// `Error::DuplicateElement`.
+pub mod set_lv8_strict {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec8", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec8",
+            2,
+            crate::de::TlvSetVisitor::strict(),
+        )
+    }
+}
+
+pub mod set_lv16 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec16", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec16", 2, crate::de::TlvSetVisitor::new())
+    }
+}
+
+/// Like `set_lv16`, but input that repeats an element is rejected with
+/// `Error::DuplicateElement`.
+pub mod set_lv16_strict {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec16", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec16",
+            2,
+            crate::de::TlvSetVisitor::strict(),
+        )
+    }
+}
+
+pub mod set_lv32 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec32", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec32", 2, crate::de::TlvSetVisitor::new())
+    }
+}
+
+/// Like `set_lv32`, but input that repeats an element is rejected with
+/// `Error::DuplicateElement`.
+pub mod set_lv32_strict {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec32", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec32",
+            2,
+            crate::de::TlvSetVisitor::strict(),
+        )
+    }
+}
+
+pub mod set_lv64 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec64", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("vec64", 2, crate::de::TlvSetVisitor::new())
+    }
+}
+
+/// Like `set_lv64`, but input that repeats an element is rejected with
+/// `Error::DuplicateElement`.
+pub mod set_lv64_strict {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        C: serde::Serialize,
+        for<'a> &'a C: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("vec64", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        C: std::iter::FromIterator<T>,
+        for<'a> &'a C: IntoIterator,
+        T: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct(
+            "vec64",
+            2,
+            crate::de::TlvSetVisitor::strict(),
+        )
+    }
+}
+
+pub mod map_lv8 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, M>(v: &M, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        M: serde::Serialize,
+        for<'a> &'a M: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("map8", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, M>(d: D) -> Result<M, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        M: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("map8", 2, crate::de::TlvMapVisitor::new())
+    }
+}
+
+pub mod map_lv16 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, M>(v: &M, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        M: serde::Serialize,
+        for<'a> &'a M: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("map16", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, M>(d: D) -> Result<M, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        M: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("map16", 2, crate::de::TlvMapVisitor::new())
+    }
+}
+
+pub mod map_lv32 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, M>(v: &M, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        M: serde::Serialize,
+        for<'a> &'a M: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("map32", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, M>(d: D) -> Result<M, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        M: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("map32", 2, crate::de::TlvMapVisitor::new())
+    }
+}
+
+pub mod map_lv64 {
+    use serde::ser::SerializeTupleStruct;
+
+    pub fn serialize<S, M>(v: &M, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        M: serde::Serialize,
+        for<'a> &'a M: IntoIterator,
+    {
+        let len = v.into_iter().count();
+        let mut t = s.serialize_tuple_struct("map64", len)?;
+        t.serialize_field(v)?;
+        t.end()
+    }
+
+    pub fn deserialize<'de, D, M>(d: D) -> Result<M, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        M: serde::Deserialize<'de>,
+    {
+        d.deserialize_tuple_struct("map64", 2, crate::de::TlvMapVisitor::new())
+    }
+}
+
+/// Optional integers encoded in place, with a sentinel value standing in for
+/// `None`. `with = "ispf::opt_u32"` uses `u32::MAX` as the sentinel; other
+/// sentinels are chosen with `serialize_sentinel` and `deserialize_sentinel`.
+///
+/// ```
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct Twalk {
+///     #[serde(with = "ispf::opt_u32")]
+///     newfid: Option<u32>,
+///     #[serde(
+///         serialize_with = "ispf::opt_u16::serialize_sentinel::<_, 0>",
+///         deserialize_with = "ispf::opt_u16::deserialize_sentinel::<_, 0>"
+///     )]
+///     port: Option<u16>,
+/// }
+/// ```
+pub mod opt_u8 {
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S>(v: &Option<u8>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serialize_sentinel::<S, { u8::MAX }>(v, s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<u8>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserialize_sentinel::<D, { u8::MAX }>(d)
+    }
+
+    pub fn serialize_sentinel<S, const SENTINEL: u8>(
+        v: &Option<u8>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match v {
+            Some(x) if *x == SENTINEL => Err(serde::ser::Error::custom(
+                format!("{} is reserved to mean no value", x),
+            )),
+            Some(x) => x.serialize(s),
+            None => SENTINEL.serialize(s),
+        }
+    }
+
+    pub fn deserialize_sentinel<'de, D, const SENTINEL: u8>(
+        d: D,
+    ) -> Result<Option<u8>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let x = u8::deserialize(d)?;
+        Ok(if x == SENTINEL { None } else { Some(x) })
+    }
+}
+
+pub mod opt_u16 {
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S>(v: &Option<u16>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serialize_sentinel::<S, { u16::MAX }>(v, s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<u16>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserialize_sentinel::<D, { u16::MAX }>(d)
+    }
+
+    pub fn serialize_sentinel<S, const SENTINEL: u16>(
+        v: &Option<u16>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match v {
+            Some(x) if *x == SENTINEL => Err(serde::ser::Error::custom(
+                format!("{} is reserved to mean no value", x),
+            )),
+            Some(x) => x.serialize(s),
+            None => SENTINEL.serialize(s),
+        }
+    }
+
+    pub fn deserialize_sentinel<'de, D, const SENTINEL: u16>(
+        d: D,
+    ) -> Result<Option<u16>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let x = u16::deserialize(d)?;
+        Ok(if x == SENTINEL { None } else { Some(x) })
+    }
+}
+
+pub mod opt_u32 {
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S>(v: &Option<u32>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serialize_sentinel::<S, { u32::MAX }>(v, s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<u32>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserialize_sentinel::<D, { u32::MAX }>(d)
+    }
+
+    pub fn serialize_sentinel<S, const SENTINEL: u32>(
+        v: &Option<u32>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match v {
+            Some(x) if *x == SENTINEL => Err(serde::ser::Error::custom(
+                format!("{} is reserved to mean no value", x),
+            )),
+            Some(x) => x.serialize(s),
+            None => SENTINEL.serialize(s),
+        }
+    }
+
+    pub fn deserialize_sentinel<'de, D, const SENTINEL: u32>(
+        d: D,
+    ) -> Result<Option<u32>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let x = u32::deserialize(d)?;
+        Ok(if x == SENTINEL { None } else { Some(x) })
+    }
+}
+
+pub mod opt_u64 {
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S>(v: &Option<u64>, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serialize_sentinel::<S, { u64::MAX }>(v, s)
+    }
+
+    pub fn deserialize<'de, D>(d: D) -> Result<Option<u64>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserialize_sentinel::<D, { u64::MAX }>(d)
+    }
+
+    pub fn serialize_sentinel<S, const SENTINEL: u64>(
+        v: &Option<u64>,
+        s: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match v {
+            Some(x) if *x == SENTINEL => Err(serde::ser::Error::custom(
+                format!("{} is reserved to mean no value", x),
+            )),
+            Some(x) => x.serialize(s),
+            None => SENTINEL.serialize(s),
+        }
+    }
+
+    pub fn deserialize_sentinel<'de, D, const SENTINEL: u64>(
+        d: D,
+    ) -> Result<Option<u64>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let x = u64::deserialize(d)?;
+        Ok(if x == SENTINEL { None } else { Some(x) })
+    }
+}
+
+/// Flag sets from the `bitflags` crate, written as their underlying integer.
+/// `bits` keeps bits that match no known flag; `bits_strict` rejects them.
+#[cfg(feature = "bitflags")]
+pub mod bits {
+    use bitflags::Flags;
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S, F>(v: &F, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        F: Flags,
+        F::Bits: Serialize,
+    {
+        v.bits().serialize(s)
+    }
+
+    pub fn deserialize<'de, D, F>(d: D) -> Result<F, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        F: Flags,
+        F::Bits: Deserialize<'de>,
+    {
+        Ok(F::from_bits_retain(F::Bits::deserialize(d)?))
+    }
+}
+
+#[cfg(feature = "bitflags")]
+pub mod bits_strict {
+    use bitflags::Flags;
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S, F>(v: &F, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+        F: Flags,
+        F::Bits: Serialize,
+    {
+        crate::bits::serialize(v, s)
+    }
+
+    pub fn deserialize<'de, D, F>(d: D) -> Result<F, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+        F: Flags,
+        F::Bits: Deserialize<'de> + std::fmt::LowerHex,
+    {
+        let bits = F::Bits::deserialize(d)?;
+        F::from_bits(bits).ok_or_else(|| {
+            let unknown = F::from_bits_retain(bits).difference(F::all());
+            serde::de::Error::custom(format!(
+                "unknown flag bits {:#x}",
+                unknown.bits()
+            ))
+        })
     }
 }
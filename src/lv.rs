@@ -0,0 +1,438 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::ops::{Deref, DerefMut};
+
+use serde::ser::SerializeTupleStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The width of a length prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    U8,
+    U16,
+    U32,
+    U64,
+}
+
+impl Width {
+    /// The number of bytes the prefix takes.
+    pub(crate) fn size(self) -> usize {
+        match self {
+            Width::U8 => 1,
+            Width::U16 => 2,
+            Width::U32 => 4,
+            Width::U64 => 8,
+        }
+    }
+
+    /// The width whose prefix takes `size` bytes.
+    pub(crate) fn from_size(size: usize) -> Option<Width> {
+        match size {
+            1 => Some(Width::U8),
+            2 => Some(Width::U16),
+            4 => Some(Width::U32),
+            8 => Some(Width::U64),
+            _ => None,
+        }
+    }
+
+    fn string(self) -> &'static str {
+        match self {
+            Width::U8 => "string8",
+            Width::U16 => "string16",
+            Width::U32 => "string32",
+            Width::U64 => "string64",
+        }
+    }
+
+    fn vec(self) -> &'static str {
+        match self {
+            Width::U8 => "vec8",
+            Width::U16 => "vec16",
+            Width::U32 => "vec32",
+            Width::U64 => "vec64",
+        }
+    }
+
+    /// A run of bytes preceded by its length.
+    pub(crate) fn bytes(self) -> &'static str {
+        match self {
+            Width::U8 => "bytes8",
+            Width::U16 => "bytes16",
+            Width::U32 => "bytes32",
+            Width::U64 => "bytes64",
+        }
+    }
+
+    /// A run of bytes preceded by its length, decoded as where it is.
+    pub(crate) fn range(self) -> &'static str {
+        match self {
+            Width::U8 => "range8",
+            Width::U16 => "range16",
+            Width::U32 => "range32",
+            Width::U64 => "range64",
+        }
+    }
+
+    /// A value preceded by its encoded length in bytes.
+    pub(crate) fn sized(self) -> &'static str {
+        match self {
+            Width::U8 => "sized8",
+            Width::U16 => "sized16",
+            Width::U32 => "sized32",
+            Width::U64 => "sized64",
+        }
+    }
+
+    /// A vector preceded by its encoded length in bytes.
+    pub(crate) fn vec_bytes(self) -> &'static str {
+        match self {
+            Width::U8 => "vec8b",
+            Width::U16 => "vec16b",
+            Width::U32 => "vec32b",
+            Width::U64 => "vec64b",
+        }
+    }
+}
+
+/// A value that can be written after a length prefix: strings are prefixed
+/// by their length in bytes and vectors by their number of elements.
+pub trait SerializeLv {
+    fn serialize_lv<S: Serializer>(
+        &self,
+        width: Width,
+        s: S,
+    ) -> Result<S::Ok, S::Error>;
+}
+
+/// A value that can be read after a length prefix, the counterpart of
+/// `SerializeLv`.
+pub trait DeserializeLv<'de>: Sized {
+    fn deserialize_lv<D: Deserializer<'de>>(
+        width: Width,
+        d: D,
+    ) -> Result<Self, D::Error>;
+}
+
+impl SerializeLv for String {
+    fn serialize_lv<S: Serializer>(
+        &self,
+        width: Width,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut t = s.serialize_tuple_struct(width.string(), self.len())?;
+        t.serialize_field(&crate::ser::RawBytes(self.as_bytes()))?;
+        t.end()
+    }
+}
+
+impl<'de> DeserializeLv<'de> for String {
+    fn deserialize_lv<D: Deserializer<'de>>(
+        width: Width,
+        d: D,
+    ) -> Result<Self, D::Error> {
+        d.deserialize_tuple_struct(
+            width.string(),
+            2,
+            crate::de::TlvStringVisitor::new(),
+        )
+    }
+}
+
+impl<T: Serialize> SerializeLv for Vec<T> {
+    fn serialize_lv<S: Serializer>(
+        &self,
+        width: Width,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut t = s.serialize_tuple_struct(width.vec(), self.len())?;
+        t.serialize_field(self)?;
+        t.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> DeserializeLv<'de> for Vec<T> {
+    fn deserialize_lv<D: Deserializer<'de>>(
+        width: Width,
+        d: D,
+    ) -> Result<Self, D::Error> {
+        d.deserialize_tuple_struct(
+            width.vec(),
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )
+    }
+}
+
+macro_rules! lv_type {
+    ($name:ident, $width:expr, $doc:expr) => {
+        #[doc = $doc]
+        #[derive(
+            Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+        )]
+        pub struct $name<T>(pub T);
+
+        impl<T> $name<T> {
+            pub fn into_inner(self) -> T {
+                self.0
+            }
+        }
+
+        impl<T> From<T> for $name<T> {
+            fn from(v: T) -> Self {
+                $name(v)
+            }
+        }
+
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $name<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+
+        impl<T: SerializeLv> Serialize for $name<T> {
+            fn serialize<S: Serializer>(
+                &self,
+                s: S,
+            ) -> Result<S::Ok, S::Error> {
+                self.0.serialize_lv($width, s)
+            }
+        }
+
+        impl<'de, T: DeserializeLv<'de>> Deserialize<'de> for $name<T> {
+            fn deserialize<D: Deserializer<'de>>(
+                d: D,
+            ) -> Result<Self, D::Error> {
+                T::deserialize_lv($width, d).map($name)
+            }
+        }
+    };
+}
+
+lv_type!(
+    Lv8,
+    Width::U8,
+    "A string or vector preceded by an 8-bit length, the equivalent of \
+     `str_lv8` or `vec_lv8` carried in the type."
+);
+lv_type!(
+    Lv16,
+    Width::U16,
+    "A string or vector preceded by a 16-bit length, the equivalent of \
+     `str_lv16` or `vec_lv16` carried in the type."
+);
+lv_type!(
+    Lv32,
+    Width::U32,
+    "A string or vector preceded by a 32-bit length, the equivalent of \
+     `str_lv32` or `vec_lv32` carried in the type."
+);
+lv_type!(
+    Lv64,
+    Width::U64,
+    "A string or vector preceded by a 64-bit length, the equivalent of \
+     `str_lv64` or `vec_lv64` carried in the type."
+);
+
+/// Define a `with`-module like `str_lv16` or `vec_lv32b` for a combination
+/// of prefix and unit the crate does not provide itself, such as a string
+/// behind an inclusive 24-bit length.
+///
+/// The kind is one of `str`, `bytes`, `vec` (counted in elements) and
+/// `vec_bytes` (counted in bytes), and the prefix one of `u8`, `u16`, `u24`,
+/// `u32` and `u64`. A trailing `inclusive` makes the prefix count itself,
+/// which every kind but `vec` supports.
+///
+/// ```
+/// ispf::lv_module!(pub mod str_lv24i: str, u24, inclusive);
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Entry {
+///     #[serde(with = "str_lv24i")]
+///     name: String,
+/// }
+/// ```
+#[macro_export]
+macro_rules! lv_module {
+    (
+        $(#[$meta:meta])*
+        $vis:vis mod $name:ident : $kind:ident, $prefix:ident
+        $(, $inclusive:ident)? $(,)?
+    ) => {
+        $crate::lv_module!(
+            @prefix [$(#[$meta])* $vis $name] $kind, $prefix $(, $inclusive)?
+        );
+    };
+    (@prefix $head:tt $kind:ident, u8 $(, $i:ident)?) => {
+        $crate::lv_module!(@kind $head $kind, "8" $(, $i)?);
+    };
+    (@prefix $head:tt $kind:ident, u16 $(, $i:ident)?) => {
+        $crate::lv_module!(@kind $head $kind, "16" $(, $i)?);
+    };
+    (@prefix $head:tt $kind:ident, u24 $(, $i:ident)?) => {
+        $crate::lv_module!(@kind $head $kind, "24" $(, $i)?);
+    };
+    (@prefix $head:tt $kind:ident, u32 $(, $i:ident)?) => {
+        $crate::lv_module!(@kind $head $kind, "32" $(, $i)?);
+    };
+    (@prefix $head:tt $kind:ident, u64 $(, $i:ident)?) => {
+        $crate::lv_module!(@kind $head $kind, "64" $(, $i)?);
+    };
+    (@kind $head:tt str, $w:literal) => {
+        $crate::lv_module!(@str $head concat!("string", $w));
+    };
+    (@kind $head:tt str, $w:literal, inclusive) => {
+        $crate::lv_module!(@str $head concat!("string", $w, "i"));
+    };
+    (@kind $head:tt bytes, $w:literal) => {
+        $crate::lv_module!(@bytes $head concat!("bytes", $w));
+    };
+    (@kind $head:tt bytes, $w:literal, inclusive) => {
+        $crate::lv_module!(@bytes $head concat!("bytes", $w, "i"));
+    };
+    (@kind $head:tt vec, $w:literal) => {
+        $crate::lv_module!(@vec $head concat!("vec", $w));
+    };
+    (@kind $head:tt vec_bytes, $w:literal) => {
+        $crate::lv_module!(@vec_bytes $head concat!("vec", $w, "b"));
+    };
+    (@kind $head:tt vec_bytes, $w:literal, inclusive) => {
+        $crate::lv_module!(@vec_bytes $head concat!("vec", $w, "bi"));
+    };
+    (@str [$(#[$meta:meta])* $vis:vis $name:ident] $tuple:expr) => {
+        $(#[$meta])*
+        $vis mod $name {
+            use $crate::__private::serde;
+            use serde::ser::SerializeTupleStruct;
+
+            pub fn serialize<S>(v: &str, s: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                let mut t = s.serialize_tuple_struct($tuple, v.len())?;
+                t.serialize_field(&$crate::__private::RawBytes(v.as_bytes()))?;
+                t.end()
+            }
+
+            pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+                T: From<&'de str>,
+            {
+                d.deserialize_tuple_struct(
+                    $tuple,
+                    2,
+                    $crate::__private::TlvStringVisitor::new(),
+                )
+            }
+        }
+    };
+    (@bytes [$(#[$meta:meta])* $vis:vis $name:ident] $tuple:expr) => {
+        $(#[$meta])*
+        $vis mod $name {
+            use $crate::__private::serde;
+            use serde::ser::SerializeTupleStruct;
+
+            pub fn serialize<S>(v: &[u8], s: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                let mut t = s.serialize_tuple_struct($tuple, v.len())?;
+                t.serialize_field(&$crate::__private::RawBytes(v))?;
+                t.end()
+            }
+
+            pub fn deserialize<'de, D, T>(d: D) -> Result<T, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+                T: From<&'de [u8]>,
+            {
+                d.deserialize_tuple_struct(
+                    $tuple,
+                    2,
+                    $crate::__private::TlvBytesVisitor::new(),
+                )
+            }
+        }
+    };
+    (@vec [$(#[$meta:meta])* $vis:vis $name:ident] $tuple:expr) => {
+        $(#[$meta])*
+        $vis mod $name {
+            use $crate::__private::serde;
+            use serde::ser::SerializeTupleStruct;
+
+            pub fn serialize<S, C>(v: &C, s: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+                C: ?Sized + serde::Serialize,
+                for<'a> &'a C: IntoIterator,
+            {
+                let len = v.into_iter().count();
+                let mut t = s.serialize_tuple_struct($tuple, len)?;
+                t.serialize_field(v)?;
+                t.end()
+            }
+
+            pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+                C: std::iter::FromIterator<T>,
+                T: serde::Deserialize<'de>,
+            {
+                d.deserialize_tuple_struct(
+                    $tuple,
+                    2,
+                    $crate::__private::TlvVecVisitor::new(),
+                )
+            }
+        }
+    };
+    (@vec_bytes [$(#[$meta:meta])* $vis:vis $name:ident] $tuple:expr) => {
+        $(#[$meta])*
+        $vis mod $name {
+            use $crate::__private::serde;
+            use serde::ser::SerializeTupleStruct;
+
+            pub fn serialize<'a, S, C, T>(
+                v: &'a C,
+                s: S,
+            ) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+                C: serde::Serialize,
+                &'a C: IntoIterator<Item = &'a T>,
+                T: $crate::WireSize + 'a,
+            {
+                let sz = v.into_iter().map(|e| e.wire_size()).sum();
+                let mut t = s.serialize_tuple_struct($tuple, sz)?;
+                t.serialize_field(v)?;
+                t.end()
+            }
+
+            pub fn deserialize<'de, D, C, T>(d: D) -> Result<C, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+                C: std::iter::FromIterator<T>,
+                T: serde::Deserialize<'de>,
+            {
+                d.deserialize_tuple_struct(
+                    $tuple,
+                    2,
+                    $crate::__private::TlvVecVisitor::new(),
+                )
+            }
+        }
+    };
+}
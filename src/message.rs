@@ -0,0 +1,300 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, SeqAccess, Visitor};
+use serde::ser::{self, SerializeTuple};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::__private::ByteCount;
+use crate::de::{from_bytes, NumDe};
+use crate::error::{Error, Result};
+use crate::ser::{to_slice, NumSer, RawBytes};
+use crate::size::FixedSize;
+use crate::{BigEndian, LittleEndian};
+
+/// A message identified on the wire by a type code in its header.
+pub trait Message: Serialize + DeserializeOwned + 'static {
+    const TYPE: u8;
+}
+
+type Handler<R> = Box<dyn Fn(&[u8]) -> Result<R> + Send + Sync>;
+
+/// Routes incoming frames to a handler for their message type, read from the
+/// byte at `offset`.
+///
+/// With the default `R` of `Box<dyn Any + Send>`, messages can be registered
+/// with `register` and decoded with `decode`, then recovered with `downcast`.
+/// Handlers must be `Send` and `Sync`, so a dispatcher can be moved into a
+/// spawned task or shared between connections.
+pub struct Dispatcher<Endian: NumDe, R = Box<dyn Any + Send>> {
+    offset: usize,
+    handlers: BTreeMap<u8, Handler<R>>,
+    phantom: PhantomData<Endian>,
+}
+
+impl<Endian: NumDe, R> Dispatcher<Endian, R> {
+    pub fn new(offset: usize) -> Self {
+        Dispatcher {
+            offset,
+            handlers: BTreeMap::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Decode frames of type `M::TYPE` as `M` and pass them to `f`. A later
+    /// route for the same type replaces an earlier one.
+    pub fn route<M, F>(&mut self, f: F) -> &mut Self
+    where
+        M: Message,
+        F: Fn(M) -> R + Send + Sync + 'static,
+    {
+        self.handlers.insert(
+            M::TYPE,
+            Box::new(move |b| from_bytes::<Endian, M>(b).map(&f)),
+        );
+        self
+    }
+
+    /// The type code of a frame.
+    pub fn message_type(&self, b: &[u8]) -> Result<u8> {
+        b.get(self.offset).copied().ok_or(Error::OffsetOutOfRange {
+            offset: self.offset,
+            len: b.len(),
+        })
+    }
+
+    /// Decode a frame and hand it to the route for its type.
+    pub fn dispatch(&self, b: &[u8]) -> Result<R> {
+        let typ = self.message_type(b)?;
+        match self.handlers.get(&typ) {
+            Some(handler) => handler(b),
+            None => Err(Error::UnknownMessageType(typ)),
+        }
+    }
+}
+
+impl<Endian: NumDe> Dispatcher<Endian> {
+    /// Decode frames of type `M::TYPE` as `M`.
+    pub fn register<M: Message + Send>(&mut self) -> &mut Self {
+        self.route(|m: M| Box::new(m) as Box<dyn Any + Send>)
+    }
+
+    /// Decode a frame as whichever registered message its type selects.
+    pub fn decode(&self, b: &[u8]) -> Result<Box<dyn Any + Send>> {
+        self.dispatch(b)
+    }
+}
+
+/// The size, type and tag fields that start a message framed as for
+/// `RawMessage`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Header {
+    pub size: u32,
+    pub typ: u8,
+    pub tag: u16,
+}
+
+impl FixedSize for Header {
+    const WIRE_SIZE: usize = RawMessage::HEADER_LEN;
+}
+
+/// Decode the fixed-size prefix of a message as `T`, such as a `Header`,
+/// without reading any further into `b`. Nothing after the prefix is
+/// checked, so a router or logger can look at a message before deciding
+/// whether to decode the rest.
+pub fn peek<'a, Endian, T>(b: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'a> + FixedSize,
+    Endian: NumDe,
+{
+    let prefix = b.get(..T::WIRE_SIZE).ok_or(Error::Eof)?;
+    from_bytes::<Endian, T>(prefix)
+}
+
+pub fn peek_le<'a, T>(b: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'a> + FixedSize,
+{
+    peek::<LittleEndian, T>(b)
+}
+
+pub fn peek_be<'a, T>(b: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'a> + FixedSize,
+{
+    peek::<BigEndian, T>(b)
+}
+
+/// The size, type and tag of a message framed as for `RawMessage`.
+pub fn peek_header<Endian: NumDe>(b: &[u8]) -> Result<Header> {
+    peek::<Endian, Header>(b)
+}
+
+pub fn peek_header_le(b: &[u8]) -> Result<Header> {
+    peek_header::<LittleEndian>(b)
+}
+
+pub fn peek_header_be(b: &[u8]) -> Result<Header> {
+    peek_header::<BigEndian>(b)
+}
+
+/// The type of a message framed as for `RawMessage`, which is the same in
+/// either byte order.
+pub fn peek_type(b: &[u8]) -> Result<u8> {
+    b.get(4).copied().ok_or(Error::Eof)
+}
+
+/// Overwrite the field at `offset` in an encoded message with `value`,
+/// leaving every other byte as it was. Nothing is checked or recomputed, so
+/// a checksum covering the field must be fixed up separately.
+pub fn patch_field<Endian, T>(
+    b: &mut [u8],
+    offset: usize,
+    value: &T,
+) -> Result<()>
+where
+    T: Serialize + FixedSize,
+    Endian: NumSer,
+{
+    let len = b.len();
+    let field = offset
+        .checked_add(T::WIRE_SIZE)
+        .and_then(|end| b.get_mut(offset..end))
+        .ok_or(Error::OffsetOutOfRange { offset, len })?;
+    to_slice::<Endian, T>(value, field).map(drop)
+}
+
+pub fn patch_field_le<T>(b: &mut [u8], offset: usize, value: &T) -> Result<()>
+where
+    T: Serialize + FixedSize,
+{
+    patch_field::<LittleEndian, T>(b, offset, value)
+}
+
+pub fn patch_field_be<T>(b: &mut [u8], offset: usize, value: &T) -> Result<()>
+where
+    T: Serialize + FixedSize,
+{
+    patch_field::<BigEndian, T>(b, offset, value)
+}
+
+/// Replace the tag of a message framed as for `RawMessage` and return the
+/// tag it had, as a proxy does when renumbering tags between connections.
+pub fn retag<Endian>(b: &mut [u8], tag: u16) -> Result<u16>
+where
+    Endian: NumSer + NumDe,
+{
+    let old = peek_header::<Endian>(b)?.tag;
+    patch_field::<Endian, u16>(b, RawMessage::TAG_OFFSET, &tag)?;
+    Ok(old)
+}
+
+pub fn retag_le(b: &mut [u8], tag: u16) -> Result<u16> {
+    retag::<LittleEndian>(b, tag)
+}
+
+pub fn retag_be(b: &mut [u8], tag: u16) -> Result<u16> {
+    retag::<BigEndian>(b, tag)
+}
+
+/// A message framed as `size[4] type[1] tag[2] body`, where `size` counts the
+/// whole message, with its body left undecoded. Any well-framed message can
+/// be read this way, whether or not its layout is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawMessage<'a> {
+    pub typ: u8,
+    pub tag: u16,
+    pub body: Cow<'a, [u8]>,
+}
+
+impl RawMessage<'_> {
+    /// The length of the size, type and tag fields.
+    pub const HEADER_LEN: usize = 7;
+
+    /// Where the tag lies in the message.
+    pub const TAG_OFFSET: usize = 5;
+
+    pub fn into_owned(self) -> RawMessage<'static> {
+        RawMessage {
+            typ: self.typ,
+            tag: self.tag,
+            body: Cow::Owned(self.body.into_owned()),
+        }
+    }
+}
+
+impl Serialize for RawMessage<'_> {
+    fn serialize<S>(&self, s: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let size = u32::try_from(Self::HEADER_LEN + self.body.len()).map_err(
+            |_| {
+                <S::Error as ser::Error>::custom(Error::LengthOverflow {
+                    max: u32::MAX as usize,
+                    actual: Self::HEADER_LEN + self.body.len(),
+                })
+            },
+        )?;
+        let mut t = s.serialize_tuple(4)?;
+        t.serialize_element(&size)?;
+        t.serialize_element(&self.typ)?;
+        t.serialize_element(&self.tag)?;
+        t.serialize_element(&RawBytes(&self.body))?;
+        t.end()
+    }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for RawMessage<'a> {
+    fn deserialize<D>(d: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        d.deserialize_tuple(4, RawMessageVisitor(PhantomData))
+    }
+}
+
+struct RawMessageVisitor<'a>(PhantomData<&'a ()>);
+
+impl<'de: 'a, 'a> Visitor<'de> for RawMessageVisitor<'a> {
+    type Value = RawMessage<'a>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a framed message")
+    }
+
+    fn visit_seq<A>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let missing = |i| de::Error::invalid_length(i, &"a framed message");
+        let size: u32 = seq.next_element()?.ok_or_else(|| missing(0))?;
+        let typ = seq.next_element()?.ok_or_else(|| missing(1))?;
+        let tag = seq.next_element()?.ok_or_else(|| missing(2))?;
+        let len = (size as usize)
+            .checked_sub(RawMessage::HEADER_LEN)
+            .ok_or_else(|| {
+                de::Error::custom(Error::LengthUnderflow {
+                    min: RawMessage::HEADER_LEN,
+                    actual: size as usize,
+                })
+            })?;
+        let body = seq
+            .next_element_seed(ByteCount::<Cow<'a, [u8]>>::new(len))?
+            .ok_or_else(|| missing(3))?;
+        Ok(RawMessage { typ, tag, body })
+    }
+}
@@ -0,0 +1,368 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! The messages of 9P2000, as defined in Plan 9's `intro(5)`.
+//!
+//! Every message is a `Message`: the `size[4] type[1] tag[2]` header, whose
+//! size and type are filled in when it is serialized, followed by a `Body`
+//! holding one of the T or R message structs. Messages are little-endian on
+//! the wire, so use `to_bytes_le`, `from_bytes_le` and the like.
+//!
+//! The 9P2000.L and 9P2000.u dialects are in `ispf::p9l` and `ispf::p9u`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::FixedSize;
+
+/// The version string of the protocol.
+pub const VERSION: &str = "9P2000";
+
+/// The tag of a `Tversion`, which is sent before any tags are in use.
+pub const NOTAG: u16 = !0;
+
+/// The fid standing for no fid, as the `afid` of an unauthenticated attach.
+pub const NOFID: u32 = !0;
+
+/// The length of the size, type and tag fields.
+pub const HEADER_LEN: usize = 7;
+
+/// Bits of `Qid::typ`.
+pub const QTDIR: u8 = 0x80;
+pub const QTAPPEND: u8 = 0x40;
+pub const QTEXCL: u8 = 0x20;
+pub const QTAUTH: u8 = 0x08;
+pub const QTTMP: u8 = 0x04;
+pub const QTFILE: u8 = 0x00;
+
+/// Bits of `Stat::mode` and `Tcreate::perm` above the permission bits.
+pub const DMDIR: u32 = 0x8000_0000;
+pub const DMAPPEND: u32 = 0x4000_0000;
+pub const DMEXCL: u32 = 0x2000_0000;
+pub const DMAUTH: u32 = 0x0800_0000;
+pub const DMTMP: u32 = 0x0400_0000;
+
+/// Values of `Topen::mode` and `Tcreate::mode`, one of the first four
+/// combined with any of the rest.
+pub const OREAD: u8 = 0;
+pub const OWRITE: u8 = 1;
+pub const ORDWR: u8 = 2;
+pub const OEXEC: u8 = 3;
+pub const OTRUNC: u8 = 0x10;
+pub const ORCLOSE: u8 = 0x40;
+
+/// The server's unique identification for a file.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    FixedSize,
+)]
+pub struct Qid {
+    pub typ: u8,
+    pub version: u32,
+    pub path: u64,
+}
+
+/// The attributes of a file, as read by `Tstat` and written by `Twstat`. On
+/// the wire it is preceded by its size in two bytes, which is left out here.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stat {
+    pub typ: u16,
+    pub dev: u32,
+    pub qid: Qid,
+    pub mode: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub length: u64,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    #[serde(with = "crate::str_lv16")]
+    pub uid: String,
+    #[serde(with = "crate::str_lv16")]
+    pub gid: String,
+    #[serde(with = "crate::str_lv16")]
+    pub muid: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tversion {
+    pub msize: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub version: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rversion {
+    pub msize: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub version: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tauth {
+    pub afid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub uname: String,
+    #[serde(with = "crate::str_lv16")]
+    pub aname: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rauth {
+    pub aqid: Qid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rerror {
+    #[serde(with = "crate::str_lv16")]
+    pub ename: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tflush {
+    pub oldtag: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rflush;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tattach {
+    pub fid: u32,
+    pub afid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub uname: String,
+    #[serde(with = "crate::str_lv16")]
+    pub aname: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rattach {
+    pub qid: Qid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Twalk {
+    pub fid: u32,
+    pub newfid: u32,
+    #[serde(with = "crate::str_list_lv16")]
+    pub wnames: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rwalk {
+    #[serde(with = "crate::vec_lv16")]
+    pub qids: Vec<Qid>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topen {
+    pub fid: u32,
+    pub mode: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ropen {
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tcreate {
+    pub fid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub perm: u32,
+    pub mode: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rcreate {
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tread {
+    pub fid: u32,
+    pub offset: u64,
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rread {
+    #[serde(with = "crate::bytes_lv32")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Twrite {
+    pub fid: u32,
+    pub offset: u64,
+    #[serde(with = "crate::bytes_lv32")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rwrite {
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tclunk {
+    pub fid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rclunk;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tremove {
+    pub fid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rremove;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tstat {
+    pub fid: u32,
+}
+
+/// The stat is preceded by both its size and, as the protocol requires, the
+/// size of that again.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rstat {
+    #[serde(with = "crate::sized_lv16_double")]
+    pub stat: Stat,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Twstat {
+    pub fid: u32,
+    #[serde(with = "crate::sized_lv16_double")]
+    pub stat: Stat,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rwstat;
+
+/// Define `Body` over the given message structs and their type codes, and
+/// `Message`, the header followed by a `Body`, for one dialect of 9P.
+macro_rules! messages {
+    ($dialect:literal; $($name:ident = $typ:literal),* $(,)?) => {
+        /// The part of a message after its header, chosen by the type field.
+        #[derive(Clone, Debug, PartialEq, Eq, crate::Ispf)]
+        #[repr(u8)]
+        pub enum Body {
+            $($name($name) = $typ,)*
+        }
+
+        impl Body {
+            /// The type code of the message.
+            pub fn typ(&self) -> u8 {
+                match self {
+                    $(Body::$name(_) => $typ,)*
+                }
+            }
+        }
+
+        $(
+            impl From<$name> for Body {
+                fn from(m: $name) -> Body {
+                    Body::$name(m)
+                }
+            }
+        )*
+
+        #[doc = concat!("A whole ", $dialect, " message.")]
+        #[derive(Clone, Debug, PartialEq, Eq, crate::Ispf)]
+        pub struct Message {
+            #[ispf(size_of = "message", validate)]
+            pub size: u32,
+            pub typ: u8,
+            pub tag: u16,
+            #[ispf(tag_from = "typ")]
+            pub body: Body,
+        }
+
+        impl Message {
+            /// A message with the given tag. The size is filled in when it
+            /// is serialized.
+            pub fn new(tag: u16, body: impl Into<Body>) -> Self {
+                let body = body.into();
+                Message {
+                    size: 0,
+                    typ: body.typ(),
+                    tag,
+                    body,
+                }
+            }
+        }
+
+        /// The payload of a message is the data of an `Rread` or `Twrite`,
+        /// so that it can be sent with `to_vectored` rather than copied.
+        impl crate::Payload for Message {
+            fn payload(&self) -> Option<&[u8]> {
+                match &self.body {
+                    Body::Rread(m) => Some(&m.data),
+                    Body::Twrite(m) => Some(&m.data),
+                    _ => None,
+                }
+            }
+        }
+
+        #[cfg(feature = "futures")]
+        impl crate::futures::MessageTag for Message {
+            fn tag(&self) -> u16 {
+                self.tag
+            }
+
+            fn set_tag(&mut self, tag: u16) {
+                self.tag = tag;
+            }
+        }
+    };
+}
+
+pub(crate) use messages;
+
+messages! {
+    "9P2000";
+    Tversion = 100,
+    Rversion = 101,
+    Tauth = 102,
+    Rauth = 103,
+    Tattach = 104,
+    Rattach = 105,
+    Rerror = 107,
+    Tflush = 108,
+    Rflush = 109,
+    Twalk = 110,
+    Rwalk = 111,
+    Topen = 112,
+    Ropen = 113,
+    Tcreate = 114,
+    Rcreate = 115,
+    Tread = 116,
+    Rread = 117,
+    Twrite = 118,
+    Rwrite = 119,
+    Tclunk = 120,
+    Rclunk = 121,
+    Tremove = 122,
+    Rremove = 123,
+    Tstat = 124,
+    Rstat = 125,
+    Twstat = 126,
+    Rwstat = 127,
+}
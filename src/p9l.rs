@@ -0,0 +1,463 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! The messages of 9P2000.L, the dialect of 9P spoken by Linux's v9fs.
+//!
+//! It keeps the version, flush, walk, read, write, clunk and remove messages
+//! of 9P2000, which are shared with `ispf::p9`. Authentication and attaching
+//! name the user by number too, errors are reported as Linux errno values
+//! with `Rlerror`, and the rest of 9P2000 gives way to messages modelled on
+//! Linux system calls. Framing is as for `p9::Message`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::p9::messages;
+pub use crate::p9::{
+    Qid, Rattach, Rauth, Rclunk, Rflush, Rread, Rremove, Rversion, Rwalk,
+    Rwrite, Tclunk, Tflush, Tread, Tremove, Tversion, Twalk, Twrite, NOFID,
+    NOTAG,
+};
+use crate::{FixedSize, WireSize};
+
+/// The version string of the protocol.
+pub const VERSION: &str = "9P2000.L";
+
+/// The user standing for no user, as the `n_uname` of a peer that names
+/// users only by `uname`.
+pub const NONUNAME: u32 = !0;
+
+/// Bits of `Tgetattr::request_mask` and `Rgetattr::valid`.
+pub const GETATTR_MODE: u64 = 0x0000_0001;
+pub const GETATTR_NLINK: u64 = 0x0000_0002;
+pub const GETATTR_UID: u64 = 0x0000_0004;
+pub const GETATTR_GID: u64 = 0x0000_0008;
+pub const GETATTR_RDEV: u64 = 0x0000_0010;
+pub const GETATTR_ATIME: u64 = 0x0000_0020;
+pub const GETATTR_MTIME: u64 = 0x0000_0040;
+pub const GETATTR_CTIME: u64 = 0x0000_0080;
+pub const GETATTR_INO: u64 = 0x0000_0100;
+pub const GETATTR_SIZE: u64 = 0x0000_0200;
+pub const GETATTR_BLOCKS: u64 = 0x0000_0400;
+pub const GETATTR_BTIME: u64 = 0x0000_0800;
+pub const GETATTR_GEN: u64 = 0x0000_1000;
+pub const GETATTR_DATA_VERSION: u64 = 0x0000_2000;
+pub const GETATTR_BASIC: u64 = 0x0000_07ff;
+pub const GETATTR_ALL: u64 = 0x0000_3fff;
+
+/// Bits of `Tsetattr::valid`.
+pub const SETATTR_MODE: u32 = 0x0000_0001;
+pub const SETATTR_UID: u32 = 0x0000_0002;
+pub const SETATTR_GID: u32 = 0x0000_0004;
+pub const SETATTR_SIZE: u32 = 0x0000_0008;
+pub const SETATTR_ATIME: u32 = 0x0000_0010;
+pub const SETATTR_MTIME: u32 = 0x0000_0020;
+pub const SETATTR_CTIME: u32 = 0x0000_0040;
+pub const SETATTR_ATIME_SET: u32 = 0x0000_0080;
+pub const SETATTR_MTIME_SET: u32 = 0x0000_0100;
+
+/// Values of `Tlock::typ`, `Tgetlock::typ` and `Rgetlock::typ`.
+pub const LOCK_TYPE_RDLCK: u8 = 0;
+pub const LOCK_TYPE_WRLCK: u8 = 1;
+pub const LOCK_TYPE_UNLCK: u8 = 2;
+
+/// Values of `Rlock::status`.
+pub const LOCK_SUCCESS: u8 = 0;
+pub const LOCK_BLOCKED: u8 = 1;
+pub const LOCK_ERROR: u8 = 2;
+pub const LOCK_GRACE: u8 = 3;
+
+/// Bits of `Tlock::flags`.
+pub const LOCK_FLAGS_BLOCK: u32 = 1;
+pub const LOCK_FLAGS_RECLAIM: u32 = 2;
+
+/// One entry of the data of an `Rreaddir`. The offset is that of the next
+/// entry, to be passed to the `Treaddir` that continues after this one.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dirent {
+    pub qid: Qid,
+    pub offset: u64,
+    pub typ: u8,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+}
+
+impl WireSize for Dirent {
+    fn wire_size(&self) -> usize {
+        Qid::WIRE_SIZE + 8 + 1 + 2 + self.name.len()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rlerror {
+    pub ecode: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tauth {
+    pub afid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub uname: String,
+    #[serde(with = "crate::str_lv16")]
+    pub aname: String,
+    pub n_uname: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tattach {
+    pub fid: u32,
+    pub afid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub uname: String,
+    #[serde(with = "crate::str_lv16")]
+    pub aname: String,
+    pub n_uname: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tstatfs {
+    pub fid: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rstatfs {
+    pub typ: u32,
+    pub bsize: u32,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub fsid: u64,
+    pub namelen: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tlopen {
+    pub fid: u32,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rlopen {
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tlcreate {
+    pub fid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub flags: u32,
+    pub mode: u32,
+    pub gid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rlcreate {
+    pub qid: Qid,
+    pub iounit: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tsymlink {
+    pub fid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    #[serde(with = "crate::str_lv16")]
+    pub symtgt: String,
+    pub gid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rsymlink {
+    pub qid: Qid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tmknod {
+    pub dfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub mode: u32,
+    pub major: u32,
+    pub minor: u32,
+    pub gid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rmknod {
+    pub qid: Qid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trename {
+    pub fid: u32,
+    pub dfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rrename;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Treadlink {
+    pub fid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rreadlink {
+    #[serde(with = "crate::str_lv16")]
+    pub target: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tgetattr {
+    pub fid: u32,
+    pub request_mask: u64,
+}
+
+/// The attributes of a file. Only those whose bits are set in `valid` are
+/// meaningful.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rgetattr {
+    pub valid: u64,
+    pub qid: Qid,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u64,
+    pub rdev: u64,
+    pub size: u64,
+    pub blksize: u64,
+    pub blocks: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+    pub ctime_sec: u64,
+    pub ctime_nsec: u64,
+    pub btime_sec: u64,
+    pub btime_nsec: u64,
+    pub gen: u64,
+    pub data_version: u64,
+}
+
+/// New attributes for a file. Only those whose bits are set in `valid` are
+/// changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tsetattr {
+    pub fid: u32,
+    pub valid: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    pub atime_sec: u64,
+    pub atime_nsec: u64,
+    pub mtime_sec: u64,
+    pub mtime_nsec: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rsetattr;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Txattrwalk {
+    pub fid: u32,
+    pub newfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rxattrwalk {
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Txattrcreate {
+    pub fid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub attr_size: u64,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rxattrcreate;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Treaddir {
+    pub fid: u32,
+    pub offset: u64,
+    pub count: u32,
+}
+
+/// The entries of a directory, preceded by their size in bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rreaddir {
+    #[serde(with = "crate::vec_lv32b")]
+    pub entries: Vec<Dirent>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tfsync {
+    pub fid: u32,
+    pub datasync: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rfsync;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tlock {
+    pub fid: u32,
+    pub typ: u8,
+    pub flags: u32,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub client_id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rlock {
+    pub status: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tgetlock {
+    pub fid: u32,
+    pub typ: u8,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub client_id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rgetlock {
+    pub typ: u8,
+    pub start: u64,
+    pub length: u64,
+    pub proc_id: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub client_id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tlink {
+    pub dfid: u32,
+    pub fid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rlink;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tmkdir {
+    pub dfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub mode: u32,
+    pub gid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rmkdir {
+    pub qid: Qid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trenameat {
+    pub olddirfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub oldname: String,
+    pub newdirfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub newname: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rrenameat;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tunlinkat {
+    pub dirfid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Runlinkat;
+
+messages! {
+    "9P2000.L";
+    Rlerror = 7,
+    Tstatfs = 8,
+    Rstatfs = 9,
+    Tlopen = 12,
+    Rlopen = 13,
+    Tlcreate = 14,
+    Rlcreate = 15,
+    Tsymlink = 16,
+    Rsymlink = 17,
+    Tmknod = 18,
+    Rmknod = 19,
+    Trename = 20,
+    Rrename = 21,
+    Treadlink = 22,
+    Rreadlink = 23,
+    Tgetattr = 24,
+    Rgetattr = 25,
+    Tsetattr = 26,
+    Rsetattr = 27,
+    Txattrwalk = 30,
+    Rxattrwalk = 31,
+    Txattrcreate = 32,
+    Rxattrcreate = 33,
+    Treaddir = 40,
+    Rreaddir = 41,
+    Tfsync = 50,
+    Rfsync = 51,
+    Tlock = 52,
+    Rlock = 53,
+    Tgetlock = 54,
+    Rgetlock = 55,
+    Tlink = 70,
+    Rlink = 71,
+    Tmkdir = 72,
+    Rmkdir = 73,
+    Trenameat = 74,
+    Rrenameat = 75,
+    Tunlinkat = 76,
+    Runlinkat = 77,
+    Tversion = 100,
+    Rversion = 101,
+    Tauth = 102,
+    Rauth = 103,
+    Tattach = 104,
+    Rattach = 105,
+    Tflush = 108,
+    Rflush = 109,
+    Twalk = 110,
+    Rwalk = 111,
+    Tread = 116,
+    Rread = 117,
+    Twrite = 118,
+    Rwrite = 119,
+    Tclunk = 120,
+    Rclunk = 121,
+    Tremove = 122,
+    Rremove = 123,
+}
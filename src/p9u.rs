@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! The messages of 9P2000.u, the Unix extension of 9P2000.
+//!
+//! The message types are those of 9P2000, so a server picks `p9::Message` or
+//! `p9u::Message` by the version agreed with `Tversion`. Most messages are
+//! unchanged and shared with `ispf::p9`. The stat adds a Unix extension
+//! string and numeric ids, `Tcreate` the extension of a special file,
+//! `Rerror` an errno, and authentication and attaching a numeric user.
+//! Framing is as for `p9::Message`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::p9::messages;
+pub use crate::p9::{
+    Qid, Rattach, Rauth, Rclunk, Rcreate, Rflush, Ropen, Rread, Rremove,
+    Rversion, Rwalk, Rwrite, Rwstat, Tclunk, Tflush, Topen, Tread, Tremove,
+    Tstat, Tversion, Twalk, Twrite, NOFID, NOTAG,
+};
+pub use crate::p9l::{Tattach, Tauth, NONUNAME};
+
+/// The version string of the protocol.
+pub const VERSION: &str = "9P2000.u";
+
+/// Bits of `Qid::typ` added by the extension.
+pub const QTSYMLINK: u8 = 0x02;
+pub const QTLINK: u8 = 0x01;
+
+/// Bits of `Stat::mode` and `Tcreate::perm` added by the extension.
+pub const DMSYMLINK: u32 = 0x0200_0000;
+pub const DMLINK: u32 = 0x0100_0000;
+pub const DMDEVICE: u32 = 0x0080_0000;
+pub const DMNAMEDPIPE: u32 = 0x0020_0000;
+pub const DMSOCKET: u32 = 0x0010_0000;
+pub const DMSETUID: u32 = 0x0008_0000;
+pub const DMSETGID: u32 = 0x0004_0000;
+
+/// The attributes of a file, as for `p9::Stat`, followed by what a Unix
+/// server adds. The extension holds the target of a symbolic link or the
+/// type and numbers of a device, as in `c 1 3`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Stat {
+    pub typ: u16,
+    pub dev: u32,
+    pub qid: Qid,
+    pub mode: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub length: u64,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    #[serde(with = "crate::str_lv16")]
+    pub uid: String,
+    #[serde(with = "crate::str_lv16")]
+    pub gid: String,
+    #[serde(with = "crate::str_lv16")]
+    pub muid: String,
+    #[serde(with = "crate::str_lv16")]
+    pub extension: String,
+    pub n_uid: u32,
+    pub n_gid: u32,
+    pub n_muid: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rerror {
+    #[serde(with = "crate::str_lv16")]
+    pub ename: String,
+    pub errno: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tcreate {
+    pub fid: u32,
+    #[serde(with = "crate::str_lv16")]
+    pub name: String,
+    pub perm: u32,
+    pub mode: u8,
+    #[serde(with = "crate::str_lv16")]
+    pub extension: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rstat {
+    #[serde(with = "crate::sized_lv16_double")]
+    pub stat: Stat,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Twstat {
+    pub fid: u32,
+    #[serde(with = "crate::sized_lv16_double")]
+    pub stat: Stat,
+}
+
+messages! {
+    "9P2000.u";
+    Tversion = 100,
+    Rversion = 101,
+    Tauth = 102,
+    Rauth = 103,
+    Tattach = 104,
+    Rattach = 105,
+    Rerror = 107,
+    Tflush = 108,
+    Rflush = 109,
+    Twalk = 110,
+    Rwalk = 111,
+    Topen = 112,
+    Ropen = 113,
+    Tcreate = 114,
+    Rcreate = 115,
+    Tread = 116,
+    Rread = 117,
+    Twrite = 118,
+    Rwrite = 119,
+    Tclunk = 120,
+    Rclunk = 121,
+    Tremove = 122,
+    Rremove = 123,
+    Tstat = 124,
+    Rstat = 125,
+    Twstat = 126,
+    Rwstat = 127,
+}
@@ -0,0 +1,709 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Support code for the `ispf-macros` derives. Nothing in here is part of the
+//! public API.
+
+use std::convert::TryFrom;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+
+pub use serde;
+
+use serde::de::{self, DeserializeSeed};
+use serde::ser;
+
+use crate::checksum::Checksum;
+use crate::error::Error;
+use crate::lv::{DeserializeLv, SerializeLv, Width};
+
+pub use crate::de::{TlvBytesVisitor, TlvStringVisitor, TlvVecVisitor};
+pub use crate::ser::RawBytes;
+
+/// `e` as the error type of the deserializer in use, typed for an ispf one.
+pub fn de_error<E: de::Error>(e: Error) -> E {
+    e.into_de()
+}
+
+/// An integer field that holds the length of another field.
+pub trait CountField: Sized + Copy {
+    const MAX_LEN: usize;
+
+    fn from_len<E: ser::Error>(len: usize) -> Result<Self, E>;
+    fn to_len<E: de::Error>(self) -> Result<usize, E>;
+}
+
+macro_rules! count_field {
+    ($($t:ty),*) => {$(
+        impl CountField for $t {
+            const MAX_LEN: usize = if (<$t>::MAX as u128) < usize::MAX as u128 {
+                <$t>::MAX as usize
+            } else {
+                usize::MAX
+            };
+
+            fn from_len<E: ser::Error>(len: usize) -> Result<Self, E> {
+                <$t>::try_from(len).map_err(|_| {
+                    E::custom(Error::LengthOverflow {
+                        max: Self::MAX_LEN,
+                        actual: len,
+                    })
+                })
+            }
+
+            fn to_len<E: de::Error>(self) -> Result<usize, E> {
+                usize::try_from(self).map_err(|_| {
+                    E::custom(format!("length {} does not fit in memory", self))
+                })
+            }
+        }
+    )*};
+}
+
+count_field!(u8, u16, u32, u64);
+
+/// The value stored in a length field whose content is off from the actual
+/// length by `adjust`.
+pub fn adjust_len<E: ser::Error>(len: usize, adjust: i64) -> Result<usize, E> {
+    let stored = len as i128 + adjust as i128;
+    usize::try_from(stored).map_err(|_| {
+        if stored < 0 {
+            E::custom(Error::LengthUnderflow {
+                min: adjust.unsigned_abs() as usize,
+                actual: len,
+            })
+        } else {
+            E::custom(Error::LengthOverflow {
+                max: usize::MAX,
+                actual: len,
+            })
+        }
+    })
+}
+
+/// The actual length described by a length field whose content is off by
+/// `adjust`.
+pub fn unadjust_len<E: de::Error>(
+    stored: usize,
+    adjust: i64,
+) -> Result<usize, E> {
+    let len = stored as i128 - adjust as i128;
+    usize::try_from(len).map_err(|_| {
+        if len < 0 {
+            E::custom(Error::LengthUnderflow {
+                min: adjust as usize,
+                actual: stored,
+            })
+        } else {
+            E::custom(Error::LengthOverflow {
+                max: usize::MAX,
+                actual: stored,
+            })
+        }
+    })
+}
+
+/// The number of elements in a collection.
+pub fn len<'a, C>(v: &'a C) -> usize
+where
+    C: ?Sized,
+    &'a C: IntoIterator,
+{
+    v.into_iter().count()
+}
+
+/// The number of bytes in a byte buffer.
+pub fn byte_len<T: ?Sized + AsRef<[u8]>>(v: &T) -> usize {
+    v.as_ref().len()
+}
+
+/// Deserialize a collection of exactly `len` elements with no prefix of its
+/// own, its length having been read from another field.
+pub struct Counted<C, T> {
+    len: usize,
+    phantom: PhantomData<(C, T)>,
+}
+
+impl<C, T> Counted<C, T> {
+    pub fn new(len: usize) -> Self {
+        Counted {
+            len,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, C, T> DeserializeSeed<'de> for Counted<C, T>
+where
+    C: FromIterator<T>,
+    T: de::Deserialize<'de>,
+{
+    type Value = C;
+
+    fn deserialize<D>(self, d: D) -> Result<C, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_tuple(self.len, crate::de::TlvVecVisitor::new())
+    }
+}
+
+/// A byte buffer that can be built from bytes borrowed from the input.
+pub trait FromBytes<'de> {
+    fn from_bytes(b: &'de [u8]) -> Self;
+}
+
+impl<'de: 'a, 'a> FromBytes<'de> for &'a [u8] {
+    fn from_bytes(b: &'de [u8]) -> Self {
+        b
+    }
+}
+
+impl<'de> FromBytes<'de> for Vec<u8> {
+    fn from_bytes(b: &'de [u8]) -> Self {
+        b.to_vec()
+    }
+}
+
+impl<'de> FromBytes<'de> for Box<[u8]> {
+    fn from_bytes(b: &'de [u8]) -> Self {
+        b.into()
+    }
+}
+
+impl<'de: 'a, 'a> FromBytes<'de> for std::borrow::Cow<'a, [u8]> {
+    fn from_bytes(b: &'de [u8]) -> Self {
+        std::borrow::Cow::Borrowed(b)
+    }
+}
+
+/// Deserialize exactly `len` bytes with no prefix of their own, their length
+/// having been read from another field.
+pub struct ByteCount<T> {
+    len: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T> ByteCount<T> {
+    pub fn new(len: usize) -> Self {
+        ByteCount {
+            len,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: FromBytes<'de>> DeserializeSeed<'de> for ByteCount<T> {
+    type Value = T;
+
+    fn deserialize<D>(self, d: D) -> Result<T, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct("raw", self.len, self)
+    }
+}
+
+impl<'de, T: FromBytes<'de>> de::Visitor<'de> for ByteCount<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} bytes", self.len)
+    }
+
+    fn visit_borrowed_bytes<E>(self, b: &'de [u8]) -> Result<T, E> {
+        Ok(T::from_bytes(b))
+    }
+}
+
+/// Deserialize all remaining bytes.
+pub struct Rest<T> {
+    phantom: PhantomData<T>,
+}
+
+impl<T> Rest<T> {
+    pub fn new() -> Self {
+        Rest {
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for Rest<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'de, T: FromBytes<'de>> DeserializeSeed<'de> for Rest<T> {
+    type Value = T;
+
+    fn deserialize<D>(self, d: D) -> Result<T, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_bytes(self)
+    }
+}
+
+impl<'de, T: FromBytes<'de>> de::Visitor<'de> for Rest<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("remaining bytes")
+    }
+
+    fn visit_borrowed_bytes<E>(self, b: &'de [u8]) -> Result<T, E> {
+        Ok(T::from_bytes(b))
+    }
+}
+
+/// A string or vector, bounded by the number of bytes each of its elements
+/// takes at most.
+pub trait LvElement {
+    const MAX_ELEMENT_SIZE: usize;
+}
+
+impl LvElement for String {
+    const MAX_ELEMENT_SIZE: usize = 1;
+}
+
+impl<T: crate::MaxSize> LvElement for Vec<T> {
+    const MAX_ELEMENT_SIZE: usize = T::MAX_WIRE_SIZE;
+}
+
+/// The largest encoding of a string or vector `T` holding at most `max_len`
+/// elements after a prefix of `prefix` bytes.
+pub const fn lv_max_size<T: LvElement>(prefix: usize, max_len: usize) -> usize {
+    prefix.saturating_add(max_len.saturating_mul(T::MAX_ELEMENT_SIZE))
+}
+
+/// Reserve a size field `width` bytes wide, filled in with the length of the
+/// enclosing message.
+pub struct SizeField(pub usize);
+
+impl ser::Serialize for SizeField {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ser::SerializeTupleStruct;
+        s.serialize_tuple_struct("sizefield", self.0)?.end()
+    }
+}
+
+/// Serialize a vector after its encoded length in bytes, in a prefix of the
+/// given width.
+pub struct LvBytes<'a, T>(pub &'a T, pub Width);
+
+impl<'a, T: ser::Serialize> ser::Serialize for LvBytes<'a, T> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ser::SerializeTupleStruct;
+        let mut t = s.serialize_tuple_struct(self.1.sized(), 1)?;
+        t.serialize_field(self.0)?;
+        t.end()
+    }
+}
+
+/// Deserialize a vector after its encoded length in bytes, the counterpart
+/// of `LvBytes`.
+pub struct LvBytesSeed<T> {
+    width: Width,
+    phantom: PhantomData<T>,
+}
+
+impl<T> LvBytesSeed<T> {
+    pub fn new(width: Width) -> Self {
+        LvBytesSeed {
+            width,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>> DeserializeSeed<'de>
+    for LvBytesSeed<Vec<T>>
+{
+    type Value = Vec<T>;
+
+    fn deserialize<D>(self, d: D) -> Result<Vec<T>, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct(
+            self.width.vec_bytes(),
+            2,
+            crate::de::TlvVecVisitor::new(),
+        )
+    }
+}
+
+/// A field that can be packed into some of the bits of a larger integer.
+pub trait BitField: Copy {
+    fn to_bits(self) -> u64;
+    fn from_bits(v: u64) -> Self;
+}
+
+impl BitField for bool {
+    fn to_bits(self) -> u64 {
+        u64::from(self)
+    }
+    fn from_bits(v: u64) -> Self {
+        v != 0
+    }
+}
+
+macro_rules! bit_field {
+    ($($t:ty),*) => {$(
+        impl BitField for $t {
+            fn to_bits(self) -> u64 {
+                u64::from(self)
+            }
+            fn from_bits(v: u64) -> Self {
+                v as $t
+            }
+        }
+    )*};
+}
+
+bit_field!(u8, u16, u32, u64);
+
+/// The bits of `value`, checked to fit in `width` bits.
+pub fn pack_bits<T: BitField, E: ser::Error>(
+    value: T,
+    width: u32,
+    field: &str,
+) -> Result<u64, E> {
+    let v = value.to_bits();
+    if width < 64 && v >> width != 0 {
+        return Err(E::custom(format_args!(
+            "value {} of field `{}` does not fit in {} bits",
+            v, field, width,
+        )));
+    }
+    Ok(v)
+}
+
+/// The `width` bits of `v` starting `shift` bits in.
+pub fn unpack_bits<T: BitField>(v: u64, shift: u32, width: u32) -> T {
+    let mask = if width < 64 {
+        (1 << width) - 1
+    } else {
+        u64::MAX
+    };
+    T::from_bits(v >> shift & mask)
+}
+
+/// Serialize a value as the only field of a tuple struct with the given name
+/// and length, as the frames of the serializer expect.
+pub struct Wrap<'a, T>(pub &'static str, pub usize, pub &'a T);
+
+impl<'a, T: ser::Serialize> ser::Serialize for Wrap<'a, T> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ser::SerializeTupleStruct;
+        let mut t = s.serialize_tuple_struct(self.0, self.1)?;
+        t.serialize_field(self.2)?;
+        t.end()
+    }
+}
+
+/// A mark between the fields of a struct, telling the serializer and
+/// deserializer where something starts or ends: "sumat" for the checksum
+/// field of an enclosing "checksum" value, and "sumfrom" and "sumto" for the
+/// bytes it covers. It takes nothing of the input.
+pub struct Mark(pub &'static str);
+
+impl ser::Serialize for Mark {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ser::SerializeTupleStruct;
+        s.serialize_tuple_struct(self.0, 0)?.end()
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for Mark {
+    type Value = ();
+
+    fn deserialize<D>(self, d: D) -> Result<(), D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct(self.0, 0, MarkVisitor)
+    }
+}
+
+struct MarkVisitor;
+
+impl<'de> de::Visitor<'de> for MarkVisitor {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a mark")
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A>(self, _: A) -> Result<(), A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        Ok(())
+    }
+}
+
+/// Deserialize a field that may be missing from the end of the input, taking
+/// its default value if so. `since` is the version that added it.
+pub struct Trailing<T> {
+    since: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Trailing<T> {
+    pub fn new(since: usize) -> Self {
+        Trailing {
+            since,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, T> DeserializeSeed<'de> for Trailing<T>
+where
+    T: de::Deserialize<'de> + Default,
+{
+    type Value = T;
+
+    fn deserialize<D>(self, d: D) -> Result<T, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct("since", self.since, self)
+    }
+}
+
+impl<'de, T> de::Visitor<'de> for Trailing<T>
+where
+    T: de::Deserialize<'de> + Default,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a field that may be missing")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<T, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        Ok(seq.next_element()?.unwrap_or_default())
+    }
+}
+
+/// Deserialize a field that starts at the next multiple of `align` bytes
+/// into the input, skipping the padding before it.
+pub struct Aligned<T> {
+    align: usize,
+    phantom: PhantomData<T>,
+}
+
+impl<T> Aligned<T> {
+    pub fn new(align: usize) -> Self {
+        Aligned {
+            align,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>> DeserializeSeed<'de> for Aligned<T> {
+    type Value = T;
+
+    fn deserialize<D>(self, d: D) -> Result<T, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_tuple_struct("align", self.align, self)
+    }
+}
+
+impl<'de, T: de::Deserialize<'de>> de::Visitor<'de> for Aligned<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "a field aligned to {} bytes", self.align)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<T, A::Error>
+    where
+        A: de::SeqAccess<'de>,
+    {
+        seq.next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))
+    }
+}
+
+/// The length of a "checksum" tuple struct.
+pub const fn checksum_spec(sum: Checksum, last: bool, over: bool) -> usize {
+    sum.spec(last, over)
+}
+
+/// Check the checksum field of `message`, at its start or, if `last`, its end.
+/// With `over`, `message` holds just the fields the checksum covers.
+pub fn verify_checksum<E: de::Error>(
+    sum: Checksum,
+    last: bool,
+    over: bool,
+    message: &[u8],
+    stored: u64,
+) -> Result<(), E> {
+    let covered = if over {
+        Some(message)
+    } else {
+        sum.covered(message, last)
+    };
+    let covered = covered.ok_or_else(|| {
+        Error::LengthUnderflow {
+            min: sum.width(),
+            actual: message.len(),
+        }
+        .into_de::<E>()
+    })?;
+    let computed = sum.compute(covered);
+    if computed != stored {
+        return Err(Error::ChecksumMismatch {
+            expected: computed,
+            actual: stored,
+        }
+        .into_de());
+    }
+    Ok(())
+}
+
+/// The largest of `sizes`, or zero if there are none.
+pub const fn max_size(sizes: &[usize]) -> usize {
+    let mut max = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        if sizes[i] > max {
+            max = sizes[i];
+        }
+        i += 1;
+    }
+    max
+}
+
+/// An enum whose tag can be kept apart from its body, so that it can live in
+/// another field.
+pub trait Tagged {
+    type Tag: Copy;
+
+    fn tag(&self) -> Self::Tag;
+}
+
+/// The serializing half of `Tagged`.
+pub trait TaggedSer: Tagged {
+    fn serialize_body<S: ser::Serializer>(
+        &self,
+        s: S,
+    ) -> Result<S::Ok, S::Error>;
+}
+
+/// The deserializing half of `Tagged`.
+pub trait TaggedDe<'de>: Tagged + Sized {
+    fn deserialize_body<D: de::Deserializer<'de>>(
+        tag: Self::Tag,
+        d: D,
+    ) -> Result<Self, D::Error>;
+}
+
+/// Convert the value of a header field to the tag of `T`.
+pub fn tag_from<T, V, E>(v: V) -> Result<T::Tag, E>
+where
+    T: Tagged,
+    T::Tag: TryFrom<V>,
+    V: Copy + std::fmt::Display,
+    E: de::Error,
+{
+    T::Tag::try_from(v)
+        .map_err(|_| E::custom(format!("tag {} is out of range", v)))
+}
+
+/// Serialize the body of a tagged enum without its tag.
+pub struct Body<'a, T>(pub &'a T);
+
+impl<'a, T: TaggedSer> ser::Serialize for Body<'a, T> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_body(s)
+    }
+}
+
+/// Deserialize the body of a tagged enum whose tag was read from another
+/// field.
+pub struct WithTag<T: Tagged> {
+    tag: T::Tag,
+}
+
+impl<T: Tagged> WithTag<T> {
+    pub fn new(tag: T::Tag) -> Self {
+        WithTag { tag }
+    }
+}
+
+impl<'de, T: TaggedDe<'de>> DeserializeSeed<'de> for WithTag<T> {
+    type Value = T;
+
+    fn deserialize<D>(self, d: D) -> Result<T, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        T::deserialize_body(self.tag, d)
+    }
+}
+
+/// Serialize a string or vector after a length prefix of the given width.
+pub struct Lv<'a, T>(pub &'a T, pub Width);
+
+impl<'a, T: SerializeLv> ser::Serialize for Lv<'a, T> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_lv(self.1, s)
+    }
+}
+
+/// Serialize bytes after a length prefix of the given width, as one run.
+pub struct LvRaw<'a>(pub &'a [u8], pub Width);
+
+impl ser::Serialize for LvRaw<'_> {
+    fn serialize<S: ser::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        use ser::SerializeTupleStruct;
+        let mut t = s.serialize_tuple_struct(self.1.bytes(), self.0.len())?;
+        t.serialize_field(&RawBytes(self.0))?;
+        t.end()
+    }
+}
+
+/// Deserialize a string or vector after a length prefix of the given width.
+pub struct LvSeed<T> {
+    width: Width,
+    phantom: PhantomData<T>,
+}
+
+impl<T> LvSeed<T> {
+    pub fn new(width: Width) -> Self {
+        LvSeed {
+            width,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'de, T: DeserializeLv<'de>> DeserializeSeed<'de> for LvSeed<T> {
+    type Value = T;
+
+    fn deserialize<D>(self, d: D) -> Result<T, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        T::deserialize_lv(self.width, d)
+    }
+}
@@ -0,0 +1,85 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::fmt;
+use std::ops::Range;
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{ser, Deserializer, Serializer};
+
+use crate::lv::{DeserializeLv, SerializeLv, Width};
+
+/// Where a length-prefixed byte field lies in the input it was decoded from,
+/// in place of a copy of its bytes.
+///
+/// A message holding a `ByteRange` owns nothing from the input, so it can be
+/// decoded with `DeserializeOwned` and the data written onward from the
+/// original buffer later. The offset counts from the start of the slice given
+/// to `from_bytes` and the like. Used with `lv` in the derive, or wrapped in
+/// `Lv32` and the like. A `ByteRange` cannot be serialized, as it has no bytes
+/// of its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ByteRange {
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl ByteRange {
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+
+    /// The bytes of the field, given the input it was decoded from.
+    pub fn get<'a>(&self, input: &'a [u8]) -> Option<&'a [u8]> {
+        input.get(self.range())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl SerializeLv for ByteRange {
+    fn serialize_lv<S: Serializer>(
+        &self,
+        _width: Width,
+        _s: S,
+    ) -> Result<S::Ok, S::Error> {
+        Err(ser::Error::custom("a ByteRange has no bytes to serialize"))
+    }
+}
+
+impl<'de> DeserializeLv<'de> for ByteRange {
+    fn deserialize_lv<D: Deserializer<'de>>(
+        width: Width,
+        d: D,
+    ) -> Result<Self, D::Error> {
+        d.deserialize_tuple_struct(width.range(), 2, ByteRangeVisitor)
+    }
+}
+
+struct ByteRangeVisitor;
+
+impl<'de> Visitor<'de> for ByteRangeVisitor {
+    type Value = ByteRange;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array prefixed by a length")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<ByteRange, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let offset = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let len = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(ByteRange { offset, len })
+    }
+}
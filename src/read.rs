@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::io;
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use crate::de::{Deserializer, NumDe};
+use crate::error::{Error, Result};
+use crate::Width;
+
+/// Decodes values one after another from a `std::io::Read`, such as a socket,
+/// through a buffer of its own.
+///
+/// A value is decoded from what has been buffered so far. When a field runs
+/// past the end of the buffer, reading goes on until the buffer holds all
+/// that field, then decoding starts over. A large length-prefixed field is
+/// so read whole before it is decoded again, while a value made of many
+/// small fields may be started over once per read. Reading never waits for
+/// more than the value is known to need, so a peer that sends one message
+/// and waits for the reply is not left waiting. Each value must mark its own
+/// end: a value ending in a field that takes the rest of the input would stop
+/// wherever the buffer happened to.
+pub struct ReadDeserializer<R, Endian: NumDe> {
+    r: R,
+    buf: Vec<u8>,
+    /// Where the bytes not yet decoded start in `buf`.
+    pos: usize,
+    eof: bool,
+    max_str_len: usize,
+    presence: (u8, u8),
+    variant_width: Width,
+    endian: PhantomData<Endian>,
+}
+
+impl<R: io::Read, Endian: NumDe> ReadDeserializer<R, Endian> {
+    /// The least read into the buffer at once.
+    const CHUNK: usize = 4 * 1024;
+
+    pub fn new(r: R) -> Self {
+        ReadDeserializer {
+            r,
+            buf: Vec::new(),
+            pos: 0,
+            eof: false,
+            max_str_len: usize::MAX,
+            presence: (0, 1),
+            variant_width: Width::U32,
+            endian: PhantomData,
+        }
+    }
+
+    /// As for `Deserializer::max_str_len`.
+    pub fn max_str_len(mut self, max: usize) -> Self {
+        self.max_str_len = max;
+        self
+    }
+
+    /// As for `Deserializer::presence_flags`.
+    pub fn presence_flags(mut self, absent: u8, present: u8) -> Self {
+        self.presence = (absent, present);
+        self
+    }
+
+    /// As for `Deserializer::variant_width`.
+    pub fn variant_width(mut self, width: Width) -> Self {
+        self.variant_width = width;
+        self
+    }
+
+    /// Decode the next value, or return `None` if the reader ends before it
+    /// starts. A reader ending part way through a value fails as decoding a
+    /// truncated slice would.
+    pub fn next_value<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        if self.pos == self.buf.len() {
+            self.fill_by(1)?;
+            if self.eof && self.pos == self.buf.len() {
+                return Ok(None);
+            }
+        }
+        loop {
+            let input = &self.buf[self.pos..];
+            let mut de = Deserializer::<Endian>::from_bytes(input)
+                .max_str_len(self.max_str_len)
+                .presence_flags(self.presence.0, self.presence.1)
+                .variant_width(self.variant_width)
+                .partial(!self.eof);
+            match T::deserialize(&mut de) {
+                Ok(value) => {
+                    self.pos += input.len() - de.remaining().len();
+                    return Ok(Some(value));
+                }
+                // Running out within bytes that are all buffered, such as
+                // a sized field, is malformed input and waits for nothing.
+                Err(Error::Eof) if !self.eof && de.needed() > input.len() => {
+                    let need = de.needed() - input.len();
+                    self.fill_by(need)?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The bytes read but not yet decoded.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Give back the reader. Anything buffered is lost.
+    pub fn into_inner(self) -> R {
+        self.r
+    }
+
+    /// Read at least `need` more bytes into the buffer, or up to the end of
+    /// the reader. A read may bring in more than is needed. The buffer grows
+    /// with what arrives rather than by `need` at once, which a corrupt
+    /// length could make huge.
+    fn fill_by(&mut self, need: usize) -> Result<()> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        let mut len = self.buf.len();
+        let target = len.saturating_add(need);
+        while len < target {
+            if len == self.buf.len() {
+                self.buf.resize(len + len.max(Self::CHUNK), 0);
+            }
+            match self.r.read(&mut self.buf[len..]) {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buf.truncate(len);
+                    return Err(Error::io(e));
+                }
+            }
+        }
+        self.buf.truncate(len);
+        Ok(())
+    }
+}
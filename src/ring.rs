@@ -0,0 +1,305 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Passing messages through a ring in memory shared by a producer and a
+//! consumer, such as a device emulator and its backend, with no system calls
+//! on the way.
+//!
+//! The region starts with `RING_HEADER_LEN` bytes holding the two indices,
+//! each on its own cache line, followed by the data area, whose length must
+//! be a power of two. The producer's index counts the bytes ever written and
+//! the consumer's the bytes ever read, both wrapping at `u32::MAX`. Each
+//! message is stored as its length in four bytes, then the message, padded
+//! to a multiple of four bytes, wrapping round the end of the data area.
+
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::de::NumDe;
+use crate::error::{Error, Result};
+use crate::ser::NumSer;
+use crate::{BigEndian, LittleEndian};
+
+/// The bytes at the start of a ring's region that hold its indices.
+pub const RING_HEADER_LEN: usize = 128;
+
+/// Where the consumer's index lies, a cache line after the producer's.
+const TAIL_OFFSET: usize = 64;
+
+/// The largest data area, so that the indices never get a whole lap apart.
+const MAX_CAPACITY: usize = 1 << 31;
+
+/// The part of a region shared by both ends.
+struct Region<'a> {
+    mem: *mut u8,
+    capacity: usize,
+    phantom: PhantomData<&'a mut [u8]>,
+}
+
+impl Region<'_> {
+    /// Check the layout of the `len` bytes at `mem`.
+    fn new(mem: *mut u8, len: usize) -> Result<Self> {
+        let capacity = len.saturating_sub(RING_HEADER_LEN);
+        if len < RING_HEADER_LEN
+            || !capacity.is_power_of_two()
+            || !(8..=MAX_CAPACITY).contains(&capacity)
+            || mem.align_offset(std::mem::align_of::<AtomicU32>()) != 0
+        {
+            return Err(Error::InvalidRing { len });
+        }
+        Ok(Region {
+            mem,
+            capacity,
+            phantom: PhantomData,
+        })
+    }
+
+    /// The producer's index.
+    fn head(&self) -> &AtomicU32 {
+        // SAFETY: the region was checked to be aligned and long enough, and
+        // the index is only ever accessed atomically.
+        unsafe { &*(self.mem as *const AtomicU32) }
+    }
+
+    /// The consumer's index.
+    fn tail(&self) -> &AtomicU32 {
+        // SAFETY: as for `head`.
+        unsafe { &*(self.mem.add(TAIL_OFFSET) as *const AtomicU32) }
+    }
+
+    /// The error for indices that no producer and consumer could have left.
+    fn corrupt(&self) -> Error {
+        Error::InvalidRing {
+            len: RING_HEADER_LEN + self.capacity,
+        }
+    }
+
+    /// Copy `b` into the data area at index `at`, wrapping round its end.
+    fn write(&self, at: u32, b: &[u8]) {
+        let at = at as usize & (self.capacity - 1);
+        let first = b.len().min(self.capacity - at);
+        // SAFETY: both copies stay within the data area, and the indices
+        // keep the consumer off bytes the producer has not yet published.
+        unsafe {
+            let data = self.mem.add(RING_HEADER_LEN);
+            ptr::copy_nonoverlapping(b.as_ptr(), data.add(at), first);
+            ptr::copy_nonoverlapping(
+                b[first..].as_ptr(),
+                data,
+                b.len() - first,
+            );
+        }
+    }
+
+    /// Copy the bytes at index `at` into `b`, wrapping round the end of the
+    /// data area.
+    fn read(&self, at: u32, b: &mut [u8]) {
+        let at = at as usize & (self.capacity - 1);
+        let first = b.len().min(self.capacity - at);
+        // SAFETY: as for `write`, with the roles swapped.
+        unsafe {
+            let data = self.mem.add(RING_HEADER_LEN);
+            ptr::copy_nonoverlapping(data.add(at), b.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(
+                data,
+                b[first..].as_mut_ptr(),
+                b.len() - first,
+            );
+        }
+    }
+}
+
+/// The number of bytes a message of `len` bytes takes in the ring.
+fn record_len(len: usize) -> usize {
+    4 + ((len + 3) & !3)
+}
+
+/// The end of a ring that writes messages, made by `ring` or `from_raw`.
+pub struct RingProducer<'a, Endian> {
+    region: Region<'a>,
+    buf: Vec<u8>,
+    endian: PhantomData<fn() -> Endian>,
+}
+
+/// The end of a ring that reads messages, made by `ring` or `from_raw`.
+pub struct RingConsumer<'a, Endian> {
+    region: Region<'a>,
+    buf: Vec<u8>,
+    endian: PhantomData<fn() -> Endian>,
+}
+
+// SAFETY: each end only writes the bytes the indices give it, so the two
+// can be used from different threads.
+unsafe impl<Endian> Send for RingProducer<'_, Endian> {}
+unsafe impl<Endian> Send for RingConsumer<'_, Endian> {}
+
+/// Lay out a ring over `mem` and return both of its ends, for a ring used
+/// within one process. `mem` must be aligned to four bytes, and its length
+/// must be `RING_HEADER_LEN` plus a power of two of at least eight. Any
+/// messages left in `mem` are discarded.
+pub fn ring<Endian>(
+    mem: &mut [u8],
+) -> Result<(RingProducer<'_, Endian>, RingConsumer<'_, Endian>)> {
+    let len = mem.len();
+    let mem = mem.as_mut_ptr();
+    let region = Region::new(mem, len)?;
+    region.head().store(0, Ordering::Relaxed);
+    region.tail().store(0, Ordering::Relaxed);
+    Ok((
+        RingProducer {
+            region,
+            buf: Vec::new(),
+            endian: PhantomData,
+        },
+        RingConsumer {
+            region: Region::new(mem, len)?,
+            buf: Vec::new(),
+            endian: PhantomData,
+        },
+    ))
+}
+
+pub fn ring_le(
+    mem: &mut [u8],
+) -> Result<(
+    RingProducer<'_, LittleEndian>,
+    RingConsumer<'_, LittleEndian>,
+)> {
+    ring::<LittleEndian>(mem)
+}
+
+pub fn ring_be(
+    mem: &mut [u8],
+) -> Result<(RingProducer<'_, BigEndian>, RingConsumer<'_, BigEndian>)> {
+    ring::<BigEndian>(mem)
+}
+
+impl<Endian: NumSer> RingProducer<'_, Endian> {
+    /// The producing end of a ring in the `len` bytes at `mem`, laid out as
+    /// for `ring`. A fresh region must start zeroed, as newly mapped shared
+    /// memory is.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must stay valid for reads and writes of `len` bytes for as long
+    /// as the producer lives, and no other producer may use the region at
+    /// the same time.
+    pub unsafe fn from_raw(mem: *mut u8, len: usize) -> Result<Self> {
+        Ok(RingProducer {
+            region: Region::new(mem, len)?,
+            buf: Vec::new(),
+            endian: PhantomData,
+        })
+    }
+
+    /// Serialize `value` into the ring, or return `false` if there is not
+    /// yet room for it. A message that could never fit fails, as do indices
+    /// further apart than the data area, which only a corrupt region holds.
+    pub fn try_send<T: Serialize>(&mut self, value: &T) -> Result<bool> {
+        self.buf.clear();
+        crate::to_bytes_into::<Endian, T>(value, &mut self.buf)?;
+        let need = record_len(self.buf.len());
+        if need > self.region.capacity {
+            return Err(Error::LengthOverflow {
+                max: self.region.capacity - 4,
+                actual: self.buf.len(),
+            });
+        }
+        let head = self.region.head().load(Ordering::Relaxed);
+        let tail = self.region.tail().load(Ordering::Acquire);
+        let used = head.wrapping_sub(tail) as usize;
+        if used > self.region.capacity {
+            return Err(self.region.corrupt());
+        }
+        if self.region.capacity - used < need {
+            return Ok(false);
+        }
+        let len = Endian::serialize_u32(self.buf.len() as u32);
+        self.region.write(head, &len);
+        self.region.write(head.wrapping_add(4), &self.buf);
+        self.region
+            .head()
+            .store(head.wrapping_add(need as u32), Ordering::Release);
+        Ok(true)
+    }
+
+    /// The number of bytes free in the data area.
+    pub fn free(&self) -> usize {
+        let head = self.region.head().load(Ordering::Relaxed);
+        let tail = self.region.tail().load(Ordering::Acquire);
+        self.region
+            .capacity
+            .saturating_sub(head.wrapping_sub(tail) as usize)
+    }
+}
+
+impl<Endian: NumDe> RingConsumer<'_, Endian> {
+    /// The consuming end of a ring in the `len` bytes at `mem`, laid out as
+    /// for `ring`. A fresh region must start zeroed, as newly mapped shared
+    /// memory is.
+    ///
+    /// # Safety
+    ///
+    /// `mem` must stay valid for reads and writes of `len` bytes for as long
+    /// as the consumer lives, and no other consumer may use the region at
+    /// the same time.
+    pub unsafe fn from_raw(mem: *mut u8, len: usize) -> Result<Self> {
+        Ok(RingConsumer {
+            region: Region::new(mem, len)?,
+            buf: Vec::new(),
+            endian: PhantomData,
+        })
+    }
+
+    /// Take and decode the next message, or return `None` if the ring is
+    /// empty. A message that fails to decode is still taken. Indices further
+    /// apart than the data area, or a length that runs past what the
+    /// producer has written, fail and go on failing, as the ring cannot be
+    /// followed past them.
+    pub fn try_recv<T: DeserializeOwned>(&mut self) -> Result<Option<T>> {
+        let tail = self.region.tail().load(Ordering::Relaxed);
+        let head = self.region.head().load(Ordering::Acquire);
+        let ready = head.wrapping_sub(tail) as usize;
+        if ready == 0 {
+            return Ok(None);
+        }
+        if ready > self.region.capacity {
+            return Err(self.region.corrupt());
+        }
+        let mut len = [0; 4];
+        self.region.read(tail, &mut len);
+        let len = Endian::deserialize_u32(len) as usize;
+        if len > self.region.capacity - 4 {
+            return Err(Error::LengthTooLarge {
+                len,
+                remaining: self.region.capacity - 4,
+            });
+        }
+        let need = record_len(len);
+        if need > ready {
+            return Err(Error::LengthTooLarge {
+                len,
+                remaining: ready.saturating_sub(4),
+            });
+        }
+        self.buf.resize(len, 0);
+        self.region.read(tail.wrapping_add(4), &mut self.buf);
+        self.region
+            .tail()
+            .store(tail.wrapping_add(need as u32), Ordering::Release);
+        crate::from_bytes::<Endian, T>(&self.buf).map(Some)
+    }
+
+    /// Whether the producer has written nothing that has not been taken.
+    pub fn is_empty(&self) -> bool {
+        let tail = self.region.tail().load(Ordering::Relaxed);
+        self.region.head().load(Ordering::Acquire) == tail
+    }
+}
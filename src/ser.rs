@@ -5,23 +5,34 @@
 // Copyright 2022 Oxide Computer Company
 
 use serde::{ser, Serialize};
+use std::convert::TryFrom;
+use std::io;
 use std::marker::PhantomData;
 
 use crate::error::{Error, Result};
 use crate::BigEndian;
+use crate::ByteOrder;
 use crate::LittleEndian;
+use crate::{Checksum, Width};
 
 pub trait NumSer {
+    const BIG_ENDIAN: bool;
     fn serialize_u16(v: u16) -> [u8; 2];
+    fn serialize_u24(v: u32) -> [u8; 3];
     fn serialize_u32(v: u32) -> [u8; 4];
     fn serialize_u64(v: u64) -> [u8; 8];
     fn serialize_u128(v: u128) -> [u8; 16];
 }
 
 impl NumSer for LittleEndian {
+    const BIG_ENDIAN: bool = false;
     fn serialize_u16(v: u16) -> [u8; 2] {
         v.to_le_bytes()
     }
+    fn serialize_u24(v: u32) -> [u8; 3] {
+        let b = v.to_le_bytes();
+        [b[0], b[1], b[2]]
+    }
     fn serialize_u32(v: u32) -> [u8; 4] {
         v.to_le_bytes()
     }
@@ -34,9 +45,14 @@ impl NumSer for LittleEndian {
 }
 
 impl NumSer for BigEndian {
+    const BIG_ENDIAN: bool = true;
     fn serialize_u16(v: u16) -> [u8; 2] {
         v.to_be_bytes()
     }
+    fn serialize_u24(v: u32) -> [u8; 3] {
+        let b = v.to_be_bytes();
+        [b[1], b[2], b[3]]
+    }
     fn serialize_u32(v: u32) -> [u8; 4] {
         v.to_be_bytes()
     }
@@ -48,1001 +64,4618 @@ impl NumSer for BigEndian {
     }
 }
 
-pub struct Serializer<Endian: NumSer> {
-    output: Vec<u8>,
+pub struct Serializer<Endian: NumSer, O: Output = Vec<u8>> {
+    output: O,
+    frames: Vec<Frame>,
+    /// The number of open frames that may rewrite what is under them.
+    patches: usize,
+    reject_interior_nul: bool,
+    presence: (u8, u8),
+    variant_width: Width,
+    version: usize,
+    /// Whether integers are written in the opposite order to `Endian`, as
+    /// within a value that declares its own byte order.
+    swap: bool,
     endian: PhantomData<Endian>,
 }
 
-pub fn to_bytes_le<T>(value: &T) -> Result<Vec<u8>>
-where
-    T: Serialize,
-{
-    to_bytes::<LittleEndian, T>(value)
+/// Bookkeeping for an open tuple struct, resolved when the tuple struct ends.
+enum Frame {
+    Plain,
+    /// A field padded with zeros out to `size` bytes, of which at least the
+    /// last must be padding.
+    Fixed {
+        start: usize,
+        size: usize,
+    },
+    /// A value preceded by its own encoded length, in a prefix `width` bytes
+    /// wide that is written once the value is complete.
+    Sized {
+        start: usize,
+        width: usize,
+    },
+    /// A value with a field written over it `offset` bytes in. The field is
+    /// serialized first, `field` bytes long, and moved into place once the
+    /// value after it is complete.
+    At {
+        start: usize,
+        offset: usize,
+        field: Option<usize>,
+    },
+    /// A value holding its own encoded length in a field `width` bytes wide.
+    /// The field is written as zeros at `at` and filled in once the value is
+    /// complete, unless the length is `known` in advance, in which case it is
+    /// written as it is and checked once the value is complete.
+    SizeOf {
+        start: usize,
+        width: usize,
+        at: Option<usize>,
+        known: Option<usize>,
+    },
+    /// A byte field of `len` bytes that are costly to produce, such as those
+    /// of a `ReadBytes`, which an output that only counts skips producing.
+    Stream {
+        len: usize,
+    },
+    /// A field from a protocol version later than the peer's, discarded
+    /// once written.
+    Skip {
+        start: usize,
+    },
+    /// A value whose first or last field is a checksum over the rest, as
+    /// packed by `Checksum::spec`, computed once the value is complete. A
+    /// checksum over a range of fields has its field marked at `at` and the
+    /// bytes it covers from `from` up to `to`.
+    Checksum {
+        start: usize,
+        spec: usize,
+        at: Option<usize>,
+        from: Option<usize>,
+        to: Option<usize>,
+    },
+    /// A value with a byte order of its own. `swap` is restored once the
+    /// value is complete.
+    Endian {
+        swap: bool,
+    },
 }
 
-pub fn to_bytes_be<T>(value: &T) -> Result<Vec<u8>>
-where
-    T: Serialize,
-{
-    to_bytes::<BigEndian, T>(value)
+impl Frame {
+    /// Whether the bytes of the value may be read back or rewritten before
+    /// the frame is resolved.
+    fn patches(&self) -> bool {
+        matches!(
+            self,
+            Frame::At { .. }
+                | Frame::SizeOf { known: None, .. }
+                | Frame::Skip { .. }
+                | Frame::Checksum { .. }
+                | Frame::Sized { .. }
+        )
+    }
 }
 
-pub fn to_bytes<Endian, T>(value: &T) -> Result<Vec<u8>>
-where
-    T: Serialize,
-    Endian: NumSer,
-{
-    let mut serializer = Serializer {
-        output: Vec::new(),
-        endian: PhantomData::<Endian> {},
-    };
-    value.serialize(&mut serializer)?;
-    Ok(serializer.output)
-}
+/// Where a `Serializer` writes. Besides appending, the serializer reads back
+/// and rewrites what it has written to fill in lengths and checksums. It
+/// calls `settle` once everything written so far is final, after which an
+/// output may pass those bytes on and drop them.
+pub trait Output {
+    /// The number of bytes written so far.
+    fn len(&self) -> usize;
 
-impl<'a, Endian: NumSer> ser::Serializer for &'a mut Serializer<Endian> {
-    type Ok = ();
-    type Error = Error;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 
-    type SerializeSeq = Self;
-    type SerializeTuple = Self;
-    type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Self;
-    type SerializeMap = Self;
-    type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    /// The bytes written from position `from` onward, or `None` if the
+    /// output only counts them. `from` is never before the last settled
+    /// position.
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]>;
 
-    fn serialize_bool(self, _v: bool) -> Result<Self::Ok> {
-        unimplemented!()
+    /// Append `b`, failing if there is no room for it.
+    fn extend(&mut self, b: &[u8]) -> Result<()>;
+
+    /// Drop everything after the first `len` bytes.
+    fn truncate(&mut self, len: usize);
+
+    /// Append `n` zero bytes.
+    fn extend_zeros(&mut self, mut n: usize) -> Result<()> {
+        const ZEROS: [u8; 64] = [0; 64];
+        while n > 0 {
+            let k = n.min(ZEROS.len());
+            self.extend(&ZEROS[..k])?;
+            n -= k;
+        }
+        Ok(())
     }
 
-    fn serialize_i8(self, _v: i8) -> Result<Self::Ok> {
-        unimplemented!()
+    /// Overwrite the bytes at position `at` with `b`.
+    fn patch(&mut self, at: usize, b: &[u8]) -> Result<()> {
+        if let Some(tail) = self.tail_mut(at) {
+            tail[..b.len()].copy_from_slice(b);
+        }
+        Ok(())
     }
 
-    fn serialize_i16(self, _v: i16) -> Result<Self::Ok> {
-        unimplemented!()
+    /// Everything written so far is final.
+    fn settle(&mut self) -> Result<()> {
+        Ok(())
     }
 
-    fn serialize_i32(self, _v: i32) -> Result<Self::Ok> {
-        unimplemented!()
+    /// Whether the output only counts what is written, keeping no bytes.
+    /// Fields whose bytes are costly to produce, such as a `ReadBytes`, are
+    /// then counted without being produced.
+    fn counts_only(&self) -> bool {
+        false
     }
 
-    fn serialize_i64(self, _v: i64) -> Result<Self::Ok> {
-        unimplemented!()
+    /// Whether the output passes settled bytes on as it goes. A value that
+    /// holds its own size is then sized before it is written, so that the
+    /// size goes out first and the rest of the value need not be held back.
+    fn streams(&self) -> bool {
+        false
     }
+}
 
-    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
-        self.output.push(v);
-        Ok(())
+impl Output for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
     }
 
-    fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
-        self.output.extend_from_slice(&Endian::serialize_u16(v));
-        Ok(())
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]> {
+        Some(&mut self[from..])
     }
 
-    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
-        self.output.extend_from_slice(&Endian::serialize_u32(v));
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        self.extend_from_slice(b);
         Ok(())
     }
 
-    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
-        self.output.extend_from_slice(&Endian::serialize_u64(v));
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len)
+    }
+
+    fn extend_zeros(&mut self, n: usize) -> Result<()> {
+        self.resize(self.len() + n, 0);
         Ok(())
     }
+}
 
-    fn serialize_f32(self, _v: f32) -> Result<Self::Ok> {
-        unimplemented!()
+/// The end of a caller's `Vec`, after whatever it already held.
+pub(crate) struct AppendOutput<'a> {
+    vec: &'a mut Vec<u8>,
+    base: usize,
+}
+
+impl<'a> AppendOutput<'a> {
+    pub(crate) fn new(vec: &'a mut Vec<u8>) -> Self {
+        let base = vec.len();
+        AppendOutput { vec, base }
     }
+}
 
-    fn serialize_f64(self, _v: f64) -> Result<Self::Ok> {
-        unimplemented!()
+impl Output for AppendOutput<'_> {
+    fn len(&self) -> usize {
+        self.vec.len() - self.base
     }
 
-    fn serialize_char(self, _v: char) -> Result<Self::Ok> {
-        unimplemented!()
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]> {
+        Some(&mut self.vec[self.base + from..])
     }
 
-    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        self.output.extend_from_slice(v.as_bytes());
-        self.output.push(0); //default is null terminated
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        self.vec.extend_from_slice(b);
         Ok(())
     }
 
-    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
+    fn truncate(&mut self, len: usize) {
+        self.vec.truncate(self.base + len)
+    }
+
+    fn extend_zeros(&mut self, n: usize) -> Result<()> {
+        self.vec.resize(self.vec.len() + n, 0);
         Ok(())
     }
+}
 
-    fn serialize_none(self) -> Result<Self::Ok> {
-        unimplemented!()
+/// An output that keeps no bytes, only their number.
+#[derive(Default)]
+pub(crate) struct CountOutput(usize);
+
+impl Output for CountOutput {
+    fn len(&self) -> usize {
+        self.0
     }
 
-    fn serialize_some<T: ?Sized>(self, _value: &T) -> Result<Self::Ok>
-    where
-        T: Serialize,
-    {
-        unimplemented!()
+    fn tail_mut(&mut self, _from: usize) -> Option<&mut [u8]> {
+        None
     }
 
-    fn serialize_unit(self) -> Result<Self::Ok> {
-        unimplemented!()
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        self.extend_zeros(b.len())
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
-        unimplemented!()
+    fn truncate(&mut self, len: usize) {
+        self.0 = self.0.min(len);
     }
 
-    fn serialize_unit_variant(
-        self,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-    ) -> Result<Self::Ok> {
-        println!("{} {} {}", _name, _variant_index, _variant);
-        unimplemented!()
+    fn extend_zeros(&mut self, n: usize) -> Result<()> {
+        self.0 = self.0.checked_add(n).ok_or(Error::LengthOverflow {
+            max: usize::MAX - self.0,
+            actual: n,
+        })?;
+        Ok(())
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(
-        self,
-        _name: &'static str,
-        _value: &T,
-    ) -> Result<Self::Ok>
-    where
-        T: Serialize,
-    {
-        unimplemented!()
+    fn counts_only(&self) -> bool {
+        true
     }
+}
 
-    fn serialize_newtype_variant<T: ?Sized>(
-        self,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-        _value: &T,
-    ) -> Result<Self::Ok>
-    where
-        T: Serialize,
-    {
-        unimplemented!()
+/// A caller's buffer, filled from the front. Writing past its end fails with
+/// `Error::BufferTooSmall`.
+pub struct SliceOutput<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SliceOutput<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        SliceOutput { buf, len: 0 }
     }
 
-    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
-        Ok(self)
+    /// The part of the buffer written to.
+    pub fn into_written(self) -> &'a mut [u8] {
+        &mut self.buf[..self.len]
     }
+}
 
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Ok(self)
+impl Output for SliceOutput<'_> {
+    fn len(&self) -> usize {
+        self.len
     }
 
-    fn serialize_tuple_struct(
-        self,
-        _name: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeTupleStruct> {
-        Ok(self)
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]> {
+        Some(&mut self.buf[from..self.len])
     }
 
-    fn serialize_tuple_variant(
-        self,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeTupleVariant> {
-        Ok(self)
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        let end = self.len + b.len();
+        if end > self.buf.len() {
+            return Err(Error::BufferTooSmall {
+                capacity: self.buf.len(),
+            });
+        }
+        self.buf[self.len..end].copy_from_slice(b);
+        self.len = end;
+        Ok(())
     }
 
-    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
-        unimplemented!()
+    fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
     }
+}
 
-    fn serialize_struct(
-        self,
-        _name: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeStruct> {
-        Ok(self)
+/// A `std::io::Write`. Bytes are held back only while they may still be
+/// rewritten, and are passed on once settled and there are enough of them to
+/// be worth a write. `finish` writes the rest. If serializing fails, what was
+/// passed on stays written.
+pub struct WriteOutput<W: io::Write> {
+    w: W,
+    buf: Vec<u8>,
+    /// The number of bytes already passed to `w`.
+    written: usize,
+}
+
+impl<W: io::Write> WriteOutput<W> {
+    /// Settled bytes are held until there are at least this many.
+    const CHUNK: usize = 8 * 1024;
+
+    pub fn new(w: W) -> Self {
+        WriteOutput {
+            w,
+            buf: Vec::new(),
+            written: 0,
+        }
     }
 
-    fn serialize_struct_variant(
-        self,
-        _name: &'static str,
-        _variant_index: u32,
-        _variant: &'static str,
-        _len: usize,
-    ) -> Result<Self::SerializeStructVariant> {
-        Ok(self)
+    /// Write out the bytes still held, returning the writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.w.write_all(&self.buf).map_err(Error::io)?;
+        Ok(self.w)
     }
 }
 
-impl<'a, Endian: NumSer> ser::SerializeSeq for &'a mut Serializer<Endian> {
-    type Ok = ();
-    type Error = Error;
+impl<W: io::Write> Output for WriteOutput<W> {
+    fn len(&self) -> usize {
+        self.written + self.buf.len()
+    }
 
-    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
-    where
-        T: ?Sized + Serialize,
-    {
-        value.serialize(&mut **self)
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]> {
+        Some(&mut self.buf[from - self.written..])
     }
 
-    fn end(self) -> Result<()> {
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        self.buf.extend_from_slice(b);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len - self.written)
+    }
+
+    fn settle(&mut self) -> Result<()> {
+        if self.buf.len() >= Self::CHUNK {
+            self.w.write_all(&self.buf).map_err(Error::io)?;
+            self.written += self.buf.len();
+            self.buf.clear();
+        }
         Ok(())
     }
+
+    fn streams(&self) -> bool {
+        true
+    }
 }
 
-impl<'a, Endian: NumSer> ser::SerializeTuple for &'a mut Serializer<Endian> {
-    type Ok = ();
-    type Error = Error;
+/// Only `BytesMut` among the `bytes::BufMut` types, as the serializer must be
+/// able to rewrite what it has written, which `BufMut` does not allow.
+#[cfg(feature = "bytes")]
+impl Output for bytes::BytesMut {
+    fn len(&self) -> usize {
+        bytes::BytesMut::len(self)
+    }
 
-    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
-    where
-        T: ?Sized + Serialize,
-    {
-        value.serialize(&mut **self)
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]> {
+        Some(&mut self[from..])
     }
 
-    fn end(self) -> Result<()> {
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        self.extend_from_slice(b);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        bytes::BytesMut::truncate(self, len)
+    }
+
+    fn extend_zeros(&mut self, n: usize) -> Result<()> {
+        self.resize(bytes::BytesMut::len(self) + n, 0);
         Ok(())
     }
 }
 
-impl<'a, Endian: NumSer> ser::SerializeTupleStruct
-    for &'a mut Serializer<Endian>
-{
-    type Ok = ();
-    type Error = Error;
+/// A byte slice that serializes as raw bytes rather than as a sequence of
+/// individual `u8` elements.
+pub struct RawBytes<'a>(pub &'a [u8]);
 
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<()>
+impl Serialize for RawBytes<'_> {
+    fn serialize<S>(&self, s: S) -> std::result::Result<S::Ok, S::Error>
     where
-        T: ?Sized + Serialize,
+        S: ser::Serializer,
     {
-        unimplemented!()
+        s.serialize_bytes(self.0)
     }
+}
 
-    fn end(self) -> Result<()> {
-        unimplemented!()
+/// An iterator that serializes as a sequence of exactly `len` elements. The
+/// iterator is consumed by the first call to `serialize`.
+pub struct IterSeq<I> {
+    iter: std::cell::Cell<Option<I>>,
+    len: usize,
+}
+
+impl<I> IterSeq<I> {
+    pub fn new(iter: I, len: usize) -> Self {
+        IterSeq {
+            iter: std::cell::Cell::new(Some(iter)),
+            len,
+        }
     }
 }
 
-impl<'a, Endian: NumSer> ser::SerializeTupleVariant
-    for &'a mut Serializer<Endian>
+impl<I> Serialize for IterSeq<I>
+where
+    I: Iterator,
+    I::Item: Serialize,
 {
-    type Ok = ();
-    type Error = Error;
-
-    fn serialize_field<T>(&mut self, _value: &T) -> Result<()>
+    fn serialize<S>(&self, s: S) -> std::result::Result<S::Ok, S::Error>
     where
-        T: ?Sized + Serialize,
+        S: ser::Serializer,
     {
-        unimplemented!()
+        use ser::SerializeSeq;
+
+        let iter = self
+            .iter
+            .take()
+            .ok_or_else(|| ser::Error::custom("iterator already consumed"))?;
+        let mut seq = s.serialize_seq(Some(self.len))?;
+        let mut count = 0usize;
+        for e in iter {
+            count += 1;
+            if count > self.len {
+                break;
+            }
+            seq.serialize_element(&e)?;
+        }
+        if count != self.len {
+            return Err(ser::Error::custom(format!(
+                "iterator yielded a different number of elements than its \
+                 length of {}",
+                self.len
+            )));
+        }
+        seq.end()
     }
+}
 
-    fn end(self) -> Result<()> {
-        unimplemented!()
+/// Fail if a value's length exceeds a user-specified maximum.
+pub fn check_max_len<E: ser::Error>(
+    len: usize,
+    max: usize,
+) -> std::result::Result<(), E> {
+    if len > max {
+        return Err(E::custom(Error::LengthOverflow { max, actual: len }));
     }
+    Ok(())
 }
 
-impl<'a, Endian: NumSer> ser::SerializeMap for &'a mut Serializer<Endian> {
-    type Ok = ();
-    type Error = Error;
+/// Encode a string as Latin-1, failing on characters outside of U+0000 to
+/// U+00FF.
+pub fn encode_latin1<E: ser::Error>(
+    v: &str,
+) -> std::result::Result<Vec<u8>, E> {
+    v.chars()
+        .map(|c| {
+            u8::try_from(u32::from(c)).map_err(|_| {
+                E::custom(format!("{:?} cannot be encoded as latin-1", c))
+            })
+        })
+        .collect()
+}
 
-    fn serialize_key<T>(&mut self, _key: &T) -> Result<()>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!()
+impl<Endian: NumSer> Serializer<Endian> {
+    pub fn new() -> Self {
+        Self::with_output(Vec::new())
     }
 
-    fn serialize_value<T>(&mut self, _value: &T) -> Result<()>
-    where
-        T: ?Sized + Serialize,
-    {
-        unimplemented!()
+    /// Consume the serializer, returning the bytes written so far.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.output
     }
+}
 
-    fn end(self) -> Result<()> {
-        unimplemented!()
+impl<Endian: NumSer, O: Output> Serializer<Endian, O> {
+    /// A serializer writing to `output`, which may be any `Output` such as a
+    /// `SliceOutput` or a `WriteOutput`.
+    pub fn with_output(output: O) -> Self {
+        Serializer {
+            output,
+            frames: Vec::new(),
+            patches: 0,
+            reject_interior_nul: false,
+            presence: (0, 1),
+            variant_width: Width::U32,
+            version: usize::MAX,
+            swap: false,
+            endian: PhantomData::<Endian> {},
+        }
     }
-}
 
-impl<'a, Endian: NumSer> ser::SerializeStruct for &'a mut Serializer<Endian> {
-    type Ok = ();
-    type Error = Error;
+    /// Fail with `Error::InteriorNul` when a null-terminated string contains
+    /// a null byte, rather than writing a string that will be truncated when
+    /// it is read back.
+    pub fn reject_interior_nul(mut self, reject: bool) -> Self {
+        self.reject_interior_nul = reject;
+        self
+    }
 
-    fn serialize_field<T>(
-        &mut self,
-        _key: &'static str,
-        value: &T,
-    ) -> Result<()>
+    /// Set the flag bytes written ahead of an `Option` to mark it absent or
+    /// present. The defaults are 0 and 1.
+    pub fn presence_flags(mut self, absent: u8, present: u8) -> Self {
+        self.presence = (absent, present);
+        self
+    }
+
+    /// Set the protocol version of the peer. Fields marked as added in a later
+    /// version are left out. By default every field is written.
+    pub fn version(mut self, version: usize) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Set the width of the variant index written for an enum. The default is
+    /// `Width::U32`; `Width::U8` suits enums declared `#[repr(u8)]`.
+    pub fn variant_width(mut self, width: Width) -> Self {
+        self.variant_width = width;
+        self
+    }
+
+    /// Consume the serializer, returning its output.
+    pub fn into_output(self) -> O {
+        self.output
+    }
+
+    /// Write an enum variant index at the configured width.
+    fn write_variant(&mut self, index: u32) -> Result<()> {
+        let too_wide = || {
+            <Error as ser::Error>::custom(format!(
+                "variant index {} does not fit in {:?}",
+                index, self.variant_width
+            ))
+        };
+        match self.variant_width {
+            Width::U8 => u8::try_from(index)
+                .map_err(|_| too_wide())?
+                .serialize(&mut *self),
+            Width::U16 => u16::try_from(index)
+                .map_err(|_| too_wide())?
+                .serialize(&mut *self),
+            Width::U32 => index.serialize(&mut *self),
+            Width::U64 => u64::from(index).serialize(&mut *self),
+        }
+    }
+
+    /// Write a length prefix of type `T`, failing if `len` does not fit.
+    fn write_prefix<T>(&mut self, len: usize) -> Result<()>
     where
-        T: ?Sized + Serialize,
+        T: TryFrom<usize> + Bounded + Serialize,
     {
-        value.serialize(&mut **self)
+        let prefix = T::try_from(len).map_err(|_| Error::LengthOverflow {
+            max: T::MAX_LEN,
+            actual: len,
+        })?;
+        prefix.serialize(self)
     }
 
-    fn end(self) -> Result<()> {
-        Ok(())
+    /// Reserve the size field of the innermost "sizeof" value, `width` bytes
+    /// wide.
+    fn write_size_field(&mut self, width: usize) -> Result<()> {
+        let at = self.position();
+        let frame = self
+            .frames
+            .iter_mut()
+            .rev()
+            .find(|f| matches!(f, Frame::SizeOf { .. }));
+        let known = match frame {
+            Some(Frame::SizeOf {
+                width: w,
+                at: slot @ None,
+                known,
+                ..
+            }) if *w == width => {
+                *slot = Some(at);
+                *known
+            }
+            _ => {
+                return Err(Error::Message(
+                    "size field outside of a sized message".to_string(),
+                ))
+            }
+        };
+        match known {
+            Some(size) => {
+                let prefix = self.prefix(width, size)?;
+                self.write(&prefix[..width])
+            }
+            None => self.output.extend_zeros(width),
+        }
     }
-}
-
-impl<'a, Endian: NumSer> ser::SerializeStructVariant
-    for &'a mut Serializer<Endian>
-{
-    type Ok = ();
-    type Error = Error;
 
-    fn serialize_field<T>(
-        &mut self,
-        _key: &'static str,
-        _value: &T,
-    ) -> Result<()>
+    /// Size the value about to be written in an open "sizeof" frame, when
+    /// the output passes bytes on as it goes, so that its size field can be
+    /// written with the rest rather than held back until the value is done.
+    /// The value is counted with the serializer's settings from where it will
+    /// start, so that alignment and left out fields count as they will.
+    fn size_in_advance<T>(&mut self, value: &T) -> Result<()>
     where
         T: ?Sized + Serialize,
     {
-        unimplemented!()
+        let (start, width) = match self.frames.last() {
+            Some(Frame::SizeOf {
+                start,
+                width,
+                at: None,
+                known: None,
+            }) if self.output.streams() && *start == self.position() => {
+                (*start, *width)
+            }
+            _ => return Ok(()),
+        };
+        let mut counter =
+            Serializer::<Endian, _>::with_output(CountOutput(start))
+                .reject_interior_nul(self.reject_interior_nul)
+                .presence_flags(self.presence.0, self.presence.1)
+                .version(self.version)
+                .variant_width(self.variant_width);
+        counter.swap = self.swap;
+        counter.frames.push(Frame::SizeOf {
+            start,
+            width,
+            at: None,
+            known: None,
+        });
+        counter.patches = 1;
+        value.serialize(&mut counter)?;
+        let size = counter.position() - start;
+        if let Some(Frame::SizeOf { known, .. }) = self.frames.last_mut() {
+            *known = Some(size);
+        }
+        self.patches -= 1;
+        Ok(())
     }
 
-    fn end(self) -> Result<()> {
-        unimplemented!()
+    /// Record the current position as the checksum field or an end of the
+    /// bytes it covers, as `mark` says, in the innermost "checksum" value.
+    fn mark_checksum(&mut self, mark: &str) -> Result<()> {
+        let at = self.position();
+        let frame = self
+            .frames
+            .iter_mut()
+            .rev()
+            .find(|f| matches!(f, Frame::Checksum { .. }));
+        let slot = match (frame, mark) {
+            (Some(Frame::Checksum { at, .. }), "sumat") => at,
+            (Some(Frame::Checksum { from, .. }), "sumfrom") => from,
+            (Some(Frame::Checksum { to, .. }), _) => to,
+            _ => {
+                return Err(Error::Message(
+                    "checksum range outside of a checksummed message"
+                        .to_string(),
+                ))
+            }
+        };
+        *slot = Some(at);
+        Ok(())
     }
-}
-
-///////////////////////////////////////////////////////////////////////////////
 
-#[test]
+    /// Fill in the checksum field of the value starting at `start`, which is
+    /// at `at` and covers the bytes in `range` for a checksum over a range
+    /// of fields.
+    fn write_checksum(
+        &mut self,
+        start: usize,
+        spec: usize,
+        at: Option<usize>,
+        range: Option<(usize, usize)>,
+    ) -> Result<()> {
+        let (sum, last, over) = Checksum::from_spec(spec).ok_or_else(|| {
+            Error::Message(format!("unknown checksum {}", spec))
+        })?;
+        let len = self.position() - start;
+        if len < sum.width() {
+            return Err(Error::LengthUnderflow {
+                min: sum.width(),
+                actual: len,
+            });
+        }
+        if over {
+            let ((from, to), at) = match range.zip(at) {
+                Some(marks) => marks,
+                None => {
+                    return Err(Error::Message(
+                        "checksum range without its marks".to_string(),
+                    ))
+                }
+            };
+            let value = match self.output.tail_mut(from) {
+                Some(b) => sum.compute(&b[..to - from]),
+                None => return Ok(()),
+            };
+            return self.patch_checksum(sum, at, value);
+        }
+        let at = if last {
+            self.position() - sum.width()
+        } else {
+            start
+        };
+        let value = match self.output.tail_mut(start) {
+            Some(b) => sum.compute(sum.covered(b, last).unwrap_or_default()),
+            None => return Ok(()),
+        };
+        self.patch_checksum(sum, at, value)
+    }
+
+    /// Write `value` into the checksum field at `at`.
+    fn patch_checksum(
+        &mut self,
+        sum: Checksum,
+        at: usize,
+        value: u64,
+    ) -> Result<()> {
+        let u16_bytes = self.order(Endian::serialize_u16(value as u16));
+        let u32_bytes = self.order(Endian::serialize_u32(value as u32));
+        if let Some(b) = self.output.tail_mut(at) {
+            let field = &mut b[..sum.width()];
+            match sum.width() {
+                1 => field[0] = value as u8,
+                2 => field.copy_from_slice(&u16_bytes),
+                _ => field.copy_from_slice(&u32_bytes),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a 24-bit length prefix, failing if `len` does not fit.
+    fn write_prefix_u24(&mut self, len: usize) -> Result<()> {
+        const MAX: usize = 0xff_ffff;
+        if len > MAX {
+            return Err(Error::LengthOverflow {
+                max: MAX,
+                actual: len,
+            });
+        }
+        let b = self.order(Endian::serialize_u24(len as u32));
+        self.write(&b)
+    }
+
+    /// A length prefix `width` bytes wide, in the first `width` bytes of the
+    /// array.
+    fn prefix(&self, width: usize, len: usize) -> Result<[u8; 8]> {
+        fn fit<T: TryFrom<usize> + Bounded>(len: usize) -> Result<T> {
+            T::try_from(len).map_err(|_| Error::LengthOverflow {
+                max: T::MAX_LEN,
+                actual: len,
+            })
+        }
+        let mut b = [0u8; 8];
+        match width {
+            1 => b[0] = fit::<u8>(len)?,
+            2 => b[..2].copy_from_slice(
+                &self.order(Endian::serialize_u16(fit::<u16>(len)?)),
+            ),
+            4 => b[..4].copy_from_slice(
+                &self.order(Endian::serialize_u32(fit::<u32>(len)?)),
+            ),
+            _ => {
+                b.copy_from_slice(
+                    &self.order(Endian::serialize_u64(fit::<u64>(len)?)),
+                )
+            }
+        }
+        Ok(b)
+    }
+
+    /// The number of bytes written so far.
+    fn position(&self) -> usize {
+        self.output.len()
+    }
+
+    /// Append `b`, letting the output know it is final when nothing open
+    /// could rewrite it.
+    fn write(&mut self, b: &[u8]) -> Result<()> {
+        self.output.extend(b)?;
+        self.settle()
+    }
+
+    fn settle(&mut self) -> Result<()> {
+        if self.patches == 0 {
+            self.output.settle()?;
+        }
+        Ok(())
+    }
+
+    /// Put the bytes of an integer in `Endian` order into the order of the
+    /// value being written.
+    fn order<const N: usize>(&self, mut b: [u8; N]) -> [u8; N] {
+        if self.swap {
+            b.reverse();
+        }
+        b
+    }
+}
+
+impl<Endian: NumSer> Default for Serializer<Endian> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Add the width of a length prefix to a length that includes it.
+fn inclusive(len: usize, width: usize) -> Result<usize> {
+    len.checked_add(width).ok_or(Error::LengthOverflow {
+        max: usize::MAX - width,
+        actual: len,
+    })
+}
+
+/// The largest length representable by a length prefix type.
+trait Bounded {
+    const MAX_LEN: usize;
+}
+
+impl Bounded for u8 {
+    const MAX_LEN: usize = u8::MAX as usize;
+}
+
+impl Bounded for u16 {
+    const MAX_LEN: usize = u16::MAX as usize;
+}
+
+impl Bounded for u32 {
+    const MAX_LEN: usize = u32::MAX as usize;
+}
+
+impl Bounded for u64 {
+    const MAX_LEN: usize = usize::MAX;
+}
+
+pub fn to_bytes_le<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    to_bytes::<LittleEndian, T>(value)
+}
+
+pub fn to_bytes_be<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    to_bytes::<BigEndian, T>(value)
+}
+
+/// Serialize `value` in a byte order chosen at run time.
+pub fn to_bytes_in<T>(value: &T, order: ByteOrder) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    match order {
+        ByteOrder::Little => to_bytes_le(value),
+        ByteOrder::Big => to_bytes_be(value),
+    }
+}
+
+pub fn to_bytes<Endian, T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+    Endian: NumSer,
+{
+    let mut serializer = Serializer::<Endian>::new();
+    value.serialize(&mut serializer)?;
+    Ok(serializer.into_bytes())
+}
+
+/// Serialize `value` onto the end of `out`, returning the number of bytes
+/// added. Reusing one `Vec` across messages saves allocating a new one for
+/// each. On failure `out` is left as it was.
+pub fn to_bytes_into<Endian, T>(value: &T, out: &mut Vec<u8>) -> Result<usize>
+where
+    T: Serialize,
+    Endian: NumSer,
+{
+    let mut serializer =
+        Serializer::<Endian, _>::with_output(AppendOutput::new(out));
+    match value.serialize(&mut serializer) {
+        Ok(()) => Ok(serializer.position()),
+        Err(e) => {
+            serializer.output.truncate(0);
+            Err(e)
+        }
+    }
+}
+
+pub fn to_bytes_into_le<T>(value: &T, out: &mut Vec<u8>) -> Result<usize>
+where
+    T: Serialize,
+{
+    to_bytes_into::<LittleEndian, T>(value, out)
+}
+
+pub fn to_bytes_into_be<T>(value: &T, out: &mut Vec<u8>) -> Result<usize>
+where
+    T: Serialize,
+{
+    to_bytes_into::<BigEndian, T>(value, out)
+}
+
+/// Serialize `value` into the front of `buf`, returning the number of bytes
+/// written. No output `Vec` is allocated. Fails with `Error::BufferTooSmall`
+/// if the message does not fit, leaving the contents of `buf` unspecified.
+pub fn to_slice<Endian, T>(value: &T, buf: &mut [u8]) -> Result<usize>
+where
+    T: Serialize,
+    Endian: NumSer,
+{
+    let mut serializer =
+        Serializer::<Endian, _>::with_output(SliceOutput::new(buf));
+    value.serialize(&mut serializer)?;
+    Ok(serializer.position())
+}
+
+pub fn to_slice_le<T>(value: &T, buf: &mut [u8]) -> Result<usize>
+where
+    T: Serialize,
+{
+    to_slice::<LittleEndian, T>(value, buf)
+}
+
+pub fn to_slice_be<T>(value: &T, buf: &mut [u8]) -> Result<usize>
+where
+    T: Serialize,
+{
+    to_slice::<BigEndian, T>(value, buf)
+}
+
+/// Serialize `value` onto `w`, for writing a message straight to a socket or
+/// file. Bytes are passed on in chunks as they become final rather than all
+/// at once at the end, so a message that fails part way may already be
+/// partly written. Serialize with `to_bytes` first where a peer must never
+/// see a truncated message.
+pub fn to_writer<Endian, T, W>(value: &T, w: W) -> Result<()>
+where
+    T: Serialize,
+    Endian: NumSer,
+    W: io::Write,
+{
+    let mut serializer =
+        Serializer::<Endian, _>::with_output(WriteOutput::new(w));
+    value.serialize(&mut serializer)?;
+    serializer.into_output().finish()?;
+    Ok(())
+}
+
+pub fn to_writer_le<T, W>(value: &T, w: W) -> Result<()>
+where
+    T: Serialize,
+    W: io::Write,
+{
+    to_writer::<LittleEndian, T, W>(value, w)
+}
+
+pub fn to_writer_be<T, W>(value: &T, w: W) -> Result<()>
+where
+    T: Serialize,
+    W: io::Write,
+{
+    to_writer::<BigEndian, T, W>(value, w)
+}
+
+/// The number of bytes `value` encodes to, counted without producing them.
+/// Byte order does not affect the size, so none is chosen. Useful for filling
+/// leading size fields and sizing buffers ahead of time.
+///
+/// A `ReadBytes` field is counted by its length, without reading from it, so
+/// the value can still be serialized for real afterwards.
+pub fn serialized_size<T>(value: &T) -> Result<usize>
+where
+    T: Serialize,
+{
+    let mut serializer =
+        Serializer::<LittleEndian, _>::with_output(CountOutput::default());
+    value.serialize(&mut serializer)?;
+    Ok(serializer.position())
+}
+
+impl<Endian: NumSer, O: Output> ser::Serializer for &mut Serializer<Endian, O> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
+        self.write(&[v])?;
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
+        let b = self.order(Endian::serialize_u16(v));
+        self.write(&b)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
+        let b = self.order(Endian::serialize_u32(v));
+        self.write(&b)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
+        let b = self.order(Endian::serialize_u64(v));
+        self.write(&b)
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Self::Ok> {
+        unimplemented!()
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
+        if self.reject_interior_nul && memchr::memchr(0, v.as_bytes()).is_some()
+        {
+            return Err(Error::InteriorNul);
+        }
+        self.write(v.as_bytes())?;
+        self.write(&[0])?; //default is null terminated
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        self.write(v)?;
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok> {
+        self.write(&[self.presence.0])?;
+        Ok(())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write(&[self.presence.1])?;
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        self.serialize_unit()
+    }
+
+    /// Enums are written as a variant index, 32 bits wide unless set with
+    /// `variant_width`, followed by the variant's fields, if any. Use the
+    /// `Ispf` derive for explicit discriminants.
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok> {
+        self.write_variant(variant_index)
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_variant(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        let frame = match name {
+            "fixed" => Frame::Fixed {
+                start: self.position(),
+                size: len,
+            },
+            "at" => Frame::At {
+                start: self.position(),
+                offset: len,
+                field: None,
+            },
+            "sizeof" => Frame::SizeOf {
+                start: self.position(),
+                width: len,
+                at: None,
+                known: None,
+            },
+            "stream" => Frame::Stream { len },
+            "since" if len > self.version => Frame::Skip {
+                start: self.position(),
+            },
+            "checksum" => Frame::Checksum {
+                start: self.position(),
+                spec: len,
+                at: None,
+                from: None,
+                to: None,
+            },
+            "sumat" | "sumfrom" | "sumto" => {
+                self.mark_checksum(name)?;
+                Frame::Plain
+            }
+            "sizefield" => {
+                self.write_size_field(len)?;
+                Frame::Plain
+            }
+            "endian" => {
+                let frame = Frame::Endian { swap: self.swap };
+                self.swap = (len != 0) != Endian::BIG_ENDIAN;
+                frame
+            }
+            "align" => {
+                let misalign = self.position() % len.max(1);
+                if misalign != 0 {
+                    self.output.extend_zeros(len - misalign)?;
+                }
+                Frame::Plain
+            }
+            "sized8" => Frame::Sized {
+                start: self.position(),
+                width: 1,
+            },
+            "sized16" => Frame::Sized {
+                start: self.position(),
+                width: 2,
+            },
+            "sized32" => Frame::Sized {
+                start: self.position(),
+                width: 4,
+            },
+            "sized64" => Frame::Sized {
+                start: self.position(),
+                width: 8,
+            },
+            "string8" | "bytes8" | "vec8" | "vec8b" | "map8" => {
+                self.write_prefix::<u8>(len)?;
+                Frame::Plain
+            }
+            "string16" | "bytes16" | "vec16" | "vec16b" | "map16" => {
+                self.write_prefix::<u16>(len)?;
+                Frame::Plain
+            }
+            "string24" | "bytes24" | "vec24" | "vec24b" => {
+                self.write_prefix_u24(len)?;
+                Frame::Plain
+            }
+            "string32" | "bytes32" | "vec32" | "vec32b" | "map32" => {
+                self.write_prefix::<u32>(len)?;
+                Frame::Plain
+            }
+            "string64" | "bytes64" | "vec64" | "vec64b" | "map64" => {
+                self.write_prefix::<u64>(len)?;
+                Frame::Plain
+            }
+            "string8i" | "bytes8i" | "vec8bi" => {
+                self.write_prefix::<u8>(inclusive(len, 1)?)?;
+                Frame::Plain
+            }
+            "string16i" | "bytes16i" | "vec16bi" => {
+                self.write_prefix::<u16>(inclusive(len, 2)?)?;
+                Frame::Plain
+            }
+            "string24i" | "bytes24i" | "vec24bi" => {
+                self.write_prefix_u24(inclusive(len, 3)?)?;
+                Frame::Plain
+            }
+            "string32i" | "bytes32i" | "vec32bi" => {
+                self.write_prefix::<u32>(inclusive(len, 4)?)?;
+                Frame::Plain
+            }
+            "string64i" | "bytes64i" | "vec64bi" => {
+                self.write_prefix::<u64>(inclusive(len, 8)?)?;
+                Frame::Plain
+            }
+            _ => Frame::Plain,
+        };
+        if frame.patches() {
+            self.patches += 1;
+        }
+        self.frames.push(frame);
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        self.write_variant(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(self)
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.write_variant(variant_index)?;
+        Ok(self)
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeSeq
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeTuple
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeTupleStruct
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Some(Frame::Stream { len }) = self.frames.last() {
+            if self.output.counts_only() {
+                let len = *len;
+                return self.output.extend_zeros(len);
+            }
+        }
+        self.size_in_advance(value)?;
+        value.serialize(&mut **self)?;
+        let at = self.position();
+        if let Some(Frame::At {
+            start,
+            field: field @ None,
+            ..
+        }) = self.frames.last_mut()
+        {
+            *field = Some(at - *start);
+        }
+        Ok(())
+    }
+
+    fn end(self) -> Result<()> {
+        let frame = self.frames.pop();
+        let patches = frame.as_ref().is_some_and(Frame::patches);
+        match frame {
+            Some(Frame::At {
+                start,
+                offset,
+                field,
+            }) => {
+                let field = field.unwrap_or_default();
+                let end = self.position();
+                let len = end - start - field;
+                if offset + field > len {
+                    return Err(Error::OffsetOutOfRange { offset, len });
+                }
+                if let Some(b) = self.output.tail_mut(start) {
+                    b.rotate_left(field);
+                    b.copy_within(b.len() - field.., offset);
+                }
+                self.output.truncate(end - field);
+                Ok(())
+            }
+            Some(Frame::Fixed { start, size }) => {
+                let written = self.position() - start;
+                if written >= size {
+                    return Err(Error::LengthOverflow {
+                        max: size.saturating_sub(1),
+                        actual: written,
+                    });
+                }
+                self.output.extend_zeros(size - written)
+            }
+            Some(Frame::Skip { start }) => {
+                self.output.truncate(start);
+                Ok(())
+            }
+            Some(Frame::Checksum {
+                start,
+                spec,
+                at,
+                from,
+                to,
+            }) => self.write_checksum(start, spec, at, from.zip(to)),
+            Some(Frame::Endian { swap }) => {
+                self.swap = swap;
+                Ok(())
+            }
+            Some(Frame::SizeOf {
+                start,
+                width,
+                at: Some(at),
+                known,
+            }) => {
+                let size = self.position() - start;
+                match known {
+                    Some(known) if known != size => Err(Error::SizeMismatch {
+                        size: known,
+                        actual: size,
+                    }),
+                    Some(_) => Ok(()),
+                    None => {
+                        let prefix = self.prefix(width, size)?;
+                        self.output.patch(at, &prefix[..width])
+                    }
+                }
+            }
+            Some(Frame::Sized { start, width }) => {
+                let prefix = self.prefix(width, self.position() - start)?;
+                self.write(&prefix[..width])?;
+                if let Some(b) = self.output.tail_mut(start) {
+                    b.rotate_right(width);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }?;
+        if patches {
+            self.patches -= 1;
+        }
+        self.settle()
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeTupleVariant
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeMap
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeStruct
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<Endian: NumSer, O: Output> ser::SerializeStructVariant
+    for &mut Serializer<Endian, O>
+{
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[test]
 fn test_struct_lv() {
     #[derive(Serialize)]
-    struct Version {
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        version: String,
+    }
+
+    let v = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, b'm', b'u', b'f', b'f', b'i', b'n',
+        b'\0',
+    ];
+
+    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_str_lv8() {
+    #[derive(Serialize)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(serialize_with = "crate::str_lv8::serialize")]
+        version: String,
+    }
+
+    let v = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, b'm', b'u', b'f', b'f', b'i',
+        b'n',
+    ];
+
+    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_str_lv16() {
+    #[derive(Serialize)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(with = "crate::str_lv16")]
+        version: String,
+    }
+
+    let v = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, b'm', b'u', b'f', b'f', b'i',
+        b'n',
+    ];
+
+    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_str_lv32() {
+    #[derive(Serialize)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(serialize_with = "crate::str_lv32::serialize")]
+        version: String,
+    }
+
+    let v = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, b'm', b'u', b'f', b'f',
+        b'i', b'n',
+    ];
+
+    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_str_lv64() {
+    #[derive(Serialize)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(serialize_with = "crate::str_lv64::serialize")]
+        version: String,
+    }
+
+    let v = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
+        b'f', b'f', b'i', b'n',
+    ];
+
+    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+}
+
+#[test]
+fn test_nested_struct() {
+    #[derive(Serialize)]
+    struct Version {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[serde(serialize_with = "crate::str_lv64::serialize")]
+        version: String,
+        info: Info,
+    }
+
+    #[derive(Serialize)]
+    struct Info {
+        typ: u8,
+        version: u32,
+        path: u64,
+    }
+
+    let v = Version {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        msize: 99,
+        version: "muffin".into(),
+        info: Info {
+            typ: 3,
+            version: 12345,
+            path: 678910,
+        },
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
+        b'f', b'f', b'i', b'n', 3, 57, 48, 0, 0, 254, 91, 10, 0, 0, 0, 0, 0,
+    ];
+
+    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv8() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv8")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv16() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv16")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv32() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv32")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv64() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv64")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, 0, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv8b() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv8b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    impl crate::WireSize for Dirent {
+        fn wire_size(&self) -> usize {
+            use std::mem::size_of;
+            size_of::<u64>()        // offset
+                + size_of::<u8>()   // typ
+                + size_of::<u16>()  // name.len TODO: awkward, user specifying
+                                    //                serde inserted value
+                + self.name.len() // name
+        }
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv16b() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv16b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    impl crate::WireSize for Dirent {
+        fn wire_size(&self) -> usize {
+            use std::mem::size_of;
+            size_of::<u64>()        // offset
+                + size_of::<u8>()   // typ
+                + size_of::<u16>()  // name.len TODO: awkward, user specifying
+                                    //                serde inserted value
+                + self.name.len() // name
+        }
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv32b() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv32b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    impl crate::WireSize for Dirent {
+        fn wire_size(&self) -> usize {
+            use std::mem::size_of;
+            size_of::<u64>()        // offset
+                + size_of::<u8>()   // typ
+                + size_of::<u16>()  // name.len TODO: awkward, user specifying
+                                    //                serde inserted value
+                + self.name.len() // name
+        }
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_vec_lv64b() {
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Rreaddir {
+        pub size: u32,
+        pub typ: u8,
+        pub tag: u16,
+        #[serde(with = "crate::vec_lv64b")]
+        pub data: Vec<Dirent>,
+    }
+
+    #[derive(Debug, Serialize, PartialEq)]
+    pub struct Dirent {
+        pub offset: u64,
+        pub typ: u8,
+        #[serde(with = "crate::str_lv16")]
+        pub name: String,
+    }
+
+    impl crate::WireSize for Dirent {
+        fn wire_size(&self) -> usize {
+            use std::mem::size_of;
+            size_of::<u64>()        // offset
+                + size_of::<u8>()   // typ
+                + size_of::<u16>()  // name.len TODO: awkward, user specifying
+                                    //                serde inserted value
+                + self.name.len() // name
+        }
+    }
+
+    let r = Rreaddir {
+        size: 47,
+        typ: 9,
+        tag: 15,
+        data: vec![
+            Dirent {
+                offset: 37,
+                typ: 2,
+                name: "blueberry".into(),
+            },
+            Dirent {
+                offset: 73,
+                typ: 9,
+                name: "muffin".into(),
+            },
+        ],
+    };
+
+    let expected = vec![
+        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, 0, 0, 0, 0, // len
+        // .1
+        37, 0, 0, 0, 0, 0, 0, 0, // offset
+        2, // typ
+        9, 0, // name.len
+        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
+        // .2
+        73, 0, 0, 0, 0, 0, 0, 0, // offset
+        9, // typ
+        6, 0, // name.len
+        b'm', b'u', b'f', b'f', b'i', b'n', //name
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_bytes_lv32() {
+    #[derive(Serialize)]
+    struct Rread<'a> {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        #[serde(with = "crate::bytes_lv32")]
+        data: &'a [u8],
+    }
+
+    let payload = [0xde, 0xad, 0xbe, 0xef];
+    let r = Rread {
+        size: 15,
+        typ: 117,
+        tag: 4,
+        data: &payload,
+    };
+
+    let expected =
+        vec![15, 0, 0, 0, 117, 4, 0, 4, 0, 0, 0, 0xde, 0xad, 0xbe, 0xef];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[cfg(feature = "serde_bytes")]
+#[test]
+fn test_struct_bytebuf_lv16() {
+    use serde_bytes::{ByteBuf, Bytes};
+
+    #[derive(Serialize)]
+    struct Rread<'a> {
+        size: u32,
+        #[serde(with = "crate::bytebuf_lv16")]
+        owned: ByteBuf,
+        #[serde(with = "crate::bytebuf_lv16")]
+        borrowed: &'a Bytes,
+    }
+
+    let r = Rread {
+        size: 12,
+        owned: ByteBuf::from(vec![0xab, 0xcd]),
+        borrowed: Bytes::new(&[0xde, 0xad]),
+    };
+
+    let expected = vec![12, 0, 0, 0, 2, 0, 0xab, 0xcd, 2, 0, 0xde, 0xad];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_bytes_lv16_vec() {
+    #[derive(Serialize)]
+    struct Twrite {
+        fid: u32,
+        offset: u64,
+        #[serde(with = "crate::bytes_lv16")]
+        data: Vec<u8>,
+    }
+
+    let w = Twrite {
+        fid: 7,
+        offset: 512,
+        data: vec![1, 2, 3],
+    };
+
+    let expected = vec![7, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 3, 0, 1, 2, 3];
+
+    assert_eq!(to_bytes_le(&w).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_rest() {
+    #[derive(Serialize)]
+    struct Frame {
+        typ: u8,
+        tag: u16,
+        #[serde(with = "crate::rest")]
+        body: Vec<u8>,
+    }
+
+    let f = Frame {
+        typ: 100,
+        tag: 1,
+        body: vec![9, 8, 7, 6],
+    };
+
+    assert_eq!(to_bytes_le(&f).unwrap(), vec![100, 1, 0, 9, 8, 7, 6]);
+}
+
+#[test]
+fn test_struct_fixed_array() {
+    #[derive(Serialize)]
+    struct Rattach {
+        size: u32,
+        typ: u8,
+        tag: u16,
+        qid: [u8; 13],
+    }
+
+    let r = Rattach {
+        size: 20,
+        typ: 105,
+        tag: 1,
+        qid: [0x80, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8],
+    };
+
+    let expected = vec![
+        20, 0, 0, 0, 105, 1, 0, 0x80, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8,
+    ];
+
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_str_fixed() {
+    #[derive(Serialize)]
+    struct Entry {
+        id: u16,
+        #[serde(serialize_with = "crate::str_fixed::serialize::<_, 8>")]
+        name: String,
+    }
+
+    let e = Entry {
+        id: 3,
+        name: "eth0".into(),
+    };
+
+    let expected = vec![3, 0, b'e', b't', b'h', b'0', 0, 0, 0, 0];
+    assert_eq!(to_bytes_le(&e).unwrap(), expected);
+
+    let e = Entry {
+        id: 3,
+        name: "ethernet".into(),
+    };
+
+    assert_eq!(
+        to_bytes_le(&e),
+        Err(Error::LengthOverflow { max: 7, actual: 8 })
+    );
+}
+
+#[test]
+fn test_struct_str_lv8_latin1() {
+    #[derive(Serialize)]
+    struct User {
+        id: u8,
+        #[serde(with = "crate::str_lv8_latin1")]
+        name: String,
+    }
+
+    let u = User {
+        id: 1,
+        name: "Zoë".into(),
+    };
+    assert_eq!(to_bytes_le(&u).unwrap(), vec![1, 3, b'Z', b'o', 0xeb]);
+
+    let u = User {
+        id: 1,
+        name: "Zoë ☃".into(),
+    };
+    assert!(matches!(to_bytes_le(&u), Err(Error::Message(_))));
+}
+
+#[test]
+fn test_struct_cstr() {
+    use std::ffi::CString;
+
+    #[derive(Serialize)]
+    struct Open {
+        mode: u8,
+        #[serde(with = "crate::cstr")]
+        path: CString,
+    }
+
+    let o = Open {
+        mode: 2,
+        path: CString::new("/etc").unwrap(),
+    };
+
+    assert_eq!(to_bytes_le(&o).unwrap(), b"\x02/etc\0".to_vec());
+}
+
+#[test]
+fn test_struct_str_lv16_bounded() {
+    #[derive(Serialize)]
+    struct Twalk {
+        fid: u32,
+        #[serde(serialize_with = "crate::str_lv16::serialize_bounded::<_, 4>")]
+        name: String,
+    }
+
+    let t = Twalk {
+        fid: 1,
+        name: "usr".into(),
+    };
+    assert_eq!(
+        to_bytes_le(&t).unwrap(),
+        vec![1, 0, 0, 0, 3, 0, b'u', b's', b'r']
+    );
+
+    let t = Twalk {
+        fid: 1,
+        name: "muffin".into(),
+    };
+    assert_eq!(
+        to_bytes_le(&t),
+        Err(Error::Message(
+            Error::LengthOverflow { max: 4, actual: 6 }.to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_length_overflow() {
+    #[derive(Serialize)]
+    struct Name {
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    #[derive(Serialize)]
+    struct Fids {
+        #[serde(with = "crate::vec_lv8")]
+        fids: Vec<u32>,
+    }
+
+    #[derive(Serialize)]
+    struct FidBytes {
+        #[serde(with = "crate::vec_lv8b")]
+        fids: Vec<Fid>,
+    }
+
+    #[derive(Clone, Serialize)]
+    struct Fid {
+        id: u32,
+    }
+
+    impl crate::WireSize for Fid {
+        fn wire_size(&self) -> usize {
+            std::mem::size_of::<u32>()
+        }
+    }
+
+    let n = Name {
+        name: "a".repeat(256),
+    };
+    assert_eq!(
+        to_bytes_le(&n),
+        Err(Error::LengthOverflow {
+            max: 255,
+            actual: 256
+        })
+    );
+
+    let f = Fids { fids: vec![0; 255] };
+    assert_eq!(to_bytes_le(&f).unwrap().len(), 1 + 255 * 4);
+
+    let f = Fids { fids: vec![0; 300] };
+    assert_eq!(
+        to_bytes_le(&f),
+        Err(Error::LengthOverflow {
+            max: 255,
+            actual: 300
+        })
+    );
+
+    let f = FidBytes {
+        fids: vec![Fid { id: 0 }; 64],
+    };
+    assert_eq!(
+        to_bytes_le(&f),
+        Err(Error::LengthOverflow {
+            max: 255,
+            actual: 256
+        })
+    );
+}
+
+#[test]
+fn test_struct_opt_str_lv16() {
+    #[derive(Serialize)]
+    struct Tauth {
+        afid: u32,
+        #[serde(with = "crate::opt_str_lv16")]
+        uname: Option<String>,
+        #[serde(with = "crate::opt_str_lv16")]
+        aname: Option<String>,
+    }
+
+    let t = Tauth {
+        afid: 1,
+        uname: Some("root".into()),
+        aname: None,
+    };
+
+    let expected = vec![1, 0, 0, 0, 4, 0, b'r', b'o', b'o', b't', 0, 0];
+    assert_eq!(to_bytes_le(&t).unwrap(), expected);
+}
+
+#[test]
+fn test_struct_lv24() {
+    #[derive(Serialize)]
+    struct Record {
+        #[serde(with = "crate::str_lv24")]
+        name: String,
+        #[serde(with = "crate::vec_lv24")]
+        ids: Vec<u16>,
+    }
+
+    let r = Record {
+        name: "abc".into(),
+        ids: vec![1, 2],
+    };
+
+    let expected = vec![3, 0, 0, b'a', b'b', b'c', 2, 0, 0, 1, 0, 2, 0];
+    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+
+    let expected = vec![0, 0, 3, b'a', b'b', b'c', 0, 0, 2, 0, 1, 0, 2];
+    assert_eq!(to_bytes_be(&r).unwrap(), expected);
+
+    let r = Record {
+        name: "a".repeat(0x100_0000),
+        ids: vec![],
+    };
+    assert_eq!(
+        to_bytes_le(&r),
+        Err(Error::LengthOverflow {
+            max: 0xff_ffff,
+            actual: 0x100_0000
+        })
+    );
+}
+
+#[test]
+fn test_struct_lv_inclusive() {
+    #[derive(Serialize)]
+    struct Attr {
+        typ: u8,
+        #[serde(with = "crate::str_lv16_inclusive")]
+        name: String,
+        #[serde(with = "crate::bytes_lv8_inclusive")]
+        value: Vec<u8>,
+    }
+
+    let a = Attr {
+        typ: 1,
+        name: "ab".into(),
+        value: vec![7, 8, 9],
+    };
+
+    let expected = vec![1, 4, 0, b'a', b'b', 4, 7, 8, 9];
+    assert_eq!(to_bytes_le(&a).unwrap(), expected);
+
+    let a = Attr {
+        typ: 1,
+        name: "ab".into(),
+        value: vec![0; 255],
+    };
+    assert_eq!(
+        to_bytes_le(&a),
+        Err(Error::LengthOverflow {
+            max: 255,
+            actual: 256
+        })
+    );
+}
+
+#[test]
+fn test_reject_interior_nul() {
+    #[derive(Serialize)]
+    struct Version {
+        msize: u32,
+        version: String,
+    }
+
+    let v = Version {
+        msize: 99,
+        version: "muf\0fin".into(),
+    };
+
+    let mut s = Serializer::<LittleEndian>::new();
+    v.serialize(&mut s).unwrap();
+    assert_eq!(s.into_bytes(), b"\x63\0\0\0muf\0fin\0".to_vec());
+
+    let mut s = Serializer::<LittleEndian>::new().reject_interior_nul(true);
+    assert_eq!(v.serialize(&mut s), Err(Error::InteriorNul));
+}
+
+#[test]
+fn test_struct_lv_utf16() {
+    #[derive(Serialize)]
+    struct Name {
+        #[serde(with = "crate::str_lv16_utf16")]
+        name: String,
+    }
+
+    let n = Name {
+        name: "h\u{e9}\u{1f600}".into(),
+    };
+
+    let out = to_bytes_le(&n).unwrap();
+    assert_eq!(out, vec![4, 0, b'h', 0, 0xe9, 0, 0x3d, 0xd8, 0x00, 0xde]);
+
+    let out = to_bytes_be(&n).unwrap();
+    assert_eq!(out, vec![0, 4, 0, b'h', 0, 0xe9, 0xd8, 0x3d, 0xde, 0x00]);
+}
+
+#[test]
+fn test_tuple() {
+    #[derive(Serialize)]
+    struct Point(u16, u32);
+
+    #[derive(Serialize)]
+    struct Entry {
+        pair: (u16, u32),
+        point: Point,
+    }
+
+    let e = Entry {
+        pair: (1, 2),
+        point: Point(3, 4),
+    };
+
+    let out = to_bytes_le(&e).unwrap();
+    assert_eq!(out, vec![1, 0, 2, 0, 0, 0, 3, 0, 4, 0, 0, 0]);
+}
+
+#[test]
+fn test_newtype() {
+    #[derive(Serialize)]
+    struct Fid(u32);
+
+    #[derive(Serialize)]
+    struct Tclunk {
+        tag: u16,
+        fid: Fid,
+    }
+
+    let t = Tclunk {
+        tag: 1,
+        fid: Fid(7),
+    };
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![1, 0, 7, 0, 0, 0]);
+}
+
+#[test]
+fn test_unit() {
+    #[derive(Serialize)]
+    struct Marker;
+
+    #[derive(Serialize)]
+    struct Rflush {
+        tag: u16,
+        marker: Marker,
+        nothing: (),
+        phantom: PhantomData<u64>,
+    }
+
+    let r = Rflush {
+        tag: 3,
+        marker: Marker,
+        nothing: (),
+        phantom: PhantomData,
+    };
+    let out = to_bytes_le(&r).unwrap();
+    assert_eq!(out, vec![3, 0]);
+}
+
+#[test]
+fn test_option() {
+    #[derive(Serialize)]
+    struct Qid {
+        typ: u8,
+        path: u64,
+    }
+
+    #[derive(Serialize)]
+    struct Rwalk {
+        tag: u16,
+        qid: Option<Qid>,
+        version: Option<u32>,
+    }
+
+    let r = Rwalk {
+        tag: 1,
+        qid: Some(Qid { typ: 2, path: 3 }),
+        version: None,
+    };
+
+    let out = to_bytes_le(&r).unwrap();
+    assert_eq!(out, vec![1, 0, 1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    let mut s = Serializer::<LittleEndian>::new().presence_flags(0xff, 0x80);
+    r.serialize(&mut s).unwrap();
+    assert_eq!(
+        s.into_bytes(),
+        vec![1, 0, 0x80, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0xff]
+    );
+}
+
+#[test]
+fn test_opt_sentinel() {
+    #[derive(Serialize)]
+    struct Twalk {
+        #[serde(with = "crate::opt_u32")]
+        newfid: Option<u32>,
+        #[serde(serialize_with = "crate::opt_u16::serialize_sentinel::<_, 0>")]
+        port: Option<u16>,
+    }
+
+    let t = Twalk {
+        newfid: None,
+        port: Some(80),
+    };
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![0xff, 0xff, 0xff, 0xff, 80, 0]);
+
+    let t = Twalk {
+        newfid: Some(7),
+        port: None,
+    };
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![7, 0, 0, 0, 0, 0]);
+
+    let t = Twalk {
+        newfid: Some(u32::MAX),
+        port: None,
+    };
+    assert!(to_bytes_le(&t).is_err());
+}
+
+#[test]
+fn test_struct_map_lv() {
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Attrs {
+        #[serde(with = "crate::map_lv16")]
+        attrs: BTreeMap<u8, u16>,
+        tail: u8,
+    }
+
+    let mut attrs = BTreeMap::new();
+    attrs.insert(1, 10);
+    attrs.insert(2, 20);
+    let a = Attrs { attrs, tail: 9 };
+
+    let out = to_bytes_le(&a).unwrap();
+    assert_eq!(out, vec![2, 0, 1, 10, 0, 2, 20, 0, 9]);
+}
+
+#[test]
+fn test_struct_set_lv() {
+    use std::collections::BTreeSet;
+
+    #[derive(Serialize)]
+    struct Groups {
+        #[serde(with = "crate::set_lv8")]
+        gids: BTreeSet<u16>,
+    }
+
+    let g = Groups {
+        gids: vec![5, 3].into_iter().collect(),
+    };
+
+    let out = to_bytes_le(&g).unwrap();
+    assert_eq!(out, vec![2, 3, 0, 5, 0]);
+}
+
+#[test]
+fn test_struct_vec_lv_collections() {
+    use std::collections::{LinkedList, VecDeque};
+
+    #[derive(Serialize)]
+    struct Queues {
+        #[serde(with = "crate::vec_lv8")]
+        deque: VecDeque<u16>,
+        #[serde(with = "crate::vec_lv16")]
+        list: LinkedList<u8>,
+    }
+
+    let q = Queues {
+        deque: vec![1, 2].into_iter().collect(),
+        list: vec![3, 4, 5].into_iter().collect(),
+    };
+
+    let out = to_bytes_le(&q).unwrap();
+    assert_eq!(out, vec![2, 1, 0, 2, 0, 3, 0, 3, 4, 5]);
+}
+
+#[test]
+fn test_struct_vec_lvb_primitive() {
+    #[derive(Serialize)]
+    struct Ids {
+        #[serde(with = "crate::vec_lv8b")]
+        ids: Vec<u16>,
+        #[serde(with = "crate::vec_lv8b")]
+        pairs: Vec<[u8; 2]>,
+    }
+
+    let i = Ids {
+        ids: vec![1, 2, 3],
+        pairs: vec![[4, 5]],
+    };
+
+    let out = to_bytes_le(&i).unwrap();
+    assert_eq!(out, vec![6, 1, 0, 2, 0, 3, 0, 2, 4, 5]);
+}
+
+#[test]
+fn test_struct_vec_lvb_inclusive() {
+    #[derive(Serialize)]
+    struct Tlv {
+        typ: u8,
+        #[serde(with = "crate::vec_lv8b_inclusive")]
+        values: Vec<u16>,
+    }
+
+    let o = Tlv {
+        typ: 3,
+        values: vec![1, 2],
+    };
+
+    let out = to_bytes_le(&o).unwrap();
+    assert_eq!(out, vec![3, 5, 1, 0, 2, 0]);
+}
+
+#[test]
+#[cfg(feature = "smallvec")]
+fn test_struct_smallvec() {
+    use smallvec::SmallVec;
+
+    #[derive(Serialize)]
+    struct Rwalk {
+        #[serde(with = "crate::vec_lv16")]
+        qids: SmallVec<[u32; 4]>,
+    }
+
+    let r = Rwalk {
+        qids: SmallVec::from_slice(&[1, 2]),
+    };
+    let out = to_bytes_le(&r).unwrap();
+    assert_eq!(out, vec![2, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+}
+
+#[test]
+#[cfg(feature = "arrayvec")]
+fn test_struct_arrayvec() {
+    use arrayvec::ArrayVec;
+
+    #[derive(Serialize)]
+    struct Rwalk {
+        #[serde(with = "crate::arrayvec_lv16")]
+        qids: ArrayVec<u32, 4>,
+    }
+
+    let mut qids = ArrayVec::new();
+    qids.push(1);
+    qids.push(2);
+    let out = to_bytes_le(&Rwalk { qids }).unwrap();
+    assert_eq!(out, vec![2, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+}
+
+#[test]
+fn test_struct_vec_lv_slice() {
+    #[derive(Serialize)]
+    struct Twalk<'a> {
+        #[serde(serialize_with = "crate::vec_lv8::serialize_slice")]
+        fids: &'a [u16],
+        #[serde(with = "crate::vec_lv8")]
+        qids: [u8; 3],
+    }
+
+    let fids = vec![1, 2];
+    let t = Twalk {
+        fids: &fids,
+        qids: [7, 8, 9],
+    };
+
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![2, 1, 0, 2, 0, 3, 7, 8, 9]);
+
+    let mut s = Serializer::<LittleEndian>::new();
+    crate::vec_lv16::serialize(&fids[..1], &mut s).unwrap();
+    assert_eq!(s.into_bytes(), vec![1, 0, 1, 0]);
+}
+
+#[test]
+fn test_vec_lv_serialize_iter() {
+    let names = ["a", "bc"];
+
+    let mut s = Serializer::<LittleEndian>::new();
+    crate::vec_lv16::serialize_iter(
+        names.iter().map(|n| n.len() as u8),
+        &mut s,
+    )
+    .unwrap();
+    assert_eq!(s.into_bytes(), vec![2, 0, 1, 2]);
+
+    // An iterator that yields fewer elements than it promised.
+    struct Liar(u8);
+    impl Iterator for Liar {
+        type Item = u8;
+        fn next(&mut self) -> Option<u8> {
+            if self.0 == 0 {
+                return None;
+            }
+            self.0 -= 1;
+            Some(self.0)
+        }
+    }
+    impl ExactSizeIterator for Liar {
+        fn len(&self) -> usize {
+            3
+        }
+    }
+
+    let mut s = Serializer::<LittleEndian>::new();
+    assert!(crate::vec_lv8::serialize_iter(Liar(2), &mut s).is_err());
+}
+
+#[test]
+fn test_struct_lazy_lv() {
+    #[derive(serde::Deserialize, Serialize)]
+    struct Rreaddir<'a> {
+        tag: u16,
+        #[serde(borrow, with = "crate::lazy_lv16b")]
+        entries: crate::LazyVec<'a, u16>,
+    }
+
+    let b = vec![1, 0, 4, 0, 7, 0, 8, 0];
+    let r: Rreaddir = crate::from_bytes_le(b.as_slice()).unwrap();
+    assert_eq!(to_bytes_le(&r).unwrap(), b);
+}
+
+#[test]
+fn test_struct_sized_lv() {
+    #[derive(Serialize)]
+    struct Qid {
+        typ: u8,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
+
+    #[derive(Serialize)]
+    struct Rstat {
+        tag: u16,
+        #[serde(with = "crate::sized_lv16")]
+        qid: Qid,
+        tail: u8,
+    }
+
+    let r = Rstat {
+        tag: 1,
+        qid: Qid {
+            typ: 2,
+            name: "ab".into(),
+        },
+        tail: 9,
+    };
+
+    let out = to_bytes_le(&r).unwrap();
+    assert_eq!(out, vec![1, 0, 4, 0, 2, 2, b'a', b'b', 9]);
+
+    let out = to_bytes_be(&r).unwrap();
+    assert_eq!(out, vec![0, 1, 0, 4, 2, 2, b'a', b'b', 9]);
+}
+
+#[test]
+fn test_struct_sized_lv16_double() {
+    #[derive(Serialize)]
+    struct Stat {
+        typ: u16,
+        #[serde(with = "crate::str_lv16")]
+        name: String,
+    }
+
+    #[derive(Serialize)]
+    struct Twstat {
+        tag: u16,
+        fid: u32,
+        #[serde(with = "crate::sized_lv16_double")]
+        stat: Stat,
+    }
+
+    let t = Twstat {
+        tag: 1,
+        fid: 2,
+        stat: Stat {
+            typ: 3,
+            name: "a".into(),
+        },
+    };
+
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![1, 0, 2, 0, 0, 0, 7, 0, 5, 0, 3, 0, 1, 0, b'a']);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_with_length() {
+    #[crate::with_length(u16)]
+    #[derive(Serialize)]
+    struct Attach {
+        fid: u32,
+        uname: String,
+        #[with_length(u8)]
+        aname: String,
+        #[with_length(u32)]
+        wnames: Vec<u8>,
+        #[serde(with = "ispf::str_lv8")]
+        extra: String,
+    }
+
+    let a = Attach {
+        fid: 1,
+        uname: "ab".into(),
+        aname: "c".into(),
+        wnames: vec![9],
+        extra: "d".into(),
+    };
+    let out = to_bytes_le(&a).unwrap();
+    assert_eq!(
+        out,
+        vec![1, 0, 0, 0, 2, 0, b'a', b'b', 1, b'c', 1, 0, 0, 0, 9, 1, b'd']
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_lv() {
+    #[derive(crate::Ispf)]
+    struct Tversion {
+        msize: u32,
+        #[ispf(lv = "u16")]
+        version: String,
+        #[ispf(lv = "u8")]
+        extra: Vec<u16>,
+    }
+
+    let t = Tversion {
+        msize: 8192,
+        version: "9P".into(),
+        extra: vec![1, 2],
+    };
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![0, 32, 0, 0, 2, 0, b'9', b'P', 2, 1, 0, 2, 0]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_lv_unit() {
+    #[derive(crate::Ispf)]
+    struct Records {
+        #[ispf(lv = "u8", unit = "bytes")]
+        bytes: Vec<u16>,
+        #[ispf(lv = "u8", unit = "elements")]
+        elements: Vec<u16>,
+    }
+
+    let r = Records {
+        bytes: vec![1, 2],
+        elements: vec![3],
+    };
+    let out = to_bytes_le(&r).unwrap();
+    assert_eq!(out, vec![4, 1, 0, 2, 0, 1, 3, 0]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_size_of() {
+    #[derive(crate::Ispf)]
+    struct Tversion {
+        #[ispf(size_of = "message")]
+        size: u32,
+        typ: u8,
+        tag: u16,
+        msize: u32,
+        #[ispf(lv = "u16")]
+        version: String,
+    }
+
+    let t = Tversion {
+        size: 0,
+        typ: 100,
+        tag: 0xffff,
+        msize: 8192,
+        version: "9P2000".into(),
+    };
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(
+        out,
+        vec![
+            19, 0, 0, 0, 100, 0xff, 0xff, 0, 32, 0, 0, 6, 0, b'9', b'P', b'2',
+            b'0', b'0', b'0',
+        ]
+    );
+
+    #[derive(crate::Ispf)]
+    struct Outer {
+        tag: u8,
+        #[ispf(size_of = "message")]
+        size: u16,
+        inner: Tversion,
+    }
+
+    let o = Outer {
+        tag: 7,
+        size: 0,
+        inner: t,
+    };
+    let out = to_bytes_be(&o).unwrap();
+    assert_eq!(&out[..7], &[7, 0, 22, 0, 0, 0, 19]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_magic() {
+    #[derive(crate::Ispf)]
+    struct Header {
+        #[ispf(magic = 0x3950_3031)]
+        magic: u32,
+        #[ispf(magic = 2)]
+        version: u8,
+        len: u16,
+    }
+
+    let h = Header {
+        magic: 0,
+        version: 9,
+        len: 3,
+    };
+    let out = to_bytes_be(&h).unwrap();
+    assert_eq!(out, vec![b'9', b'P', b'0', b'1', 2, 0, 3]);
+}
+
+#[test]
+fn test_checksum_vectors() {
+    use crate::Checksum;
+
+    assert_eq!(Checksum::Crc32.compute(b"123456789"), 0xcbf4_3926);
+    assert_eq!(Checksum::Crc16.compute(b"123456789"), 0x29b1);
+    assert_eq!(Checksum::Sum8.compute(b"123456789"), 0xdd);
+    assert_eq!(Checksum::Crc32.compute(b""), 0);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_checksum() {
+    #[derive(crate::Ispf)]
+    struct Trailer {
+        #[ispf(size_of = "message")]
+        size: u8,
+        data: [u8; 9],
+        #[ispf(checksum = "crc32")]
+        crc: u32,
+    }
+
+    let t = Trailer {
+        size: 0,
+        data: *b"123456789",
+        crc: 0,
+    };
+    let out = to_bytes_le(&t).unwrap();
+    let crc = crate::Checksum::Crc32.compute(&out[..10]) as u32;
+    assert_eq!(out[0], 14);
+    assert_eq!(&out[10..], &crc.to_le_bytes());
+
+    #[derive(crate::Ispf)]
+    struct Header {
+        #[ispf(checksum = "crc16")]
+        crc: u16,
+        #[ispf(lv = "u8")]
+        data: String,
+    }
+
+    let h = Header {
+        crc: 0,
+        data: "123456789".into(),
+    };
+    let out = to_bytes_be(&h).unwrap();
+    let crc = crate::Checksum::Crc16.compute(&out[2..]) as u16;
+    assert_eq!(&out[..2], &crc.to_be_bytes());
+
+    // A checksum in the middle, over the fields after the header only.
+    #[derive(crate::Ispf)]
+    struct Record {
+        kind: u8,
+        #[ispf(checksum = "crc16", over = "len..")]
+        crc: u16,
+        len: u8,
+        #[ispf(lv = "u8")]
+        name: String,
+    }
+
+    let r = Record {
+        kind: 7,
+        crc: 0,
+        len: 9,
+        name: "ab".into(),
+    };
+    let out = to_bytes_le(&r).unwrap();
+    let crc = crate::Checksum::Crc16.compute(&out[3..]) as u16;
+    assert_eq!(out[0], 7);
+    assert_eq!(&out[1..3], &crc.to_le_bytes());
+    assert_eq!(&out[3..], &[9, 2, b'a', b'b']);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_since() {
+    #[derive(crate::Ispf)]
+    struct Hello {
+        fid: u16,
+        #[ispf(since = 2)]
+        flags: u8,
+        #[ispf(since = 3)]
+        mode: u16,
+    }
+
+    let h = Hello {
+        fid: 1,
+        flags: 2,
+        mode: 3,
+    };
+    assert_eq!(to_bytes_le(&h).unwrap(), vec![1, 0, 2, 3, 0]);
+
+    let mut s = Serializer::<LittleEndian>::new().version(2);
+    h.serialize(&mut s).unwrap();
+    assert_eq!(s.into_bytes(), vec![1, 0, 2]);
+
+    let mut s = Serializer::<LittleEndian>::new().version(1);
+    h.serialize(&mut s).unwrap();
+    assert_eq!(s.into_bytes(), vec![1, 0]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_default() {
+    #[derive(crate::Ispf)]
+    struct Rlerror {
+        ecode: u32,
+        #[ispf(default)]
+        detail: String,
+    }
+
+    let r = Rlerror {
+        ecode: 2,
+        detail: "x".into(),
+    };
+    assert_eq!(to_bytes_le(&r).unwrap(), vec![2, 0, 0, 0, b'x', 0]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_align() {
+    #[derive(crate::Ispf)]
+    struct Entry {
+        kind: u8,
+        #[ispf(align = 4)]
+        ino: u32,
+        #[ispf(align = 4)]
+        len: u16,
+        #[ispf(align = 2)]
+        off: u16,
+    }
+
+    let e = Entry {
+        kind: 1,
+        ino: 2,
+        len: 3,
+        off: 4,
+    };
+    assert_eq!(
+        to_bytes_le(&e).unwrap(),
+        vec![1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 4, 0]
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_endian() {
+    #[derive(crate::Ispf)]
+    #[ispf(endian = "big")]
+    struct Header {
+        #[ispf(size_of = "message")]
+        size: u16,
+        id: u32,
+    }
+
+    #[derive(crate::Ispf)]
+    #[ispf(endian = "little")]
+    struct Frame {
+        header: Header,
+        crc: u16,
+    }
+
+    let f = Frame {
+        header: Header { size: 0, id: 1 },
+        crc: 2,
+    };
+    let expected = vec![0, 6, 0, 0, 0, 1, 2, 0];
+    assert_eq!(to_bytes_le(&f).unwrap(), expected);
+    assert_eq!(to_bytes_be(&f).unwrap(), expected);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_dual_endian() {
+    use crate::ByteOrder;
+
+    #[derive(crate::Ispf)]
+    #[ispf(dual_endian)]
+    struct Record {
+        kind: u16,
+        len: u32,
+    }
+
+    let r = Record { kind: 1, len: 2 };
+    assert_eq!(
+        r.to_bytes_in(ByteOrder::Little).unwrap(),
+        vec![1, 0, 2, 0, 0, 0]
+    );
+    assert_eq!(
+        r.to_bytes_in(ByteOrder::Big).unwrap(),
+        vec![0, 1, 0, 0, 0, 2]
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_bits() {
+    #[derive(crate::Ispf)]
+    struct Descriptor {
+        #[ispf(bits = 4)]
+        version: u8,
+        #[ispf(bits = 3)]
+        kind: u8,
+        #[ispf(bits = 1)]
+        dir: bool,
+        #[ispf(bits = 12)]
+        len: u16,
+        #[ispf(bits = 4)]
+        flags: u8,
+    }
+
+    let d = Descriptor {
+        version: 5,
+        kind: 2,
+        dir: true,
+        len: 0x123,
+        flags: 0xa,
+    };
+    assert_eq!(to_bytes_be(&d).unwrap(), vec![0xa5, 0xa1, 0x23]);
+
+    let d = Descriptor { kind: 8, ..d };
+    assert_eq!(
+        to_bytes_be(&d),
+        Err(Error::Message(
+            "value 8 of field `kind` does not fit in 3 bits".into()
+        ))
+    );
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_serialized_size() {
+    #[derive(crate::Ispf)]
+    struct Trailer {
+        #[ispf(size_of = "message")]
+        size: u8,
+        #[ispf(lv = "u16")]
+        data: String,
+        #[ispf(checksum = "crc32")]
+        crc: u32,
+    }
+
+    #[derive(Serialize, serde::Deserialize)]
+    struct Tflush {
         size: u32,
         typ: u8,
+        oldtag: u16,
+    }
+
+    #[derive(crate::IspfMessage)]
+    #[ispf(type_offset = 4, tag = "u8")]
+    enum Message {
+        #[ispf(type = 108)]
+        Tflush(Tflush),
+    }
+
+    let t = Trailer {
+        size: 0,
+        data: "hello".into(),
+        crc: 0,
+    };
+    assert_eq!(serialized_size(&t).unwrap(), 12);
+    assert_eq!(serialized_size(&t).unwrap(), to_bytes_le(&t).unwrap().len());
+
+    let m = Message::Tflush(Tflush {
+        size: 7,
+        typ: 0,
+        oldtag: 1,
+    });
+    assert_eq!(serialized_size(&m).unwrap(), 7);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_vectored() {
+    use crate::{to_vectored_le, Payload};
+
+    #[derive(Serialize, serde::Deserialize)]
+    struct Blob(#[serde(with = "crate::bytes_lv32")] Vec<u8>);
+
+    #[derive(crate::Ispf)]
+    struct Rread {
+        #[ispf(size_of = "message")]
+        size: u32,
         tag: u16,
-        msize: u32,
-        version: String,
+        data: Blob,
     }
 
-    let v = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    impl Payload for Rread {
+        fn payload(&self) -> Option<&[u8]> {
+            Some(&self.data.0)
+        }
+    }
+
+    let r = Rread {
+        size: 0,
+        tag: 1,
+        data: Blob(vec![7; 9]),
     };
+    let v = to_vectored_le(&r).unwrap();
+    assert_eq!(v.head(), &[19, 0, 0, 0, 1, 0, 9, 0, 0, 0]);
+    assert_eq!(v.payload(), Some(&r.data.0[..]));
+
+    #[derive(crate::Ispf)]
+    struct Sealed {
+        #[ispf(checksum = "sum8")]
+        sum: u8,
+        data: Blob,
+    }
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, b'm', b'u', b'f', b'f', b'i', b'n',
-        b'\0',
-    ];
+    impl Payload for Sealed {
+        fn payload(&self) -> Option<&[u8]> {
+            Some(&self.data.0)
+        }
+    }
 
-    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+    let s = Sealed {
+        sum: 0,
+        data: Blob(vec![1, 2]),
+    };
+    let v = to_vectored_le(&s).unwrap();
+    assert_eq!(v.head(), &[5, 2, 0, 0, 0, 1, 2]);
+    assert_eq!(v.payload(), None);
 }
 
 #[test]
-fn test_struct_str_lv8() {
-    #[derive(Serialize)]
-    struct Version {
+#[cfg(feature = "derive")]
+fn test_derive_payload() {
+    use crate::{from_bytes_le, to_parts_le, Payload};
+
+    #[derive(Debug, PartialEq, crate::Ispf)]
+    struct Rread {
+        #[ispf(size_of = "message")]
         size: u32,
-        typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(serialize_with = "crate::str_lv8::serialize")]
-        version: String,
+        #[ispf(lv = "u32", payload)]
+        data: Vec<u8>,
     }
 
-    let v = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    let r = Rread {
+        size: 0,
+        tag: 1,
+        data: vec![7; 9],
     };
+    assert_eq!(r.payload(), Some(&r.data[..]));
+    let (head, payload) = to_parts_le(&r).unwrap();
+    assert_eq!(head, &[19, 0, 0, 0, 1, 0, 9, 0, 0, 0]);
+    assert!(std::ptr::eq(payload.unwrap(), &r.data[..]));
+
+    let b = to_bytes_le(&r).unwrap();
+    assert_eq!(&b[..10], &head[..]);
+    assert_eq!(&b[10..], &r.data[..]);
+    let r2: Rread = from_bytes_le(&b).unwrap();
+    assert_eq!(r2.data, r.data);
+
+    #[derive(crate::Ispf)]
+    struct Rdata {
+        count: u16,
+        #[ispf(bytes_from = "count", payload)]
+        data: Vec<u8>,
+    }
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, b'm', b'u', b'f', b'f', b'i',
-        b'n',
-    ];
+    let d = Rdata {
+        count: 0,
+        data: vec![1, 2, 3],
+    };
+    let (head, payload) = to_parts_le(&d).unwrap();
+    assert_eq!(head, &[3, 0]);
+    assert_eq!(payload, Some(&[1, 2, 3][..]));
+}
 
-    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_read_bytes() {
+    use crate::{from_bytes_le, ReadBytes};
+
+    #[derive(crate::Ispf)]
+    struct Rread<'a> {
+        #[ispf(size_of = "message")]
+        size: u32,
+        #[ispf(lv = "u16")]
+        data: ReadBytes<&'a [u8]>,
+    }
+
+    let file = [5u8; 10];
+    let r = Rread {
+        size: 0,
+        data: ReadBytes::new(&file[..], 10),
+    };
+    let b = to_bytes_le(&r).unwrap();
+    assert_eq!(&b[..6], &[16, 0, 0, 0, 10, 0]);
+    assert_eq!(&b[6..], &file);
+
+    let r: Rread = from_bytes_le(&b).unwrap();
+    assert_eq!(r.size, 16);
+    assert_eq!(r.data.into_inner(), Some(&file[..]));
+
+    // The size is known before the data is read, so the message goes out as
+    // the data is read rather than once all of it has been.
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+
+    struct Sent(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sent {
+        fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(b);
+            Ok(b.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A file that notes how much had been sent when it was last read.
+    struct File {
+        data: std::io::Cursor<Vec<u8>>,
+        sent: Rc<RefCell<Vec<u8>>>,
+        seen: Rc<RefCell<usize>>,
+    }
+
+    impl Read for File {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            *self.seen.borrow_mut() = self.sent.borrow().len();
+            self.data.read(buf)
+        }
+    }
+
+    // The derive decodes as well, which a file never is here.
+    impl<'de> crate::DeserializeLv<'de> for ReadBytes<File> {
+        fn deserialize_lv<D: serde::Deserializer<'de>>(
+            _: crate::Width,
+            _: D,
+        ) -> std::result::Result<Self, D::Error> {
+            Err(serde::de::Error::custom("not decoded"))
+        }
+    }
+
+    #[derive(crate::Ispf)]
+    struct Rbig {
+        #[ispf(size_of = "message")]
+        size: u32,
+        #[ispf(lv = "u32")]
+        data: ReadBytes<File>,
+    }
+
+    let sent = Rc::new(RefCell::new(Vec::new()));
+    let seen = Rc::new(RefCell::new(0));
+    let data: Vec<u8> = (0..40000).map(|i| i as u8).collect();
+    let r = Rbig {
+        size: 0,
+        data: ReadBytes::new(
+            File {
+                data: std::io::Cursor::new(data.clone()),
+                sent: sent.clone(),
+                seen: seen.clone(),
+            },
+            data.len(),
+        ),
+    };
+    assert_eq!(crate::serialized_size(&r), Ok(40008));
+    crate::to_writer_le(&r, Sent(sent.clone())).unwrap();
+    assert!(*seen.borrow() > 0);
+    let sent = sent.borrow();
+    assert_eq!(&sent[..8], &[0x48, 0x9c, 0, 0, 0x40, 0x9c, 0, 0]);
+    assert_eq!(&sent[8..], &data[..]);
 }
 
 #[test]
-fn test_struct_str_lv16() {
+#[cfg(feature = "derive")]
+fn test_derive_count_from() {
+    #[derive(crate::Ispf)]
+    struct Twalk {
+        fid: u32,
+        nwqid: u16,
+        #[ispf(count_from = "nwqid")]
+        qids: Vec<u32>,
+    }
+
+    let t = Twalk {
+        fid: 1,
+        nwqid: 0,
+        qids: vec![7, 8],
+    };
+
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![1, 0, 0, 0, 2, 0, 7, 0, 0, 0, 8, 0, 0, 0]);
+
+    #[derive(crate::Ispf)]
+    struct Small {
+        n: u8,
+        #[ispf(count_from = "n")]
+        v: Vec<u8>,
+    }
+
+    let s = Small {
+        n: 0,
+        v: vec![0; 256],
+    };
+    assert!(to_bytes_le(&s).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_bytes_from() {
+    #[derive(crate::Ispf)]
+    struct Rread<'a> {
+        tag: u16,
+        count: u32,
+        #[ispf(bytes_from = "count")]
+        data: &'a [u8],
+    }
+
+    let r = Rread {
+        tag: 1,
+        count: 0,
+        data: b"abc",
+    };
+
+    let out = to_bytes_le(&r).unwrap();
+    assert_eq!(out, vec![1, 0, 3, 0, 0, 0, b'a', b'b', b'c']);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_len_adjust() {
+    #[derive(crate::Ispf)]
+    struct Record {
+        size: u16,
+        #[ispf(bytes_from = "size", len_adjust = 2)]
+        data: Vec<u8>,
+    }
+
+    let r = Record {
+        size: 0,
+        data: b"abc".to_vec(),
+    };
+    let out = to_bytes_be(&r).unwrap();
+    assert_eq!(out, vec![0, 5, b'a', b'b', b'c']);
+
+    #[derive(crate::Ispf)]
+    struct Short {
+        n: u8,
+        #[ispf(count_from = "n", len_adjust = -1)]
+        v: Vec<u8>,
+    }
+
+    let s = Short {
+        n: 0,
+        v: vec![7, 8],
+    };
+    assert_eq!(to_bytes_le(&s).unwrap(), vec![1, 7, 8]);
+
+    let s = Short { n: 0, v: vec![] };
+    assert!(to_bytes_le(&s).is_err());
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_try_from() {
+    use std::convert::TryFrom;
+
+    #[derive(Clone, Copy)]
+    enum QidType {
+        _File,
+        Dir,
+    }
+
+    impl From<QidType> for u8 {
+        fn from(t: QidType) -> u8 {
+            match t {
+                QidType::_File => 0x00,
+                QidType::Dir => 0x80,
+            }
+        }
+    }
+
+    impl TryFrom<u8> for QidType {
+        type Error = ();
+
+        fn try_from(v: u8) -> std::result::Result<Self, ()> {
+            match v {
+                0x00 => Ok(QidType::_File),
+                0x80 => Ok(QidType::Dir),
+                _ => Err(()),
+            }
+        }
+    }
+
+    #[derive(crate::Ispf)]
+    struct Qid {
+        #[ispf(try_from = "u8")]
+        typ: QidType,
+        version: u32,
+    }
+
+    let q = Qid {
+        typ: QidType::Dir,
+        version: 1,
+    };
+    assert_eq!(to_bytes_le(&q).unwrap(), vec![0x80, 1, 0, 0, 0]);
+}
+
+#[test]
+fn test_unit_variant() {
+    #[derive(Serialize)]
+    enum Mode {
+        _Read,
+        Write,
+    }
+
+    assert_eq!(to_bytes_be(&Mode::Write).unwrap(), vec![0, 0, 0, 1]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_unit_enum() {
+    #[derive(crate::Ispf, Clone, Copy)]
+    #[ispf(tag = "u16")]
+    enum Kind {
+        _Plain,
+        Dir = 0x80,
+        Link,
+    }
+
+    #[derive(crate::Ispf, Clone, Copy)]
+    #[repr(u8)]
+    enum Small {
+        _A,
+        B,
+    }
+
+    assert_eq!(to_bytes_be(&Kind::Dir).unwrap(), vec![0, 0x80]);
+    assert_eq!(to_bytes_le(&Kind::Link).unwrap(), vec![0x81, 0]);
+    assert_eq!(to_bytes_le(&Small::B).unwrap(), vec![1]);
+}
+
+#[test]
+fn test_data_variants() {
+    #[derive(Serialize)]
+    enum Msg {
+        _Ping,
+        Data(u16),
+        Pair(u8, u8),
+        Walk { fid: u32, newfid: u32 },
+    }
+
+    assert_eq!(to_bytes_le(&Msg::Data(7)).unwrap(), vec![1, 0, 0, 0, 7, 0]);
+    assert_eq!(to_bytes_be(&Msg::Data(7)).unwrap(), vec![0, 0, 0, 1, 0, 7]);
+    assert_eq!(
+        to_bytes_be(&Msg::Pair(1, 2)).unwrap(),
+        vec![0, 0, 0, 2, 1, 2]
+    );
+    assert_eq!(
+        to_bytes_le(&Msg::Walk { fid: 1, newfid: 2 }).unwrap(),
+        vec![3, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_serialized_size() {
     #[derive(Serialize)]
     struct Version {
         size: u32,
         typ: u8,
         tag: u16,
         msize: u32,
-        #[serde(with = "crate::str_lv16")]
+        #[serde(with = "ispf::str_lv16")]
         version: String,
     }
 
     let v = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+        size: 0,
+        typ: 100,
+        tag: 0,
+        msize: 8192,
+        version: "9P2000".into(),
     };
+    assert_eq!(serialized_size(&v).unwrap(), 19);
+    assert_eq!(serialized_size(&v).unwrap(), to_bytes_be(&v).unwrap().len());
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, b'm', b'u', b'f', b'f', b'i',
-        b'n',
-    ];
+    #[derive(Serialize)]
+    struct Short(#[serde(with = "ispf::str_lv8")] String);
+    assert!(serialized_size(&Short("x".repeat(256))).is_err());
+}
 
-    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_sizes() {
+    use crate::{FixedSize, MaxSize};
+
+    #[derive(crate::Ispf, crate::FixedSize)]
+    struct Qid {
+        typ: u8,
+        version: u32,
+        path: u64,
+    }
+
+    #[derive(crate::Ispf, crate::FixedSize)]
+    #[ispf(tag = "u16")]
+    enum Mode {
+        _Read,
+        _Write,
+    }
+
+    #[derive(crate::Ispf, crate::MaxSize)]
+    struct Rwalk {
+        tag: u16,
+        nwqid: u8,
+        #[ispf(count_from = "nwqid")]
+        qids: Vec<Qid>,
+        #[ispf(lv = "u8")]
+        name: String,
+    }
+
+    #[derive(crate::Ispf, crate::MaxSize)]
+    #[ispf(tag = "u8")]
+    enum Reply {
+        _Empty,
+        _Walk(Rwalk),
+        _Mode { mode: Mode, extra: [u32; 2] },
+    }
+
+    let q = Qid {
+        typ: 0,
+        version: 1,
+        path: 2,
+    };
+    assert_eq!(Qid::WIRE_SIZE, 13);
+    assert_eq!(to_bytes_le(&q).unwrap().len(), Qid::WIRE_SIZE);
+    assert_eq!(Mode::WIRE_SIZE, 2);
+    assert_eq!(<[Qid; 2]>::WIRE_SIZE, 26);
+    assert_eq!(Qid::MAX_WIRE_SIZE, 13);
+
+    assert_eq!(Rwalk::MAX_WIRE_SIZE, 2 + 1 + 255 * 13 + 1 + 255);
+    let r = Rwalk {
+        tag: 1,
+        nwqid: 0,
+        qids: (0..255)
+            .map(|_| Qid {
+                typ: 0,
+                version: 0,
+                path: 0,
+            })
+            .collect(),
+        name: "x".repeat(255),
+    };
+    assert_eq!(to_bytes_le(&r).unwrap().len(), Rwalk::MAX_WIRE_SIZE);
+    assert_eq!(Reply::MAX_WIRE_SIZE, 1 + Rwalk::MAX_WIRE_SIZE);
+    assert_eq!(<Option<u32>>::MAX_WIRE_SIZE, 5);
 }
 
 #[test]
-fn test_struct_str_lv32() {
-    #[derive(Serialize)]
-    struct Version {
+#[cfg(feature = "derive")]
+fn test_derive_assert_size() {
+    #[derive(crate::Ispf)]
+    #[ispf(assert_size = 7)]
+    struct Header {
         size: u32,
         typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(serialize_with = "crate::str_lv32::serialize")]
-        version: String,
     }
 
-    let v = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    let h = Header {
+        size: 7,
+        typ: 100,
+        tag: 1,
     };
+    assert_eq!(to_bytes_le(&h).unwrap().len(), 7);
+}
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, b'm', b'u', b'f', b'f',
-        b'i', b'n',
-    ];
+#[test]
+fn test_variant_lv_fields() {
+    #[derive(Serialize)]
+    enum Req {
+        Attach(#[serde(with = "ispf::str_lv16")] String, u32),
+        Walk {
+            fid: u32,
+            #[serde(with = "ispf::vec_lv16")]
+            names: Vec<u8>,
+        },
+    }
 
-    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+    assert_eq!(
+        to_bytes_le(&Req::Attach("ab".into(), 9)).unwrap(),
+        vec![0, 0, 0, 0, 2, 0, b'a', b'b', 9, 0, 0, 0]
+    );
+    assert_eq!(
+        to_bytes_le(&Req::Walk {
+            fid: 1,
+            names: vec![5, 6]
+        })
+        .unwrap(),
+        vec![1, 0, 0, 0, 1, 0, 0, 0, 2, 0, 5, 6]
+    );
 }
 
 #[test]
-fn test_struct_str_lv64() {
+fn test_lv_module() {
+    crate::lv_module!(#[allow(dead_code)] mod str_lv24i: str, u24, inclusive);
+    crate::lv_module!(#[allow(dead_code)] mod bytes_lv24: bytes, u24);
+    crate::lv_module!(
+        #[allow(dead_code)]
+        mod vec_lv24bi: vec_bytes, u24, inclusive
+    );
+
     #[derive(Serialize)]
-    struct Version {
-        size: u32,
+    struct Entry<'a> {
+        #[serde(with = "str_lv24i")]
+        name: String,
+        #[serde(with = "bytes_lv24")]
+        data: &'a [u8],
+        #[serde(with = "vec_lv24bi")]
+        ids: Vec<u16>,
+    }
+
+    let e = Entry {
+        name: "ab".into(),
+        data: &[7],
+        ids: vec![1],
+    };
+    assert_eq!(
+        to_bytes_be(&e).unwrap(),
+        vec![0, 0, 5, b'a', b'b', 0, 0, 1, 7, 0, 0, 5, 0, 1]
+    );
+}
+
+#[test]
+fn test_skip() {
+    #[derive(Serialize)]
+    struct Entry {
+        id: u16,
+        #[serde(skip)]
+        cached: u32,
+        #[serde(skip_serializing)]
+        seen: bool,
+        len: u8,
+    }
+
+    let e = Entry {
+        id: 1,
+        cached: 2,
+        seen: true,
+        len: 3,
+    };
+    assert_eq!(to_bytes_le(&e).unwrap(), vec![1, 0, 3]);
+
+    let other = Entry {
+        cached: e.cached + 1,
+        seen: !e.seen,
+        ..e
+    };
+    assert_eq!(to_bytes_le(&other).unwrap(), vec![1, 0, 3]);
+}
+
+#[test]
+fn test_variant_width() {
+    #[derive(Serialize)]
+    #[repr(u8)]
+    enum Mode {
+        _Read,
+        Write(u16),
+    }
+
+    let mut s = Serializer::<BigEndian>::new().variant_width(Width::U8);
+    Mode::Write(7).serialize(&mut s).unwrap();
+    assert_eq!(s.into_bytes(), vec![1, 0, 7]);
+
+    let mut s = Serializer::<BigEndian>::new().variant_width(Width::U16);
+    Mode::Write(7).serialize(&mut s).unwrap();
+    assert_eq!(s.into_bytes(), vec![0, 1, 0, 7]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_data_enum() {
+    #[derive(crate::Ispf)]
+    #[repr(u8)]
+    enum Body {
+        Clunk {
+            fid: u32,
+        },
+        Walk {
+            fid: u32,
+            n: u8,
+            #[ispf(count_from = "n")]
+            names: Vec<u16>,
+        } = 110,
+        Flush(u16),
+        Ack,
+    }
+
+    assert_eq!(
+        to_bytes_le(&Body::Clunk { fid: 5 }).unwrap(),
+        vec![0, 5, 0, 0, 0]
+    );
+    let walk = Body::Walk {
+        fid: 1,
+        n: 0,
+        names: vec![3, 4],
+    };
+    assert_eq!(
+        to_bytes_le(&walk).unwrap(),
+        vec![110, 1, 0, 0, 0, 2, 3, 0, 4, 0]
+    );
+    assert_eq!(to_bytes_be(&Body::Flush(9)).unwrap(), vec![111, 0, 9]);
+    assert_eq!(to_bytes_be(&Body::Ack).unwrap(), vec![112]);
+}
+
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_tag_from() {
+    use crate::Lv16;
+
+    #[derive(crate::Ispf)]
+    #[repr(u8)]
+    enum Body {
+        Tversion { msize: u32, version: Lv16<String> } = 100,
+        Rclunk = 121,
+    }
+
+    #[derive(crate::Ispf)]
+    struct Message {
         typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(serialize_with = "crate::str_lv64::serialize")]
-        version: String,
+        #[ispf(tag_from = "typ")]
+        body: Body,
     }
 
-    let v = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
+    let m = Message {
+        typ: 0,
+        tag: 1,
+        body: Body::Tversion {
+            msize: 8192,
+            version: Lv16("9P".into()),
+        },
+    };
+    assert_eq!(
+        to_bytes_le(&m).unwrap(),
+        vec![100, 1, 0, 0, 0x20, 0, 0, 2, 0, b'9', b'P']
+    );
+
+    let m = Message {
+        typ: 0,
+        tag: 2,
+        body: Body::Rclunk,
     };
+    assert_eq!(to_bytes_le(&m).unwrap(), vec![121, 2, 0]);
+}
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
-        b'f', b'f', b'i', b'n',
-    ];
+#[test]
+#[cfg(feature = "derive")]
+fn test_derive_other_variant() {
+    #[derive(crate::Ispf)]
+    #[repr(u8)]
+    enum Body {
+        _Ping = 1,
+        #[ispf(other)]
+        Unknown {
+            tag: u8,
+            data: Vec<u8>,
+        },
+    }
 
-    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+    let b = Body::Unknown {
+        tag: 9,
+        data: vec![1, 2, 3],
+    };
+    assert_eq!(to_bytes_le(&b).unwrap(), vec![9, 1, 2, 3]);
+
+    // A tag that belongs to a known variant would read back as that one.
+    let b = Body::Unknown {
+        tag: 1,
+        data: vec![],
+    };
+    assert!(to_bytes_le(&b).is_err());
+
+    // The other variant still counts when numbering those after it.
+    #[derive(crate::Ispf)]
+    #[repr(u8)]
+    enum Mid {
+        _A = 1,
+        #[ispf(other)]
+        Unknown(u8, Vec<u8>),
+        B,
+    }
+
+    assert_eq!(to_bytes_le(&Mid::B).unwrap(), vec![3]);
+    assert_eq!(to_bytes_le(&Mid::Unknown(2, vec![7])).unwrap(), vec![2, 7]);
+    assert!(to_bytes_le(&Mid::Unknown(3, vec![7])).is_err());
 }
 
 #[test]
-fn test_nested_struct() {
-    #[derive(Serialize)]
-    struct Version {
+#[cfg(feature = "derive")]
+fn test_derive_message() {
+    #[derive(Serialize, serde::Deserialize)]
+    struct Tflush {
         size: u32,
         typ: u8,
         tag: u16,
-        msize: u32,
-        #[serde(serialize_with = "crate::str_lv64::serialize")]
-        version: String,
-        info: Info,
+        oldtag: u16,
     }
 
-    #[derive(Serialize)]
-    struct Info {
+    #[derive(Serialize, serde::Deserialize)]
+    struct Rflush {
+        size: u32,
         typ: u8,
-        version: u32,
-        path: u64,
+        tag: u16,
     }
 
-    let v = Version {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        msize: 99,
-        version: "muffin".into(),
-        info: Info {
-            typ: 3,
-            version: 12345,
-            path: 678910,
-        },
-    };
+    #[derive(crate::IspfMessage)]
+    #[ispf(type_offset = 4, tag = "u8")]
+    enum Message {
+        #[ispf(type = 108)]
+        Tflush(Tflush),
+        #[ispf(type = 109)]
+        Rflush(Rflush),
+    }
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 99, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, b'm', b'u',
-        b'f', b'f', b'i', b'n', 3, 57, 48, 0, 0, 254, 91, 10, 0, 0, 0, 0, 0,
-    ];
+    let m = Message::Tflush(Tflush {
+        size: 9,
+        typ: 0,
+        tag: 1,
+        oldtag: 2,
+    });
+    assert_eq!(to_bytes_le(&m).unwrap(), vec![9, 0, 0, 0, 108, 1, 0, 2, 0]);
+
+    let m = Message::Rflush(Rflush {
+        size: 7,
+        typ: 0,
+        tag: 1,
+    });
+    assert_eq!(to_bytes_le(&m).unwrap(), vec![7, 0, 0, 0, 109, 1, 0]);
+
+    #[derive(crate::IspfMessage)]
+    #[ispf(type_offset = 4, tag = "u8")]
+    enum Short {
+        #[ispf(type = 1)]
+        Tag(u16),
+    }
 
-    assert_eq!(to_bytes_le(&v).unwrap(), expected);
+    assert_eq!(
+        to_bytes_le(&Short::Tag(1)),
+        Err(Error::OffsetOutOfRange { offset: 4, len: 2 })
+    );
 }
 
 #[test]
-fn test_struct_vec_lv8() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv8")]
-        pub data: Vec<Dirent>,
+fn test_raw_message() {
+    use crate::RawMessage;
+
+    let m = RawMessage {
+        typ: 108,
+        tag: 1,
+        body: vec![2, 0].into(),
+    };
+    assert_eq!(to_bytes_le(&m).unwrap(), vec![9, 0, 0, 0, 108, 1, 0, 2, 0]);
+    assert_eq!(to_bytes_be(&m).unwrap(), vec![0, 0, 0, 9, 108, 0, 1, 2, 0]);
+}
+
+#[test]
+#[cfg(feature = "bitflags")]
+fn test_bits() {
+    bitflags::bitflags! {
+        #[derive(Clone, Copy)]
+        struct Mode: u16 {
+            const READ = 0x1;
+            const WRITE = 0x2;
+        }
     }
 
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    #[derive(Serialize)]
+    struct Open {
+        #[serde(with = "crate::bits")]
+        mode: Mode,
+        #[serde(with = "crate::bits_strict")]
+        other: Mode,
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let o = Open {
+        mode: Mode::READ | Mode::from_bits_retain(0x100),
+        other: Mode::READ | Mode::WRITE,
     };
+    assert_eq!(to_bytes_be(&o).unwrap(), vec![1, 1, 0, 3]);
+}
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+#[test]
+fn test_pad() {
+    use crate::Pad;
 
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    #[derive(Serialize)]
+    struct Header {
+        kind: u8,
+        reserved: Pad<3>,
+        len: u32,
+    }
+
+    let h = Header {
+        kind: 1,
+        reserved: Pad,
+        len: 2,
+    };
+    assert_eq!(to_bytes_be(&h).unwrap(), vec![1, 0, 0, 0, 0, 0, 0, 2]);
 }
 
 #[test]
-fn test_struct_vec_lv16() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv16")]
-        pub data: Vec<Dirent>,
+fn test_to_writer() {
+    #[derive(Serialize)]
+    struct Msg {
+        typ: u8,
+        len: u16,
     }
 
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
+    let mut out = vec![0xff];
+    to_writer_be(&Msg { typ: 1, len: 2 }, &mut out).unwrap();
+    assert_eq!(out, vec![0xff, 1, 0, 2]);
+
+    let mut buf = [0u8; 2];
+    let mut short: &mut [u8] = &mut buf;
+    match to_writer_le(&Msg { typ: 1, len: 2 }, &mut short) {
+        Err(Error::Io { kind, .. }) => {
+            assert_eq!(kind, io::ErrorKind::WriteZero)
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_output() {
+    #[derive(Serialize)]
+    struct Msg {
+        typ: u8,
         #[serde(with = "crate::str_lv16")]
-        pub name: String,
+        name: String,
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let m = Msg {
+        typ: 1,
+        name: "hi".into(),
     };
+    let mut buf = [0u8; 8];
+    let mut s =
+        Serializer::<BigEndian, _>::with_output(SliceOutput::new(&mut buf));
+    m.serialize(&mut s).unwrap();
+    assert_eq!(s.into_output().into_written(), &[1, 0, 2, b'h', b'i']);
+
+    /// Records the size of each write.
+    struct Chunks(Vec<usize>, Vec<u8>);
+
+    impl io::Write for Chunks {
+        fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+            self.0.push(b.len());
+            self.1.extend_from_slice(b);
+            Ok(b.len())
+        }
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    let msgs: Vec<Msg> = (0..3)
+        .map(|i| Msg {
+            typ: i,
+            name: "x".repeat(5000),
+        })
+        .collect();
+    let mut s = Serializer::<LittleEndian, _>::with_output(WriteOutput::new(
+        Chunks(Vec::new(), Vec::new()),
+    ));
+    msgs.serialize(&mut s).unwrap();
+    let w = s.into_output().finish().unwrap();
+    assert_eq!(w.0, vec![10006, 5003]);
+    assert_eq!(w.1, to_bytes_le(&msgs).unwrap());
+
+    #[derive(Serialize)]
+    struct Batch(#[serde(with = "crate::sized_lv32")] Vec<Msg>);
+
+    let batch = Batch(msgs);
+    let mut s = Serializer::<LittleEndian, _>::with_output(WriteOutput::new(
+        Chunks(Vec::new(), Vec::new()),
+    ));
+    batch.serialize(&mut s).unwrap();
+    let w = s.into_output().finish().unwrap();
+    assert_eq!(w.0, vec![15013]);
+    assert_eq!(w.1, to_bytes_le(&batch).unwrap());
 }
 
 #[test]
-fn test_struct_vec_lv32() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv32")]
-        pub data: Vec<Dirent>,
-    }
-
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
+#[cfg(feature = "bytes")]
+fn test_output_bytes() {
+    #[derive(Serialize)]
+    struct Msg {
+        typ: u8,
         #[serde(with = "crate::str_lv16")]
-        pub name: String,
+        name: String,
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let m = Msg {
+        typ: 1,
+        name: "hi".into(),
     };
-
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
-
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    let mut s = Serializer::<BigEndian, _>::with_output(bytes::BytesMut::new());
+    m.serialize(&mut s).unwrap();
+    let out = s.into_output().freeze();
+    assert_eq!(&out[..], &[1, 0, 2, b'h', b'i']);
 }
 
 #[test]
-fn test_struct_vec_lv64() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv64")]
-        pub data: Vec<Dirent>,
+fn test_to_vectored() {
+    use crate::{to_vectored_le, Payload};
+
+    #[derive(Serialize)]
+    struct Rread {
+        typ: u8,
+        tag: u16,
+        #[serde(with = "crate::bytes_lv32")]
+        data: Vec<u8>,
     }
 
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    impl Payload for Rread {
+        fn payload(&self) -> Option<&[u8]> {
+            Some(&self.data)
+        }
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let r = Rread {
+        typ: 117,
+        tag: 1,
+        data: vec![7; 1000],
     };
+    let v = to_vectored_le(&r).unwrap();
+    assert_eq!(v.head(), &[117, 1, 0, 0xe8, 3, 0, 0]);
+    assert!(std::ptr::eq(v.payload().unwrap(), &r.data[..]));
+    assert_eq!(v.len(), 1007);
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 2, 0, 0, 0, 0, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+    let mut out = Vec::new();
+    v.write_all(&mut out).unwrap();
+    assert_eq!(out, to_bytes_le(&r).unwrap());
+
+    #[derive(Serialize)]
+    struct Trailing {
+        #[serde(with = "crate::bytes_lv8")]
+        data: Vec<u8>,
+        end: u8,
+    }
+
+    impl Payload for Trailing {
+        fn payload(&self) -> Option<&[u8]> {
+            Some(&self.data)
+        }
+    }
 
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    let t = Trailing {
+        data: vec![1, 2],
+        end: 3,
+    };
+    let v = to_vectored_le(&t).unwrap();
+    assert_eq!(v.head(), &[2, 1, 2, 3]);
+    assert_eq!(v.payload(), None);
 }
 
 #[test]
-fn test_struct_vec_lv8b() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv8b")]
-        pub data: Vec<Dirent>,
-    }
+fn test_to_parts() {
+    use crate::{to_parts_be, Payload};
 
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    #[derive(Serialize)]
+    struct Rread {
+        tag: u16,
+        #[serde(with = "crate::bytes_lv16")]
+        data: Vec<u8>,
     }
 
-    impl crate::WireSize for Dirent {
-        fn wire_size(&self) -> usize {
-            use std::mem::size_of;
-            size_of::<u64>()        // offset
-                + size_of::<u8>()   // typ
-                + size_of::<u16>()  // name.len TODO: awkward, user specifying
-                                    //                serde inserted value
-                + self.name.len() // name
+    impl Payload for Rread {
+        fn payload(&self) -> Option<&[u8]> {
+            Some(&self.data)
         }
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let r = Rread {
+        tag: 2,
+        data: vec![9; 300],
     };
+    let (head, payload) = to_parts_be(&r).unwrap();
+    assert_eq!(head, &[0, 2, 1, 44]);
+    assert!(std::ptr::eq(payload.unwrap(), &r.data[..]));
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
-
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    let r = Rread {
+        tag: 2,
+        data: Vec::new(),
+    };
+    assert_eq!(to_parts_be(&r).unwrap(), (vec![0, 2, 0, 0], None));
 }
 
 #[test]
-fn test_struct_vec_lv16b() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv16b")]
-        pub data: Vec<Dirent>,
-    }
-
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+fn test_to_bytes_into() {
+    #[derive(Serialize)]
+    struct Msg {
+        typ: u8,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
     }
 
-    impl crate::WireSize for Dirent {
-        fn wire_size(&self) -> usize {
-            use std::mem::size_of;
-            size_of::<u64>()        // offset
-                + size_of::<u8>()   // typ
-                + size_of::<u16>()  // name.len TODO: awkward, user specifying
-                                    //                serde inserted value
-                + self.name.len() // name
-        }
+    let mut out = Vec::new();
+    for (typ, name) in [(1, "a"), (2, "bc")] {
+        let m = Msg {
+            typ,
+            name: name.into(),
+        };
+        to_bytes_into_be(&m, &mut out).unwrap();
     }
+    assert_eq!(out, vec![1, 1, b'a', 2, 2, b'b', b'c']);
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let long = Msg {
+        typ: 3,
+        name: "x".repeat(256),
     };
+    assert_eq!(
+        to_bytes_into_le(&long, &mut out),
+        Err(Error::LengthOverflow {
+            max: 255,
+            actual: 256
+        })
+    );
+    assert_eq!(out.len(), 7);
+}
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+#[test]
+fn test_to_slice() {
+    #[derive(Serialize)]
+    struct Msg {
+        typ: u8,
+        len: u16,
+        #[serde(with = "crate::str_lv8")]
+        name: String,
+    }
 
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    let m = Msg {
+        typ: 1,
+        len: 2,
+        name: "hi".into(),
+    };
+    let mut buf = [0xffu8; 8];
+    assert_eq!(to_slice_be(&m, &mut buf).unwrap(), 6);
+    assert_eq!(buf, [1, 0, 2, 2, b'h', b'i', 0xff, 0xff]);
+    assert_eq!(
+        to_slice_le(&m, &mut buf[..5]),
+        Err(Error::BufferTooSmall { capacity: 5 })
+    );
 }
 
 #[test]
-fn test_struct_vec_lv32b() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv32b")]
-        pub data: Vec<Dirent>,
+fn test_read_bytes() {
+    use crate::{to_writer_le, Lv32, ReadBytes};
+
+    /// A reader handing out a few bytes at a time.
+    struct Trickle(std::io::Cursor<Vec<u8>>);
+
+    impl std::io::Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = buf.len().min(7);
+            self.0.read(&mut buf[..n])
+        }
     }
 
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    #[derive(Serialize)]
+    struct Rread {
+        tag: u16,
+        data: Lv32<ReadBytes<Trickle>>,
     }
 
-    impl crate::WireSize for Dirent {
-        fn wire_size(&self) -> usize {
-            use std::mem::size_of;
-            size_of::<u64>()        // offset
-                + size_of::<u8>()   // typ
-                + size_of::<u16>()  // name.len TODO: awkward, user specifying
-                                    //                serde inserted value
-                + self.name.len() // name
-        }
+    #[derive(Serialize)]
+    struct Expected {
+        tag: u16,
+        #[serde(with = "crate::bytes_lv32")]
+        data: Vec<u8>,
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let data: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+    let r = Rread {
+        tag: 3,
+        data: Lv32(ReadBytes::new(
+            Trickle(std::io::Cursor::new(data.clone())),
+            data.len(),
+        )),
     };
+    let mut out = Vec::new();
+    to_writer_le(&r, &mut out).unwrap();
+    let expected = Expected { tag: 3, data };
+    assert_eq!(out, to_bytes_le(&expected).unwrap());
+    assert!(to_bytes_le(&r).is_err());
+    assert_eq!(r.data.0.len(), 20000);
+
+    // Only the given length is read, and without a prefix.
+    let b = [1u8, 2, 3, 4];
+    let mut reader = &b[..];
+    assert_eq!(
+        to_bytes_be(&ReadBytes::new(&mut reader, 3)).unwrap(),
+        &[1, 2, 3]
+    );
+    assert_eq!(reader, &[4]);
+
+    let short = ReadBytes::new(&b[..], 5);
+    assert_eq!(
+        to_bytes_le(&short).unwrap_err(),
+        Error::Message("reader ended before its length of 5".into()),
+    );
+
+    // Counting the bytes leaves the reader alone; writing them uses it up.
+    let once = ReadBytes::new(&b[..], 4);
+    assert_eq!(crate::serialized_size(&once), Ok(4));
+    assert_eq!(to_bytes_le(&once).unwrap(), &b);
+    assert_eq!(
+        to_bytes_le(&once).unwrap_err(),
+        Error::Message("reader already consumed".into()),
+    );
+}
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+#[test]
+#[cfg(feature = "futures")]
+fn test_message_sink() {
+    use crate::futures::MessageSink;
+    use ::futures::SinkExt;
 
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+    #[derive(Serialize)]
+    struct Msg {
+        size: u16,
+        tag: u16,
+    }
+
+    let mut out = Vec::new();
+    ::futures::executor::block_on(async {
+        let mut sink =
+            MessageSink::<_, Msg, BigEndian>::new(&mut out).buffer_limit(8);
+        sink.feed(Msg { size: 4, tag: 1 }).await.unwrap();
+        sink.feed(Msg { size: 4, tag: 2 }).await.unwrap();
+        assert_eq!(sink.buffered(), &[0, 4, 0, 1, 0, 4, 0, 2]);
+        // The buffer is at its limit, so it is written before the next.
+        sink.feed(Msg { size: 4, tag: 3 }).await.unwrap();
+        assert_eq!(sink.buffered(), &[0, 4, 0, 3]);
+        sink.send(Msg { size: 4, tag: 4 }).await.unwrap();
+        assert_eq!(sink.buffered(), &[]);
+        sink.close().await.unwrap();
+    });
+    assert_eq!(out, [0, 4, 0, 1, 0, 4, 0, 2, 0, 4, 0, 3, 0, 4, 0, 4]);
 }
 
 #[test]
-fn test_struct_vec_lv64b() {
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Rreaddir {
-        pub size: u32,
-        pub typ: u8,
-        pub tag: u16,
-        #[serde(with = "crate::vec_lv64b")]
-        pub data: Vec<Dirent>,
-    }
+fn test_tagged() {
+    use crate::Tagged;
 
-    #[derive(Debug, Serialize, PartialEq)]
-    pub struct Dirent {
-        pub offset: u64,
-        pub typ: u8,
-        #[serde(with = "crate::str_lv16")]
-        pub name: String,
+    #[derive(Serialize)]
+    struct Record {
+        a: Tagged<1, u16>,
+        b: Tagged<2, (u8, u8)>,
     }
 
-    impl crate::WireSize for Dirent {
-        fn wire_size(&self) -> usize {
-            use std::mem::size_of;
-            size_of::<u64>()        // offset
-                + size_of::<u8>()   // typ
-                + size_of::<u16>()  // name.len TODO: awkward, user specifying
-                                    //                serde inserted value
-                + self.name.len() // name
-        }
+    let r = Record {
+        a: Tagged(0x0102),
+        b: Tagged((3, 4)),
+    };
+    assert_eq!(to_bytes_be(&r).unwrap(), vec![1, 1, 2, 2, 3, 4]);
+}
+
+#[test]
+fn test_lv_types() {
+    use crate::{Lv16, Lv8};
+
+    #[derive(Serialize)]
+    struct Tattach {
+        uname: Lv16<String>,
+        qids: Lv8<Vec<u16>>,
     }
 
-    let r = Rreaddir {
-        size: 47,
-        typ: 9,
-        tag: 15,
-        data: vec![
-            Dirent {
-                offset: 37,
-                typ: 2,
-                name: "blueberry".into(),
-            },
-            Dirent {
-                offset: 73,
-                typ: 9,
-                name: "muffin".into(),
-            },
-        ],
+    let t = Tattach {
+        uname: Lv16("ab".into()),
+        qids: vec![1, 2].into(),
     };
 
-    let expected = vec![
-        47, 0, 0, 0, 9, 15, 0, 37, 0, 0, 0, 0, 0, 0, 0, // len
-        // .1
-        37, 0, 0, 0, 0, 0, 0, 0, // offset
-        2, // typ
-        9, 0, // name.len
-        b'b', b'l', b'u', b'e', b'b', b'e', b'r', b'r', b'y', // name
-        // .2
-        73, 0, 0, 0, 0, 0, 0, 0, // offset
-        9, // typ
-        6, 0, // name.len
-        b'm', b'u', b'f', b'f', b'i', b'n', //name
-    ];
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(out, vec![2, 0, b'a', b'b', 2, 1, 0, 2, 0]);
+}
 
-    assert_eq!(to_bytes_le(&r).unwrap(), expected);
+#[test]
+fn test_struct_str_list_lv() {
+    #[derive(Serialize)]
+    struct Twalk {
+        fid: u32,
+        #[serde(with = "crate::str_list_lv16")]
+        wname: Vec<String>,
+    }
+
+    let t = Twalk {
+        fid: 1,
+        wname: vec!["usr".into(), "b".into()],
+    };
+
+    let out = to_bytes_le(&t).unwrap();
+    assert_eq!(
+        out,
+        vec![1, 0, 0, 0, 2, 0, 3, 0, b'u', b's', b'r', 1, 0, b'b']
+    );
 }
@@ -0,0 +1,94 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::marker::PhantomData;
+
+use crate::__private::{lv_max_size, LvElement};
+use crate::lv::{Lv16, Lv32, Lv64, Lv8};
+use crate::pad::Pad;
+use crate::tagged::Tagged;
+
+/// A type whose values always encode to the same number of bytes.
+pub trait FixedSize {
+    const WIRE_SIZE: usize;
+}
+
+/// A type whose values encode to at most a known number of bytes. Every
+/// `FixedSize` type is also `MaxSize`.
+pub trait MaxSize {
+    const MAX_WIRE_SIZE: usize;
+}
+
+impl<T: FixedSize> MaxSize for T {
+    const MAX_WIRE_SIZE: usize = T::WIRE_SIZE;
+}
+
+macro_rules! fixed_int {
+    ($($t:ty),*) => {$(
+        impl FixedSize for $t {
+            const WIRE_SIZE: usize = std::mem::size_of::<$t>();
+        }
+    )*};
+}
+
+fixed_int!(u8, u16, u32, u64);
+
+impl FixedSize for () {
+    const WIRE_SIZE: usize = 0;
+}
+
+impl<T: ?Sized> FixedSize for PhantomData<T> {
+    const WIRE_SIZE: usize = 0;
+}
+
+impl<T: FixedSize, const N: usize> FixedSize for [T; N] {
+    const WIRE_SIZE: usize = T::WIRE_SIZE * N;
+}
+
+impl<const N: usize> FixedSize for Pad<N> {
+    const WIRE_SIZE: usize = N;
+}
+
+impl<const TYPE: u8, T: FixedSize> FixedSize for Tagged<TYPE, T> {
+    const WIRE_SIZE: usize = 1 + T::WIRE_SIZE;
+}
+
+macro_rules! fixed_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: FixedSize),*> FixedSize for ($($t,)*) {
+            const WIRE_SIZE: usize = 0 $(+ $t::WIRE_SIZE)*;
+        }
+    };
+}
+
+fixed_tuple!(A);
+fixed_tuple!(A, B);
+fixed_tuple!(A, B, C);
+fixed_tuple!(A, B, C, D);
+fixed_tuple!(A, B, C, D, E);
+fixed_tuple!(A, B, C, D, E, F);
+fixed_tuple!(A, B, C, D, E, F, G);
+fixed_tuple!(A, B, C, D, E, F, G, H);
+
+impl<T: MaxSize> MaxSize for Option<T> {
+    const MAX_WIRE_SIZE: usize = 1 + T::MAX_WIRE_SIZE;
+}
+
+macro_rules! max_lv {
+    ($name:ident, $t:ty) => {
+        impl<T: LvElement> MaxSize for $name<T> {
+            const MAX_WIRE_SIZE: usize = lv_max_size::<T>(
+                std::mem::size_of::<$t>(),
+                <$t as crate::__private::CountField>::MAX_LEN,
+            );
+        }
+    };
+}
+
+max_lv!(Lv8, u8);
+max_lv!(Lv16, u16);
+max_lv!(Lv32, u32);
+max_lv!(Lv64, u64);
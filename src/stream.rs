@@ -0,0 +1,149 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::cell::Cell;
+use std::io;
+
+use serde::ser::{self, SerializeTuple, SerializeTupleStruct};
+use serde::{Deserializer, Serialize, Serializer};
+
+use crate::lv::{DeserializeLv, SerializeLv, Width};
+use crate::ser::RawBytes;
+
+/// A byte field of known length whose bytes come from a `std::io::Read`,
+/// such as a file, rather than from memory.
+///
+/// The bytes are read and written in chunks as the field is serialized, so
+/// with `to_writer` a large field goes from the reader to the writer without
+/// being held in memory whole. A message holding its own size is sized before
+/// it is written, so the size goes out first. A checksum over the field still
+/// holds the message back until it is filled in. The reader is consumed by the
+/// first call to `serialize`, but counting the bytes, as `serialized_size`
+/// does, leaves it alone.
+///
+/// Serializing writes the bytes alone; with `lv` in the derive, or wrapped in
+/// `Lv32` and the like, they follow their length. Deserializing an `lv` field
+/// gives a `ReadBytes<&[u8]>` borrowing the bytes from the input.
+pub struct ReadBytes<R> {
+    reader: Cell<Option<R>>,
+    len: usize,
+}
+
+impl<R> ReadBytes<R> {
+    /// The most read from the reader at once.
+    const CHUNK: usize = 8 * 1024;
+
+    /// A field of `len` bytes read from `reader`. Serializing fails if the
+    /// reader ends before `len` bytes; anything after them is left unread.
+    pub fn new(reader: R, len: usize) -> Self {
+        ReadBytes {
+            reader: Cell::new(Some(reader)),
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Give back the reader, or `None` if the field has been serialized.
+    pub fn into_inner(self) -> Option<R> {
+        self.reader.into_inner()
+    }
+}
+
+impl<R: io::Read> ReadBytes<R> {
+    /// Copy the bytes from the reader to `field`, a chunk at a time.
+    fn copy<E: ser::Error>(
+        &self,
+        mut field: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut reader = self
+            .reader
+            .take()
+            .ok_or_else(|| E::custom("reader already consumed"))?;
+        let mut buf = vec![0; self.len.min(Self::CHUNK)];
+        let mut left = self.len;
+        while left > 0 {
+            let n = left.min(buf.len());
+            reader
+                .read_exact(&mut buf[..n])
+                .map_err(|e| match e.kind() {
+                    io::ErrorKind::UnexpectedEof => E::custom(format!(
+                        "reader ended before its length of {}",
+                        self.len
+                    )),
+                    _ => E::custom(e),
+                })?;
+            field(&buf[..n])?;
+            left -= n;
+        }
+        Ok(())
+    }
+
+    fn chunks(&self) -> usize {
+        self.len.div_ceil(Self::CHUNK)
+    }
+}
+
+/// The bytes of a `ReadBytes` as the only field of a "stream" tuple struct,
+/// which a serializer that only counts bytes counts without reading them.
+struct Stream<'a, R>(&'a ReadBytes<R>);
+
+impl<R: io::Read> Serialize for Stream<'_, R> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut t = s.serialize_tuple_struct("stream", self.0.len)?;
+        t.serialize_field(&Chunks(self.0))?;
+        t.end()
+    }
+}
+
+/// The bytes of a `ReadBytes`, read from its reader a chunk at a time.
+struct Chunks<'a, R>(&'a ReadBytes<R>);
+
+impl<R: io::Read> Serialize for Chunks<'_, R> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut t = s.serialize_tuple(self.0.chunks())?;
+        self.0.copy(|b| t.serialize_element(&RawBytes(b)))?;
+        t.end()
+    }
+}
+
+impl<R: io::Read> Serialize for ReadBytes<R> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        Stream(self).serialize(s)
+    }
+}
+
+impl<R: io::Read> SerializeLv for ReadBytes<R> {
+    fn serialize_lv<S: Serializer>(
+        &self,
+        width: Width,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut t = s.serialize_tuple_struct(width.bytes(), self.len)?;
+        t.serialize_field(&Stream(self))?;
+        t.end()
+    }
+}
+
+impl<'de: 'a, 'a> DeserializeLv<'de> for ReadBytes<&'a [u8]> {
+    fn deserialize_lv<D: Deserializer<'de>>(
+        width: Width,
+        d: D,
+    ) -> Result<Self, D::Error> {
+        let b: &'a [u8] = d.deserialize_tuple_struct(
+            width.bytes(),
+            2,
+            crate::de::TlvBytesVisitor::new(),
+        )?;
+        Ok(ReadBytes::new(b, b.len()))
+    }
+}
@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::Error;
+
+/// A value preceded by the constant type byte `TYPE`, which is checked when
+/// decoding.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tagged<const TYPE: u8, T>(pub T);
+
+impl<const TYPE: u8, T> Tagged<TYPE, T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<const TYPE: u8, T> From<T> for Tagged<TYPE, T> {
+    fn from(v: T) -> Self {
+        Tagged(v)
+    }
+}
+
+impl<const TYPE: u8, T> Deref for Tagged<TYPE, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<const TYPE: u8, T> DerefMut for Tagged<TYPE, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<const TYPE: u8, T: Serialize> Serialize for Tagged<TYPE, T> {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut t = s.serialize_tuple(2)?;
+        t.serialize_element(&TYPE)?;
+        t.serialize_element(&self.0)?;
+        t.end()
+    }
+}
+
+impl<'de, const TYPE: u8, T: Deserialize<'de>> Deserialize<'de>
+    for Tagged<TYPE, T>
+{
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_tuple(2, TaggedVisitor(PhantomData))
+    }
+}
+
+struct TaggedVisitor<const TYPE: u8, T>(PhantomData<T>);
+
+impl<'de, const TYPE: u8, T: Deserialize<'de>> Visitor<'de>
+    for TaggedVisitor<TYPE, T>
+{
+    type Value = Tagged<TYPE, T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value of type {}", TYPE)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let typ: u8 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        if typ != TYPE {
+            return Err(Error::UnexpectedType {
+                expected: TYPE,
+                actual: typ,
+            }
+            .into_de());
+        }
+        let value = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(Tagged(value))
+    }
+}
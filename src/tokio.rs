@@ -0,0 +1,17 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+//! Reading and writing whole messages over tokio's `AsyncRead` and
+//! `AsyncWrite`.
+//!
+//! A message starts with a field holding its size, such as a derived
+//! `size_of = "message"` field, which counts the whole message, itself
+//! included. The size is read first, then the rest of the message, and the
+//! message is decoded from the lot, size field and all.
+
+use ::tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+crate::frame::message_io!();
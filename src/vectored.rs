@@ -0,0 +1,224 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Copyright 2022 Oxide Computer Company
+
+use std::io::{self, IoSlice};
+
+use serde::Serialize;
+
+use crate::error::Result;
+use crate::ser::{NumSer, Output, Serializer};
+use crate::{BigEndian, LittleEndian};
+
+/// A message with a byte field, such as the data of a read reply, that may
+/// be sent from where it is rather than copied in with the rest.
+///
+/// The field must be serialized as a run of bytes, as with `bytes_lv32`.
+pub trait Payload {
+    /// The bytes of the payload field, if the message has one.
+    fn payload(&self) -> Option<&[u8]>;
+}
+
+/// An encoded message in two parts: everything up to the payload, and the
+/// payload itself, borrowed from the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vectored<'a> {
+    head: Vec<u8>,
+    payload: Option<&'a [u8]>,
+}
+
+impl<'a> Vectored<'a> {
+    /// The bytes before the payload, or the whole message if the payload
+    /// could not be left out.
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// The payload, if it follows the head.
+    pub fn payload(&self) -> Option<&'a [u8]> {
+        self.payload
+    }
+
+    /// The number of bytes in the whole message.
+    pub fn len(&self) -> usize {
+        self.head.len() + self.payload.map_or(0, <[u8]>::len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The message as slices for `write_vectored`.
+    pub fn io_slices(&self) -> [IoSlice<'_>; 2] {
+        [
+            IoSlice::new(&self.head),
+            IoSlice::new(self.payload.unwrap_or_default()),
+        ]
+    }
+
+    /// Write the whole message to `w` with vectored writes, continuing after
+    /// short ones.
+    pub fn write_all<W: io::Write>(&self, mut w: W) -> io::Result<()> {
+        let mut head: &[u8] = &self.head;
+        let mut payload = self.payload.unwrap_or_default();
+        while !head.is_empty() || !payload.is_empty() {
+            let n = match w
+                .write_vectored(&[IoSlice::new(head), IoSlice::new(payload)])
+            {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            let from_head = n.min(head.len());
+            head = &head[from_head..];
+            payload = &payload[n - from_head..];
+        }
+        Ok(())
+    }
+
+    /// The head and the payload.
+    pub fn into_parts(self) -> (Vec<u8>, Option<&'a [u8]>) {
+        (self.head, self.payload)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// The payload has not been written yet, or was not written as one run
+    /// of bytes.
+    Waiting,
+    /// The payload has been written, and left out of `head`.
+    Deferred,
+    /// The payload was left out but has since been copied into `head`, or
+    /// there is none.
+    Inline,
+}
+
+/// An output that leaves out the payload when it is written as one run of
+/// bytes and nothing follows it. Anything that needs the payload's bytes in
+/// place, such as a checksum over it or a field after it, copies it in.
+struct VectoredOutput<'a> {
+    head: Vec<u8>,
+    payload: &'a [u8],
+    state: State,
+}
+
+impl VectoredOutput<'_> {
+    /// Copy in a payload that was left out.
+    fn inline(&mut self) {
+        if self.state == State::Deferred {
+            self.head.extend_from_slice(self.payload);
+            self.state = State::Inline;
+        }
+    }
+}
+
+impl Output for VectoredOutput<'_> {
+    fn len(&self) -> usize {
+        match self.state {
+            State::Deferred => self.head.len() + self.payload.len(),
+            _ => self.head.len(),
+        }
+    }
+
+    fn tail_mut(&mut self, from: usize) -> Option<&mut [u8]> {
+        self.inline();
+        Some(&mut self.head[from..])
+    }
+
+    fn extend(&mut self, b: &[u8]) -> Result<()> {
+        if self.state == State::Waiting && std::ptr::eq(b, self.payload) {
+            self.state = State::Deferred;
+            return Ok(());
+        }
+        self.inline();
+        self.head.extend_from_slice(b);
+        Ok(())
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.inline();
+            self.head.truncate(len);
+        }
+    }
+
+    fn patch(&mut self, at: usize, b: &[u8]) -> Result<()> {
+        if at + b.len() > self.head.len() {
+            self.inline();
+        }
+        self.head[at..at + b.len()].copy_from_slice(b);
+        Ok(())
+    }
+}
+
+/// Serialize `value`, leaving its payload out of the encoded head when the
+/// payload comes last, so that a large payload is sent from where it is.
+/// Otherwise the whole message is in the head.
+pub fn to_vectored<Endian, T>(value: &T) -> Result<Vectored<'_>>
+where
+    T: Serialize + Payload,
+    Endian: NumSer,
+{
+    let (payload, state) = match value.payload() {
+        Some(p) if !p.is_empty() => (p, State::Waiting),
+        _ => (&[][..], State::Inline),
+    };
+    let output = VectoredOutput {
+        head: Vec::new(),
+        payload,
+        state,
+    };
+    let mut serializer = Serializer::<Endian, _>::with_output(output);
+    value.serialize(&mut serializer)?;
+    let output = serializer.into_output();
+    let payload = match output.state {
+        State::Deferred => Some(output.payload),
+        _ => None,
+    };
+    Ok(Vectored {
+        head: output.head,
+        payload,
+    })
+}
+
+pub fn to_vectored_le<T>(value: &T) -> Result<Vectored<'_>>
+where
+    T: Serialize + Payload,
+{
+    to_vectored::<LittleEndian, T>(value)
+}
+
+pub fn to_vectored_be<T>(value: &T) -> Result<Vectored<'_>>
+where
+    T: Serialize + Payload,
+{
+    to_vectored::<BigEndian, T>(value)
+}
+
+/// Serialize `value` into a head and, if it could be left out, the payload
+/// borrowed from `value`, as for `to_vectored`.
+pub fn to_parts<Endian, T>(value: &T) -> Result<(Vec<u8>, Option<&[u8]>)>
+where
+    T: Serialize + Payload,
+    Endian: NumSer,
+{
+    to_vectored::<Endian, T>(value).map(Vectored::into_parts)
+}
+
+pub fn to_parts_le<T>(value: &T) -> Result<(Vec<u8>, Option<&[u8]>)>
+where
+    T: Serialize + Payload,
+{
+    to_parts::<LittleEndian, T>(value)
+}
+
+pub fn to_parts_be<T>(value: &T) -> Result<(Vec<u8>, Option<&[u8]>)>
+where
+    T: Serialize + Payload,
+{
+    to_parts::<BigEndian, T>(value)
+}